@@ -1,9 +1,11 @@
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use std::default::Default;
-use std::{str, env};
+use std::{str, str::FromStr, env, path::Path, sync::Arc};
 use crate::database::models::punishment::PunishmentType;
+use crate::socket::chat::chat_filter::ChatFilterConfig;
+use crate::database::store::{StorageBackend, redis::RedisOptions};
 use crate::util::webhook::WebhookUtils;
 
 use super::database::models::level_color::LevelColor;
@@ -75,12 +77,107 @@ pub async fn deserialize_mars_config() -> anyhow::Result<MarsConfig> {
         deserialize_mars_options(),
         deserialize_mars_data()
     )?;
+    Ok(build_mars_config(token, options, data))
+}
+
+fn build_mars_config(token: String, options: MarsConfigOptions, data: MarsConfigData) -> MarsConfig {
     let webhooks = WebhookUtils::new(
         &(if options.reports_webhook_url.is_empty() { None } else { Some(options.reports_webhook_url.clone()) }), 
         &(if options.punishments_webhook_url.is_empty() { None } else { Some(options.punishments_webhook_url.clone()) }), 
         &(if options.notes_webhook_url.is_empty() { None } else { Some(options.notes_webhook_url.clone()) })
     );
-    Ok(MarsConfig { token, options, data, webhooks })
+    MarsConfig { token, options, data, webhooks }
+}
+
+pub const DEFAULT_NETWORK_ID : &str = "default";
+// every other network's redis prefix lives under this, the default network's keys never start with it
+pub const NETWORK_REDIS_NAMESPACE : &str = "network:";
+
+// one entry of networks.yml, every network gets its own database, redis namespace and token
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NetworkDefinition {
+    id: String,
+    token: String,
+    // defaults to "<mongo-database>-<id>"
+    mongo_database: Option<String>,
+    // defaults to "network:<id>:", must start with "network:" so it can't share keys with the default network
+    redis_prefix: Option<String>,
+    // data files found here replace the default ones, e.g. <data-directory>/broadcasts.yml
+    data_directory: Option<String>,
+    #[serde(default)]
+    webhooks: NetworkWebhooks
+}
+
+#[derive(Deserialize, Default)]
+struct NetworkWebhooks {
+    punishments: Option<String>,
+    reports: Option<String>,
+    notes: Option<String>
+}
+
+pub struct NetworkConfig {
+    pub id: String,
+    pub mongo_database: String,
+    // where socket recordings of this network are replayed
+    pub replay_database: String,
+    pub redis_prefix: String,
+    pub config: Arc<MarsConfig>
+}
+
+// the default network runs on MARS_API_TOKEN and the options as they are, without a redis prefix
+// any others come from networks.yml, which is optional
+pub async fn deserialize_network_configs(default_config: Arc<MarsConfig>) -> anyhow::Result<Vec<NetworkConfig>> {
+    let mut networks = vec![NetworkConfig {
+        id: DEFAULT_NETWORK_ID.to_owned(),
+        mongo_database: default_config.options.mongo_database.clone(),
+        replay_database: get_replay_database(&default_config.options.mongo_database),
+        redis_prefix: String::new(),
+        config: Arc::clone(&default_config)
+    }];
+    let networks_path = env::var("MARS_NETWORKS_PATH").unwrap_or("./networks.yml".to_string());
+    if !Path::new(&networks_path).exists() {
+        return Ok(networks);
+    };
+    let definitions = deserialize_mars_data_component::<Vec<NetworkDefinition>>(&networks_path).await?;
+    for definition in definitions {
+        let id = definition.id;
+        if id.is_empty() || !id.chars().all(|character| character.is_ascii_alphanumeric() || character == '-' || character == '_') {
+            return Err(anyhow!("Network ID '{}' may only contain letters, digits, '-' and '_'", id));
+        };
+        let mut options = deserialize_mars_options().await?;
+        let mongo_database = definition.mongo_database.unwrap_or_else(|| format!("{}-{}", options.mongo_database, id));
+        let replay_database = get_replay_database(&mongo_database);
+        let redis_prefix = definition.redis_prefix.unwrap_or_else(|| format!("{}{}:", NETWORK_REDIS_NAMESPACE, id));
+        for network in networks.iter() {
+            if network.id == id {
+                return Err(anyhow!("Network '{}' is defined twice", id));
+            } else if network.config.token == definition.token {
+                return Err(anyhow!("Network '{}' uses the same token as '{}'", id, network.id));
+            } else if [&network.mongo_database, &network.replay_database].iter().any(|database| **database == mongo_database || **database == replay_database) {
+                return Err(anyhow!("Network '{}' uses the same database as '{}'", id, network.id));
+            } else if !network.redis_prefix.is_empty() && (redis_prefix.starts_with(&network.redis_prefix) || network.redis_prefix.starts_with(&redis_prefix)) {
+                return Err(anyhow!("The redis prefix of network '{}' overlaps the one of '{}'", id, network.id));
+            };
+        }
+        if definition.token.is_empty() {
+            return Err(anyhow!("Network '{}' has no token", id));
+        } else if !redis_prefix.starts_with(NETWORK_REDIS_NAMESPACE) || redis_prefix.len() == NETWORK_REDIS_NAMESPACE.len() {
+            return Err(anyhow!("The redis prefix of network '{}' must start with '{}' and name the network, e.g. '{}{}:'", id, NETWORK_REDIS_NAMESPACE, NETWORK_REDIS_NAMESPACE, id));
+        };
+
+        options.mongo_database = mongo_database.clone();
+        if let Some(url) = definition.webhooks.punishments { options.punishments_webhook_url = url; };
+        if let Some(url) = definition.webhooks.reports { options.reports_webhook_url = url; };
+        if let Some(url) = definition.webhooks.notes { options.notes_webhook_url = url; };
+        let data = deserialize_mars_data_in(definition.data_directory.as_deref().map(Path::new)).await?;
+        networks.push(NetworkConfig { id, mongo_database, replay_database, redis_prefix, config: Arc::new(build_mars_config(definition.token, options, data)) });
+    }
+    Ok(networks)
+}
+
+fn get_replay_database(mongo_database: &str) -> String {
+    format!("{}-replay", mongo_database)
 }
 
 async fn deserialize_mars_options() -> Result<MarsConfigOptions, ConfigDeserializeError> {
@@ -92,13 +189,32 @@ async fn deserialize_mars_options() -> Result<MarsConfigOptions, ConfigDeseriali
             "listen-port" => { if let Ok(i) = v.to_string().parse::<u32>() { config.port = i; } },
             "listen-host" => { config.host = v.to_string(); },
             "mongo-url" => {config.mongo_url = v.to_string();},
-            "redis-host" => { config.redis_host = Some(v.to_string()); },
+            "mongo-database" => { config.mongo_database = v.to_string(); },
+            "mongo-min-pool-size" => { if let Ok(i) = v.to_string().parse::<u32>() { config.mongo_min_pool_size = Some(i); } },
+            "mongo-max-pool-size" => { if let Ok(i) = v.to_string().parse::<u32>() { config.mongo_max_pool_size = Some(i); } },
+            "redis-host" => { config.redis.host = Some(v.to_string()); },
+            "redis-username" => { config.redis.username = Some(v.to_string()); },
+            "redis-password" => { config.redis.password = Some(v.to_string()); },
+            "redis-database" => { if let Ok(i) = v.to_string().parse::<i64>() { config.redis.database = Some(i); } },
+            "redis-tls" => { if let Ok(b) = v.to_string().parse::<bool>() { config.redis.tls = b; } },
+            "redis-tls-insecure" => { if let Ok(b) = v.to_string().parse::<bool>() { config.redis.tls_insecure = b; } },
+            "redis-sentinels" => { config.redis.sentinels = v.split(',').map(|sentinel| sentinel.trim().to_owned()).filter(|sentinel| !sentinel.is_empty()).collect(); },
+            "redis-sentinel-master" => { config.redis.sentinel_master = v.to_string(); },
+            "redis-pool-max-open" => { if let Ok(i) = v.to_string().parse::<u64>() { config.redis.pool_max_open = i; } },
+            "redis-pool-max-idle" => { if let Ok(i) = v.to_string().parse::<u64>() { config.redis.pool_max_idle = i; } },
+            "redis-pool-timeout-seconds" => { if let Ok(i) = v.to_string().parse::<u64>() { config.redis.pool_timeout_seconds = i; } },
+            "redis-pool-max-lifetime-seconds" => { if let Ok(i) = v.to_string().parse::<u64>() { config.redis.pool_max_lifetime_seconds = i; } },
             "enable-ip-hashing" => { if let Ok(b) = v.to_string().parse::<bool>() { config.enable_ip_hashing = b; } },
             "webhooks.punishments" => { config.punishments_webhook_url = v.to_string(); },
             "webhooks.reports" => { config.reports_webhook_url = v.to_string(); },
             "webhooks.notes" => { config.notes_webhook_url = v.to_string(); },
             "webhooks.debug" => { config.debug_log_webhook_url = v.to_string(); },
-            "enable-exponential-exp" => { if let Ok(b) = v.to_string().parse::<bool>() { config.use_exponential_exp = b; } }
+            "enable-exponential-exp" => { if let Ok(b) = v.to_string().parse::<bool>() { config.use_exponential_exp = b; } },
+            "storage-backend" => { if let Ok(backend) = StorageBackend::from_str(&v.to_string()) { config.storage_backend = backend; } },
+            "chat-retention-days" => { if let Ok(i) = v.to_string().parse::<u32>() { config.chat_retention_days = i; } },
+            "death-retention-days" => { if let Ok(i) = v.to_string().parse::<u32>() { config.death_retention_days = i; } },
+            "session-retention-days" => { if let Ok(i) = v.to_string().parse::<u32>() { config.session_retention_days = i; } },
+            "archive-directory" => { config.archive_directory = v.to_string(); }
             _ => {}
         }
     });
@@ -106,10 +222,16 @@ async fn deserialize_mars_options() -> Result<MarsConfigOptions, ConfigDeseriali
 }
 
 async fn deserialize_mars_data() -> Result<MarsConfigData, ConfigDeserializeError> {
-    let level_colors_path = env::var("MARS_LEVEL_COLORS_PATH").unwrap_or("./level_colors.yml".to_string());
-    let join_sounds_path = env::var("MARS_JOIN_SOUNDS_PATH").unwrap_or("./join_sounds.yml".to_string());
-    let broadcasts_path = env::var("MARS_BROADCASTS_PATH").unwrap_or("./broadcasts.yml".to_string());
-    let pun_types_path = env::var("MARS_PUNTYPES_PATH").unwrap_or("./punishment_types.yml".to_string());
+    deserialize_mars_data_in(None).await
+}
+
+// files missing from the directory fall back to the default ones
+async fn deserialize_mars_data_in(directory: Option<&Path>) -> Result<MarsConfigData, ConfigDeserializeError> {
+    let level_colors_path = get_data_path(directory, "MARS_LEVEL_COLORS_PATH", "level_colors.yml");
+    let join_sounds_path = get_data_path(directory, "MARS_JOIN_SOUNDS_PATH", "join_sounds.yml");
+    let broadcasts_path = get_data_path(directory, "MARS_BROADCASTS_PATH", "broadcasts.yml");
+    let pun_types_path = get_data_path(directory, "MARS_PUNTYPES_PATH", "punishment_types.yml");
+    let chat_filter_path = get_data_path(directory, "MARS_CHAT_FILTER_PATH", "chat_filter.yml");
 
     let (
         level_colors, 
@@ -125,14 +247,28 @@ async fn deserialize_mars_data() -> Result<MarsConfigData, ConfigDeserializeErro
         Ok(values) => values,
         Err(e) => return Err(e)
     };
+    // optional, chat filtering stays off without it
+    let chat_filter = if Path::new(&chat_filter_path).exists() {
+        deserialize_mars_data_component::<ChatFilterConfig>(&chat_filter_path).await?
+    } else {
+        ChatFilterConfig::default()
+    };
     Ok(MarsConfigData { 
         level_colors,
         join_sounds,
         broadcasts,
-        punishment_types
+        punishment_types,
+        chat_filter
     })
 }
 
+fn get_data_path(directory: Option<&Path>, env_variable: &str, file_name: &str) -> String {
+    if let Some(path) = directory.map(|directory| directory.join(file_name)).filter(|path| path.exists()) {
+        return path.to_string_lossy().into_owned();
+    };
+    env::var(env_variable).unwrap_or(format!("./{}", file_name))
+}
+
 async fn deserialize_mars_data_component<T: DeserializeOwned>(
     file_path: &String,
 ) -> Result<T, ConfigDeserializeError> {
@@ -169,13 +305,22 @@ pub struct MarsConfigOptions {
     pub port: u32,
     pub host: String,
     pub mongo_url: String,
-    pub redis_host: Option<String>,
+    pub mongo_database: String,
+    pub mongo_min_pool_size: Option<u32>,
+    pub mongo_max_pool_size: Option<u32>,
+    pub redis: RedisOptions,
+    pub storage_backend: StorageBackend,
     pub enable_ip_hashing: bool,
     pub punishments_webhook_url: String,
     pub reports_webhook_url: String,
     pub notes_webhook_url: String,
     pub debug_log_webhook_url: String,
-    pub use_exponential_exp: bool
+    pub use_exponential_exp: bool,
+    // 0 keeps documents forever, expired ones are archived to archive_directory before deletion
+    pub chat_retention_days: u32,
+    pub death_retention_days: u32,
+    pub session_retention_days: u32,
+    pub archive_directory: String
 }
 
 impl Default for MarsConfigOptions {
@@ -184,13 +329,21 @@ impl Default for MarsConfigOptions {
             mongo_url: String::new(), 
             port: 3000, 
             host: String::new(), 
-            redis_host: None, 
+            mongo_database: String::from("mars-api"),
+            mongo_min_pool_size: Some(2),
+            mongo_max_pool_size: Some(8),
+            redis: RedisOptions::default(),
+            storage_backend: StorageBackend::External,
             enable_ip_hashing: false,
             punishments_webhook_url: String::new(),
             reports_webhook_url: String::new(),
             notes_webhook_url: String::new(),
             debug_log_webhook_url: String::new(),
-            use_exponential_exp: false
+            use_exponential_exp: false,
+            chat_retention_days: 30,
+            death_retention_days: 0,
+            session_retention_days: 0,
+            archive_directory: String::from("./archive")
         }
     }
 }
@@ -200,5 +353,6 @@ pub struct MarsConfigData {
     pub level_colors: Vec<LevelColor>,
     pub join_sounds: Vec<JoinSound>,
     pub broadcasts: Vec<Broadcast>,
-    pub punishment_types: Vec<PunishmentType>
+    pub punishment_types: Vec<PunishmentType>,
+    pub chat_filter: ChatFilterConfig
 }
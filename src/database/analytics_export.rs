@@ -0,0 +1,327 @@
+use std::{collections::HashMap, fs::{self, File}, io::{BufWriter, Write}, path::{Path, PathBuf}, str::FromStr};
+
+use chrono::{TimeZone, Utc};
+use futures::StreamExt;
+use mongodb::{bson::doc, options::FindOptions};
+use rocket::serde::json::serde_json;
+use serde::{Serialize, de::DeserializeOwned};
+
+use super::{CollectionOwner, Database, models::{death::Death, r#match::Match, session::Session}};
+
+// documents newer than this may still be waiting in the write-behind queue, so they're left for the next export
+const EXPORT_LAG_MS: u64 = 60_000;
+// set on every exported document, so one whose timestamp was backfilled into the past is still exported once
+const EXPORTED_FIELD: &str = "exportedAt";
+const MARK_BATCH_SIZE: usize = 1000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExportFormat {
+    Csv,
+    Ndjson
+}
+
+impl ExportFormat {
+    fn get_extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson"
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "ndjson" | "json" => Ok(ExportFormat::Ndjson),
+            other => Err(format!("Unknown export format '{}', expected csv or ndjson", other))
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct ExportSummary {
+    pub matches: u64,
+    pub participants: u64,
+    pub deaths: u64,
+    pub sessions: u64,
+    pub files: Vec<PathBuf>
+}
+
+#[derive(Serialize)]
+struct MatchRow {
+    match_id: String,
+    server_id: String,
+    level_id: String,
+    level_name: String,
+    // "|"-separated
+    gamemodes: String,
+    loaded_at: u64,
+    started_at: Option<u64>,
+    ended_at: Option<u64>,
+    length_ms: u64,
+    parties: usize,
+    participants: usize,
+    first_blood_attacker_id: Option<String>,
+    first_blood_victim_id: Option<String>
+}
+
+#[derive(Serialize)]
+struct ParticipantRow {
+    match_id: String,
+    level_id: String,
+    ended_at: Option<u64>,
+    player_id: String,
+    player_name: String,
+    party_name: Option<String>,
+    game_playtime: u64,
+    time_away: u64,
+    kills: u32,
+    deaths: u32,
+    void_kills: u32,
+    void_deaths: u32,
+    bow_shots_taken: u32,
+    bow_shots_hit: u32,
+    damage_taken: f64,
+    damage_given: f64,
+    damage_given_bow: f64,
+    blocks_placed: u32,
+    blocks_broken: u32,
+    messages: u32
+}
+
+#[derive(Serialize)]
+struct DeathRow {
+    death_id: String,
+    match_id: String,
+    server_id: String,
+    created_at: u64,
+    victim_id: String,
+    victim_name: String,
+    attacker_id: Option<String>,
+    attacker_name: Option<String>,
+    weapon: Option<String>,
+    entity: Option<String>,
+    distance: Option<u32>,
+    key: String,
+    cause: String
+}
+
+// sessions are exported without the IP they came from
+#[derive(Serialize)]
+struct SessionRow {
+    session_id: String,
+    player_id: String,
+    player_name: String,
+    server_id: String,
+    created_at: u64,
+    ended_at: Option<u64>,
+    length_ms: Option<u64>
+}
+
+enum RowWriter {
+    Csv(Box<csv::Writer<File>>),
+    Ndjson(BufWriter<File>)
+}
+
+impl RowWriter {
+    fn write<T: Serialize>(&mut self, row: &T) -> anyhow::Result<()> {
+        match self {
+            RowWriter::Csv(writer) => writer.serialize(row)?,
+            RowWriter::Ndjson(writer) => {
+                serde_json::to_writer(&mut *writer, row)?;
+                writer.write_all(b"\n")?;
+            }
+        };
+        Ok(())
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        match self {
+            RowWriter::Csv(mut writer) => writer.flush()?,
+            RowWriter::Ndjson(mut writer) => writer.flush()?
+        };
+        Ok(())
+    }
+}
+
+// writes one dataset as <directory>/<dataset>/day=YYYY-MM-DD/part-<run>.<ext>, one file per day and run
+struct PartitionedWriter {
+    directory: PathBuf,
+    format: ExportFormat,
+    run: u64,
+    writers: HashMap<String, RowWriter>,
+    files: Vec<PathBuf>
+}
+
+impl PartitionedWriter {
+    fn new(directory: &Path, dataset: &str, format: ExportFormat, run: u64) -> Self {
+        PartitionedWriter { directory: directory.join(dataset), format, run, writers: HashMap::new(), files: Vec::new() }
+    }
+
+    fn write<T: Serialize>(&mut self, timestamp: u64, row: &T) -> anyhow::Result<()> {
+        let day = get_day(timestamp);
+        if !self.writers.contains_key(&day) {
+            let partition = self.directory.join(format!("day={}", day));
+            fs::create_dir_all(&partition)?;
+            let path = partition.join(format!("part-{}.{}", self.run, self.format.get_extension()));
+            let file = File::create(&path)?;
+            let writer = match self.format {
+                ExportFormat::Csv => RowWriter::Csv(Box::new(csv::Writer::from_writer(file))),
+                ExportFormat::Ndjson => RowWriter::Ndjson(BufWriter::new(file))
+            };
+            self.writers.insert(day.clone(), writer);
+            self.files.push(path);
+        };
+        self.writers.get_mut(&day).unwrap().write(row)
+    }
+
+    fn finish(self) -> anyhow::Result<Vec<PathBuf>> {
+        for writer in self.writers.into_values() {
+            writer.finish()?;
+        }
+        Ok(self.files)
+    }
+}
+
+// exports everything that finished and hasn't been exported yet into the given directory
+// each dataset's documents are marked once its files are written, so a failed export resumes where it stopped
+pub async fn export_analytics(database: &Database, directory: &Path, format: ExportFormat, now: u64) -> anyhow::Result<ExportSummary> {
+    fs::create_dir_all(directory)?;
+    let until = now.saturating_sub(EXPORT_LAG_MS);
+    let mut summary = ExportSummary::default();
+
+    // participants are exported along with their match
+    let mut exported = Vec::new();
+    let mut match_writer = PartitionedWriter::new(directory, "matches", format, now);
+    let mut participant_writer = PartitionedWriter::new(directory, "participants", format, now);
+    let mut matches = find_unexported::<Match>(database, "endedAt", until).await?;
+    while let Some(current_match) = matches.next().await {
+        exported.push(current_match.id.clone());
+        let ended_at = current_match.ended_at.unwrap_or(0);
+        for participant in current_match.participants.values() {
+            participant_writer.write(ended_at, &ParticipantRow {
+                match_id: current_match.id.clone(),
+                level_id: current_match.level.id.clone(),
+                ended_at: current_match.ended_at,
+                player_id: participant.id.clone(),
+                player_name: participant.name.clone(),
+                party_name: participant.party_name.clone().or_else(|| participant.last_party_name.clone()),
+                game_playtime: participant.stats.game_playtime,
+                time_away: participant.stats.time_away,
+                kills: participant.stats.kills,
+                deaths: participant.stats.deaths,
+                void_kills: participant.stats.void_kills,
+                void_deaths: participant.stats.void_deaths,
+                bow_shots_taken: participant.stats.bow_shots_taken,
+                bow_shots_hit: participant.stats.bow_shots_hit,
+                damage_taken: participant.stats.damage_taken,
+                damage_given: participant.stats.damage_given,
+                damage_given_bow: participant.stats.damage_given_bow,
+                blocks_placed: participant.stats.blocks_placed.values().sum(),
+                blocks_broken: participant.stats.blocks_broken.values().sum(),
+                messages: participant.stats.messages.staff + participant.stats.messages.global + participant.stats.messages.team
+            })?;
+            summary.participants += 1;
+        }
+        match_writer.write(ended_at, &MatchRow {
+            match_id: current_match.id.clone(),
+            server_id: current_match.server_id.clone(),
+            level_id: current_match.level.id.clone(),
+            level_name: current_match.level.name.clone(),
+            gamemodes: current_match.level.gamemodes.iter().map(get_serialized_name).collect::<Vec<_>>().join("|"),
+            loaded_at: current_match.loaded_at,
+            started_at: current_match.started_at,
+            ended_at: current_match.ended_at,
+            length_ms: current_match.get_length(),
+            parties: current_match.parties.len(),
+            participants: current_match.participants.len(),
+            first_blood_attacker_id: current_match.first_blood.as_ref().map(|first_blood| first_blood.attacker.id.clone()),
+            first_blood_victim_id: current_match.first_blood.as_ref().map(|first_blood| first_blood.victim.id.clone())
+        })?;
+        summary.matches += 1;
+    }
+    summary.files.extend(match_writer.finish()?);
+    summary.files.extend(participant_writer.finish()?);
+    mark_exported::<Match>(database, exported, now).await?;
+
+    let mut exported = Vec::new();
+    let mut death_writer = PartitionedWriter::new(directory, "deaths", format, now);
+    let mut deaths = find_unexported::<Death>(database, "createdAt", until).await?;
+    while let Some(death) = deaths.next().await {
+        exported.push(death.id.clone());
+        death_writer.write(death.created_at, &DeathRow {
+            death_id: death.id,
+            match_id: death.match_id,
+            server_id: death.server_id,
+            created_at: death.created_at,
+            victim_id: death.victim.id,
+            victim_name: death.victim.name,
+            attacker_id: death.attacker.as_ref().map(|attacker| attacker.id.clone()),
+            attacker_name: death.attacker.map(|attacker| attacker.name),
+            weapon: death.weapon,
+            entity: death.entity,
+            distance: death.distance,
+            key: death.key,
+            cause: get_serialized_name(&death.cause)
+        })?;
+        summary.deaths += 1;
+    }
+    summary.files.extend(death_writer.finish()?);
+    mark_exported::<Death>(database, exported, now).await?;
+
+    let mut exported = Vec::new();
+    let mut session_writer = PartitionedWriter::new(directory, "sessions", format, now);
+    let mut sessions = find_unexported::<Session>(database, "endedAt", until).await?;
+    while let Some(session) = sessions.next().await {
+        exported.push(session.id.clone());
+        let length_ms = session.length();
+        session_writer.write(session.ended_at.unwrap_or(0), &SessionRow {
+            session_id: session.id,
+            player_id: session.player.id,
+            player_name: session.player.name,
+            server_id: session.server_id,
+            created_at: session.created_at,
+            ended_at: session.ended_at,
+            length_ms
+        })?;
+        summary.sessions += 1;
+    }
+    summary.files.extend(session_writer.finish()?);
+    mark_exported::<Session>(database, exported, now).await?;
+
+    Ok(summary)
+}
+
+// documents not exported yet with field < until, oldest first, skipping any that can't be read
+async fn find_unexported<T>(database: &Database, field: &str, until: u64) -> anyhow::Result<futures::stream::BoxStream<'static, T>>
+    where T: CollectionOwner<T> + DeserializeOwned + Send + 'static {
+    let filter = doc! { field: { "$lt": until as i64 }, EXPORTED_FIELD: null };
+    let options = FindOptions::builder().sort(doc! { field: 1 }).build();
+    let cursor = T::get_collection(database).find(filter, options).await?;
+    Ok(cursor.filter_map(|result| async move { result.ok() }).boxed())
+}
+
+async fn mark_exported<T: CollectionOwner<T>>(database: &Database, ids: Vec<String>, now: u64) -> anyhow::Result<()> {
+    for chunk in ids.chunks(MARK_BATCH_SIZE) {
+        database.store.update_many(T::get_collection_name(), doc! { "_id": { "$in": chunk } }, doc! { "$set": { EXPORTED_FIELD: now as i64 } }, false).await?;
+    }
+    Ok(())
+}
+
+fn get_day(timestamp: u64) -> String {
+    match Utc.timestamp_millis_opt(timestamp as i64).single() {
+        Some(time) => time.format("%Y-%m-%d").to_string(),
+        None => String::from("unknown")
+    }
+}
+
+// the name an enum is stored under, e.g. "CAPTURE_THE_WOOL"
+fn get_serialized_name<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new()
+    }
+}
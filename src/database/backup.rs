@@ -0,0 +1,279 @@
+use std::{collections::{BTreeMap, HashSet}, fs::File, io::{BufRead, BufReader, BufWriter, Write}, path::Path};
+
+use anyhow::anyhow;
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use futures::StreamExt;
+use mongodb::{bson::{self, doc, Bson, Document}, options::FindOptions};
+use rocket::serde::json::{serde_json, Value};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{MarsAPIState, socket::leaderboard::LEADERBOARD_KEY_PREFIX, util::time::get_u64_time_millis};
+
+use super::{CollectionOwner, Database, cache::RedisAdapter, store::{IndexDefinition, query}, models::{achievement::Achievement, archive_aggregate::ArchiveAggregate, chat_message::ChatMessage, dead_letter::DeadLetter, death::Death, ip_identity::IpIdentity, level::Level, player::Player, punishment::Punishment, r#match::Match, rank::Rank, session::Session, tag::Tag}};
+
+const BACKUP_FORMAT: &str = "mars-api-backup";
+// bumped whenever a change to the archive layout would make older restores misread it
+const BACKUP_VERSION: u32 = 1;
+// leaderboards are written under this prefix first, and moved over the live ones once all of them are in
+const STAGING_PREFIX: &str = "restore:";
+
+// a gzipped NDJSON archive: a header, then one line per document or leaderboard, then a trailer with the counts
+// documents are kept as canonical extended JSON, so every BSON type comes back as it was
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum BackupLine {
+    Header {
+        format: String,
+        version: u32,
+        #[serde(rename = "createdAt")]
+        created_at: u64
+    },
+    Document {
+        collection: String,
+        document: Value
+    },
+    Leaderboard {
+        key: String,
+        members: Vec<(String, f64)>
+    },
+    End {
+        documents: BTreeMap<String, u64>,
+        leaderboards: u64
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct BackupSummary {
+    pub documents: BTreeMap<String, u64>,
+    pub leaderboards: u64
+}
+
+struct BackupCollection {
+    name: &'static str,
+    // checks a document still reads as its model
+    validate: fn(Document) -> anyhow::Result<()>,
+    // _id and the model's unique indexes, checked across the archive before anything is written
+    unique_indexes: Vec<IndexDefinition>
+}
+
+fn validate<T: DeserializeOwned>(document: Document) -> anyhow::Result<()> {
+    bson::from_document::<T>(document)?;
+    Ok(())
+}
+
+fn collection<T: CollectionOwner<T> + DeserializeOwned>() -> BackupCollection {
+    let mut unique_indexes = vec![IndexDefinition::new(doc! { "_id": 1 }).unique()];
+    unique_indexes.extend(T::get_indexes().into_iter().filter(|index| index.unique));
+    BackupCollection { name: T::get_collection_name(), validate: validate::<T>, unique_indexes }
+}
+
+// what a document holds for an index's fields, missing ones count as null like they do in mongo
+fn get_index_key(document: &Document, index: &IndexDefinition) -> String {
+    let values : Vec<Bson> = index.keys.keys().map(|field| query::get_path(document, field).cloned().unwrap_or(Bson::Null)).collect();
+    Bson::Array(values).into_canonical_extjson().to_string()
+}
+
+fn get_backup_collections() -> Vec<BackupCollection> {
+    vec![
+        collection::<Player>(),
+        collection::<Match>(),
+        collection::<Punishment>(),
+        collection::<Rank>(),
+        collection::<Tag>(),
+        collection::<Achievement>(),
+        collection::<Level>(),
+        collection::<Session>(),
+        collection::<Death>(),
+        collection::<IpIdentity>(),
+        collection::<ChatMessage>(),
+        collection::<DeadLetter>(),
+        collection::<ArchiveAggregate>()
+    ]
+}
+
+// writes to <path>.partial first, so an interrupted backup never looks complete
+pub async fn create_backup(database: &Database, redis: &RedisAdapter, path: &Path) -> anyhow::Result<BackupSummary> {
+    database.write_behind.flush().await;
+    let mut partial_path = path.as_os_str().to_owned();
+    partial_path.push(".partial");
+    let mut encoder = GzEncoder::new(BufWriter::new(File::create(&partial_path)?), Compression::default());
+    let mut summary = BackupSummary::default();
+    write_line(&mut encoder, &BackupLine::Header { format: BACKUP_FORMAT.to_owned(), version: BACKUP_VERSION, created_at: get_u64_time_millis() })?;
+
+    for collection in get_backup_collections() {
+        let mut count = 0;
+        let mut cursor = database.store.find(collection.name, Document::new(), FindOptions::default()).await?;
+        while let Some(document) = cursor.next().await {
+            let document = Bson::Document(document?).into_canonical_extjson();
+            write_line(&mut encoder, &BackupLine::Document { collection: collection.name.to_owned(), document })?;
+            count += 1;
+        }
+        summary.documents.insert(collection.name.to_owned(), count);
+    }
+
+    let mut keys = redis.store.keys_with_prefix(LEADERBOARD_KEY_PREFIX).await?;
+    keys.sort();
+    for key in keys {
+        let members = redis.store.zrevrange_with_scores(&key, 0, i64::MAX as usize).await?;
+        write_line(&mut encoder, &BackupLine::Leaderboard { key, members })?;
+        summary.leaderboards += 1;
+    }
+
+    write_line(&mut encoder, &BackupLine::End { documents: summary.documents.clone(), leaderboards: summary.leaderboards })?;
+    encoder.finish()?.flush()?;
+    std::fs::rename(&partial_path, path)?;
+    Ok(summary)
+}
+
+// the whole archive is validated before anything is written, and only into collections that are all empty
+// leaderboards in the archive replace the ones in redis
+pub async fn restore_backup(state: &MarsAPIState, path: &Path) -> anyhow::Result<BackupSummary> {
+    let (database, redis) = (&state.database, &state.redis);
+    let collections = get_backup_collections();
+    let mut seen_keys : HashSet<(&str, String, String)> = HashSet::new();
+    let summary = read_backup(path, |line| match line {
+        BackupLine::Document { collection: name, document } => {
+            let collection = collections.iter().find(|collection| collection.name == name)
+                .ok_or_else(|| anyhow!("Unknown collection '{}'", name))?;
+            let document = to_document(document)?;
+            for index in collection.unique_indexes.iter() {
+                let key = get_index_key(&document, index);
+                if !seen_keys.insert((collection.name, index.get_name(), key.clone())) {
+                    return Err(anyhow!("Duplicate {} for unique index '{}' in '{}'", key, index.get_name(), collection.name));
+                };
+            }
+            (collection.validate)(document)
+        },
+        _ => Ok(())
+    })?;
+
+    for collection in collections.iter() {
+        let existing = database.store.count_documents(collection.name, Document::new()).await?;
+        if existing > 0 {
+            return Err(anyhow!("Collection '{}' already has {} document(s), restores need an empty database", collection.name, existing));
+        };
+    }
+
+    // whatever is cached belongs to the database being replaced, and would be served over the restored documents
+    state.player_cache.flush().await;
+    state.match_cache.flush().await;
+
+    // the collections were empty, so a restore that fails partway is undone and can simply be run again
+    if let Err(e) = write_backup(database, redis, path).await {
+        for collection in collections.iter() {
+            if let Err(cleanup_error) = database.store.delete_many(collection.name, Document::new()).await {
+                return Err(anyhow!("{}, and the partial restore of '{}' could not be removed: {}", e, collection.name, cleanup_error));
+            };
+        }
+        return Err(e);
+    };
+    Ok(summary)
+}
+
+// the archive was fully read once already, so only I/O or the database can fail from here
+// documents go first, so leaderboards are only touched once every document is in
+// a failure while staging leaderboards leaves the live ones as they were
+async fn write_backup(database: &Database, redis: &RedisAdapter, path: &Path) -> anyhow::Result<()> {
+    let reader = BufReader::new(MultiGzDecoder::new(File::open(path)?));
+    for line in reader.lines() {
+        if let BackupLine::Document { collection, document } = serde_json::from_str::<BackupLine>(&line?)? {
+            database.store.insert_one(&collection, to_document(document)?).await?;
+        };
+    }
+    let mut staged : Vec<String> = Vec::new();
+    if let Err(e) = stage_leaderboards(redis, path, &mut staged).await {
+        for key in staged.iter() {
+            let _ = redis.store.del(&format!("{}{}", STAGING_PREFIX, key)).await;
+        }
+        return Err(e);
+    };
+    // leaderboards missing from the archive go too
+    redis.del_with_prefix(LEADERBOARD_KEY_PREFIX).await;
+    for key in staged.iter() {
+        redis.store.rename(&format!("{}{}", STAGING_PREFIX, key), key).await?;
+    }
+    Ok(())
+}
+
+// staged keys are recorded as soon as they may exist, so a failure can remove them
+async fn stage_leaderboards(redis: &RedisAdapter, path: &Path, staged: &mut Vec<String>) -> anyhow::Result<()> {
+    let reader = BufReader::new(MultiGzDecoder::new(File::open(path)?));
+    for line in reader.lines() {
+        if let BackupLine::Leaderboard { key, members } = serde_json::from_str::<BackupLine>(&line?)? {
+            // an empty sorted set doesn't exist in redis, so there would be nothing to move
+            if members.is_empty() {
+                continue;
+            };
+            let staging_key = format!("{}{}", STAGING_PREFIX, key);
+            staged.push(key);
+            redis.store.del(&staging_key).await?;
+            redis.store.zadd(&staging_key, &members).await?;
+        };
+    }
+    Ok(())
+}
+
+// reads and checks the archive's structure, handing every line in between to the visitor
+fn read_backup<F>(path: &Path, mut visit: F) -> anyhow::Result<BackupSummary> where F: FnMut(BackupLine) -> anyhow::Result<()> {
+    let reader = BufReader::new(MultiGzDecoder::new(File::open(path)?));
+    let mut summary = BackupSummary::default();
+    let mut has_header = false;
+    let mut expected : Option<BackupSummary> = None;
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_number = index + 1;
+        if expected.is_some() {
+            return Err(anyhow!("Line {}: unexpected data after the end of the backup", line_number));
+        };
+        let parsed : BackupLine = serde_json::from_str(&line).map_err(|e| anyhow!("Line {}: {}", line_number, e))?;
+        match parsed {
+            BackupLine::Header { format, version, .. } => {
+                if has_header || line_number != 1 {
+                    return Err(anyhow!("Line {}: unexpected header", line_number));
+                };
+                if format != BACKUP_FORMAT {
+                    return Err(anyhow!("Not a mars-api backup (format '{}')", format));
+                };
+                if version > BACKUP_VERSION {
+                    return Err(anyhow!("Backup version {} is newer than the supported version {}", version, BACKUP_VERSION));
+                };
+                has_header = true;
+                continue;
+            },
+            BackupLine::End { documents, leaderboards } => {
+                expected = Some(BackupSummary { documents, leaderboards });
+                continue;
+            },
+            _ if !has_header => return Err(anyhow!("Line {}: missing header", line_number)),
+            BackupLine::Document { ref collection, .. } => *summary.documents.entry(collection.clone()).or_insert(0) += 1,
+            BackupLine::Leaderboard { .. } => summary.leaderboards += 1
+        };
+        visit(parsed).map_err(|e| anyhow!("Line {}: {}", line_number, e))?;
+    }
+
+    let expected = expected.ok_or_else(|| anyhow!("The backup is truncated, it has no end marker"))?;
+    for (collection, count) in expected.documents.iter() {
+        let found = summary.documents.get(collection).copied().unwrap_or(0);
+        if found != *count {
+            return Err(anyhow!("Expected {} document(s) in '{}', found {}", count, collection, found));
+        };
+        summary.documents.entry(collection.clone()).or_insert(0);
+    }
+    if summary.documents.len() != expected.documents.len() || summary.leaderboards != expected.leaderboards {
+        return Err(anyhow!("The backup's contents don't match its end marker"));
+    };
+    Ok(summary)
+}
+
+fn write_line(encoder: &mut GzEncoder<BufWriter<File>>, line: &BackupLine) -> anyhow::Result<()> {
+    serde_json::to_writer(&mut *encoder, line)?;
+    encoder.write_all(b"\n")?;
+    Ok(())
+}
+
+fn to_document(value: Value) -> anyhow::Result<Document> {
+    match Bson::try_from(value)? {
+        Bson::Document(document) => Ok(document),
+        _ => Err(anyhow!("Not a document"))
+    }
+}
@@ -1,23 +1,84 @@
-use std::{marker::PhantomData, time::Duration, sync::Arc};
+use std::{collections::HashMap, marker::PhantomData, sync::{Arc, atomic::{AtomicU64, Ordering}}};
 
-use futures::Future;
+use futures::{future::join_all, stream::BoxStream};
 use mars_api_rs_macro::IdentifiableDocument;
-use mobc::{Pool, Manager};
 use mongodb::bson::doc;
-use redis::{aio::Connection, Client, AsyncCommands, RedisResult};
 use rocket::serde::json;
 use serde::{Serialize, de::DeserializeOwned};
 use anyhow::anyhow;
 
-use crate::{config::ConfigMissingFieldError, util::r#macro::unwrap_helper};
+use crate::util::r#macro::unwrap_helper;
 
-use super::{Database, CollectionOwner};
+use super::{Database, CollectionOwner, SaveError, Versioned, VersionConflict, store::{CacheIndex, CacheStore}};
+
+// how many times an update re-reads and re-applies its change before giving up
+pub const MAX_UPDATE_ATTEMPTS: u32 = 5;
+
+// the field of a name indexed value that the index is keyed on
+const NAME_INDEX_FIELD: &str = "nameLower";
 
 pub struct Cache<R> {
     pub redis: Arc<RedisAdapter>,
     pub resource_name: String,
     pub lifetime_ms: u64,
-    pub resource_type: PhantomData<R>
+    pub resource_type: PhantomData<R>,
+    // values are cached by id, with <resource>_name:<name> pointing at the id
+    pub name_indexed: bool,
+    pub cas_metrics: CasMetrics,
+    pub hit_metrics: HitMetrics
+}
+
+#[derive(Default)]
+pub struct CasMetrics {
+    pub writes: AtomicU64,
+    pub conflicts: AtomicU64,
+    // updates that still conflicted after MAX_UPDATE_ATTEMPTS
+    pub exhausted: AtomicU64
+}
+
+impl CasMetrics {
+    pub fn record_write(&self, conflicted: bool) {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        if conflicted {
+            self.conflicts.fetch_add(1, Ordering::Relaxed);
+        };
+    }
+
+    pub fn record_exhausted(&self) {
+        self.exhausted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_conflict_rate(&self) -> f64 {
+        let writes = self.writes.load(Ordering::Relaxed);
+        if writes == 0 { 0.0 } else { self.conflicts.load(Ordering::Relaxed) as f64 / writes as f64 }
+    }
+}
+
+#[derive(Default)]
+pub struct HitMetrics {
+    pub hits: AtomicU64,
+    pub misses: AtomicU64
+}
+
+impl HitMetrics {
+    pub fn record(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_hit_rate(&self) -> f64 {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let lookups = hits + self.misses.load(Ordering::Relaxed);
+        if lookups == 0 { 0.0 } else { hits as f64 / lookups as f64 }
+    }
+}
+
+#[derive(Debug)]
+pub enum UpdateError<E> {
+    Missing,
+    // the mutation itself refused to apply
+    Rejected(E),
+    Conflict(VersionConflict)
 }
 
 impl<R> Cache<R> where R: CollectionOwner<R> + DeserializeOwned + Unpin + Sync + std::marker::Send 
@@ -26,9 +87,44 @@ impl<R> Cache<R> where R: CollectionOwner<R> + DeserializeOwned + Unpin + Sync +
         format!("{}:{}", self.resource_name, key.to_lowercase())
     }
 
+    fn get_name_index_prefix(&self) -> String {
+        format!("{}_name:", self.resource_name)
+    }
+
+    // where a value is cached, a name resolves through the index and anything else is taken as the id
+    async fn resolve_key(&self, key: &str) -> String {
+        if !self.name_indexed {
+            return self.generate_formatted_key(key);
+        };
+        let index_key = format!("{}{}", self.get_name_index_prefix(), key.to_lowercase());
+        match self.redis.store.get(&index_key).await {
+            Ok(Some(id)) => self.generate_formatted_key(&id),
+            _ => self.generate_formatted_key(key)
+        }
+    }
+
+    // a name indexed value is always written under its id, whatever key it was looked up by
+    fn get_write_key(&self, key: &str, value: &R) -> String {
+        if self.name_indexed { self.generate_formatted_key(&value.get_id_value()) } else { self.generate_formatted_key(key) }
+    }
+
+    async fn write_indexed(&self, resource_key: &str, stringified: &str, expected_version: Option<u64>, expiry_ms: Option<usize>) -> anyhow::Result<bool> {
+        let target_prefix = format!("{}:", self.resource_name);
+        let index_prefix = self.get_name_index_prefix();
+        let index = CacheIndex {
+            prefix: &index_prefix,
+            field: NAME_INDEX_FIELD,
+            target: resource_key.strip_prefix(&target_prefix).unwrap_or(resource_key),
+            target_prefix: &target_prefix
+        };
+        self.redis.store.set_indexed(resource_key, stringified, expected_version, expiry_ms, &index).await
+    }
+
     pub async fn query(&self, key: &str) -> Option<R> {
-        let resource_key = self.generate_formatted_key(key);
-        if let Ok(value) = self.redis.get(&resource_key).await { Some(value) } else { None }
+        let resource_key = self.resolve_key(key).await;
+        let value = self.redis.get(&resource_key).await.ok();
+        self.hit_metrics.record(value.is_some());
+        value
     }
 
     pub async fn get(&self, database: &Database, key: &str) -> Option<R> {
@@ -59,85 +155,205 @@ impl<R> Cache<R> where R: CollectionOwner<R> + DeserializeOwned + Unpin + Sync +
     }
 
     pub async fn set_with_expiry(&self, database: &Database, key: &str, value: &R, persist: bool, expiry_ms: Option<usize>) {
-        let resource_key = self.generate_formatted_key(key);
+        let resource_key = self.get_write_key(key, value);
         if persist {
             database.save(value).await;
         }
-        self.redis.set_with_expiry(&resource_key, value, expiry_ms).await;
+        if !self.name_indexed {
+            self.redis.set_with_expiry(&resource_key, value, expiry_ms).await;
+        } else if let Ok(stringified) = json::to_string(value) {
+            if let Err(e) = self.write_indexed(&resource_key, &stringified, None, expiry_ms).await {
+                warn!("Could not cache {}: {}", resource_key, e);
+            };
+        };
     }
 
-    pub async fn persist_cached_value(&self, database: &Database, key: &String) {
-        if let Some(record) = self.query(key).await {
-            database.save(&record).await;
-        }
+    // writes the value as the next version of whatever copy it was read from,
+    // failing if someone else wrote a newer version in the meantime
+    pub async fn compare_and_set(&self, database: &Database, key: &str, value: &mut R, persist: bool, expiry_ms: Option<usize>) -> Result<(), VersionConflict>
+        where R: Versioned {
+        let expected_version = value.get_version();
+        value.set_version(expected_version + 1);
+        let result = self.write_versioned(database, key, value, expected_version, persist, expiry_ms).await;
+        if result.is_err() {
+            value.set_version(expected_version);
+        };
+        self.cas_metrics.record_write(result.is_err());
+        result
     }
-}
 
-// begin: mobc manager wrapper
-pub struct RedisConnectionManager {
-    client: Client,
-}
+    async fn write_versioned(&self, database: &Database, key: &str, value: &R, expected_version: u64, persist: bool, expiry_ms: Option<usize>) -> Result<(), VersionConflict>
+        where R: Versioned {
+        let conflict = VersionConflict { collection: R::get_collection_name().to_owned(), id: value.get_id_value(), version: value.get_version() };
+        let resource_key = self.get_write_key(key, value);
+        let stringified = json::to_string(value).map_err(|_| conflict.clone())?;
+        let written = if self.name_indexed {
+            self.write_indexed(&resource_key, &stringified, Some(expected_version), expiry_ms).await
+        } else {
+            self.redis.store.set_if_version(&resource_key, &stringified, expected_version, expiry_ms).await
+        };
+        match written {
+            Ok(true) => {},
+            Ok(false) => return Err(conflict),
+            Err(e) => warn!("Could not cache {}: {}", resource_key, e)
+        };
+        if persist {
+            match database.save_versioned(value).await {
+                Ok(_) => {},
+                // the database is ahead of the cache, so the cached copy can't be trusted either
+                Err(SaveError::Conflict(conflict)) => {
+                    self.redis.del(&resource_key).await;
+                    return Err(conflict);
+                },
+                Err(e) => warn!("{}", e)
+            };
+        };
+        Ok(())
+    }
 
-impl RedisConnectionManager {
-    pub fn new(c: Client) -> Self {
-        Self { client: c }
+    // reads the current value, applies the mutation and writes it back, starting over if it raced another writer
+    pub async fn update<E, F>(&self, database: &Database, key: &str, persist: bool, mut mutate: F) -> Result<R, UpdateError<E>>
+        where R: Versioned, F: FnMut(&mut R) -> Result<(), E> {
+        let mut last_conflict = None;
+        for _ in 0..MAX_UPDATE_ATTEMPTS {
+            let mut value = self.get(database, key).await.ok_or(UpdateError::Missing)?;
+            mutate(&mut value).map_err(UpdateError::Rejected)?;
+            match self.compare_and_set(database, key, &mut value, persist, None).await {
+                Ok(_) => return Ok(value),
+                Err(conflict) => last_conflict = Some(conflict)
+            };
+        }
+        self.cas_metrics.record_exhausted();
+        Err(UpdateError::Conflict(last_conflict.unwrap()))
     }
-}
 
-#[async_trait]
-impl Manager for RedisConnectionManager {
-    type Connection = Connection;
-    type Error = redis::RedisError;
+    // writes every cached value the database doesn't already hold at that version or later
+    // returns how many were written
+    pub async fn persist_all(&self, database: &Database) -> usize where R: Versioned {
+        let prefix = format!("{}:", self.resource_name);
+        let keys = match self.redis.store.keys_with_prefix(&prefix).await {
+            Ok(keys) => keys,
+            Err(e) => {
+                warn!("Could not list cached {} values: {}", self.resource_name, e);
+                return 0;
+            }
+        };
+        let mut persisted = 0;
+        for key in keys {
+            let value = match self.redis.get::<R>(&key).await {
+                Ok(value) => value,
+                Err(_) => continue
+            };
+            match database.save_versioned(&value).await {
+                Ok(_) => persisted += 1,
+                Err(SaveError::Unwritten(reason)) => warn!("{}", reason),
+                Err(SaveError::Conflict(_)) => {}
+            };
+        }
+        persisted
+    }
 
-    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
-        let c = self.client.get_async_connection().await?;
-        Ok(c)
+    // writes the cached copy unless the database already holds a newer version
+    // returns whether there was a cached copy
+    pub async fn persist_cached_value(&self, database: &Database, key: &str) -> Result<bool, SaveError> where R: Versioned {
+        let record = match self.query(key).await {
+            Some(record) => record,
+            None => return Ok(false)
+        };
+        // queued writes would otherwise land on top of it
+        database.write_behind.flush().await;
+        persist_versioned(database, &record).await?;
+        Ok(true)
     }
 
-    async fn check(&self, mut conn: Self::Connection) -> Result<Self::Connection, Self::Error> {
-        redis::cmd("PING").query_async(&mut conn).await?;
-        Ok(conn)
+    // values such as matches in progress may only be in the cache, so they're persisted before they're dropped
+    // a stale copy, one the database has a newer version of, is dropped without writing it
+    pub async fn persist_and_evict(&self, database: &Database, key: &str) -> Result<Option<String>, SaveError> where R: Versioned {
+        match self.persist_cached_value(database, key).await {
+            Ok(_) | Err(SaveError::Conflict(_)) => Ok(self.evict(key).await),
+            Err(e) => Err(e)
+        }
     }
-}
-// end: mobc manager wrapper
-
-const CACHE_POOL_MAX_OPEN: u64 = 16; // max connections
-const CACHE_POOL_MAX_IDLE: u64 = 8; // max unused connections
-const CACHE_POOL_TIMEOUT_SECONDS: u64 = 1; // await a connection from pool @ 1 second max
-const CACHE_POOL_EXPIRE_SECONDS: u64 = 60; // inactive connections die after a minute
-
-pub async fn get_redis_pool(redis_host: &Option<String>) -> anyhow::Result<RedisAdapter> {
-    match redis_host {
-        None => Err(ConfigMissingFieldError {field_name: String::from("redis-host") }.into()),
-        Some(redis_host) => {
-            let redis_uri = format!("redis://{}", redis_host);
-            info!("Connecting to redis at {}", &redis_uri);
-            let client = redis::Client::open(redis_uri)?;
-            let manager = RedisConnectionManager::new(client);
-            let pool = Pool::builder()
-                .get_timeout(Some(Duration::from_secs(CACHE_POOL_TIMEOUT_SECONDS)))
-                .max_open(CACHE_POOL_MAX_OPEN)
-                .max_idle(CACHE_POOL_MAX_IDLE)
-                .max_lifetime(Some(Duration::from_secs(CACHE_POOL_EXPIRE_SECONDS)))
-                .build(manager);
-            let redis_adapter = RedisAdapter { pool };
-            if !redis_adapter.ping().await {
-                return Err(anyhow!("Could not connect to Redis. Is it running?"));
+
+    // like flush, but every value is persisted first and any that couldn't be written are kept
+    // returns how many were evicted and how many were kept
+    pub async fn persist_and_flush(&self, database: &Database) -> (usize, usize) where R: Versioned {
+        let prefix = format!("{}:", self.resource_name);
+        let keys = match self.redis.store.keys_with_prefix(&prefix).await {
+            Ok(keys) => keys,
+            Err(e) => {
+                warn!("Could not list cached {} values: {}", self.resource_name, e);
+                return (0, 0);
+            }
+        };
+        let (mut evicted, mut kept) = (0, 0);
+        for key in keys {
+            match self.persist_and_evict(database, &key[prefix.len()..]).await {
+                Ok(Some(_)) => evicted += 1,
+                Ok(None) => {},
+                Err(e) => {
+                    warn!("Kept cached {} '{}': {}", self.resource_name, key, e);
+                    kept += 1;
+                }
             };
-            info!("Connected to redis successfully.");
-            Ok(redis_adapter)
         }
+        (evicted, kept)
+    }
+
+    // drops a cached value and its name index entry, returning the id of the value
+    pub async fn evict(&self, key: &str) -> Option<String> {
+        let resource_key = self.resolve_key(key).await;
+        let raw = self.redis.store.get(&resource_key).await.ok()??;
+        self.redis.del(&resource_key).await;
+        // read loosely, a corrupted value still has to be evictable
+        let value = json::from_str::<json::Value>(&raw).ok();
+        let target = resource_key[self.resource_name.len() + 1..].to_owned();
+        if self.name_indexed {
+            if let Some(name) = value.as_ref().and_then(|value| value.get(NAME_INDEX_FIELD)?.as_str()) {
+                let index_key = format!("{}{}", self.get_name_index_prefix(), name);
+                let _ = self.redis.store.del_if_equals(&index_key, &target).await;
+            };
+        };
+        Some(value.as_ref().and_then(|value| value.get("_id")?.as_str().map(str::to_owned)).unwrap_or(target))
+    }
+
+    // drops every cached value of this resource along with the name index, returning how many values there were
+    pub async fn flush(&self) -> usize {
+        let evicted = self.redis.del_with_prefix(&format!("{}:", self.resource_name)).await;
+        if self.name_indexed {
+            self.redis.del_with_prefix(&self.get_name_index_prefix()).await;
+        };
+        evicted
+    }
+}
+
+// a version conflict on the very version the database already holds means there was nothing left to write
+pub async fn persist_versioned<R>(database: &Database, record: &R) -> Result<(), SaveError>
+    where R: CollectionOwner<R> + Serialize + IdentifiableDocument + Versioned {
+    match database.save_versioned(record).await {
+        Err(SaveError::Conflict(conflict)) => {
+            let filter = doc! { "_id": record.get_id_value(), "version": record.get_version() as i64 };
+            match database.store.count_documents(R::get_collection_name(), filter).await {
+                Ok(stored) if stored > 0 => Ok(()),
+                _ => Err(SaveError::Conflict(conflict))
+            }
+        },
+        result => result
     }
 }
 
+// typed JSON access over whichever cache store is configured
 pub struct RedisAdapter {
-    pub pool: Pool<RedisConnectionManager>
+    pub store: Arc<dyn CacheStore>
 }
 
 impl RedisAdapter {
+    pub fn new(store: Arc<dyn CacheStore>) -> Self {
+        RedisAdapter { store }
+    }
+
     pub async fn ping(&self) -> bool {
-        let mut conn = unwrap_helper::result_return_default!(self.pool.get().await, false);
-        redis::cmd("PING").arg("we love warzone").query_async::<Connection, String>(&mut conn).await.is_ok()
+        self.store.ping().await
     }
 
     pub async fn set<T>(&self, key: &str, value: &T) where T: Serialize {
@@ -145,19 +361,8 @@ impl RedisAdapter {
     }
 
     pub async fn set_with_expiry<T>(&self, key: &str, value: &T, expiry_ms: Option<usize>) where T: Serialize {
-        let mut conn = match self.pool.get().await {
-            Ok(conn) => conn,
-            Err(_) => return
-        };
-        match json::to_string(value) {
-            Ok(stringified) => { 
-                if expiry_ms.is_some() {
-                    let _ : RedisResult<String> = conn.pset_ex(&key, &stringified, expiry_ms.unwrap()).await;
-                } else {
-                    let _ = redis::cmd("SET").arg(key).arg(&stringified).query_async::<Connection, String>(&mut conn).await; 
-                };
-            },
-            _ => {}
+        if let Ok(stringified) = json::to_string(value) {
+            let _ = self.store.set(key, &stringified, expiry_ms).await;
         };
     }
 
@@ -169,13 +374,121 @@ impl RedisAdapter {
     }
 
     pub async fn get<T>(&self, key: &str) -> anyhow::Result<T> where T: DeserializeOwned {
-        let mut conn = self.pool.get().await?;
-        let raw : String = redis::cmd("GET").arg(key).query_async::<Connection, String>(&mut conn).await?;
+        let raw = self.store.get(key).await?.ok_or_else(|| anyhow!("No value for key '{}'", key))?;
         Ok(json::from_str::<T>(&raw)?)
     }
 
-    pub async fn submit<T, O: Future<Output = T>, F: FnOnce(mobc::Connection<RedisConnectionManager>) -> O>(&self, task: F) -> anyhow::Result<T> {
-        let conn : mobc::Connection<RedisConnectionManager> = self.pool.get().await?;
-        Ok(task(conn).await)
+    pub async fn del(&self, key: &str) {
+        let _ = self.store.del(key).await;
+    }
+
+    // returns how many keys were deleted
+    pub async fn del_with_prefix(&self, prefix: &str) -> usize {
+        let keys = match self.store.keys_with_prefix(prefix).await {
+            Ok(keys) => keys,
+            Err(e) => {
+                warn!("Could not list keys under '{}': {}", prefix, e);
+                return 0;
+            }
+        };
+        join_all(keys.iter().map(|key| self.del(key))).await;
+        keys.len()
+    }
+
+    pub async fn hset<T>(&self, key: &str, field: &str, value: &T) where T: Serialize {
+        if let Ok(stringified) = json::to_string(value) {
+            let _ = self.store.hset(key, field, &stringified).await;
+        };
+    }
+
+    pub async fn hget<T>(&self, key: &str, field: &str) -> Option<T> where T: DeserializeOwned {
+        let raw = self.store.hget(key, field).await.ok()??;
+        json::from_str::<T>(&raw).ok()
+    }
+
+    pub async fn hdel(&self, key: &str, field: &str) {
+        let _ = self.store.hdel(key, field).await;
+    }
+
+    pub async fn hvals<T>(&self, key: &str) -> Vec<T> where T: DeserializeOwned {
+        let raw_values = self.store.hvals(key).await.unwrap_or_default();
+        raw_values.iter().filter_map(|raw| json::from_str::<T>(raw).ok()).collect()
+    }
+
+    // raw field values, for hashes that hold numbers rather than JSON
+    pub async fn hgetall(&self, key: &str) -> HashMap<String, String> {
+        self.store.hgetall(key).await.unwrap_or_default()
+    }
+
+    pub async fn hset_all(&self, key: &str, fields: &[(String, String)]) {
+        let _ = self.store.hset_all(key, fields).await;
+    }
+
+    pub async fn replace_hash(&self, key: &str, fields: &[(String, String)], expiry_ms: usize) {
+        let _ = self.store.replace_hash(key, fields, expiry_ms).await;
+    }
+
+    pub async fn hset_if_better<T>(&self, key: &str, field: &str, value: &T, order_field: &str, higher_is_better: bool) where T: Serialize {
+        if let Ok(stringified) = json::to_string(value) {
+            let _ = self.store.hset_if_better(key, field, &stringified, order_field, higher_is_better).await;
+        };
+    }
+
+    pub async fn hincrbyfloat(&self, key: &str, field: &str, increment: f64) {
+        let _ = self.store.hincrbyfloat(key, field, increment).await;
+    }
+
+    pub async fn sadd(&self, key: &str, member: &str) {
+        let _ = self.store.sadd(key, member).await;
+    }
+
+    pub async fn srem(&self, key: &str, member: &str) {
+        let _ = self.store.srem(key, member).await;
+    }
+
+    pub async fn smembers(&self, key: &str) -> Vec<String> {
+        self.store.smembers(key).await.unwrap_or_default()
+    }
+
+    // pushes onto a capped, expiring list and returns it newest first
+    pub async fn push_recent(&self, key: &str, value: &str, max_len: usize, expiry_ms: usize) -> Vec<String> {
+        self.store.push_recent(key, value, max_len, expiry_ms).await.unwrap_or_default()
+    }
+
+    pub async fn del_if_equals<T>(&self, key: &str, value: &T) where T: Serialize {
+        if let Ok(stringified) = json::to_string(value) {
+            let _ = self.store.del_if_equals(key, &stringified).await;
+        };
+    }
+
+    pub async fn zadd(&self, key: &str, members: &[(String, f64)]) {
+        let _ = self.store.zadd(key, members).await;
+    }
+
+    pub async fn zadd_if_greater(&self, key: &str, member: &str, score: f64) {
+        let _ = self.store.zadd_if_greater(key, member, score).await;
+    }
+
+    pub async fn zincrby(&self, key: &str, member: &str, increment: f64) {
+        let _ = self.store.zincrby(key, member, increment).await;
+    }
+
+    pub async fn zrevrange_with_scores(&self, key: &str, start: usize, stop: usize) -> Vec<(String, f64)> {
+        self.store.zrevrange_with_scores(key, start, stop).await.unwrap_or_default()
+    }
+
+    pub async fn zrevrank(&self, key: &str, member: &str) -> Option<u64> {
+        self.store.zrevrank(key, member).await.unwrap_or(None)
+    }
+
+    // returns how many subscribers received the message
+    pub async fn publish<T>(&self, channel: &str, message: &T) -> usize where T: Serialize {
+        let stringified = unwrap_helper::result_return_default!(json::to_string(message), 0);
+        self.store.publish(channel, &stringified).await.unwrap_or(0)
+    }
+
+    // yields (channel, message) pairs
+    pub async fn subscribe(&self, channels: &[String]) -> anyhow::Result<BoxStream<'static, (String, String)>> {
+        self.store.subscribe(channels).await
     }
 }
@@ -0,0 +1,275 @@
+use std::{collections::{BTreeMap, HashMap, HashSet}, path::Path};
+
+use anyhow::anyhow;
+use mongodb::bson::{self, doc, Bson, Document};
+use rocket::serde::json::{serde_json, Value};
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::socket::leaderboard::MarsLeaderboards;
+
+use super::{CollectionOwner, Database, models::{level::Level, player::{Player, PlayerStats}, punishment::Punishment, rank::Rank}};
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportIssue {
+    pub collection: String,
+    pub id: Option<String>,
+    pub reason: String
+}
+
+#[derive(Serialize, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub imported: BTreeMap<String, u64>,
+    // documents left out because they clash with what's already in the database
+    pub conflicts: Vec<ImportIssue>,
+    // documents left out because they don't read as their model
+    pub invalid: Vec<ImportIssue>,
+    // CSV columns that aren't a player stat
+    pub ignored_columns: Vec<String>
+}
+
+impl ImportReport {
+    fn conflict(&mut self, collection: &str, id: &str, reason: String) {
+        self.conflicts.push(ImportIssue { collection: collection.to_owned(), id: Some(id.to_owned()), reason });
+    }
+
+    fn invalid(&mut self, collection: &str, id: Option<String>, reason: String) {
+        self.invalid.push(ImportIssue { collection: collection.to_owned(), id, reason });
+    }
+}
+
+// imports a directory holding rank, level, player and punishment exports of the reference API,
+// e.g. from `mongoexport --collection=player --out=player.json`, or a CSV file of player stats
+// what a file already imported, or would import in a dry run, so a repeated ID or name in it is caught
+#[derive(Default)]
+struct SeenInFile {
+    ids: HashSet<String>,
+    names: HashSet<String>
+}
+
+impl SeenInFile {
+    fn find_duplicate(&self, id: &str, name_lower: Option<&str>) -> Option<String> {
+        if self.ids.contains(id) {
+            return Some(String::from("A document with this ID appears earlier in the file"));
+        };
+        match name_lower {
+            Some(name_lower) if self.names.contains(name_lower) => Some(format!("The name '{}' appears earlier in the file", name_lower)),
+            _ => None
+        }
+    }
+
+    fn add(&mut self, id: &str, name_lower: Option<&str>) {
+        self.ids.insert(id.to_owned());
+        if let Some(name_lower) = name_lower {
+            self.names.insert(name_lower.to_owned());
+        };
+    }
+}
+
+// nothing that already exists is overwritten, and the all-time leaderboards are rebuilt afterwards
+pub async fn import(database: &Database, leaderboards: &MarsLeaderboards, path: &Path, dry_run: bool) -> anyhow::Result<ImportReport> {
+    let mut report = ImportReport { dry_run, ..Default::default() };
+    if path.is_dir() {
+        // ranks first, so players and punishments come after what they refer to
+        import_collection::<Rank>(database, path, dry_run, &mut report).await?;
+        import_collection::<Level>(database, path, dry_run, &mut report).await?;
+        import_collection::<Player>(database, path, dry_run, &mut report).await?;
+        import_collection::<Punishment>(database, path, dry_run, &mut report).await?;
+    } else {
+        import_player_csv(database, path, dry_run, &mut report).await?;
+    };
+    if !dry_run {
+        leaderboards.populate_all_time().await;
+    };
+    Ok(report)
+}
+
+async fn import_collection<R>(database: &Database, directory: &Path, dry_run: bool, report: &mut ImportReport) -> anyhow::Result<()>
+    where R: CollectionOwner<R> + DeserializeOwned + Serialize {
+    let collection = R::get_collection_name();
+    let path = ["json", "ndjson"].iter().map(|extension| directory.join(format!("{}.{}", collection, extension))).find(|path| path.exists());
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(())
+    };
+    let content = std::fs::read_to_string(&path)?;
+    // mongoexport writes one document per line, or an array with --jsonArray
+    let values : Vec<Value> = if content.trim_start().starts_with('[') {
+        serde_json::from_str(&content)?
+    } else {
+        content.lines().filter(|line| !line.trim().is_empty()).map(serde_json::from_str).collect::<Result<_, _>>()?
+    };
+
+    let mut seen = SeenInFile::default();
+    for value in values {
+        let mut document = match Bson::try_from(value) {
+            Ok(Bson::Document(document)) => document,
+            Ok(_) => {
+                report.invalid(collection, None, String::from("Not a document"));
+                continue;
+            },
+            Err(e) => {
+                report.invalid(collection, None, e.to_string());
+                continue;
+            }
+        };
+        let id = match document.get("_id") {
+            Some(Bson::String(id)) => id.clone(),
+            Some(Bson::ObjectId(id)) => id.to_hex(),
+            _ => {
+                report.invalid(collection, None, String::from("Missing _id"));
+                continue;
+            }
+        };
+        document.insert("_id", &id);
+        if let (Ok(name), false) = (document.get_str("name").map(str::to_lowercase), document.contains_key("nameLower")) {
+            document.insert("nameLower", name);
+        };
+        let record = match bson::from_document::<R>(document.clone()) {
+            Ok(record) => record,
+            Err(e) => {
+                report.invalid(collection, Some(id), e.to_string());
+                continue;
+            }
+        };
+        // punishments can share names, like find_conflict they're only told apart by ID
+        let name_lower = if collection == Punishment::get_collection_name() { None } else { document.get_str("nameLower").ok() };
+        if let Some(reason) = seen.find_duplicate(&id, name_lower) {
+            report.conflict(collection, &id, reason);
+            continue;
+        };
+        if let Some(reason) = find_conflict(database, collection, &id, &document).await? {
+            report.conflict(collection, &id, reason);
+            continue;
+        };
+        if !dry_run {
+            if let Err(e) = R::get_collection(database).insert_one(&record).await {
+                report.conflict(collection, &id, e.to_string());
+                continue;
+            };
+        };
+        seen.add(&id, name_lower);
+        *report.imported.entry(collection.to_owned()).or_insert(0) += 1;
+    }
+    Ok(())
+}
+
+// an existing document with the same ID, or for named documents another one with the same name
+async fn find_conflict(database: &Database, collection: &str, id: &str, document: &Document) -> anyhow::Result<Option<String>> {
+    if database.store.count_documents(collection, doc! { "_id": id }).await? > 0 {
+        return Ok(Some(String::from("A document with this ID already exists")));
+    };
+    if collection == Punishment::get_collection_name() {
+        return Ok(None);
+    };
+    if let Ok(name_lower) = document.get_str("nameLower") {
+        let existing = database.store.find_one(collection, doc! { "nameLower": name_lower }, Default::default()).await?;
+        if let Some(existing) = existing {
+            return Ok(Some(format!("The name '{}' is already used by '{}'", name_lower, existing.get_str("_id").unwrap_or_default())));
+        };
+    };
+    Ok(None)
+}
+
+// one player per row, with "id" and "name" columns and any number of stat columns
+// stat columns match PlayerStats fields regardless of case and underscores, e.g. "kills", "first_bloods" or "serverPlaytime"
+async fn import_player_csv(database: &Database, path: &Path, dry_run: bool, report: &mut ImportReport) -> anyhow::Result<()> {
+    let collection = Player::get_collection_name();
+    let mut reader = csv::Reader::from_path(path)?;
+    let headers = reader.headers()?.clone();
+    let stat_fields : HashMap<String, String> = match serde_json::to_value(serde_json::from_value::<PlayerStats>(serde_json::json!({}))?)? {
+        Value::Object(fields) => fields.into_iter()
+            .filter(|(_, value)| value.is_number())
+            .map(|(field, _)| (normalize_column(&field), field))
+            .collect(),
+        _ => HashMap::new()
+    };
+    let (mut id_column, mut name_column) = (None, None);
+    let mut stat_columns = Vec::new();
+    for (index, header) in headers.iter().enumerate() {
+        match normalize_column(header).as_str() {
+            "id" | "uuid" => id_column = Some(index),
+            "name" | "username" => name_column = Some(index),
+            normalized => match stat_fields.get(normalized) {
+                Some(field) => stat_columns.push((index, field.clone())),
+                None => report.ignored_columns.push(header.to_owned())
+            }
+        };
+    }
+    let (id_column, name_column) = match (id_column, name_column) {
+        (Some(id_column), Some(name_column)) => (id_column, name_column),
+        _ => return Err(anyhow!("The CSV needs an id and a name column"))
+    };
+
+    let mut seen = SeenInFile::default();
+    for (index, row) in reader.records().enumerate() {
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => {
+                report.invalid(collection, None, format!("Row {}: {}", index + 1, e));
+                continue;
+            }
+        };
+        let id = row.get(id_column).unwrap_or_default().trim().to_owned();
+        let name = row.get(name_column).unwrap_or_default().trim().to_owned();
+        if id.is_empty() || name.is_empty() {
+            report.invalid(collection, None, format!("Row {}: missing id or name", index + 1));
+            continue;
+        };
+        // a row with a cell that isn't a number is left out whole, rather than imported without that stat
+        let mut stats = serde_json::Map::new();
+        let mut non_number = None;
+        for (column, field) in stat_columns.iter() {
+            let cell = row.get(*column).unwrap_or_default().trim();
+            if cell.is_empty() {
+                continue;
+            };
+            let value = match (cell.parse::<i64>(), cell.parse::<f64>()) {
+                (Ok(integer), _) => Value::from(integer),
+                (_, Ok(float)) => Value::from(float),
+                _ => {
+                    non_number = Some(cell.to_owned());
+                    break;
+                }
+            };
+            stats.insert(field.clone(), value);
+        }
+        if let Some(cell) = non_number {
+            report.invalid(collection, Some(id), format!("Row {}: '{}' is not a number", index + 1, cell));
+            continue;
+        };
+        let player : Player = match serde_json::from_value(serde_json::json!({
+            "_id": id, "name": name, "nameLower": name.to_lowercase(), "lastSessionId": null, "firstJoinedAt": 0.0, "lastJoinedAt": 0.0,
+            "ips": [], "notes": [], "rankIds": [], "tagIds": [], "activeTagId": null, "stats": stats, "gamemodeStats": {}, "activeJoinSoundId": null
+        })) {
+            Ok(player) => player,
+            Err(e) => {
+                report.invalid(collection, Some(id), format!("Row {}: {}", index + 1, e));
+                continue;
+            }
+        };
+        if let Some(reason) = seen.find_duplicate(&player.id, Some(&player.name_lower)) {
+            report.conflict(collection, &player.id, reason);
+            continue;
+        };
+        if let Some(reason) = find_conflict(database, collection, &player.id, &doc! { "nameLower": &player.name_lower }).await? {
+            report.conflict(collection, &player.id, reason);
+            continue;
+        };
+        if !dry_run {
+            if let Err(e) = database.players.insert_one(&player).await {
+                report.conflict(collection, &player.id, e.to_string());
+                continue;
+            };
+        };
+        seen.add(&player.id, Some(&player.name_lower));
+        *report.imported.entry(collection.to_owned()).or_insert(0) += 1;
+    }
+    Ok(())
+}
+
+fn normalize_column(column: &str) -> String {
+    column.chars().filter(|character| *character != '_' && *character != ' ' && *character != '-').collect::<String>().to_lowercase()
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+use super::{CollectionOwner, Database, models::{achievement::Achievement, archive_aggregate::ArchiveAggregate, chat_message::ChatMessage, dead_letter::DeadLetter, death::Death, ip_identity::IpIdentity, level::Level, player::Player, punishment::Punishment, r#match::Match, rank::Rank, session::Session, tag::Tag}, store::IndexDefinition};
+
+#[derive(Default, Debug)]
+pub struct IndexReport {
+    // "collection.index" names
+    pub created: Vec<String>,
+    pub failed: Vec<String>,
+    // indexes in the database that no model declares, left in place
+    pub extra: Vec<String>
+}
+
+impl IndexReport {
+    pub fn log(&self) {
+        if !self.created.is_empty() {
+            info!("Created {} missing index(es): {}", self.created.len(), self.created.join(", "));
+        };
+        if !self.failed.is_empty() {
+            warn!("Could not ensure {} index(es): {}", self.failed.len(), self.failed.join(", "));
+        };
+        if !self.extra.is_empty() {
+            info!("Found {} index(es) no model declares: {}", self.extra.len(), self.extra.join(", "));
+        };
+    }
+}
+
+// creates every declared index that's missing, existing indexes are matched by name
+pub async fn ensure_indexes(database: &Database) -> IndexReport {
+    let mut report = IndexReport::default();
+    ensure_collection_indexes::<Tag>(database, &mut report).await;
+    ensure_collection_indexes::<Achievement>(database, &mut report).await;
+    ensure_collection_indexes::<Player>(database, &mut report).await;
+    ensure_collection_indexes::<Session>(database, &mut report).await;
+    ensure_collection_indexes::<Punishment>(database, &mut report).await;
+    ensure_collection_indexes::<Rank>(database, &mut report).await;
+    ensure_collection_indexes::<Match>(database, &mut report).await;
+    ensure_collection_indexes::<Death>(database, &mut report).await;
+    ensure_collection_indexes::<Level>(database, &mut report).await;
+    ensure_collection_indexes::<IpIdentity>(database, &mut report).await;
+    ensure_collection_indexes::<ChatMessage>(database, &mut report).await;
+    ensure_collection_indexes::<DeadLetter>(database, &mut report).await;
+    ensure_collection_indexes::<ArchiveAggregate>(database, &mut report).await;
+    report
+}
+
+async fn ensure_collection_indexes<R: CollectionOwner<R>>(database: &Database, report: &mut IndexReport) {
+    let collection = R::get_collection_name();
+    let mut existing : HashMap<String, IndexDefinition> = match database.store.list_indexes(collection).await {
+        Ok(indexes) => indexes.into_iter().collect(),
+        Err(e) => {
+            warn!("Could not list indexes of '{}': {}", collection, e);
+            report.failed.extend(R::get_indexes().iter().map(|index| format!("{}.{}", collection, index.get_name())));
+            return;
+        }
+    };
+    existing.remove("_id_");
+    for index in R::get_indexes() {
+        let name = index.get_name();
+        match existing.remove(&name) {
+            // an index can't be changed in place, so a mismatch has to be fixed by hand
+            Some(current) if current.unique != index.unique => {
+                warn!("Index '{}' of '{}' exists with unique={}, expected unique={}", name, collection, current.unique, index.unique);
+                report.failed.push(format!("{}.{}", collection, name));
+            },
+            Some(_) => {},
+            None => match database.store.create_index(collection, &index).await {
+                Ok(_) => report.created.push(format!("{}.{}", collection, name)),
+                Err(e) => {
+                    warn!("Could not create index '{}' of '{}': {}", name, collection, e);
+                    report.failed.push(format!("{}.{}", collection, name));
+                }
+            }
+        };
+    }
+    let mut extra : Vec<String> = existing.into_keys().map(|name| format!("{}.{}", collection, name)).collect();
+    extra.sort();
+    report.extra.extend(extra);
+}
@@ -2,10 +2,10 @@ use std::collections::HashMap;
 use std::ffi::c_int;
 use futures::{Stream, StreamExt, TryStreamExt};
 use futures::stream::FuturesUnordered;
-use mongodb::bson::{doc, Document};
+use mongodb::bson::doc;
 use mongodb::error::Error;
 use mongodb::options::FindOptions;
-use crate::database::Database;
+use crate::database::{CollectionOwner, Database};
 use crate::database::migrations::DatabaseMigration;
 use crate::database::models::ip_identity::IpIdentity;
 use crate::database::models::player::Player;
@@ -23,37 +23,34 @@ impl DatabaseMigration for DenormalizeIpIdentitiesMigration {
         // batch-read into memory 50k records at a time
         find_options.batch_size = Some(50_000);
 
-        let count = database.players.count_documents(doc! {}, None).await.unwrap_or(0);
+        let count = database.players.count_documents(doc! {}).await.unwrap_or(0);
         info!("{} document(s) in the player collection to migrate", count);
 
-        let mut cursor = database.players.find(doc! {}, Some(find_options)).await.expect("find all players to succeed");
+        // raw documents so the ones that fail to deserialize can be logged
+        let mut cursor = database.store.find(Player::get_collection_name(), doc! {}, find_options).await.expect("find all players to succeed");
         // aggregate 25k players' worth of IPs into memory at a time before flushing
         let step_size = 25_000u32;
         let mut total_accumulated = 0u32;
         let mut accumulated = 0u32;
         let mut error_count = 0u32;
         let mut ip_map : HashMap<String, Vec<String>> = HashMap::new();
-        while let Ok(more) = cursor.advance().await {
-            if !more {
-                break
-            }
+        while let Some(doc_result) = cursor.next().await {
             if accumulated >= step_size {
                 info!("Flushing batch of IPs to ip identities, accumulation progress: {}/{}", total_accumulated, count);
                 Self::flush_ips(database, ip_map).await;
                 ip_map = HashMap::new();
                 accumulated = 0;
             }
-            let player = match cursor.deserialize_current() {
+            let doc = match doc_result {
+                Ok(doc) => doc,
+                Err(e) => {
+                    warn!("Error to parse doc: {}", e);
+                    return
+                }
+            };
+            let player = match mongodb::bson::from_document::<Player>(doc.clone()) {
                 Ok(p) => p,
                 Err(e) => {
-                    let doc_result : mongodb::bson::raw::Result<Document> = cursor.current().try_into();
-                    let doc = match doc_result {
-                        Ok(doc) => doc,
-                        Err(e) => {
-                            warn!("Error to parse doc: {}", e);
-                            return
-                        }
-                    };
                     warn!("document in question: {:?}", doc);
                     warn!("Deserialization error: {}", e);
                     error_count += 1;
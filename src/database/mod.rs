@@ -1,13 +1,12 @@
-use std::{str::FromStr, time::Duration};
+use std::{str::FromStr, sync::Arc};
 use std::collections::HashSet;
 
-use anyhow::anyhow;
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
 use mars_api_rs_macro::IdentifiableDocument;
-use mongodb::{bson::{doc, oid::ObjectId}, Client, Collection, Cursor, options::{ClientOptions, FindOneOptions, UpdateOptions}, results::DeleteResult};
+use mongodb::{bson::{doc, oid::ObjectId, Document}, options::{FindOneOptions, UpdateOptions}};
 use mongodb::options::FindOptions;
-use rand::Rng;
+use uuid::Uuid;
 use rocket::form::validate::Contains;
 use rocket::serde::DeserializeOwned;
 use serde::Serialize;
@@ -18,20 +17,75 @@ use crate::{database::models::player::Player, util::r#macro::unwrap_helper};
 use crate::database::models::ip_identity::IpIdentity;
 use crate::database::models::player::SimplePlayer;
 use crate::util::validation::verbose_result_ok;
+use crate::config::MarsConfigOptions;
 
-use self::models::{achievement::Achievement, death::Death, level::Level, punishment::Punishment, r#match::Match, rank::Rank, session::Session};
+use self::stat_delta::StatDelta;
+use self::write_behind::WriteBehindQueue;
+use self::store::{Collection, Cursor, DeleteResult, DocumentStore, IndexDefinition, mongo::MongoDocumentStore};
+use self::models::{achievement::Achievement, archive_aggregate::ArchiveAggregate, chat_message::ChatMessage, dead_letter::DeadLetter, death::Death, level::Level, punishment::Punishment, r#match::Match, rank::Rank, session::Session};
 
 pub mod models;
 pub mod migrations;
 pub mod cache;
+pub mod presence;
+pub mod stat_delta;
+pub mod player_cache;
+pub mod write_behind;
+pub mod indexes;
+pub mod retention;
+pub mod analytics_export;
+pub mod backup;
+pub mod importer;
+pub mod store;
 
 pub trait CollectionOwner<T> {
     fn get_collection(database: &Database) -> &Collection<T>;
     fn get_collection_name() -> &'static str;
+    // ensured at startup, see indexes::ensure_indexes
+    fn get_indexes() -> Vec<IndexDefinition> { Vec::new() }
 }
 
+// documents that carry a version, so concurrent writers holding stale copies can be detected
+pub trait Versioned {
+    fn get_version(&self) -> u64;
+    fn set_version(&mut self, version: u64);
+    // fields only written when the document is first inserted
+    fn get_insert_only_fields() -> &'static [&'static str] { &[] }
+}
+
+#[derive(Debug, Clone)]
+pub struct VersionConflict {
+    pub collection: String,
+    pub id: String,
+    pub version: u64
+}
+
+impl std::fmt::Display for VersionConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Version {} of {} '{}' was already superseded", self.version, self.collection, self.id)
+    }
+}
+impl std::error::Error for VersionConflict {}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Conflict(VersionConflict),
+    // the record couldn't be serialized or the write itself failed
+    Unwritten(String)
+}
+
+impl std::fmt::Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Conflict(conflict) => conflict.fmt(f),
+            Self::Unwritten(reason) => write!(f, "{}", reason)
+        }
+    }
+}
+impl std::error::Error for SaveError {}
+
 pub struct Database {
-    pub mongo: mongodb::Database,
+    pub store: Arc<dyn DocumentStore>,
     pub tags: Collection<Tag>,
     pub achievements: Collection<Achievement>,
     pub players: Collection<Player>,
@@ -41,7 +95,11 @@ pub struct Database {
     pub matches: Collection<Match>,
     pub deaths: Collection<Death>,
     pub levels: Collection<Level>,
-    pub ip_identities: Collection<IpIdentity>
+    pub ip_identities: Collection<IpIdentity>,
+    pub chat_messages: Collection<ChatMessage>,
+    pub dead_letters: Collection<DeadLetter>,
+    pub archive_aggregates: Collection<ArchiveAggregate>,
+    pub write_behind: Arc<WriteBehindQueue>
 }
 
 impl Database {
@@ -63,7 +121,7 @@ impl Database {
     }
 
     pub async fn get_all_documents<T>(&self) -> Vec<T> 
-        where T: DeserializeOwned + Serialize + IdentifiableDocument + CollectionOwner<T> + Unpin + Send + Sync {
+        where T: DeserializeOwned + Serialize + IdentifiableDocument + CollectionOwner<T> + Unpin + Send + Sync + 'static {
         // Self::consume_cursor_into_owning_vec_option(T::get_collection(&self).find(doc! {}, None).await.ok()).await
         let cursor = match T::get_collection(&self).find(None, None).await {
             Ok(cursor) => cursor,
@@ -81,7 +139,7 @@ impl Database {
     }
 
     pub async fn delete_by_id<T>(&self, id: &str) -> Option<DeleteResult> where T: DeserializeOwned + Serialize + IdentifiableDocument + CollectionOwner<T> {
-        let response = T::get_collection(&self).delete_one(doc! {"_id": id}, None).await;
+        let response = T::get_collection(&self).delete_one(doc! {"_id": id}).await;
         if let Ok(delete_result) = response {
             Some(delete_result)
         } else {
@@ -106,18 +164,27 @@ impl Database {
         }
     }
 
-    pub async fn ensure_player_name_uniqueness(&self, name: &String, keep_id: &String) {
-        let num: u16;
-        {
-            let mut rng = rand::thread_rng();
-            num = rng.gen_range(0..=1000);
+    // moves anyone else holding the name to a placeholder no player can have, returning their IDs
+    // nameLower is unique, so there's at most one of them and the placeholder can't collide
+    pub async fn ensure_player_name_uniqueness(&self, name: &String, keep_id: &String) -> anyhow::Result<Vec<String>> {
+        let filter = doc! { "nameLower": name.to_lowercase(), "_id": { "$ne": keep_id } };
+        let options = FindOptions::builder().projection(doc! { "_id": 1 }).build();
+        let mut cursor = self.store.find(Player::get_collection_name(), filter.clone(), options).await?;
+        let mut holder_ids = Vec::new();
+        while let Some(holder) = cursor.next().await {
+            if let Ok(holder_id) = holder?.get_str("_id") {
+                holder_ids.push(holder_id.to_owned());
+            };
+        }
+        // a queued write of a holder could otherwise put the name back
+        for holder_id in holder_ids.iter() {
+            self.write_behind.flush_document::<Player>(holder_id).await;
         }
-        let temp_name = format!(">WZPlayer{}", num);
-        let _ = self.players.update_many(doc! {
-            "$and": [{"nameLower": name.to_lowercase()}, {"$not": {"_id": &keep_id}}]
-        }, doc! {
+        let temp_name = format!(">{}", Uuid::new_v4().simple());
+        self.players.update_many(filter, doc! {
             "$set": {"name": &temp_name, "nameLower": &temp_name}
-        }, None).await;
+        }, None).await?;
+        Ok(holder_ids)
     }
 
     pub async fn get_active_player_session(&self, player: &Player) -> Option<Session> {
@@ -189,13 +256,52 @@ impl Database {
         }, doc! { "$set": serialized }, Some(update_opts)).await;
     }
 
+    // only writes the record if the stored copy is older than it, the upsert trips over the _id
+    // index when a newer copy exists
+    pub async fn save_versioned<R>(&self, record: &R) -> Result<(), SaveError>
+        where R: CollectionOwner<R> + Serialize + IdentifiableDocument + Versioned {
+        let collection = R::get_collection(self);
+        let mut serialized = mongodb::bson::to_document(record).map_err(|e| {
+            SaveError::Unwritten(format!("Could not serialize {} '{}': {}", R::get_collection_name(), record.get_id_value(), e))
+        })?;
+        let mut insert_only = Document::new();
+        for field in R::get_insert_only_fields() {
+            if let Some(value) = serialized.remove(*field) {
+                insert_only.insert(*field, value);
+            };
+        }
+        let version = record.get_version() as i64;
+        let mut update = doc! { "$set": serialized };
+        if !insert_only.is_empty() {
+            update.insert("$setOnInsert", insert_only);
+        };
+        let update_opts = UpdateOptions::builder().upsert(Some(true)).build();
+        let result = collection.update_one(doc! {
+            "_id": record.get_id_value(),
+            "$or": [{ "version": { "$lt": version } }, { "version": null }]
+        }, update, Some(update_opts)).await;
+        if let Err(e) = result {
+            let newer = collection.count_documents(doc! { "_id": record.get_id_value(), "version": { "$gte": version } }).await.unwrap_or(0);
+            if newer > 0 {
+                return Err(SaveError::Conflict(VersionConflict { collection: R::get_collection_name().to_owned(), id: record.get_id_value(), version: record.get_version() }));
+            };
+            return Err(SaveError::Unwritten(format!("Could not save {} '{}': {}", R::get_collection_name(), record.get_id_value(), e)));
+        };
+        Ok(())
+    }
+
+    // stat changes land with the next write-behind flush, folded together with any others of the player
+    pub async fn apply_stat_delta(&self, player_id: &str, delta: &StatDelta) {
+        self.write_behind.apply_stat_delta(player_id, delta.clone()).await;
+    }
+
     pub async fn insert_one<R>(&self, record: &R) where R: CollectionOwner<R> + Serialize + IdentifiableDocument {
         let collection = R::get_collection(&self);
         // let bson = mongodb::bson::to_bson(record).unwrap();
         // let serialized = bson.as_document().unwrap().clone();
         // let update_opts = UpdateOptions::builder().upsert(Some(true)).build();
         // let doc = doc! {};
-        let _ = collection.insert_one(record, None).await;
+        let _ = collection.insert_one(record).await;
         // let _ = collection.update_one(doc! {
         //     "_id": record.get_id_value()
         // }, doc! { "$set": serialized }, Some(update_opts)).await;
@@ -220,40 +326,34 @@ impl Database {
     }
 }
 
-const DB_NAME: &'static str = "mars-api";
-
-pub async fn ping_database(mongo: &mongodb::Database) -> bool {
-    mongo.run_command(doc! { "ping": 1 }, None).await.is_ok()
+impl Database {
+    pub fn new(store: Arc<dyn DocumentStore>) -> Self {
+        Database {
+            tags: Collection::new(Tag::get_collection_name(), Arc::clone(&store)),
+            achievements: Collection::new(Achievement::get_collection_name(), Arc::clone(&store)),
+            players: Collection::new(Player::get_collection_name(), Arc::clone(&store)),
+            sessions: Collection::new(Session::get_collection_name(), Arc::clone(&store)),
+            punishments: Collection::new(Punishment::get_collection_name(), Arc::clone(&store)),
+            ranks: Collection::new(Rank::get_collection_name(), Arc::clone(&store)),
+            matches: Collection::new(Match::get_collection_name(), Arc::clone(&store)),
+            levels: Collection::new(Level::get_collection_name(), Arc::clone(&store)),
+            deaths: Collection::new(Death::get_collection_name(), Arc::clone(&store)),
+            ip_identities: Collection::new(IpIdentity::get_collection_name(), Arc::clone(&store)),
+            chat_messages: Collection::new(ChatMessage::get_collection_name(), Arc::clone(&store)),
+            dead_letters: Collection::new(DeadLetter::get_collection_name(), Arc::clone(&store)),
+            archive_aggregates: Collection::new(ArchiveAggregate::get_collection_name(), Arc::clone(&store)),
+            write_behind: Arc::new(WriteBehindQueue::new(Arc::clone(&store))),
+            store
+        }
+    }
 }
 
-pub async fn connect(db_url: &String, min_pool_size: Option<u32>, max_pool_size: Option<u32>) -> anyhow::Result<Database> {
-    let mut client_options = ClientOptions::parse(db_url).await?;
-    client_options.min_pool_size = min_pool_size;
-    client_options.max_pool_size = max_pool_size;
-    client_options.connect_timeout = Some(Duration::new(5, 0));
-    client_options.server_selection_timeout = Some(Duration::new(5, 0));
-
-
-    let client = Client::with_options(client_options)?;
-    let db = client.database(DB_NAME);
-    if !ping_database(&db).await {
-        return Err(anyhow!("Could not connect to the database. Is it running?"));
-    };
-
-    let tags = db.collection::<Tag>(Tag::get_collection_name());
-    let achievements = db.collection::<Achievement>(Achievement::get_collection_name());
-    let players = db.collection::<Player>(Player::get_collection_name());
-    let sessions = db.collection::<Session>(Session::get_collection_name());
-    let punishments = db.collection::<Punishment>(Punishment::get_collection_name());
-    let ranks = db.collection::<Rank>(Rank::get_collection_name());
-    let matches = db.collection::<Match>(Match::get_collection_name());
-    let levels = db.collection::<Level>(Level::get_collection_name());
-    let deaths = db.collection::<Death>(Death::get_collection_name());
-    let ip_identities = db.collection::<IpIdentity>(IpIdentity::get_collection_name());
+pub async fn connect(options: &MarsConfigOptions) -> anyhow::Result<Database> {
+    connect_named(&options.mongo_url, &options.mongo_database, options.mongo_min_pool_size, options.mongo_max_pool_size).await
+}
 
+pub async fn connect_named(db_url: &str, db_name: &str, min_pool_size: Option<u32>, max_pool_size: Option<u32>) -> anyhow::Result<Database> {
+    let store = MongoDocumentStore::connect(db_url, db_name, min_pool_size, max_pool_size).await?;
     info!("Connected to database successfully.");
-    Ok(Database { 
-        mongo: db, tags, achievements, players, sessions, 
-        punishments, ranks, matches, levels, deaths, ip_identities
-    })
+    Ok(Database::new(Arc::new(store)))
 }
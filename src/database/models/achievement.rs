@@ -5,7 +5,7 @@ use serde::{Deserialize, Serialize};
 use crate::database::CollectionOwner;
 
 impl CollectionOwner<Achievement> for Achievement {
-    fn get_collection(database: &crate::database::Database) -> &mongodb::Collection<Achievement> {
+    fn get_collection(database: &crate::database::Database) -> &crate::database::store::Collection<Achievement> {
         &database.achievements
     }
 
@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+
+use mars_api_rs_derive::IdentifiableDocument;
+use mars_api_rs_macro::IdentifiableDocument;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::database::{CollectionOwner, store::IndexDefinition};
+
+// what's left of archived documents in mongo, counted per collection and UTC day
+#[derive(Deserialize, Serialize, IdentifiableDocument, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveAggregate {
+    // "<collection>:<day>"
+    #[id]
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub collection: String,
+    // YYYY-MM-DD
+    pub day: String,
+    pub count: u64,
+    // e.g. "cause:FALL" for deaths or "playtime" for sessions, a colon since dots would nest the counter
+    #[serde(default)]
+    pub counters: HashMap<String, f64>
+}
+
+impl CollectionOwner<ArchiveAggregate> for ArchiveAggregate {
+    fn get_collection(database: &crate::database::Database) -> &crate::database::store::Collection<ArchiveAggregate> {
+        &database.archive_aggregates
+    }
+
+    fn get_collection_name() -> &'static str {
+        "archive_aggregate"
+    }
+
+    fn get_indexes() -> Vec<IndexDefinition> {
+        vec![IndexDefinition::new(doc! { "collection": 1, "day": 1 })]
+    }
+}
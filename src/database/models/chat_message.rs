@@ -0,0 +1,44 @@
+use mars_api_rs_derive::IdentifiableDocument;
+use mars_api_rs_macro::IdentifiableDocument;
+use mongodb::bson::doc;
+use serde::{Deserialize, Serialize};
+
+use crate::{database::{CollectionOwner, store::IndexDefinition}, socket::player::player_events::ChatChannel};
+
+use super::player::SimplePlayer;
+
+#[derive(Deserialize, Serialize, IdentifiableDocument, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatMessage {
+    #[id]
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub player: SimplePlayer,
+    pub server_id: String,
+    #[serde(default)]
+    pub match_id: Option<String>,
+    pub channel: ChatChannel,
+    pub message: String,
+    // checks from the chat filter that matched
+    #[serde(default)]
+    pub flags: Vec<String>,
+    pub created_at: u64
+}
+
+impl CollectionOwner<ChatMessage> for ChatMessage {
+    fn get_collection(database: &crate::database::Database) -> &crate::database::store::Collection<ChatMessage> {
+        &database.chat_messages
+    }
+
+    fn get_collection_name() -> &'static str {
+        "chat_message"
+    }
+
+    fn get_indexes() -> Vec<IndexDefinition> {
+        vec![
+            IndexDefinition::new(doc! { "createdAt": -1 }),
+            IndexDefinition::new(doc! { "player.id": 1, "createdAt": -1 }),
+            IndexDefinition::new(doc! { "serverId": 1, "createdAt": -1 })
+        ]
+    }
+}
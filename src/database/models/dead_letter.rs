@@ -0,0 +1,90 @@
+use mars_api_rs_derive::IdentifiableDocument;
+use mars_api_rs_macro::IdentifiableDocument;
+use mongodb::bson::doc;
+use rocket::serde::json::Value;
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumString};
+use uuid::Uuid;
+
+use crate::{database::{CollectionOwner, store::IndexDefinition}, util::time::get_u64_time_millis};
+
+#[derive(Deserialize, Serialize, Display, EnumString, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum DeadLetterReason {
+    // could not be decompressed or decoded into an {e, d} packet
+    Undecodable,
+    UnknownEvent,
+    // a known event the router doesn't handle, e.g. a plugin-bound one
+    Unroutable,
+    MalformedData,
+    HandlerPanic,
+    HandlerError,
+    // the match or player changed underneath the handler
+    VersionConflict
+}
+
+// a socket event that failed to process, kept so it can be inspected and redriven
+#[derive(Deserialize, Serialize, IdentifiableDocument, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetter {
+    #[id]
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub server_id: String,
+    pub reason: DeadLetterReason,
+    pub error: String,
+    #[serde(default)]
+    pub event: Option<String>,
+    #[serde(default)]
+    pub data: Option<Value>,
+    // the packet as received when it couldn't be decoded, as text if it was UTF-8 and hex otherwise
+    #[serde(default)]
+    pub raw: Option<String>,
+    pub created_at: u64,
+    #[serde(default)]
+    pub redrive_attempts: u32,
+    #[serde(default)]
+    pub last_redrive_error: Option<String>,
+    #[serde(default)]
+    pub resolved_at: Option<u64>,
+    // set while a redrive is running, so two requests can't route the same event twice
+    #[serde(default)]
+    pub redriving_at: Option<u64>
+}
+
+impl DeadLetter {
+    pub fn new(server_id: &str, reason: DeadLetterReason, error: String) -> Self {
+        DeadLetter {
+            id: Uuid::new_v4().to_string(),
+            server_id: server_id.to_owned(),
+            reason,
+            error,
+            event: None,
+            data: None,
+            raw: None,
+            created_at: get_u64_time_millis(),
+            redrive_attempts: 0,
+            last_redrive_error: None,
+            resolved_at: None,
+            redriving_at: None
+        }
+    }
+}
+
+impl CollectionOwner<DeadLetter> for DeadLetter {
+    fn get_collection(database: &crate::database::Database) -> &crate::database::store::Collection<DeadLetter> {
+        &database.dead_letters
+    }
+
+    fn get_collection_name() -> &'static str {
+        "dead_letter"
+    }
+
+    fn get_indexes() -> Vec<IndexDefinition> {
+        vec![
+            IndexDefinition::new(doc! { "createdAt": -1 }),
+            IndexDefinition::new(doc! { "serverId": 1, "createdAt": -1 })
+        ]
+    }
+}
@@ -1,8 +1,9 @@
 use mars_api_rs_derive::IdentifiableDocument;
 use mars_api_rs_macro::IdentifiableDocument;
+use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
 
-use crate::database::CollectionOwner;
+use crate::database::{CollectionOwner, store::IndexDefinition};
 
 use super::player::SimplePlayer;
 
@@ -25,13 +26,20 @@ pub struct Death {
 }
 
 impl CollectionOwner<Death> for Death {
-    fn get_collection(database: &crate::database::Database) -> &mongodb::Collection<Death> {
+    fn get_collection(database: &crate::database::Database) -> &crate::database::store::Collection<Death> {
         &database.deaths
     }
 
     fn get_collection_name() -> &'static str {
         "death"
     }
+
+    fn get_indexes() -> Vec<IndexDefinition> {
+        vec![
+            IndexDefinition::new(doc! { "createdAt": 1 }),
+            IndexDefinition::new(doc! { "exportedAt": 1, "createdAt": 1 })
+        ]
+    }
 }
 
 #[derive(Deserialize, Serialize, PartialEq, Eq, Clone)]
@@ -2,7 +2,7 @@ use futures::stream::FuturesUnordered;
 use futures::StreamExt;
 use mars_api_rs_derive::IdentifiableDocument;
 use mars_api_rs_macro::IdentifiableDocument;
-use mongodb::Collection;
+use crate::database::store::Collection;
 use rocket::serde::{Deserialize, Serialize};
 use crate::database::{CollectionOwner, Database};
 use crate::database::models::player::Player;
@@ -1,8 +1,9 @@
 use mars_api_rs_macro::IdentifiableDocument;
 use mars_api_rs_derive::IdentifiableDocument;
+use mongodb::bson::doc;
 use serde::{Serialize, Deserialize};
 
-use crate::database::CollectionOwner;
+use crate::database::{CollectionOwner, store::IndexDefinition};
 
 use super::{r#match::GoalCollection, player::{PlayerRecord, ProjectileRecord, FirstBloodRecord}};
 
@@ -28,13 +29,17 @@ pub struct Level {
 }
 
 impl CollectionOwner<Level> for Level {
-    fn get_collection(database: &crate::database::Database) -> &mongodb::Collection<Level> {
+    fn get_collection(database: &crate::database::Database) -> &crate::database::store::Collection<Level> {
         &database.levels
     }
 
     fn get_collection_name() -> &'static str {
         "level"
     }
+
+    fn get_indexes() -> Vec<IndexDefinition> {
+        vec![IndexDefinition::new(doc! { "nameLower": 1 })]
+    }
 }
 
 #[derive(Serialize, Deserialize)]
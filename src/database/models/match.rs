@@ -2,9 +2,10 @@ use std::collections::{HashSet, HashMap};
 
 use mars_api_rs_derive::IdentifiableDocument;
 use mars_api_rs_macro::IdentifiableDocument;
+use mongodb::bson::doc;
 use serde::{Serialize, Deserialize};
 
-use crate::{database::CollectionOwner, util::time::get_u64_time_millis, socket::{participant::participant_context::PlayerMatchResult, r#match::match_events::MatchEndData}};
+use crate::{database::{CollectionOwner, Versioned, store::IndexDefinition}, util::time::get_u64_time_millis, socket::{participant::participant_context::PlayerMatchResult, r#match::match_events::MatchEndData}};
 
 use super::{player::SimplePlayer, level::{Level, LevelGamemode}, participant::{Participant}};
 
@@ -23,7 +24,9 @@ pub struct Match {
     pub parties: HashMap<String, Party>,
     pub participants: HashMap<String, Participant>,
     pub server_id: String,
-    pub first_blood: Option<FirstBlood>
+    pub first_blood: Option<FirstBlood>,
+    #[serde(default)]
+    pub version: u64
 }
 
 impl Match {
@@ -74,13 +77,30 @@ impl Match {
 }
 
 impl CollectionOwner<Match> for Match {
-    fn get_collection(database: &crate::database::Database) -> &mongodb::Collection<Match> {
+    fn get_collection(database: &crate::database::Database) -> &crate::database::store::Collection<Match> {
         &database.matches
     }
 
     fn get_collection_name() -> &'static str {
         "match"
     }
+
+    fn get_indexes() -> Vec<IndexDefinition> {
+        vec![
+            IndexDefinition::new(doc! { "loadedAt": -1 }),
+            IndexDefinition::new(doc! { "exportedAt": 1, "endedAt": 1 })
+        ]
+    }
+}
+
+impl Versioned for Match {
+    fn get_version(&self) -> u64 {
+        self.version
+    }
+
+    fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
 }
 
 #[derive(PartialEq)]
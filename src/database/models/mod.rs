@@ -12,4 +12,7 @@ pub mod death;
 pub mod join_sound;
 pub mod server;
 pub mod achievement;
-pub mod ip_identity;
\ No newline at end of file
+pub mod ip_identity;
+pub mod chat_message;
+pub mod dead_letter;
+pub mod archive_aggregate;
\ No newline at end of file
@@ -65,10 +65,6 @@ impl Participant {
         self.name.to_lowercase()
     }
 
-    pub async fn set_player(&self, state: &MarsAPIState, player: &Player) {
-        state.player_cache.set(&state.database, &self.get_name_lower(), player, false).await;
-    }
-
     pub fn get_simple_player(&self) -> SimplePlayer {
         return SimplePlayer { name: self.name.clone(), id: self.id.clone() }
     }
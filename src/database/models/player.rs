@@ -2,12 +2,13 @@ use std::future::Future;
 
 use mars_api_rs_macro::IdentifiableDocument;
 use mars_api_rs_derive::IdentifiableDocument;
-use mongodb::Collection;
+use crate::database::store::{Collection, IndexDefinition};
+use mongodb::bson::doc;
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 use num_traits::ToPrimitive;
 
-use crate::{database::CollectionOwner, socket::{leaderboard::ScoreType, player::{player_xp_listener::PlayerXPListener, player_events::PlayerXPGainData}, server::server_context::ServerContext, event_type::EventType}};
+use crate::{database::{CollectionOwner, Versioned, stat_delta::FlatStats}, socket::{leaderboard::ScoreType, player::{player_xp_listener::PlayerXPListener, player_events::PlayerXPGainData}, server::server_context::ServerContext, event_type::EventType}};
 use crate::database::models::server::{ServerEvents, XPMultiplier};
 
 use super::{punishment::StaffNote, level::LevelGamemode, r#match::Match};
@@ -30,7 +31,13 @@ pub struct Player {
     pub active_tag_id: Option<String>,
     pub stats: PlayerStats,
     pub gamemode_stats: HashMap<LevelGamemode, GamemodeStats>,
-    pub active_join_sound_id: Option<String>
+    pub active_join_sound_id: Option<String>,
+    // bumped on every profile write, see Versioned
+    #[serde(default)]
+    pub version: u64,
+    // the stats as read from the cache, never stored
+    #[serde(skip)]
+    pub loaded_stats: Option<Arc<FlatStats>>
 }
 
 impl Player {
@@ -98,6 +105,20 @@ impl Player {
 impl CollectionOwner<Player> for Player {
     fn get_collection(database: &crate::database::Database) -> &Collection<Player> { &database.players }
     fn get_collection_name() -> &'static str { "player" }
+    fn get_indexes() -> Vec<IndexDefinition> {
+        vec![
+            IndexDefinition::new(doc! { "nameLower": 1 }).unique(),
+            IndexDefinition::new(doc! { "rankIds": 1 }),
+            IndexDefinition::new(doc! { "tagIds": 1 })
+        ]
+    }
+}
+
+impl Versioned for Player {
+    fn get_version(&self) -> u64 { self.version }
+    fn set_version(&mut self, version: u64) { self.version = version }
+    // stats only change through increments once the player exists
+    fn get_insert_only_fields() -> &'static [&'static str] { &["stats", "gamemodeStats"] }
 }
 
 pub type GamemodeStats = PlayerStats;
@@ -257,7 +278,7 @@ impl Default for PlayerStats {
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", default)]
 pub struct PlayerObjectiveStatistics {
     pub core_leaks: u32,
     pub core_block_destroys: u32,
@@ -1,8 +1,9 @@
 use mars_api_rs_derive::IdentifiableDocument;
 use mars_api_rs_macro::IdentifiableDocument;
+use mongodb::bson::doc;
 use serde::{Serialize, Deserialize};
 use strum_macros::Display;
-use crate::{database::CollectionOwner, util::time::get_u64_time_millis};
+use crate::{database::{CollectionOwner, store::IndexDefinition}, util::time::get_u64_time_millis};
 
 use super::player::SimplePlayer;
 
@@ -48,13 +49,20 @@ impl Punishment {
 }
 
 impl CollectionOwner<Punishment> for Punishment {
-    fn get_collection(database: &crate::database::Database) -> &mongodb::Collection<Punishment> {
+    fn get_collection(database: &crate::database::Database) -> &crate::database::store::Collection<Punishment> {
         &database.punishments
     }
 
     fn get_collection_name() -> &'static str {
         "punishment"
     }
+
+    fn get_indexes() -> Vec<IndexDefinition> {
+        vec![
+            IndexDefinition::new(doc! { "target.id": 1 }),
+            IndexDefinition::new(doc! { "targetIps": 1, "action.kind": 1 })
+        ]
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -4,6 +4,7 @@ use mongodb::bson::doc;
 use serde::{Serialize, Deserialize};
 
 use crate::database::{CollectionOwner, Database};
+use crate::database::store::IndexDefinition;
 
 #[derive(Deserialize, Serialize, Debug, IdentifiableDocument)]
 #[serde(rename_all = "camelCase")]
@@ -25,13 +26,17 @@ pub struct Rank {
 }
 
 impl CollectionOwner<Rank> for Rank {
-    fn get_collection(database: &crate::database::Database) -> &mongodb::Collection<Rank> {
+    fn get_collection(database: &crate::database::Database) -> &crate::database::store::Collection<Rank> {
         &database.ranks
     }
 
     fn get_collection_name() -> &'static str {
         "rank"
     }
+
+    fn get_indexes() -> Vec<IndexDefinition> {
+        vec![IndexDefinition::new(doc! { "nameLower": 1 })]
+    }
 }
 
 impl Rank {
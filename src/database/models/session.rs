@@ -1,9 +1,10 @@
 use log::warn;
 use mars_api_rs_derive::IdentifiableDocument;
 use mars_api_rs_macro::IdentifiableDocument;
+use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
 
-use crate::database::CollectionOwner;
+use crate::database::{CollectionOwner, store::IndexDefinition};
 
 use super::player::SimplePlayer;
 
@@ -39,11 +40,20 @@ impl Session {
 }
 
 impl CollectionOwner<Session> for Session {
-    fn get_collection(database: &crate::database::Database) -> &mongodb::Collection<Session> {
+    fn get_collection(database: &crate::database::Database) -> &crate::database::store::Collection<Session> {
         &database.sessions
     }
 
     fn get_collection_name() -> &'static str {
         "session"
     }
+
+    fn get_indexes() -> Vec<IndexDefinition> {
+        vec![
+            IndexDefinition::new(doc! { "player.id": 1, "endedAt": 1 }),
+            IndexDefinition::new(doc! { "serverId": 1, "endedAt": 1 }),
+            IndexDefinition::new(doc! { "endedAt": 1 }),
+            IndexDefinition::new(doc! { "exportedAt": 1, "endedAt": 1 })
+        ]
+    }
 }
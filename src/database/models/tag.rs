@@ -1,8 +1,9 @@
 use mars_api_rs_macro::IdentifiableDocument;
 use mars_api_rs_derive::IdentifiableDocument;
+use mongodb::bson::doc;
 use serde::{Serialize, Deserialize};
 
-use crate::database::CollectionOwner;
+use crate::database::{CollectionOwner, store::IndexDefinition};
 
 #[derive(Debug, Serialize, Deserialize, IdentifiableDocument)]
 pub struct Tag {
@@ -18,11 +19,15 @@ pub struct Tag {
 }
 
 impl CollectionOwner<Tag> for Tag {
-    fn get_collection(database: &crate::database::Database) -> &mongodb::Collection<Tag> {
+    fn get_collection(database: &crate::database::Database) -> &crate::database::store::Collection<Tag> {
         &database.tags
     }
 
     fn get_collection_name() -> &'static str {
         "tag"
     }
+
+    fn get_indexes() -> Vec<IndexDefinition> {
+        vec![IndexDefinition::new(doc! { "nameLower": 1 })]
+    }
 }
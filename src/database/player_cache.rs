@@ -0,0 +1,190 @@
+use std::{collections::HashMap, marker::PhantomData, sync::Arc};
+
+use futures::future::join_all;
+use rocket::serde::json::{serde_json, Value};
+
+use super::{Database, SaveError, VersionConflict, cache::{self, Cache, CasMetrics, HitMetrics, MAX_UPDATE_ATTEMPTS, RedisAdapter, UpdateError}, models::player::Player, stat_delta::{self, FlatStats, StatDelta}};
+
+// profiles are cached as JSON like any other resource, but their stats live in a per-player hash
+// that is only ever incremented, so two writers holding different copies of a player can't lose each other's stats
+pub struct PlayerCache {
+    pub cache: Cache<Player>
+}
+
+impl PlayerCache {
+    pub fn new(redis: Arc<RedisAdapter>) -> Self {
+        PlayerCache {
+            cache: Cache {
+                redis,
+                resource_name: String::from("player"),
+                lifetime_ms: 10_800_000,
+                resource_type: PhantomData,
+                name_indexed: true,
+                cas_metrics: CasMetrics::default(),
+                hit_metrics: HitMetrics::default()
+            }
+        }
+    }
+
+    fn get_stats_key(player_id: &str) -> String {
+        format!("player_stats:{}", player_id)
+    }
+
+    // the stats hash goes too, the next read reloads both from the database
+    pub async fn evict(&self, key: &str) -> bool {
+        match self.cache.evict(key).await {
+            Some(player_id) => {
+                self.cache.redis.del(&Self::get_stats_key(&player_id)).await;
+                true
+            },
+            None => false
+        }
+    }
+
+    pub async fn flush(&self) -> usize {
+        let evicted = self.cache.flush().await;
+        self.cache.redis.del_with_prefix("player_stats:").await;
+        evicted
+    }
+
+    // writes the cached profile, with its cached stats, unless the database already holds a newer version
+    // returns whether there was a cached copy
+    pub async fn persist_cached_value(&self, database: &Database, key: &str) -> Result<bool, SaveError> {
+        let player = match self.query(key).await {
+            Some(player) => player,
+            None => return Ok(false)
+        };
+        // queued stat increments are already part of the cached stats
+        database.write_behind.flush_document::<Player>(&player.id).await;
+        cache::persist_versioned(database, &player).await?;
+        Ok(true)
+    }
+
+    pub async fn query(&self, key: &str) -> Option<Player> {
+        let mut player = self.cache.query(key).await?;
+        let cached_stats = self.cache.redis.hgetall(&Self::get_stats_key(&player.id)).await;
+        if cached_stats.is_empty() {
+            // cached before stats were split out, or the hash was evicted
+            self.seed_stats(&player).await;
+        } else {
+            stat_delta::overlay_stats(&mut player, &Self::parse_stats(cached_stats));
+        };
+        stat_delta::mark_loaded(&mut player);
+        Some(player)
+    }
+
+    pub async fn get(&self, database: &Database, key: &str) -> Option<Player> {
+        if let Some(player) = self.query(key).await {
+            return Some(player);
+        };
+        let mut player = self.cache.get(database, key).await?;
+        // the copy read misses whatever is still queued for the player, so that lands first and the copy is read again
+        if database.write_behind.flush_document::<Player>(&player.id).await {
+            player = self.cache.get(database, &player.id).await?;
+        };
+        // the database copy is the most recent, so it replaces whatever counters were left in the cache
+        self.seed_stats(&player).await;
+        stat_delta::mark_loaded(&mut player);
+        Some(player)
+    }
+
+    // a player read through this cache only writes the stats that changed since, as increments
+    // players built or loaded elsewhere are overwritten
+    // the rest of the profile is versioned, a write from a stale copy changes nothing and returns the conflict,
+    // callers that can redo their change should go through update instead
+    pub async fn set(&self, database: &Database, key: &str, player: &mut Player, persist: bool) -> Result<(), VersionConflict> {
+        let loaded_stats = match &player.loaded_stats {
+            Some(loaded_stats) => Arc::clone(loaded_stats),
+            None => {
+                self.overwrite(database, key, player, persist).await;
+                return Ok(());
+            }
+        };
+        self.cache.compare_and_set(database, key, player, persist, None).await?;
+        let delta = StatDelta::between(&loaded_stats, &stat_delta::flatten_stats(player));
+        self.apply_delta(database, &player.id, &delta).await;
+        // a later write of this copy only carries what changed after this one
+        stat_delta::mark_loaded(player);
+        Ok(())
+    }
+
+    // for changes that only touch stats, which are applied as increments without writing the profile, so there's nothing to conflict
+    pub async fn save_stats(&self, database: &Database, key: &str, player: &mut Player, persist: bool) {
+        let loaded_stats = match &player.loaded_stats {
+            Some(loaded_stats) => Arc::clone(loaded_stats),
+            None => return self.overwrite(database, key, player, persist).await
+        };
+        let delta = StatDelta::between(&loaded_stats, &stat_delta::flatten_stats(player));
+        self.apply_delta(database, &player.id, &delta).await;
+        stat_delta::mark_loaded(player);
+    }
+
+    // players built or loaded elsewhere replace the cached copy and its stats entirely
+    pub async fn overwrite(&self, database: &Database, key: &str, player: &mut Player, persist: bool) {
+        player.version += 1;
+        self.cache.set(database, key, player, false).await;
+        self.seed_stats(player).await;
+        if persist {
+            database.save(player).await;
+        };
+        stat_delta::mark_loaded(player);
+    }
+
+    // re-reads the player and re-applies the mutation until the profile write isn't stale
+    // the stat changes are only applied once, after the write that wins
+    pub async fn update<E, F>(&self, database: &Database, key: &str, mut mutate: F) -> Result<Player, UpdateError<E>>
+        where F: FnMut(&mut Player) -> Result<(), E> {
+        let mut last_conflict = None;
+        for _ in 0..MAX_UPDATE_ATTEMPTS {
+            let mut player = self.get(database, key).await.ok_or(UpdateError::Missing)?;
+            mutate(&mut player).map_err(UpdateError::Rejected)?;
+            let loaded_stats = player.loaded_stats.clone().unwrap_or_default();
+            match self.cache.compare_and_set(database, key, &mut player, true, None).await {
+                Ok(_) => {
+                    let delta = StatDelta::between(&loaded_stats, &stat_delta::flatten_stats(&player));
+                    self.apply_delta(database, &player.id, &delta).await;
+                    stat_delta::mark_loaded(&mut player);
+                    return Ok(player);
+                },
+                Err(conflict) => last_conflict = Some(conflict)
+            };
+        }
+        self.cache.cas_metrics.record_exhausted();
+        Err(UpdateError::Conflict(last_conflict.unwrap()))
+    }
+
+    pub async fn apply_delta(&self, database: &Database, player_id: &str, delta: &StatDelta) {
+        if delta.is_empty() {
+            return;
+        };
+        database.apply_stat_delta(player_id, delta).await;
+
+        let stats_key = Self::get_stats_key(player_id);
+        let redis = &self.cache.redis;
+        join_all(delta.increments.iter().map(|(path, amount)| redis.hincrbyfloat(&stats_key, path, *amount))).await;
+        // records are compared where they're stored, so a better record written in between is never replaced by a worse one
+        for (path, value) in delta.replacements.iter() {
+            match stat_delta::get_record_order(path) {
+                Some((order_field, higher_is_better)) => redis.hset_if_better(&stats_key, path, value, order_field, higher_is_better).await,
+                None => redis.hset(&stats_key, path, value).await
+            };
+        }
+        for path in delta.removals.iter() {
+            redis.hdel(&stats_key, path).await;
+        }
+        // the hash lives as long as the profile it belongs to
+        let _ = redis.store.pexpire(&stats_key, self.cache.lifetime_ms as usize).await;
+    }
+
+    // replaced in one step, so a reader never sees the hash missing or half written
+    async fn seed_stats(&self, player: &Player) {
+        let fields : Vec<(String, String)> = stat_delta::flatten_stats(player).into_iter()
+            .map(|(path, value)| (path, value.to_string()))
+            .collect();
+        self.cache.redis.replace_hash(&Self::get_stats_key(&player.id), &fields, self.cache.lifetime_ms as usize).await;
+    }
+
+    fn parse_stats(raw: HashMap<String, String>) -> FlatStats {
+        raw.into_iter().filter_map(|(path, value)| serde_json::from_str::<Value>(&value).ok().map(|value| (path, value))).collect()
+    }
+}
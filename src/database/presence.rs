@@ -0,0 +1,161 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::future::join_all;
+use rocket::serde::json::serde_json;
+use serde::{Serialize, Deserialize};
+
+use crate::{database::{cache::RedisAdapter, models::player::SimplePlayer}, socket::server::server_registry::ServerRegistry, util::time::get_u64_time_millis};
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerPresence {
+    pub player: SimplePlayer,
+    pub server_id: String,
+    pub session_id: String,
+    pub party_name: Option<String>,
+    pub joined_at: u64,
+    pub updated_at: u64
+}
+
+// the party a session is in, kept apart so the presence itself is never rewritten
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PartyPresence {
+    party_name: String,
+    updated_at: u64
+}
+
+// who is online where, kept in redis so every API instance sees the same network
+// a player's presence is one key that is only ever written whole or deleted if it still holds what was read,
+// so every transition is a single atomic write and a switch or a late logout can't leave a mix of two sessions behind
+// the keys expire unless the instance holding the server's socket keeps refreshing them
+pub struct PresenceTracker {
+    pub redis: Arc<RedisAdapter>
+}
+
+impl PresenceTracker {
+    const PLAYER_PREFIX: &'static str = "presence:player:";
+    const PARTY_PREFIX: &'static str = "presence:party:";
+    pub const TTL_MS: usize = 60_000;
+    const HEARTBEAT: Duration = Duration::from_secs(20);
+
+    fn get_player_key(player_id: &str) -> String {
+        format!("{}{}", Self::PLAYER_PREFIX, player_id)
+    }
+
+    // parties belong to a session, so a new login never inherits the last one's
+    fn get_party_key(session_id: &str) -> String {
+        format!("{}{}", Self::PARTY_PREFIX, session_id)
+    }
+
+    // replacing the key also moves a player who switched servers without logging out first
+    pub async fn set_online(&self, player: &SimplePlayer, server_id: &str, session_id: &str) {
+        let time_millis = get_u64_time_millis();
+        let presence = PlayerPresence {
+            player: player.clone(),
+            server_id: server_id.to_owned(),
+            session_id: session_id.to_owned(),
+            party_name: None,
+            joined_at: time_millis,
+            updated_at: time_millis
+        };
+        self.redis.set_with_expiry(&Self::get_player_key(&player.id), &presence, Some(Self::TTL_MS)).await;
+    }
+
+    // only clears the session that logged out, a late logout must not hide a newer login
+    pub async fn set_offline(&self, player_id: &str, session_id: &str) {
+        let (raw, presence) = match self.get_raw(player_id).await {
+            Some(read) => read,
+            None => return
+        };
+        if presence.session_id != session_id {
+            return;
+        };
+        // a login since the read replaced the value, so it isn't deleted
+        let _ = self.redis.store.del_if_equals(&Self::get_player_key(player_id), &raw).await;
+        self.redis.del(&Self::get_party_key(session_id)).await;
+    }
+
+    pub async fn set_party(&self, player_id: &str, party_name: Option<String>) {
+        let presence = match self.get_raw(player_id).await {
+            Some((_, presence)) => presence,
+            None => return
+        };
+        let party_key = Self::get_party_key(&presence.session_id);
+        match party_name {
+            Some(party_name) => {
+                let party = PartyPresence { party_name, updated_at: get_u64_time_millis() };
+                self.redis.set_with_expiry(&party_key, &party, Some(Self::TTL_MS)).await;
+            },
+            None => self.redis.del(&party_key).await
+        };
+    }
+
+    async fn get_raw(&self, player_id: &str) -> Option<(String, PlayerPresence)> {
+        let raw = self.redis.store.get(&Self::get_player_key(player_id)).await.ok()??;
+        let presence = serde_json::from_str::<PlayerPresence>(&raw).ok()?;
+        Some((raw, presence))
+    }
+
+    pub async fn get(&self, player_id: &str) -> Option<PlayerPresence> {
+        let (_, mut presence) = self.get_raw(player_id).await?;
+        if let Some(party) = self.redis.get_unchecked::<PartyPresence>(&Self::get_party_key(&presence.session_id)).await {
+            presence.party_name = Some(party.party_name);
+            presence.updated_at = party.updated_at;
+        };
+        Some(presence)
+    }
+
+    pub async fn get_all(&self) -> Vec<PlayerPresence> {
+        let keys = self.redis.store.keys_with_prefix(Self::PLAYER_PREFIX).await.unwrap_or_default();
+        let lookups : Vec<_> = keys.iter().map(|key| self.get(&key[Self::PLAYER_PREFIX.len()..])).collect();
+        join_all(lookups).await.into_iter().flatten().collect()
+    }
+
+    pub async fn get_server_players(&self, server_id: &str) -> Vec<PlayerPresence> {
+        self.get_all().await.into_iter()
+            .filter(|presence| presence.server_id.eq_ignore_ascii_case(server_id))
+            .collect()
+    }
+
+    // players who have since moved to another server keep their presence
+    pub async fn clear_server(&self, server_id: &str) {
+        let mut cleared = 0;
+        let keys = self.redis.store.keys_with_prefix(Self::PLAYER_PREFIX).await.unwrap_or_default();
+        for key in keys.iter() {
+            let (raw, presence) = match self.get_raw(&key[Self::PLAYER_PREFIX.len()..]).await {
+                Some(read) => read,
+                None => continue
+            };
+            if !presence.server_id.eq_ignore_ascii_case(server_id) {
+                continue;
+            };
+            let _ = self.redis.store.del_if_equals(key, &raw).await;
+            self.redis.del(&Self::get_party_key(&presence.session_id)).await;
+            cleared += 1;
+        }
+        if cleared > 0 {
+            info!("Cleared presence of {} player(s) on '{}'", cleared, server_id);
+        };
+    }
+
+    // keeps the presence of players on the given server from expiring
+    pub async fn refresh_server(&self, server_id: &str) {
+        for presence in self.get_server_players(server_id).await {
+            let _ = self.redis.store.pexpire(&Self::get_player_key(&presence.player.id), Self::TTL_MS).await;
+            let _ = self.redis.store.pexpire(&Self::get_party_key(&presence.session_id), Self::TTL_MS).await;
+        }
+    }
+
+    // players on servers connected to this instance stay online, the rest expire once their instance stops refreshing them
+    pub fn spawn_heartbeat(presence: Arc<PresenceTracker>, servers: Arc<ServerRegistry>) {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Self::HEARTBEAT).await;
+                for server_id in servers.get_local_server_ids().await {
+                    presence.refresh_server(&server_id).await;
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,203 @@
+use std::{collections::HashMap, fs::File, io::{BufWriter, Write}, path::{Path, PathBuf}};
+
+use chrono::{TimeZone, Utc};
+use flate2::{write::GzEncoder, Compression};
+use futures::StreamExt;
+use mongodb::{bson::{doc, Bson, Document}, options::{FindOptions, UpdateOptions}};
+use rocket::serde::json::serde_json;
+use uuid::Uuid;
+
+use crate::config::MarsConfigOptions;
+
+use super::{CollectionOwner, Database, models::{chat_message::ChatMessage, death::Death, session::Session}};
+
+const ARCHIVE_BATCH_SIZE: i64 = 1000;
+// a claim left by a run that died is taken over after this long
+const CLAIM_TIMEOUT_MS: u64 = 3_600_000;
+const CLAIMED_BY_FIELD: &str = "archivingBy";
+const CLAIMED_AT_FIELD: &str = "archivingAt";
+
+// documents of a collection whose timestamp is older than the given number of days are archived and deleted
+pub struct RetentionPolicy {
+    pub collection: &'static str,
+    pub timestamp_field: &'static str,
+    pub days: u32
+}
+
+impl RetentionPolicy {
+    // collections set to 0 days are kept forever
+    pub fn from_options(options: &MarsConfigOptions) -> Vec<RetentionPolicy> {
+        vec![
+            RetentionPolicy { collection: ChatMessage::get_collection_name(), timestamp_field: "createdAt", days: options.chat_retention_days },
+            RetentionPolicy { collection: Death::get_collection_name(), timestamp_field: "createdAt", days: options.death_retention_days },
+            // sessions still in progress have no endedAt, so they never expire
+            RetentionPolicy { collection: Session::get_collection_name(), timestamp_field: "endedAt", days: options.session_retention_days }
+        ].into_iter().filter(|policy| policy.days > 0).collect()
+    }
+
+    pub fn get_cutoff(&self, now: u64) -> u64 {
+        now.saturating_sub(u64::from(self.days) * 86_400_000)
+    }
+}
+
+#[derive(Default)]
+pub struct ArchiveSummary {
+    pub archived: u64,
+    pub path: Option<PathBuf>
+}
+
+#[derive(Default)]
+struct DayAggregate {
+    count: u64,
+    counters: HashMap<String, f64>
+}
+
+// moves expired documents to <directory>/<collection>-<now>.ndjson.gz, oldest first
+// each batch is claimed by this run first, so instances running at the same time never archive or count a document twice
+// a batch is only deleted once it's flushed to the archive, and its aggregates are counted once it's deleted
+pub async fn archive_expired(database: &Database, policy: &RetentionPolicy, directory: &Path, now: u64) -> anyhow::Result<ArchiveSummary> {
+    let mut summary = ArchiveSummary::default();
+    let mut encoder : Option<GzEncoder<BufWriter<File>>> = None;
+    let run_id = Uuid::new_v4().to_string();
+    let claimable = doc! {
+        policy.timestamp_field: { "$lt": policy.get_cutoff(now) as i64 },
+        "$or": [
+            { CLAIMED_BY_FIELD: null },
+            { CLAIMED_AT_FIELD: { "$lt": now.saturating_sub(CLAIM_TIMEOUT_MS) as i64 } }
+        ]
+    };
+    let archived : anyhow::Result<()> = async {
+        loop {
+            let options = FindOptions::builder().sort(doc! { policy.timestamp_field: 1 }).limit(ARCHIVE_BATCH_SIZE).projection(doc! { "_id": 1 }).build();
+            let candidates : Vec<Bson> = database.store.find(policy.collection, claimable.clone(), options).await?
+                .filter_map(|result| async move { result.ok().and_then(|document| document.get("_id").cloned()) })
+                .collect().await;
+            if candidates.is_empty() {
+                break;
+            };
+            let mut claim = claimable.clone();
+            claim.insert("_id", doc! { "$in": candidates });
+            database.store.update_many(policy.collection, claim, doc! { "$set": { CLAIMED_BY_FIELD: &run_id, CLAIMED_AT_FIELD: now as i64 } }, false).await?;
+
+            // whatever another run claimed in between is left to it
+            let options = FindOptions::builder().sort(doc! { policy.timestamp_field: 1 }).build();
+            let mut batch : Vec<Document> = database.store.find(policy.collection, doc! { CLAIMED_BY_FIELD: &run_id }, options).await?
+                .filter_map(|result| async move { result.ok() })
+                .map(|mut document| {
+                    document.remove(CLAIMED_BY_FIELD);
+                    document.remove(CLAIMED_AT_FIELD);
+                    document
+                })
+                .collect().await;
+            if batch.is_empty() {
+                continue;
+            };
+
+            if encoder.is_none() {
+                std::fs::create_dir_all(directory)?;
+                let path = directory.join(format!("{}-{}.ndjson.gz", policy.collection, now));
+                encoder = Some(GzEncoder::new(BufWriter::new(File::create(&path)?), Compression::default()));
+                summary.path = Some(path);
+            };
+            let writer = encoder.as_mut().unwrap();
+            for document in batch.iter() {
+                serde_json::to_writer(&mut *writer, &Bson::Document(document.clone()).into_relaxed_extjson())?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+
+            let deleted = database.store.delete_many(policy.collection, doc! { CLAIMED_BY_FIELD: &run_id }).await?;
+            summary.archived += deleted.deleted_count;
+            if deleted.deleted_count < batch.len() as u64 {
+                // a claim that timed out was taken over before the delete, the run holding it now counts those
+                let ids : Vec<Bson> = batch.iter().filter_map(|document| document.get("_id").cloned()).collect();
+                let options = FindOptions::builder().projection(doc! { "_id": 1 }).build();
+                let remaining : Vec<Bson> = database.store.find(policy.collection, doc! { "_id": { "$in": ids } }, options).await?
+                    .filter_map(|result| async move { result.ok().and_then(|document| document.get("_id").cloned()) })
+                    .collect().await;
+                batch.retain(|document| document.get("_id").is_none_or(|id| !remaining.contains(id)));
+            };
+            add_aggregates(database, policy, &batch).await;
+        }
+        Ok(())
+    }.await;
+    // the archive is closed even when a batch failed, so what was already deleted stays readable
+    let finished = match encoder {
+        Some(encoder) => encoder.finish().and_then(|mut writer| writer.flush()),
+        None => Ok(())
+    };
+    archived?;
+    finished?;
+    Ok(summary)
+}
+
+pub async fn apply_retention(database: &Database, policies: &[RetentionPolicy], directory: &Path, now: u64) {
+    for policy in policies {
+        match archive_expired(database, policy, directory, now).await {
+            Ok(ArchiveSummary { archived: 0, .. }) => {},
+            Ok(summary) => info!(
+                "Archived {} {} document(s) older than {} day(s) to {}",
+                summary.archived, policy.collection, policy.days, summary.path.map(|path| path.display().to_string()).unwrap_or_default()
+            ),
+            Err(e) => warn!("Could not archive expired {} documents: {}", policy.collection, e)
+        };
+    }
+}
+
+async fn add_aggregates(database: &Database, policy: &RetentionPolicy, batch: &[Document]) {
+    let mut by_day : HashMap<String, DayAggregate> = HashMap::new();
+    for document in batch {
+        let timestamp = match get_millis(document, policy.timestamp_field) {
+            Some(timestamp) => timestamp,
+            None => continue
+        };
+        let day = match Utc.timestamp_millis_opt(timestamp as i64).single() {
+            Some(time) => time.format("%Y-%m-%d").to_string(),
+            None => continue
+        };
+        let aggregate = by_day.entry(day).or_default();
+        aggregate.count += 1;
+        for (counter, value) in get_counters(policy.collection, document) {
+            *aggregate.counters.entry(counter).or_insert(0.0) += value;
+        }
+    }
+    for (day, aggregate) in by_day {
+        let mut increments = doc! { "count": aggregate.count as i64 };
+        for (counter, value) in aggregate.counters {
+            increments.insert(format!("counters.{}", counter), value);
+        }
+        let id = format!("{}:{}", policy.collection, day);
+        let update = doc! { "$inc": increments, "$setOnInsert": { "collection": policy.collection, "day": &day } };
+        let options = UpdateOptions::builder().upsert(true).build();
+        if let Err(e) = database.archive_aggregates.update_one(doc! { "_id": &id }, update, options).await {
+            warn!("Could not count archived documents into '{}': {}", id, e);
+        };
+    }
+}
+
+// what's worth keeping about a document once it's archived, besides being counted
+fn get_counters(collection: &str, document: &Document) -> Vec<(String, f64)> {
+    let mut counters = Vec::new();
+    if collection == Death::get_collection_name() {
+        if let Ok(cause) = document.get_str("cause") {
+            counters.push((format!("cause:{}", cause), 1.0));
+        };
+        if matches!(document.get("attacker"), Some(Bson::Document(_))) {
+            counters.push((String::from("kills"), 1.0));
+        };
+    } else if collection == Session::get_collection_name() {
+        if let (Some(created_at), Some(ended_at)) = (get_millis(document, "createdAt"), get_millis(document, "endedAt")) {
+            counters.push((String::from("playtime"), ended_at.saturating_sub(created_at) as f64));
+        };
+    };
+    counters
+}
+
+fn get_millis(document: &Document, field: &str) -> Option<u64> {
+    match document.get(field)? {
+        Bson::Int32(value) => u64::try_from(*value).ok(),
+        Bson::Int64(value) => u64::try_from(*value).ok(),
+        Bson::Double(value) if *value >= 0.0 => Some(*value as u64),
+        _ => None
+    }
+}
@@ -0,0 +1,201 @@
+use std::{collections::{BTreeMap, BTreeSet}, sync::Arc};
+
+use mongodb::bson::{self, doc, Bson, Document};
+use rocket::serde::json::{serde_json, Value};
+
+use super::models::player::Player;
+
+// every stat of a player keyed by its document path, e.g. "stats.kills" or "gamemodeStats.CAPTURE_THE_WOOL.weaponKills.BOW"
+// numbers are counters, records and achievements are kept whole
+pub type FlatStats = BTreeMap<String, Value>;
+
+// which way a record improves, and the field it's compared on
+const RECORD_ORDERS: [(&str, &str, bool); 7] = [
+    ("longestSession", "length", true),
+    ("longestProjectileKill", "distance", true),
+    ("fastestWoolCapture", "value", false),
+    ("fastestFlagCapture", "value", false),
+    ("fastestFirstBlood", "time", false),
+    ("killsInMatch", "value", true),
+    ("deathsInMatch", "value", true)
+];
+
+// the change between two copies of a player's stats, expressed so that concurrent writers can't undo each other
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct StatDelta {
+    pub increments: BTreeMap<String, f64>,
+    // records only replace a worse one, achievements only a missing one
+    pub replacements: BTreeMap<String, Value>,
+    pub removals: BTreeSet<String>
+}
+
+impl StatDelta {
+    pub fn between(before: &FlatStats, after: &FlatStats) -> Self {
+        let mut delta = StatDelta::default();
+        for (path, value) in after.iter() {
+            match (before.get(path), value) {
+                (previous, Value::Number(number)) => {
+                    let previous = previous.and_then(Value::as_f64).unwrap_or(0.0);
+                    let difference = number.as_f64().unwrap_or(0.0) - previous;
+                    if difference != 0.0 {
+                        delta.increments.insert(path.clone(), difference);
+                    };
+                },
+                (Some(previous), value) if previous == value => {},
+                (_, value) => { delta.replacements.insert(path.clone(), value.clone()); }
+            };
+        }
+        for (path, value) in before.iter() {
+            if !after.contains_key(path) && !value.is_number() {
+                delta.removals.insert(path.clone());
+            };
+        }
+        delta
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.increments.is_empty() && self.replacements.is_empty() && self.removals.is_empty()
+    }
+
+    // folds a later delta of the same player into this one
+    pub fn merge(&mut self, later: StatDelta) {
+        for (path, amount) in later.increments {
+            *self.increments.entry(path).or_insert(0.0) += amount;
+        }
+        self.increments.retain(|_, amount| *amount != 0.0);
+        for path in later.removals.iter() {
+            self.replacements.remove(path);
+        }
+        for (path, value) in later.replacements {
+            self.removals.remove(&path);
+            let keeps_current = self.replacements.get(&path).is_some_and(|current| !Self::is_better_record(&path, current, &value));
+            if !keeps_current {
+                self.replacements.insert(path, value);
+            };
+        }
+        self.removals.extend(later.removals);
+    }
+
+    // $inc for counters, $set/$unset for achievements
+    pub fn get_update(&self) -> Option<Document> {
+        let mut update = Document::new();
+        if !self.increments.is_empty() {
+            update.insert("$inc", self.increments.iter().map(|(path, amount)| (path.clone(), to_bson_number(*amount))).collect::<Document>());
+        };
+        let achievements : Document = self.replacements.iter()
+            .filter(|(path, _)| get_record_order(path).is_none())
+            .filter_map(|(path, value)| bson::to_bson(value).ok().map(|value| (path.clone(), value)))
+            .collect();
+        if !achievements.is_empty() {
+            update.insert("$set", achievements);
+        };
+        if !self.removals.is_empty() {
+            update.insert("$unset", self.removals.iter().map(|path| (path.clone(), Bson::String(String::new()))).collect::<Document>());
+        };
+        if update.is_empty() { None } else { Some(update) }
+    }
+
+    // each record is set by its own update, which only matches while the stored record is missing or worse
+    pub fn get_record_updates(&self, player_id: &str) -> Vec<(Document, Document)> {
+        self.replacements.iter().filter_map(|(path, value)| {
+            let (field, higher_is_better) = get_record_order(path)?;
+            let compared = bson::to_bson(value.get(field)?).ok()?;
+            let condition = if higher_is_better { "$lt" } else { "$gt" };
+            let filter = doc! {
+                "_id": player_id,
+                "$or": [{ path.as_str(): null }, { format!("{}.{}", path, field): { condition: compared } }]
+            };
+            Some((filter, doc! { "$set": { path.as_str(): bson::to_bson(value).ok()? } }))
+        }).collect()
+    }
+
+    // whether a record should replace the one currently held
+    pub fn is_better_record(path: &str, current: &Value, candidate: &Value) -> bool {
+        let (field, higher_is_better) = match get_record_order(path) {
+            Some(order) => order,
+            None => return true
+        };
+        match (current.get(field).and_then(Value::as_f64), candidate.get(field).and_then(Value::as_f64)) {
+            (Some(current), Some(candidate)) => if higher_is_better { candidate > current } else { candidate < current },
+            _ => true
+        }
+    }
+}
+
+// the field records of the given path are compared by, and whether a higher value is better
+pub fn get_record_order(path: &str) -> Option<(&'static str, bool)> {
+    let (parent, name) = path.rsplit_once('.')?;
+    if !parent.ends_with(".records") {
+        return None;
+    };
+    RECORD_ORDERS.iter().find(|(record, _, _)| *record == name).map(|(_, field, higher_is_better)| (*field, *higher_is_better))
+}
+
+fn to_bson_number(amount: f64) -> Bson {
+    // whole amounts stay integers so u32 fields still deserialize
+    if amount.fract() == 0.0 && amount.abs() < 9.0e15 { Bson::Int64(amount as i64) } else { Bson::Double(amount) }
+}
+
+pub fn flatten_stats(player: &Player) -> FlatStats {
+    let mut flat = FlatStats::new();
+    if let Ok(stats) = serde_json::to_value(&player.stats) {
+        flatten_into(&mut flat, "stats", stats);
+    };
+    if let Ok(gamemode_stats) = serde_json::to_value(&player.gamemode_stats) {
+        flatten_into(&mut flat, "gamemodeStats", gamemode_stats);
+    };
+    flat
+}
+
+fn flatten_into(flat: &mut FlatStats, path: &str, value: Value) {
+    let is_opaque_parent = path.ends_with(".records") || path.ends_with(".achievements");
+    match value {
+        Value::Null => {},
+        Value::Object(fields) if !is_opaque_parent => {
+            for (key, value) in fields.into_iter() {
+                flatten_into(flat, &format!("{}.{}", path, key), value);
+            }
+        },
+        Value::Object(fields) => {
+            for (key, value) in fields.into_iter().filter(|(_, value)| !value.is_null()) {
+                flat.insert(format!("{}.{}", path, key), value);
+            }
+        },
+        value => { flat.insert(path.to_owned(), value); }
+    };
+}
+
+// writes flattened stats over a player, e.g. the counters held in the cache
+pub fn overlay_stats(player: &mut Player, flat: &FlatStats) {
+    let mut root = serde_json::json!({
+        "stats": serde_json::to_value(&player.stats).unwrap_or(Value::Null),
+        "gamemodeStats": serde_json::to_value(&player.gamemode_stats).unwrap_or(Value::Null)
+    });
+    for (path, value) in flat.iter() {
+        let mut segments = path.split('.').peekable();
+        let mut current = &mut root;
+        while let Some(segment) = segments.next() {
+            if !current.is_object() {
+                *current = Value::Object(Default::default());
+            };
+            let fields = current.as_object_mut().unwrap();
+            if segments.peek().is_none() {
+                fields.insert(segment.to_owned(), value.clone());
+                break;
+            };
+            current = fields.entry(segment.to_owned()).or_insert_with(|| Value::Object(Default::default()));
+        }
+    }
+    match (serde_json::from_value(root["stats"].take()), serde_json::from_value(root["gamemodeStats"].take())) {
+        (Ok(stats), Ok(gamemode_stats)) => {
+            player.stats = stats;
+            player.gamemode_stats = gamemode_stats;
+        },
+        _ => warn!("Could not apply cached stats to {}", player.id_name())
+    };
+}
+
+// remembers the stats a player was read with, so a later write only applies what changed
+pub fn mark_loaded(player: &mut Player) {
+    player.loaded_stats = Some(Arc::new(flatten_stats(player)));
+}
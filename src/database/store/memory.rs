@@ -0,0 +1,657 @@
+use std::{collections::{HashMap, HashSet, VecDeque}, sync::Mutex, time::{Duration, Instant}};
+
+use anyhow::anyhow;
+use futures::{stream::{self, BoxStream}, StreamExt};
+use mongodb::{bson::{doc, oid::ObjectId, Bson, Document}, options::{FindOneOptions, FindOptions}};
+use rocket::serde::json::serde_json;
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use super::{query, BatchUpdate, CacheIndex, CacheStore, Cursor, DeleteResult, DocumentStore, IndexDefinition, UpdateResult};
+
+// documents kept per collection in insertion order, with _id and unique indexes kept in hash maps
+#[derive(Default)]
+pub struct MemoryDocumentStore {
+    collections: Mutex<HashMap<String, MemoryCollection>>
+}
+
+#[derive(Default)]
+struct MemoryCollection {
+    documents: Vec<Document>,
+    // _id key -> position in documents
+    positions: HashMap<String, usize>,
+    // only unique indexes do anything here
+    indexes: Vec<IndexDefinition>,
+    // unique index name -> value key -> _id key of the document holding it
+    unique_values: HashMap<String, HashMap<String, String>>
+}
+
+impl MemoryCollection {
+    // filters on a plain _id value are answered from the _id map
+    fn matching_positions(&self, filter: &Document, limit: usize) -> Vec<usize> {
+        if let (1, Some(id)) = (filter.len(), filter.get("_id")) {
+            if !matches!(id, Bson::Document(_)) {
+                return self.positions.get(&get_key(id)).copied()
+                    .filter(|position| query::matches(&self.documents[*position], filter))
+                    .into_iter().collect();
+            };
+        };
+        self.documents.iter().enumerate()
+            .filter(|(_, document)| query::matches(document, filter))
+            .map(|(position, _)| position)
+            .take(limit)
+            .collect()
+    }
+
+    // mirrors mongo's duplicate key error, the document being replaced may keep its own values
+    fn check_unique(&self, collection: &str, candidate: &Document, id_key: &str) -> anyhow::Result<()> {
+        for index in self.indexes.iter().filter(|index| index.unique) {
+            let holder = self.unique_values.get(&index.get_name()).and_then(|values| values.get(&get_index_key(candidate, index)));
+            if holder.is_some_and(|holder| holder != id_key) {
+                return Err(anyhow!("E11000 duplicate key error collection: {} index: {}", collection, index.get_name()));
+            };
+        }
+        Ok(())
+    }
+
+    fn add_keys(&mut self, document: &Document, position: usize) {
+        let id_key = get_id_key(document);
+        for index in self.indexes.iter().filter(|index| index.unique) {
+            self.unique_values.entry(index.get_name()).or_default().insert(get_index_key(document, index), id_key.clone());
+        }
+        self.positions.insert(id_key, position);
+    }
+
+    fn remove_keys(&mut self, document: &Document) {
+        let id_key = get_id_key(document);
+        for index in self.indexes.iter().filter(|index| index.unique) {
+            if let Some(values) = self.unique_values.get_mut(&index.get_name()) {
+                let value_key = get_index_key(document, index);
+                if values.get(&value_key) == Some(&id_key) {
+                    values.remove(&value_key);
+                };
+            };
+        }
+        self.positions.remove(&id_key);
+    }
+
+    // positions shift once documents are removed
+    fn rebuild_keys(&mut self) {
+        self.positions.clear();
+        self.unique_values.clear();
+        for position in 0..self.documents.len() {
+            let document = self.documents[position].clone();
+            self.add_keys(&document, position);
+        }
+    }
+
+    fn insert(&mut self, collection: &str, document: Document) -> anyhow::Result<()> {
+        let id_key = get_id_key(&document);
+        if self.positions.contains_key(&id_key) {
+            return Err(anyhow!("E11000 duplicate key error collection: {} index: _id_", collection));
+        };
+        self.check_unique(collection, &document, &id_key)?;
+        self.add_keys(&document, self.documents.len());
+        self.documents.push(document);
+        Ok(())
+    }
+}
+
+fn get_key(value: &Bson) -> String {
+    value.clone().into_canonical_extjson().to_string()
+}
+
+fn get_id_key(document: &Document) -> String {
+    get_key(document.get("_id").unwrap_or(&Bson::Null))
+}
+
+// missing fields count as null, like in mongo
+fn get_index_key(document: &Document, index: &IndexDefinition) -> String {
+    get_key(&Bson::Array(index.keys.keys().map(|field| query::get_path(document, field).cloned().unwrap_or(Bson::Null)).collect()))
+}
+
+impl MemoryDocumentStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn find_matching(&self, collection: &str, filter: &Document, sort: Option<&Document>, skip: u64, limit: Option<i64>) -> anyhow::Result<Vec<Document>> {
+        query::validate_filter(filter)?;
+        let collections = self.collections.lock().unwrap();
+        let mut found : Vec<Document> = collections.get(collection)
+            .map(|stored| stored.matching_positions(filter, usize::MAX).into_iter().map(|position| stored.documents[position].clone()).collect())
+            .unwrap_or_default();
+        if let Some(sort) = sort {
+            found.sort_by(|a, b| query::compare_by_sort(a, b, sort));
+        };
+        let found = found.into_iter().skip(skip as usize);
+        Ok(match limit {
+            Some(limit) if limit != 0 => found.take(limit.unsigned_abs() as usize).collect(),
+            _ => found.collect()
+        })
+    }
+
+    fn update(&self, collection: &str, filter: &Document, update: &Document, upsert: bool, multi: bool) -> anyhow::Result<UpdateResult> {
+        query::validate_filter(filter)?;
+        let mut collections = self.collections.lock().unwrap();
+        let stored = collections.entry(collection.to_owned()).or_default();
+        let mut result = UpdateResult::default();
+        let positions = stored.matching_positions(filter, if multi { usize::MAX } else { 1 });
+        for position in positions {
+            let mut updated = stored.documents[position].clone();
+            query::apply_update(&mut updated, update, false)?;
+            result.matched_count += 1;
+            if updated != stored.documents[position] {
+                let id_key = get_id_key(&stored.documents[position]);
+                if get_id_key(&updated) != id_key {
+                    return Err(anyhow!("Performing an update on the path '_id' would modify the immutable field '_id'"));
+                };
+                stored.check_unique(collection, &updated, &id_key)?;
+                let previous = std::mem::replace(&mut stored.documents[position], updated.clone());
+                stored.remove_keys(&previous);
+                stored.add_keys(&updated, position);
+                result.modified_count += 1;
+            };
+        };
+        if result.matched_count == 0 && upsert {
+            let mut document = query::upsert_base(filter);
+            query::apply_update(&mut document, update, true)?;
+            if !document.contains_key("_id") {
+                document.insert("_id", ObjectId::new());
+            };
+            // mongo's unique _id index fails an upsert whose filter missed an existing document
+            result.upserted_id = document.get("_id").cloned();
+            stored.insert(collection, document)?;
+        };
+        Ok(result)
+    }
+
+    fn delete(&self, collection: &str, filter: &Document, multi: bool) -> anyhow::Result<DeleteResult> {
+        query::validate_filter(filter)?;
+        let mut collections = self.collections.lock().unwrap();
+        let stored = match collections.get_mut(collection) {
+            Some(stored) => stored,
+            None => return Ok(DeleteResult::default())
+        };
+        let positions = stored.matching_positions(filter, if multi { usize::MAX } else { 1 });
+        if positions.is_empty() {
+            return Ok(DeleteResult::default());
+        };
+        let removed : HashSet<usize> = positions.iter().copied().collect();
+        let mut position = 0;
+        stored.documents.retain(|_| {
+            let keep = !removed.contains(&position);
+            position += 1;
+            keep
+        });
+        stored.rebuild_keys();
+        Ok(DeleteResult { deleted_count: removed.len() as u64 })
+    }
+}
+
+#[async_trait]
+impl DocumentStore for MemoryDocumentStore {
+    async fn ping(&self) -> bool {
+        true
+    }
+
+    async fn find(&self, collection: &str, filter: Document, options: FindOptions) -> anyhow::Result<Cursor<Document>> {
+        let found = self.find_matching(collection, &filter, options.sort.as_ref(), options.skip.unwrap_or(0), options.limit)?;
+        Ok(stream::iter(found.into_iter().map(Ok)).boxed())
+    }
+
+    async fn find_one(&self, collection: &str, filter: Document, options: FindOneOptions) -> anyhow::Result<Option<Document>> {
+        Ok(self.find_matching(collection, &filter, options.sort.as_ref(), options.skip.unwrap_or(0), Some(1))?.into_iter().next())
+    }
+
+    async fn insert_one(&self, collection: &str, mut document: Document) -> anyhow::Result<()> {
+        let mut collections = self.collections.lock().unwrap();
+        if !document.contains_key("_id") {
+            document.insert("_id", ObjectId::new());
+        };
+        collections.entry(collection.to_owned()).or_default().insert(collection, document)
+    }
+
+    async fn update_one(&self, collection: &str, filter: Document, update: Document, upsert: bool) -> anyhow::Result<UpdateResult> {
+        self.update(collection, &filter, &update, upsert, false)
+    }
+
+    async fn update_many(&self, collection: &str, filter: Document, update: Document, upsert: bool) -> anyhow::Result<UpdateResult> {
+        self.update(collection, &filter, &update, upsert, true)
+    }
+
+    async fn replace_one(&self, collection: &str, filter: Document, replacement: Document, upsert: bool) -> anyhow::Result<UpdateResult> {
+        if replacement.keys().any(|key| key.starts_with('$')) {
+            return Err(anyhow!("Replacement document must not contain update operators"));
+        };
+        self.update(collection, &filter, &replacement, upsert, false)
+    }
+
+    async fn update_batch(&self, collection: &str, updates: Vec<BatchUpdate>) -> anyhow::Result<Vec<(usize, String)>> {
+        Ok(updates.iter().enumerate()
+            .filter_map(|(index, update)| self.update(collection, &update.filter, &update.update, update.upsert, false).err().map(|e| (index, e.to_string())))
+            .collect())
+    }
+
+    async fn delete_one(&self, collection: &str, filter: Document) -> anyhow::Result<DeleteResult> {
+        self.delete(collection, &filter, false)
+    }
+
+    async fn delete_many(&self, collection: &str, filter: Document) -> anyhow::Result<DeleteResult> {
+        self.delete(collection, &filter, true)
+    }
+
+    async fn count_documents(&self, collection: &str, filter: Document) -> anyhow::Result<u64> {
+        query::validate_filter(&filter)?;
+        let collections = self.collections.lock().unwrap();
+        Ok(collections.get(collection)
+            .map(|stored| stored.matching_positions(&filter, usize::MAX).len() as u64)
+            .unwrap_or(0))
+    }
+
+    async fn list_indexes(&self, collection: &str) -> anyhow::Result<Vec<(String, IndexDefinition)>> {
+        let mut indexes = vec![(String::from("_id_"), IndexDefinition::new(doc! { "_id": 1 }).unique())];
+        if let Some(stored) = self.collections.lock().unwrap().get(collection) {
+            indexes.extend(stored.indexes.iter().map(|index| (index.get_name(), index.clone())));
+        };
+        Ok(indexes)
+    }
+
+    async fn create_index(&self, collection: &str, index: &IndexDefinition) -> anyhow::Result<()> {
+        let mut collections = self.collections.lock().unwrap();
+        let stored = collections.entry(collection.to_owned()).or_default();
+        if stored.indexes.iter().any(|existing| existing.get_name() == index.get_name()) {
+            return Ok(());
+        };
+        if index.unique {
+            let mut values = HashMap::new();
+            for document in stored.documents.iter() {
+                if values.insert(get_index_key(document, index), get_id_key(document)).is_some() {
+                    return Err(anyhow!("E11000 duplicate key error collection: {} index: {}", collection, index.get_name()));
+                };
+            }
+            stored.unique_values.insert(index.get_name(), values);
+        };
+        stored.indexes.push(index.clone());
+        Ok(())
+    }
+}
+
+enum CacheValue {
+    String(String),
+    Hash(HashMap<String, String>),
+    Set(HashSet<String>),
+    List(VecDeque<String>),
+    SortedSet(HashMap<String, f64>)
+}
+
+struct CacheEntry {
+    value: CacheValue,
+    expires_at: Option<Instant>
+}
+
+impl CacheEntry {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+fn drop_if_expired(entries: &mut HashMap<String, CacheEntry>, key: &str, now: Instant) {
+    if entries.get(key).is_some_and(|entry| entry.is_expired(now)) {
+        entries.remove(key);
+    };
+}
+
+struct Subscriber {
+    channels: Vec<String>,
+    sender: UnboundedSender<(String, String)>
+}
+
+// how often the whole keyspace is swept for expired keys nobody touches again
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+// redis-like keyspace in process, an expired key is dropped when it's next touched
+#[derive(Default)]
+pub struct MemoryCacheStore {
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    last_sweep: Mutex<Option<Instant>>,
+    subscribers: Mutex<Vec<Subscriber>>
+}
+
+macro_rules! typed_entry {
+    ($entries:expr, $key:expr, $variant:ident, $default:expr) => {
+        match &mut $entries.entry($key.to_owned()).or_insert_with(|| CacheEntry { value: CacheValue::$variant($default), expires_at: None }).value {
+            CacheValue::$variant(value) => value,
+            _ => return Err(anyhow!("WRONGTYPE Operation against a key holding the wrong kind of value"))
+        }
+    };
+}
+
+impl MemoryCacheStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the keyspace with the given key dropped if it has expired
+    fn live_entries(&self, key: &str) -> std::sync::MutexGuard<'_, HashMap<String, CacheEntry>> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        let mut last_sweep = self.last_sweep.lock().unwrap();
+        if last_sweep.is_none_or(|last_sweep| now.duration_since(last_sweep) >= SWEEP_INTERVAL) {
+            entries.retain(|_, entry| !entry.is_expired(now));
+            *last_sweep = Some(now);
+        } else {
+            drop_if_expired(&mut entries, key, now);
+        };
+        entries
+    }
+
+    fn sorted_desc(members: &HashMap<String, f64>) -> Vec<(String, f64)> {
+        let mut sorted : Vec<(String, f64)> = members.iter().map(|(member, score)| (member.clone(), *score)).collect();
+        sorted.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal).then_with(|| b.0.cmp(&a.0)));
+        sorted
+    }
+}
+
+#[async_trait]
+impl CacheStore for MemoryCacheStore {
+    async fn ping(&self) -> bool {
+        true
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        match self.live_entries(key).get(key) {
+            Some(CacheEntry { value: CacheValue::String(value), .. }) => Ok(Some(value.clone())),
+            Some(_) => Err(anyhow!("WRONGTYPE Operation against a key holding the wrong kind of value")),
+            None => Ok(None)
+        }
+    }
+
+    async fn set(&self, key: &str, value: &str, expiry_ms: Option<usize>) -> anyhow::Result<()> {
+        let expires_at = expiry_ms.map(|expiry_ms| Instant::now() + Duration::from_millis(expiry_ms as u64));
+        self.live_entries(key).insert(key.to_owned(), CacheEntry { value: CacheValue::String(value.to_owned()), expires_at });
+        Ok(())
+    }
+
+    async fn set_if_absent(&self, key: &str, value: &str, expiry_ms: usize) -> anyhow::Result<bool> {
+        let mut entries = self.live_entries(key);
+        if entries.contains_key(key) {
+            return Ok(false);
+        };
+        let expires_at = Some(Instant::now() + Duration::from_millis(expiry_ms as u64));
+        entries.insert(key.to_owned(), CacheEntry { value: CacheValue::String(value.to_owned()), expires_at });
+        Ok(true)
+    }
+
+    async fn del(&self, key: &str) -> anyhow::Result<()> {
+        self.live_entries(key).remove(key);
+        Ok(())
+    }
+
+    async fn del_if_equals(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        let mut entries = self.live_entries(key);
+        if matches!(entries.get(key), Some(CacheEntry { value: CacheValue::String(current), .. }) if current == value) {
+            entries.remove(key);
+        };
+        Ok(())
+    }
+
+    async fn pexpire(&self, key: &str, expiry_ms: usize) -> anyhow::Result<bool> {
+        Ok(match self.live_entries(key).get_mut(key) {
+            Some(entry) => {
+                entry.expires_at = Some(Instant::now() + Duration::from_millis(expiry_ms as u64));
+                true
+            },
+            None => false
+        })
+    }
+
+    async fn rename(&self, key: &str, new_key: &str) -> anyhow::Result<()> {
+        let mut entries = self.live_entries(key);
+        let entry = entries.remove(key).ok_or_else(|| anyhow!("no such key '{}'", key))?;
+        entries.insert(new_key.to_owned(), entry);
+        Ok(())
+    }
+
+    async fn set_if_version(&self, key: &str, value: &str, expected_version: u64, expiry_ms: Option<usize>) -> anyhow::Result<bool> {
+        let mut entries = self.live_entries(key);
+        let current_version = match entries.get(key) {
+            Some(CacheEntry { value: CacheValue::String(current), .. }) => serde_json::from_str::<serde_json::Value>(current).ok()
+                .and_then(|current| current.get("version").and_then(|version| version.as_u64()))
+                .unwrap_or(0),
+            Some(_) => return Err(anyhow!("WRONGTYPE Operation against a key holding the wrong kind of value")),
+            None => 0
+        };
+        if current_version > expected_version {
+            return Ok(false);
+        };
+        let expires_at = expiry_ms.map(|expiry_ms| Instant::now() + Duration::from_millis(expiry_ms as u64));
+        entries.insert(key.to_owned(), CacheEntry { value: CacheValue::String(value.to_owned()), expires_at });
+        Ok(true)
+    }
+
+    async fn set_indexed(&self, key: &str, value: &str, expected_version: Option<u64>, expiry_ms: Option<usize>, index: &CacheIndex<'_>) -> anyhow::Result<bool> {
+        let mut entries = self.live_entries(key);
+        let current = match entries.get(key) {
+            Some(CacheEntry { value: CacheValue::String(current), .. }) => serde_json::from_str::<serde_json::Value>(current).ok(),
+            Some(_) => return Err(anyhow!("WRONGTYPE Operation against a key holding the wrong kind of value")),
+            None => None
+        };
+        let current_version = current.as_ref().and_then(|current| current.get("version").and_then(|version| version.as_u64())).unwrap_or(0);
+        if expected_version.is_some_and(|expected_version| current_version > expected_version) {
+            return Ok(false);
+        };
+        let previous = current.as_ref().and_then(|current| current.get(index.field).and_then(|field| field.as_str().map(str::to_owned)));
+        let next = serde_json::from_str::<serde_json::Value>(value).ok().and_then(|next| next.get(index.field).and_then(|field| field.as_str().map(str::to_owned)));
+        let expires_at = expiry_ms.map(|expiry_ms| Instant::now() + Duration::from_millis(expiry_ms as u64));
+        entries.insert(key.to_owned(), CacheEntry { value: CacheValue::String(value.to_owned()), expires_at });
+        let now = Instant::now();
+        if let Some(next) = &next {
+            let index_key = format!("{}{}", index.prefix, next);
+            drop_if_expired(&mut entries, &index_key, now);
+            // whoever held the field value before is evicted, their cached copy still claims it
+            if let Some(CacheEntry { value: CacheValue::String(holder), .. }) = entries.get(&index_key) {
+                if holder != index.target {
+                    let holder_key = format!("{}{}", index.target_prefix, holder);
+                    entries.remove(&holder_key);
+                };
+            };
+            entries.insert(index_key, CacheEntry { value: CacheValue::String(index.target.to_owned()), expires_at });
+        };
+        if let Some(previous) = previous.filter(|previous| Some(previous) != next.as_ref()) {
+            let index_key = format!("{}{}", index.prefix, previous);
+            drop_if_expired(&mut entries, &index_key, now);
+            if matches!(entries.get(&index_key), Some(CacheEntry { value: CacheValue::String(holder), .. }) if holder == index.target) {
+                entries.remove(&index_key);
+            };
+        };
+        Ok(true)
+    }
+
+    async fn hset(&self, key: &str, field: &str, value: &str) -> anyhow::Result<()> {
+        let mut entries = self.live_entries(key);
+        typed_entry!(entries, key, Hash, HashMap::new()).insert(field.to_owned(), value.to_owned());
+        Ok(())
+    }
+
+    async fn hget(&self, key: &str, field: &str) -> anyhow::Result<Option<String>> {
+        match self.live_entries(key).get(key) {
+            Some(CacheEntry { value: CacheValue::Hash(hash), .. }) => Ok(hash.get(field).cloned()),
+            Some(_) => Err(anyhow!("WRONGTYPE Operation against a key holding the wrong kind of value")),
+            None => Ok(None)
+        }
+    }
+
+    async fn hdel(&self, key: &str, field: &str) -> anyhow::Result<()> {
+        let mut entries = self.live_entries(key);
+        if let Some(CacheEntry { value: CacheValue::Hash(hash), .. }) = entries.get_mut(key) {
+            hash.remove(field);
+            if hash.is_empty() {
+                entries.remove(key);
+            };
+        };
+        Ok(())
+    }
+
+    async fn hvals(&self, key: &str) -> anyhow::Result<Vec<String>> {
+        match self.live_entries(key).get(key) {
+            Some(CacheEntry { value: CacheValue::Hash(hash), .. }) => Ok(hash.values().cloned().collect()),
+            _ => Ok(Vec::new())
+        }
+    }
+
+    async fn hgetall(&self, key: &str) -> anyhow::Result<HashMap<String, String>> {
+        match self.live_entries(key).get(key) {
+            Some(CacheEntry { value: CacheValue::Hash(hash), .. }) => Ok(hash.clone()),
+            Some(_) => Err(anyhow!("WRONGTYPE Operation against a key holding the wrong kind of value")),
+            None => Ok(HashMap::new())
+        }
+    }
+
+    async fn hset_all(&self, key: &str, fields: &[(String, String)]) -> anyhow::Result<()> {
+        if fields.is_empty() {
+            return Ok(());
+        };
+        let mut entries = self.live_entries(key);
+        typed_entry!(entries, key, Hash, HashMap::new()).extend(fields.iter().cloned());
+        Ok(())
+    }
+
+    async fn replace_hash(&self, key: &str, fields: &[(String, String)], expiry_ms: usize) -> anyhow::Result<()> {
+        let mut entries = self.live_entries(key);
+        entries.remove(key);
+        if !fields.is_empty() {
+            let expires_at = if expiry_ms > 0 { Some(Instant::now() + Duration::from_millis(expiry_ms as u64)) } else { None };
+            entries.insert(key.to_owned(), CacheEntry { value: CacheValue::Hash(fields.iter().cloned().collect()), expires_at });
+        };
+        Ok(())
+    }
+
+    async fn hset_if_better(&self, key: &str, field: &str, value: &str, order_field: &str, higher_is_better: bool) -> anyhow::Result<bool> {
+        let mut entries = self.live_entries(key);
+        let hash = typed_entry!(entries, key, Hash, HashMap::new());
+        let get_order = |raw: &str| serde_json::from_str::<serde_json::Value>(raw).ok().and_then(|value| value.get(order_field).and_then(|order| order.as_f64()));
+        if let (Some(current), Some(candidate)) = (hash.get(field).and_then(|current| get_order(current)), get_order(value)) {
+            if (higher_is_better && candidate <= current) || (!higher_is_better && candidate >= current) {
+                return Ok(false);
+            };
+        };
+        hash.insert(field.to_owned(), value.to_owned());
+        Ok(true)
+    }
+
+    async fn hincrbyfloat(&self, key: &str, field: &str, increment: f64) -> anyhow::Result<f64> {
+        let mut entries = self.live_entries(key);
+        let hash = typed_entry!(entries, key, Hash, HashMap::new());
+        let current = match hash.get(field) {
+            Some(raw) => raw.parse::<f64>().map_err(|_| anyhow!("ERR hash value is not a float"))?,
+            None => 0.0
+        };
+        let value = current + increment;
+        // redis prints whole results without a fraction
+        hash.insert(field.to_owned(), if value.fract() == 0.0 { format!("{}", value as i64) } else { value.to_string() });
+        Ok(value)
+    }
+
+    async fn sadd(&self, key: &str, member: &str) -> anyhow::Result<()> {
+        let mut entries = self.live_entries(key);
+        typed_entry!(entries, key, Set, HashSet::new()).insert(member.to_owned());
+        Ok(())
+    }
+
+    async fn srem(&self, key: &str, member: &str) -> anyhow::Result<()> {
+        let mut entries = self.live_entries(key);
+        if let Some(CacheEntry { value: CacheValue::Set(set), .. }) = entries.get_mut(key) {
+            set.remove(member);
+            if set.is_empty() {
+                entries.remove(key);
+            };
+        };
+        Ok(())
+    }
+
+    async fn smembers(&self, key: &str) -> anyhow::Result<Vec<String>> {
+        match self.live_entries(key).get(key) {
+            Some(CacheEntry { value: CacheValue::Set(set), .. }) => Ok(set.iter().cloned().collect()),
+            _ => Ok(Vec::new())
+        }
+    }
+
+    async fn push_recent(&self, key: &str, value: &str, max_len: usize, expiry_ms: usize) -> anyhow::Result<Vec<String>> {
+        let mut entries = self.live_entries(key);
+        let list = typed_entry!(entries, key, List, VecDeque::new());
+        list.push_front(value.to_owned());
+        list.truncate(max_len.max(1));
+        let recent = list.iter().cloned().collect();
+        if let Some(entry) = entries.get_mut(key) {
+            entry.expires_at = Some(Instant::now() + Duration::from_millis(expiry_ms as u64));
+        };
+        Ok(recent)
+    }
+
+    async fn zadd(&self, key: &str, members: &[(String, f64)]) -> anyhow::Result<()> {
+        let mut entries = self.live_entries(key);
+        let sorted_set = typed_entry!(entries, key, SortedSet, HashMap::new());
+        for (member, score) in members.iter() {
+            sorted_set.insert(member.clone(), *score);
+        };
+        Ok(())
+    }
+
+    async fn zadd_if_greater(&self, key: &str, member: &str, score: f64) -> anyhow::Result<()> {
+        let mut entries = self.live_entries(key);
+        let current = typed_entry!(entries, key, SortedSet, HashMap::new()).entry(member.to_owned()).or_insert(score);
+        if score > *current {
+            *current = score;
+        };
+        Ok(())
+    }
+
+    async fn zincrby(&self, key: &str, member: &str, increment: f64) -> anyhow::Result<()> {
+        let mut entries = self.live_entries(key);
+        *typed_entry!(entries, key, SortedSet, HashMap::new()).entry(member.to_owned()).or_insert(0.0) += increment;
+        Ok(())
+    }
+
+    async fn zrevrange_with_scores(&self, key: &str, start: usize, stop: usize) -> anyhow::Result<Vec<(String, f64)>> {
+        match self.live_entries(key).get(key) {
+            Some(CacheEntry { value: CacheValue::SortedSet(members), .. }) => {
+                Ok(Self::sorted_desc(members).into_iter().skip(start).take(stop.saturating_sub(start) + 1).collect())
+            },
+            _ => Ok(Vec::new())
+        }
+    }
+
+    async fn zrevrank(&self, key: &str, member: &str) -> anyhow::Result<Option<u64>> {
+        match self.live_entries(key).get(key) {
+            Some(CacheEntry { value: CacheValue::SortedSet(members), .. }) => {
+                Ok(Self::sorted_desc(members).iter().position(|(other, _)| other == member).map(|rank| rank as u64))
+            },
+            _ => Ok(None)
+        }
+    }
+
+    async fn keys_with_prefix(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let now = Instant::now();
+        Ok(self.live_entries(prefix).iter()
+            .filter(|(key, entry)| key.starts_with(prefix) && !entry.is_expired(now))
+            .map(|(key, _)| key.clone())
+            .collect())
+    }
+
+    async fn publish(&self, channel: &str, message: &str) -> anyhow::Result<usize> {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|subscriber| !subscriber.sender.is_closed());
+        Ok(subscribers.iter()
+            .filter(|subscriber| subscriber.channels.iter().any(|subscribed| subscribed == channel))
+            .filter(|subscriber| subscriber.sender.send((channel.to_owned(), message.to_owned())).is_ok())
+            .count())
+    }
+
+    async fn subscribe(&self, channels: &[String]) -> anyhow::Result<BoxStream<'static, (String, String)>> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.subscribers.lock().unwrap().push(Subscriber { channels: channels.to_vec(), sender });
+        Ok(stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|message| (message, receiver))
+        }).boxed())
+    }
+}
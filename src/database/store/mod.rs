@@ -0,0 +1,205 @@
+use std::{borrow::Borrow, collections::HashMap, marker::PhantomData, sync::Arc};
+
+use futures::{stream::BoxStream, StreamExt};
+use mongodb::{bson::{self, Bson, Document}, options::{FindOneOptions, FindOptions, ReplaceOptions, UpdateOptions}};
+use serde::{de::DeserializeOwned, Serialize};
+use strum_macros::{Display, EnumString};
+
+pub mod mongo;
+pub mod redis;
+pub mod memory;
+pub mod prefixed;
+pub mod query;
+
+#[derive(EnumString, Display, Clone, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum StorageBackend {
+    // mongo for documents, redis for the cache and leaderboards
+    External,
+    // everything lives in process, nothing survives a restart
+    Memory
+}
+
+pub type Cursor<T> = BoxStream<'static, anyhow::Result<T>>;
+
+#[derive(Debug, Clone, Default)]
+pub struct UpdateResult {
+    pub matched_count: u64,
+    pub modified_count: u64,
+    pub upserted_id: Option<Bson>
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchUpdate {
+    pub filter: Document,
+    pub update: Document,
+    pub upsert: bool
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexDefinition {
+    pub keys: Document,
+    pub unique: bool
+}
+
+impl IndexDefinition {
+    pub fn new(keys: Document) -> Self {
+        IndexDefinition { keys, unique: false }
+    }
+
+    pub fn unique(mut self) -> Self {
+        self.unique = true;
+        self
+    }
+
+    // what mongo names an index by default, e.g. "serverId_1_createdAt_-1"
+    pub fn get_name(&self) -> String {
+        self.keys.iter().map(|(field, direction)| format!("{}_{}", field, direction)).collect::<Vec<_>>().join("_")
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DeleteResult {
+    pub deleted_count: u64
+}
+
+// untyped document storage, collections are addressed by name
+#[async_trait]
+pub trait DocumentStore: Send + Sync {
+    async fn ping(&self) -> bool;
+    async fn find(&self, collection: &str, filter: Document, options: FindOptions) -> anyhow::Result<Cursor<Document>>;
+    async fn find_one(&self, collection: &str, filter: Document, options: FindOneOptions) -> anyhow::Result<Option<Document>>;
+    async fn insert_one(&self, collection: &str, document: Document) -> anyhow::Result<()>;
+    async fn update_one(&self, collection: &str, filter: Document, update: Document, upsert: bool) -> anyhow::Result<UpdateResult>;
+    async fn update_many(&self, collection: &str, filter: Document, update: Document, upsert: bool) -> anyhow::Result<UpdateResult>;
+    async fn replace_one(&self, collection: &str, filter: Document, replacement: Document, upsert: bool) -> anyhow::Result<UpdateResult>;
+    // unordered single-document updates in one round trip, returns the index and error of each that failed
+    async fn update_batch(&self, collection: &str, updates: Vec<BatchUpdate>) -> anyhow::Result<Vec<(usize, String)>>;
+    async fn delete_one(&self, collection: &str, filter: Document) -> anyhow::Result<DeleteResult>;
+    async fn delete_many(&self, collection: &str, filter: Document) -> anyhow::Result<DeleteResult>;
+    async fn count_documents(&self, collection: &str, filter: Document) -> anyhow::Result<u64>;
+    // keyed by index name, a missing collection has none
+    async fn list_indexes(&self, collection: &str) -> anyhow::Result<Vec<(String, IndexDefinition)>>;
+    async fn create_index(&self, collection: &str, index: &IndexDefinition) -> anyhow::Result<()>;
+}
+
+// a lookup of cached JSON values by one of their string fields, <prefix><field value> holds the target the value is cached under
+// a value that takes over a field value another target held evicts that target's value, at <target_prefix><target>,
+// and the index entry for the field value it had before is dropped if it still points at its target
+pub struct CacheIndex<'a> {
+    pub prefix: &'a str,
+    pub field: &'a str,
+    pub target: &'a str,
+    pub target_prefix: &'a str
+}
+
+// string-level cache, hash, set, list, sorted set and pub/sub operations
+// typed access goes through RedisAdapter
+#[async_trait]
+pub trait CacheStore: Send + Sync {
+    async fn ping(&self) -> bool;
+    async fn get(&self, key: &str) -> anyhow::Result<Option<String>>;
+    async fn set(&self, key: &str, value: &str, expiry_ms: Option<usize>) -> anyhow::Result<()>;
+    // returns whether it was written, false if the key already held a value
+    async fn set_if_absent(&self, key: &str, value: &str, expiry_ms: usize) -> anyhow::Result<bool>;
+    async fn del(&self, key: &str) -> anyhow::Result<()>;
+    async fn del_if_equals(&self, key: &str, value: &str) -> anyhow::Result<()>;
+    // returns whether the key existed
+    async fn pexpire(&self, key: &str, expiry_ms: usize) -> anyhow::Result<bool>;
+    // moves a key over whatever the new key held, fails if there is nothing to move
+    async fn rename(&self, key: &str, new_key: &str) -> anyhow::Result<()>;
+    // writes a JSON value unless the one already there carries a "version" above the expected one
+    // returns whether it was written
+    async fn set_if_version(&self, key: &str, value: &str, expected_version: u64, expiry_ms: Option<usize>) -> anyhow::Result<bool>;
+    // like set_if_version, or an unconditional set without an expected version, that also keeps the index in step in the same operation
+    async fn set_indexed(&self, key: &str, value: &str, expected_version: Option<u64>, expiry_ms: Option<usize>, index: &CacheIndex<'_>) -> anyhow::Result<bool>;
+    async fn hset(&self, key: &str, field: &str, value: &str) -> anyhow::Result<()>;
+    async fn hget(&self, key: &str, field: &str) -> anyhow::Result<Option<String>>;
+    async fn hdel(&self, key: &str, field: &str) -> anyhow::Result<()>;
+    async fn hvals(&self, key: &str) -> anyhow::Result<Vec<String>>;
+    async fn hgetall(&self, key: &str) -> anyhow::Result<HashMap<String, String>>;
+    async fn hset_all(&self, key: &str, fields: &[(String, String)]) -> anyhow::Result<()>;
+    // swaps the whole hash for the given fields in one operation, expiring after expiry_ms
+    async fn replace_hash(&self, key: &str, fields: &[(String, String)], expiry_ms: usize) -> anyhow::Result<()>;
+    // sets a JSON field unless the one it replaces has a better number under order_field, returns whether it was set
+    async fn hset_if_better(&self, key: &str, field: &str, value: &str, order_field: &str, higher_is_better: bool) -> anyhow::Result<bool>;
+    // returns the new value
+    async fn hincrbyfloat(&self, key: &str, field: &str, increment: f64) -> anyhow::Result<f64>;
+    async fn sadd(&self, key: &str, member: &str) -> anyhow::Result<()>;
+    async fn srem(&self, key: &str, member: &str) -> anyhow::Result<()>;
+    async fn smembers(&self, key: &str) -> anyhow::Result<Vec<String>>;
+    async fn push_recent(&self, key: &str, value: &str, max_len: usize, expiry_ms: usize) -> anyhow::Result<Vec<String>>;
+    async fn zadd(&self, key: &str, members: &[(String, f64)]) -> anyhow::Result<()>;
+    async fn zadd_if_greater(&self, key: &str, member: &str, score: f64) -> anyhow::Result<()>;
+    async fn zincrby(&self, key: &str, member: &str, increment: f64) -> anyhow::Result<()>;
+    async fn zrevrange_with_scores(&self, key: &str, start: usize, stop: usize) -> anyhow::Result<Vec<(String, f64)>>;
+    async fn zrevrank(&self, key: &str, member: &str) -> anyhow::Result<Option<u64>>;
+    // every live key starting with the prefix, in no particular order
+    async fn keys_with_prefix(&self, prefix: &str) -> anyhow::Result<Vec<String>>;
+    async fn publish(&self, channel: &str, message: &str) -> anyhow::Result<usize>;
+    // yields (channel, message)
+    async fn subscribe(&self, channels: &[String]) -> anyhow::Result<BoxStream<'static, (String, String)>>;
+}
+
+// typed view over a collection of a DocumentStore, mirrors the parts of mongodb::Collection we use
+pub struct Collection<T> {
+    name: String,
+    store: Arc<dyn DocumentStore>,
+    document_type: PhantomData<T>
+}
+
+impl<T> Collection<T> {
+    pub fn new(name: &str, store: Arc<dyn DocumentStore>) -> Self {
+        Collection { name: name.to_owned(), store, document_type: PhantomData }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub async fn find(&self, filter: impl Into<Option<Document>>, options: impl Into<Option<FindOptions>>) -> anyhow::Result<Cursor<T>> where T: DeserializeOwned + Send + 'static {
+        let cursor = self.store.find(&self.name, filter.into().unwrap_or_default(), options.into().unwrap_or_default()).await?;
+        Ok(cursor.map(|result| Ok(bson::from_document::<T>(result?)?)).boxed())
+    }
+
+    pub async fn find_one(&self, filter: impl Into<Option<Document>>, options: impl Into<Option<FindOneOptions>>) -> anyhow::Result<Option<T>> where T: DeserializeOwned {
+        let document = self.store.find_one(&self.name, filter.into().unwrap_or_default(), options.into().unwrap_or_default()).await?;
+        Ok(match document {
+            Some(document) => Some(bson::from_document::<T>(document)?),
+            None => None
+        })
+    }
+
+    pub async fn insert_one(&self, record: impl Borrow<T>) -> anyhow::Result<()> where T: Serialize {
+        self.store.insert_one(&self.name, bson::to_document(record.borrow())?).await
+    }
+
+    pub async fn update_one(&self, filter: Document, update: Document, options: impl Into<Option<UpdateOptions>>) -> anyhow::Result<UpdateResult> {
+        self.store.update_one(&self.name, filter, update, Self::is_upsert(options.into())).await
+    }
+
+    pub async fn update_many(&self, filter: Document, update: Document, options: impl Into<Option<UpdateOptions>>) -> anyhow::Result<UpdateResult> {
+        self.store.update_many(&self.name, filter, update, Self::is_upsert(options.into())).await
+    }
+
+    pub async fn replace_one(&self, filter: Document, replacement: impl Borrow<T>, options: impl Into<Option<ReplaceOptions>>) -> anyhow::Result<UpdateResult> where T: Serialize {
+        let upsert = options.into().and_then(|options| options.upsert).unwrap_or(false);
+        self.store.replace_one(&self.name, filter, bson::to_document(replacement.borrow())?, upsert).await
+    }
+
+    pub async fn delete_one(&self, filter: Document) -> anyhow::Result<DeleteResult> {
+        self.store.delete_one(&self.name, filter).await
+    }
+
+    pub async fn delete_many(&self, filter: Document) -> anyhow::Result<DeleteResult> {
+        self.store.delete_many(&self.name, filter).await
+    }
+
+    pub async fn count_documents(&self, filter: impl Into<Option<Document>>) -> anyhow::Result<u64> {
+        self.store.count_documents(&self.name, filter.into().unwrap_or_default()).await
+    }
+
+    fn is_upsert(options: Option<UpdateOptions>) -> bool {
+        options.and_then(|options| options.upsert).unwrap_or(false)
+    }
+}
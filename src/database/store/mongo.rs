@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use futures::{stream::BoxStream, StreamExt};
+use mongodb::{bson::{doc, Document}, error::ErrorKind, options::{ClientOptions, FindOneOptions, FindOptions, IndexOptions, ReplaceOptions, UpdateOptions}, Client, IndexModel};
+
+use super::{BatchUpdate, Cursor, DeleteResult, DocumentStore, IndexDefinition, UpdateResult};
+
+pub struct MongoDocumentStore {
+    pub mongo: mongodb::Database,
+    client: Client
+}
+
+impl MongoDocumentStore {
+    pub async fn connect(db_url: &str, db_name: &str, min_pool_size: Option<u32>, max_pool_size: Option<u32>) -> anyhow::Result<Self> {
+        let mut client_options = ClientOptions::parse(db_url).await?;
+        client_options.min_pool_size = min_pool_size;
+        client_options.max_pool_size = max_pool_size;
+        client_options.connect_timeout = Some(Duration::new(5, 0));
+        client_options.server_selection_timeout = Some(Duration::new(5, 0));
+
+        let client = Client::with_options(client_options)?;
+        let store = MongoDocumentStore { mongo: client.database(db_name), client };
+        if !store.ping().await {
+            return Err(anyhow!("Could not connect to the database. Is it running?"));
+        };
+        Ok(store)
+    }
+
+    // another database on the same connection pool
+    pub fn with_database(&self, db_name: &str) -> Self {
+        MongoDocumentStore { mongo: self.client.database(db_name), client: self.client.clone() }
+    }
+
+    fn collection(&self, name: &str) -> mongodb::Collection<Document> {
+        self.mongo.collection::<Document>(name)
+    }
+
+    fn to_update_result(result: mongodb::results::UpdateResult) -> UpdateResult {
+        UpdateResult { matched_count: result.matched_count, modified_count: result.modified_count, upserted_id: result.upserted_id }
+    }
+}
+
+#[async_trait]
+impl DocumentStore for MongoDocumentStore {
+    async fn ping(&self) -> bool {
+        self.mongo.run_command(doc! { "ping": 1 }, None).await.is_ok()
+    }
+
+    async fn find(&self, collection: &str, filter: Document, options: FindOptions) -> anyhow::Result<Cursor<Document>> {
+        let cursor = self.collection(collection).find(filter, options).await?;
+        let stream : BoxStream<'static, anyhow::Result<Document>> = cursor.map(|result| result.map_err(anyhow::Error::from)).boxed();
+        Ok(stream)
+    }
+
+    async fn find_one(&self, collection: &str, filter: Document, options: FindOneOptions) -> anyhow::Result<Option<Document>> {
+        Ok(self.collection(collection).find_one(filter, options).await?)
+    }
+
+    async fn insert_one(&self, collection: &str, document: Document) -> anyhow::Result<()> {
+        self.collection(collection).insert_one(document, None).await?;
+        Ok(())
+    }
+
+    async fn update_one(&self, collection: &str, filter: Document, update: Document, upsert: bool) -> anyhow::Result<UpdateResult> {
+        let options = UpdateOptions::builder().upsert(upsert).build();
+        Ok(Self::to_update_result(self.collection(collection).update_one(filter, update, options).await?))
+    }
+
+    async fn update_many(&self, collection: &str, filter: Document, update: Document, upsert: bool) -> anyhow::Result<UpdateResult> {
+        let options = UpdateOptions::builder().upsert(upsert).build();
+        Ok(Self::to_update_result(self.collection(collection).update_many(filter, update, options).await?))
+    }
+
+    async fn replace_one(&self, collection: &str, filter: Document, replacement: Document, upsert: bool) -> anyhow::Result<UpdateResult> {
+        let options = ReplaceOptions::builder().upsert(upsert).build();
+        Ok(Self::to_update_result(self.collection(collection).replace_one(filter, replacement, options).await?))
+    }
+
+    // the 2.x driver has no bulk write, so this issues the update command itself
+    async fn update_batch(&self, collection: &str, updates: Vec<BatchUpdate>) -> anyhow::Result<Vec<(usize, String)>> {
+        if updates.is_empty() {
+            return Ok(Vec::new());
+        };
+        let statements : Vec<Document> = updates.into_iter()
+            .map(|update| doc! { "q": update.filter, "u": update.update, "upsert": update.upsert, "multi": false })
+            .collect();
+        let response = self.mongo.run_command(doc! { "update": collection, "updates": statements, "ordered": false }, None).await?;
+        let write_errors = response.get_array("writeErrors").map(|errors| errors.iter()
+            .filter_map(|error| error.as_document())
+            .map(|error| (
+                error.get_i32("index").unwrap_or(-1) as usize,
+                error.get_str("errmsg").unwrap_or("unknown error").to_owned()
+            ))
+            .collect())
+            .unwrap_or_default();
+        Ok(write_errors)
+    }
+
+    async fn delete_one(&self, collection: &str, filter: Document) -> anyhow::Result<DeleteResult> {
+        let result = self.collection(collection).delete_one(filter, None).await?;
+        Ok(DeleteResult { deleted_count: result.deleted_count })
+    }
+
+    async fn delete_many(&self, collection: &str, filter: Document) -> anyhow::Result<DeleteResult> {
+        let result = self.collection(collection).delete_many(filter, None).await?;
+        Ok(DeleteResult { deleted_count: result.deleted_count })
+    }
+
+    async fn count_documents(&self, collection: &str, filter: Document) -> anyhow::Result<u64> {
+        Ok(self.collection(collection).count_documents(filter, None).await?)
+    }
+
+    async fn list_indexes(&self, collection: &str) -> anyhow::Result<Vec<(String, IndexDefinition)>> {
+        let cursor = match self.collection(collection).list_indexes(None).await {
+            Ok(cursor) => cursor,
+            // NamespaceNotFound, the collection hasn't been created yet
+            Err(e) if matches!(e.kind.as_ref(), ErrorKind::Command(command_error) if command_error.code == 26) => return Ok(Vec::new()),
+            Err(e) => return Err(e.into())
+        };
+        let models : Vec<IndexModel> = cursor.map(|result| result.map_err(anyhow::Error::from)).collect::<Vec<_>>().await
+            .into_iter().collect::<anyhow::Result<_>>()?;
+        Ok(models.into_iter().map(|model| {
+            let options = model.options.unwrap_or_default();
+            let index = IndexDefinition { keys: model.keys, unique: options.unique.unwrap_or(false) };
+            (options.name.unwrap_or_else(|| index.get_name()), index)
+        }).collect())
+    }
+
+    async fn create_index(&self, collection: &str, index: &IndexDefinition) -> anyhow::Result<()> {
+        let options = IndexOptions::builder().name(index.get_name()).unique(index.unique).build();
+        let model = IndexModel::builder().keys(index.keys.clone()).options(options).build();
+        self.collection(collection).create_index(model, None).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+use std::{collections::HashMap, sync::Arc};
+
+use futures::{stream::BoxStream, StreamExt};
+
+use super::{CacheIndex, CacheStore};
+
+// a namespace inside another cache store, every key and channel is stored with the prefix in front
+// lets several networks share one redis without seeing each other's keys
+pub struct PrefixedCacheStore {
+    prefix: String,
+    inner: Arc<dyn CacheStore>
+}
+
+impl PrefixedCacheStore {
+    pub fn new(prefix: &str, inner: Arc<dyn CacheStore>) -> Self {
+        PrefixedCacheStore { prefix: prefix.to_owned(), inner }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    fn strip(&self, key: String) -> String {
+        match key.strip_prefix(&self.prefix) {
+            Some(key) => key.to_owned(),
+            None => key
+        }
+    }
+}
+
+#[async_trait]
+impl CacheStore for PrefixedCacheStore {
+    async fn ping(&self) -> bool {
+        self.inner.ping().await
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        self.inner.get(&self.key(key)).await
+    }
+
+    async fn set(&self, key: &str, value: &str, expiry_ms: Option<usize>) -> anyhow::Result<()> {
+        self.inner.set(&self.key(key), value, expiry_ms).await
+    }
+
+    async fn set_if_absent(&self, key: &str, value: &str, expiry_ms: usize) -> anyhow::Result<bool> {
+        self.inner.set_if_absent(&self.key(key), value, expiry_ms).await
+    }
+
+    async fn del(&self, key: &str) -> anyhow::Result<()> {
+        self.inner.del(&self.key(key)).await
+    }
+
+    async fn del_if_equals(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        self.inner.del_if_equals(&self.key(key), value).await
+    }
+
+    async fn pexpire(&self, key: &str, expiry_ms: usize) -> anyhow::Result<bool> {
+        self.inner.pexpire(&self.key(key), expiry_ms).await
+    }
+
+    async fn rename(&self, key: &str, new_key: &str) -> anyhow::Result<()> {
+        self.inner.rename(&self.key(key), &self.key(new_key)).await
+    }
+
+    async fn set_if_version(&self, key: &str, value: &str, expected_version: u64, expiry_ms: Option<usize>) -> anyhow::Result<bool> {
+        self.inner.set_if_version(&self.key(key), value, expected_version, expiry_ms).await
+    }
+
+    async fn set_indexed(&self, key: &str, value: &str, expected_version: Option<u64>, expiry_ms: Option<usize>, index: &CacheIndex<'_>) -> anyhow::Result<bool> {
+        let (prefix, target_prefix) = (self.key(index.prefix), self.key(index.target_prefix));
+        let index = CacheIndex { prefix: &prefix, field: index.field, target: index.target, target_prefix: &target_prefix };
+        self.inner.set_indexed(&self.key(key), value, expected_version, expiry_ms, &index).await
+    }
+
+    async fn hset(&self, key: &str, field: &str, value: &str) -> anyhow::Result<()> {
+        self.inner.hset(&self.key(key), field, value).await
+    }
+
+    async fn hget(&self, key: &str, field: &str) -> anyhow::Result<Option<String>> {
+        self.inner.hget(&self.key(key), field).await
+    }
+
+    async fn hdel(&self, key: &str, field: &str) -> anyhow::Result<()> {
+        self.inner.hdel(&self.key(key), field).await
+    }
+
+    async fn hvals(&self, key: &str) -> anyhow::Result<Vec<String>> {
+        self.inner.hvals(&self.key(key)).await
+    }
+
+    async fn hgetall(&self, key: &str) -> anyhow::Result<HashMap<String, String>> {
+        self.inner.hgetall(&self.key(key)).await
+    }
+
+    async fn hset_all(&self, key: &str, fields: &[(String, String)]) -> anyhow::Result<()> {
+        self.inner.hset_all(&self.key(key), fields).await
+    }
+
+    async fn replace_hash(&self, key: &str, fields: &[(String, String)], expiry_ms: usize) -> anyhow::Result<()> {
+        self.inner.replace_hash(&self.key(key), fields, expiry_ms).await
+    }
+
+    async fn hset_if_better(&self, key: &str, field: &str, value: &str, order_field: &str, higher_is_better: bool) -> anyhow::Result<bool> {
+        self.inner.hset_if_better(&self.key(key), field, value, order_field, higher_is_better).await
+    }
+
+    async fn hincrbyfloat(&self, key: &str, field: &str, increment: f64) -> anyhow::Result<f64> {
+        self.inner.hincrbyfloat(&self.key(key), field, increment).await
+    }
+
+    async fn sadd(&self, key: &str, member: &str) -> anyhow::Result<()> {
+        self.inner.sadd(&self.key(key), member).await
+    }
+
+    async fn srem(&self, key: &str, member: &str) -> anyhow::Result<()> {
+        self.inner.srem(&self.key(key), member).await
+    }
+
+    async fn smembers(&self, key: &str) -> anyhow::Result<Vec<String>> {
+        self.inner.smembers(&self.key(key)).await
+    }
+
+    async fn push_recent(&self, key: &str, value: &str, max_len: usize, expiry_ms: usize) -> anyhow::Result<Vec<String>> {
+        self.inner.push_recent(&self.key(key), value, max_len, expiry_ms).await
+    }
+
+    async fn zadd(&self, key: &str, members: &[(String, f64)]) -> anyhow::Result<()> {
+        self.inner.zadd(&self.key(key), members).await
+    }
+
+    async fn zadd_if_greater(&self, key: &str, member: &str, score: f64) -> anyhow::Result<()> {
+        self.inner.zadd_if_greater(&self.key(key), member, score).await
+    }
+
+    async fn zincrby(&self, key: &str, member: &str, increment: f64) -> anyhow::Result<()> {
+        self.inner.zincrby(&self.key(key), member, increment).await
+    }
+
+    async fn zrevrange_with_scores(&self, key: &str, start: usize, stop: usize) -> anyhow::Result<Vec<(String, f64)>> {
+        self.inner.zrevrange_with_scores(&self.key(key), start, stop).await
+    }
+
+    async fn zrevrank(&self, key: &str, member: &str) -> anyhow::Result<Option<u64>> {
+        self.inner.zrevrank(&self.key(key), member).await
+    }
+
+    async fn keys_with_prefix(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let keys = self.inner.keys_with_prefix(&self.key(prefix)).await?;
+        Ok(keys.into_iter().map(|key| self.strip(key)).collect())
+    }
+
+    async fn publish(&self, channel: &str, message: &str) -> anyhow::Result<usize> {
+        self.inner.publish(&self.key(channel), message).await
+    }
+
+    async fn subscribe(&self, channels: &[String]) -> anyhow::Result<BoxStream<'static, (String, String)>> {
+        let channels : Vec<String> = channels.iter().map(|channel| self.key(channel)).collect();
+        let prefix = self.prefix.clone();
+        let messages = self.inner.subscribe(&channels).await?;
+        Ok(messages.map(move |(channel, message)| match channel.strip_prefix(&prefix) {
+            Some(channel) => (channel.to_owned(), message),
+            None => (channel, message)
+        }).boxed())
+    }
+}
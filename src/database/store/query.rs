@@ -0,0 +1,376 @@
+use std::cmp::Ordering;
+
+use anyhow::anyhow;
+use mongodb::bson::{Bson, Document};
+use regex::RegexBuilder;
+
+// a small subset of mongo query semantics, enough for the queries this API issues
+
+// what mongo refuses before running a query, so a filter that only works in memory fails here too
+pub fn validate_filter(filter: &Document) -> anyhow::Result<()> {
+    for (key, condition) in filter.iter() {
+        match key.as_str() {
+            "$and" | "$or" | "$nor" => {
+                let sub_filters = match condition {
+                    Bson::Array(sub_filters) if !sub_filters.is_empty() => sub_filters,
+                    _ => return Err(anyhow!("{} must be a nonempty array", key))
+                };
+                for sub_filter in sub_filters.iter() {
+                    match sub_filter {
+                        Bson::Document(sub_filter) => validate_filter(sub_filter)?,
+                        _ => return Err(anyhow!("$or/$and/$nor entries need to be full objects"))
+                    };
+                }
+            },
+            operator if operator.starts_with('$') => return Err(anyhow!("unknown top level operator: {}", operator)),
+            _ => validate_condition(condition)?
+        };
+    }
+    Ok(())
+}
+
+fn validate_condition(condition: &Bson) -> anyhow::Result<()> {
+    if !is_operator_document(condition) {
+        return Ok(());
+    };
+    for (operator, operand) in condition.as_document().unwrap().iter() {
+        match operator.as_str() {
+            "$eq" | "$ne" | "$gt" | "$gte" | "$lt" | "$lte" | "$exists" | "$regex" | "$options" | "$size" => {},
+            "$in" | "$nin" => if !matches!(operand, Bson::Array(_)) {
+                return Err(anyhow!("{} needs an array", operator));
+            },
+            "$not" => match operand {
+                Bson::Document(_) if is_operator_document(operand) => validate_condition(operand)?,
+                Bson::RegularExpression(_) => {},
+                _ => return Err(anyhow!("$not needs a regex or a document"))
+            },
+            _ => return Err(anyhow!("unknown operator: {}", operator))
+        };
+    }
+    Ok(())
+}
+
+// expects a filter that passed validate_filter
+pub fn matches(document: &Document, filter: &Document) -> bool {
+    filter.iter().all(|(key, condition)| match key.as_str() {
+        "$and" => as_documents(condition).iter().all(|sub_filter| matches(document, sub_filter)),
+        "$or" => as_documents(condition).iter().any(|sub_filter| matches(document, sub_filter)),
+        "$nor" => !as_documents(condition).iter().any(|sub_filter| matches(document, sub_filter)),
+        path => matches_condition(&lookup(document, path), condition)
+    })
+}
+
+fn as_documents(value: &Bson) -> Vec<&Document> {
+    match value {
+        Bson::Array(values) => values.iter().filter_map(|value| value.as_document()).collect(),
+        _ => Vec::new()
+    }
+}
+
+fn is_operator_document(value: &Bson) -> bool {
+    match value {
+        Bson::Document(document) => document.keys().next().is_some_and(|key| key.starts_with('$')),
+        _ => false
+    }
+}
+
+// every value reachable by a dotted path, descending into arrays
+pub fn lookup<'a>(document: &'a Document, path: &str) -> Vec<&'a Bson> {
+    let mut current : Vec<&Bson> = Vec::new();
+    let mut segments = path.split('.');
+    match segments.next().and_then(|segment| document.get(segment)) {
+        Some(value) => current.push(value),
+        None => return current
+    };
+    for segment in segments {
+        let mut next = Vec::new();
+        for value in current.into_iter() {
+            match value {
+                Bson::Document(sub_document) => {
+                    if let Some(value) = sub_document.get(segment) {
+                        next.push(value);
+                    };
+                },
+                Bson::Array(values) => {
+                    if let Ok(index) = segment.parse::<usize>() {
+                        if let Some(value) = values.get(index) {
+                            next.push(value);
+                        };
+                    } else {
+                        next.extend(values.iter().filter_map(|value| value.as_document()).filter_map(|sub_document| sub_document.get(segment)));
+                    };
+                },
+                _ => {}
+            };
+        };
+        current = next;
+    };
+    current
+}
+
+fn matches_condition(values: &[&Bson], condition: &Bson) -> bool {
+    if !is_operator_document(condition) {
+        return matches_equal(values, condition);
+    };
+    let operators = condition.as_document().unwrap();
+    operators.iter().all(|(operator, operand)| match operator.as_str() {
+        "$eq" => matches_equal(values, operand),
+        "$ne" => !matches_equal(values, operand),
+        "$gt" => any_candidate(values, |value| compare(value, operand) == Some(Ordering::Greater)),
+        "$gte" => any_candidate(values, |value| matches!(compare(value, operand), Some(Ordering::Greater | Ordering::Equal))),
+        "$lt" => any_candidate(values, |value| compare(value, operand) == Some(Ordering::Less)),
+        "$lte" => any_candidate(values, |value| matches!(compare(value, operand), Some(Ordering::Less | Ordering::Equal))),
+        "$in" => match operand {
+            Bson::Array(options) => options.iter().any(|option| matches_equal(values, option)),
+            _ => false
+        },
+        "$nin" => match operand {
+            Bson::Array(options) => !options.iter().any(|option| matches_equal(values, option)),
+            _ => true
+        },
+        "$exists" => values.is_empty() != is_truthy(operand),
+        "$regex" => {
+            let options = operators.get_str("$options").unwrap_or("");
+            matches_regex(values, operand, options)
+        },
+        "$options" => true,
+        "$not" => !matches_condition(values, operand),
+        "$size" => values.iter().any(|value| match (value, as_f64(operand)) {
+            (Bson::Array(array), Some(size)) => array.len() as f64 == size,
+            _ => false
+        }),
+        _ => {
+            warn!("Unsupported query operator '{}' in in-memory store", operator);
+            false
+        }
+    })
+}
+
+// candidates include array elements, like mongo does for scalar conditions on arrays
+fn any_candidate<F: Fn(&Bson) -> bool>(values: &[&Bson], predicate: F) -> bool {
+    values.iter().any(|value| match value {
+        Bson::Array(elements) => predicate(value) || elements.iter().any(&predicate),
+        _ => predicate(value)
+    })
+}
+
+fn matches_equal(values: &[&Bson], expected: &Bson) -> bool {
+    if values.is_empty() {
+        return matches!(expected, Bson::Null);
+    };
+    any_candidate(values, |value| compare(value, expected) == Some(Ordering::Equal))
+}
+
+fn matches_regex(values: &[&Bson], pattern: &Bson, options: &str) -> bool {
+    let (pattern, options) = match pattern {
+        Bson::String(pattern) => (pattern.clone(), options.to_owned()),
+        Bson::RegularExpression(regex) => (regex.pattern.clone(), regex.options.clone()),
+        _ => return false
+    };
+    let regex = match RegexBuilder::new(&pattern)
+        .case_insensitive(options.contains('i'))
+        .multi_line(options.contains('m'))
+        .dot_matches_new_line(options.contains('s'))
+        .build() {
+            Ok(regex) => regex,
+            Err(_) => return false
+        };
+    any_candidate(values, |value| match value {
+        Bson::String(text) => regex.is_match(text),
+        _ => false
+    })
+}
+
+fn is_truthy(value: &Bson) -> bool {
+    match value {
+        Bson::Boolean(b) => *b,
+        Bson::Null => false,
+        other => as_f64(other).map(|number| number != 0.0).unwrap_or(true)
+    }
+}
+
+fn as_f64(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Int32(i) => Some(*i as f64),
+        Bson::Int64(i) => Some(*i as f64),
+        Bson::Double(d) => Some(*d),
+        _ => None
+    }
+}
+
+// numbers compare across int/double like mongo, other types only with themselves
+pub fn compare(a: &Bson, b: &Bson) -> Option<Ordering> {
+    if let (Some(a), Some(b)) = (as_f64(a), as_f64(b)) {
+        return a.partial_cmp(&b);
+    };
+    match (a, b) {
+        (Bson::String(a), Bson::String(b)) => Some(a.cmp(b)),
+        (Bson::Boolean(a), Bson::Boolean(b)) => Some(a.cmp(b)),
+        (Bson::Null, Bson::Null) => Some(Ordering::Equal),
+        (Bson::DateTime(a), Bson::DateTime(b)) => Some(a.cmp(b)),
+        (Bson::ObjectId(a), Bson::ObjectId(b)) => Some(a.cmp(b)),
+        (Bson::Array(a), Bson::Array(b)) => {
+            if a.len() != b.len() {
+                return None;
+            };
+            a.iter().zip(b.iter()).all(|(a, b)| compare(a, b) == Some(Ordering::Equal)).then_some(Ordering::Equal)
+        },
+        (Bson::Document(a), Bson::Document(b)) => {
+            let equal = a.len() == b.len() && a.iter().all(|(key, value)| b.get(key).is_some_and(|other| compare(value, other) == Some(Ordering::Equal)));
+            equal.then_some(Ordering::Equal)
+        },
+        _ => None
+    }
+}
+
+// orders by a mongo sort specification, missing values sort first
+pub fn compare_by_sort(a: &Document, b: &Document, sort: &Document) -> Ordering {
+    for (path, direction) in sort.iter() {
+        let a_value = lookup(a, path).first().map(|value| (*value).clone()).unwrap_or(Bson::Null);
+        let b_value = lookup(b, path).first().map(|value| (*value).clone()).unwrap_or(Bson::Null);
+        let ordering = match (&a_value, &b_value) {
+            (Bson::Null, Bson::Null) => Ordering::Equal,
+            (Bson::Null, _) => Ordering::Less,
+            (_, Bson::Null) => Ordering::Greater,
+            _ => compare(&a_value, &b_value).unwrap_or(Ordering::Equal)
+        };
+        let ordering = if as_f64(direction).unwrap_or(1.0) < 0.0 { ordering.reverse() } else { ordering };
+        if ordering != Ordering::Equal {
+            return ordering;
+        };
+    };
+    Ordering::Equal
+}
+
+// the document an upsert starts from, built from the filter's equality conditions
+pub fn upsert_base(filter: &Document) -> Document {
+    let mut base = Document::new();
+    for (key, condition) in filter.iter() {
+        if key == "$and" {
+            for sub_filter in as_documents(condition) {
+                for (key, value) in upsert_base(sub_filter) {
+                    set_path(&mut base, &key, value);
+                };
+            };
+        } else if !key.starts_with('$') && !is_operator_document(condition) {
+            set_path(&mut base, key, condition.clone());
+        } else if let Some(value) = condition.as_document().and_then(|operators| operators.get("$eq")) {
+            set_path(&mut base, key, value.clone());
+        };
+    };
+    base
+}
+
+// applies an update document, or replaces the document (keeping _id) if it has no operators
+pub fn apply_update(document: &mut Document, update: &Document, is_insert: bool) -> anyhow::Result<()> {
+    if !update.keys().any(|key| key.starts_with('$')) {
+        let id = document.get("_id").cloned();
+        *document = update.clone();
+        if let Some(id) = id {
+            document.insert("_id", id);
+        };
+        return Ok(());
+    };
+    for (operator, fields) in update.iter() {
+        let fields = fields.as_document().ok_or_else(|| anyhow!("'{}' expects a document", operator))?;
+        for (path, value) in fields.iter() {
+            match operator.as_str() {
+                "$set" => set_path(document, path, value.clone()),
+                "$setOnInsert" => if is_insert { set_path(document, path, value.clone()) },
+                "$unset" => unset_path(document, path),
+                "$inc" => {
+                    let current = get_path(document, path).cloned().unwrap_or(Bson::Int32(0));
+                    set_path(document, path, add_numbers(&current, value)?);
+                },
+                "$max" | "$min" => {
+                    let wanted = if operator == "$max" { Ordering::Greater } else { Ordering::Less };
+                    let replace = match get_path(document, path) {
+                        Some(current) => compare(value, current) == Some(wanted),
+                        None => true
+                    };
+                    if replace {
+                        set_path(document, path, value.clone());
+                    };
+                },
+                "$push" | "$addToSet" => {
+                    let mut array = match get_path(document, path) {
+                        Some(Bson::Array(array)) => array.clone(),
+                        Some(_) => return Err(anyhow!("'{}' on non-array field '{}'", operator, path)),
+                        None => Vec::new()
+                    };
+                    let items = match value.as_document().and_then(|modifiers| modifiers.get_array("$each").ok()) {
+                        Some(each) => each.clone(),
+                        None => vec![value.clone()]
+                    };
+                    for item in items.into_iter() {
+                        if operator == "$push" || !array.iter().any(|existing| compare(existing, &item) == Some(Ordering::Equal)) {
+                            array.push(item);
+                        };
+                    };
+                    set_path(document, path, Bson::Array(array));
+                },
+                "$pull" => {
+                    if let Some(Bson::Array(array)) = get_path(document, path) {
+                        let remaining : Vec<Bson> = array.iter()
+                            .filter(|existing| !matches_condition(&[*existing], value))
+                            .cloned().collect();
+                        set_path(document, path, Bson::Array(remaining));
+                    };
+                },
+                _ => return Err(anyhow!("Unsupported update operator '{}' in in-memory store", operator))
+            };
+        };
+    };
+    Ok(())
+}
+
+fn add_numbers(current: &Bson, increment: &Bson) -> anyhow::Result<Bson> {
+    Ok(match (current, increment) {
+        (Bson::Int32(a), Bson::Int32(b)) => a.checked_add(*b).map(Bson::Int32).unwrap_or(Bson::Int64(*a as i64 + *b as i64)),
+        (Bson::Int32(a), Bson::Int64(b)) => Bson::Int64(*a as i64 + b),
+        (Bson::Int64(a), Bson::Int32(b)) => Bson::Int64(a + *b as i64),
+        (Bson::Int64(a), Bson::Int64(b)) => Bson::Int64(a + b),
+        (a, b) => match (as_f64(a), as_f64(b)) {
+            (Some(a), Some(b)) => Bson::Double(a + b),
+            _ => return Err(anyhow!("Cannot increment a non-numeric value"))
+        }
+    })
+}
+
+pub fn get_path<'a>(document: &'a Document, path: &str) -> Option<&'a Bson> {
+    let mut segments = path.split('.');
+    let mut current = document.get(segments.next()?)?;
+    for segment in segments {
+        current = match current {
+            Bson::Document(sub_document) => sub_document.get(segment)?,
+            Bson::Array(values) => values.get(segment.parse::<usize>().ok()?)?,
+            _ => return None
+        };
+    };
+    Some(current)
+}
+
+pub fn set_path(document: &mut Document, path: &str, value: Bson) {
+    match path.split_once('.') {
+        None => { document.insert(path, value); },
+        Some((head, rest)) => {
+            if !matches!(document.get(head), Some(Bson::Document(_))) {
+                document.insert(head, Document::new());
+            };
+            if let Some(Bson::Document(sub_document)) = document.get_mut(head) {
+                set_path(sub_document, rest, value);
+            };
+        }
+    };
+}
+
+pub fn unset_path(document: &mut Document, path: &str) {
+    match path.split_once('.') {
+        None => { document.remove(path); },
+        Some((head, rest)) => {
+            if let Some(Bson::Document(sub_document)) = document.get_mut(head) {
+                unset_path(sub_document, rest);
+            };
+        }
+    };
+}
@@ -0,0 +1,520 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use futures::{stream::BoxStream, StreamExt};
+use mobc::{Manager, Pool};
+use redis::{aio::Connection, Client, ConnectionAddr, ConnectionInfo, ErrorKind, IntoConnectionInfo, RedisConnectionInfo, RedisError};
+
+use crate::config::ConfigMissingFieldError;
+
+use super::{CacheIndex, CacheStore};
+
+// begin: mobc manager wrapper
+pub struct RedisConnectionManager {
+    connector: Arc<RedisConnector>,
+}
+
+impl RedisConnectionManager {
+    pub fn new(connector: Arc<RedisConnector>) -> Self {
+        Self { connector }
+    }
+}
+
+#[async_trait]
+impl Manager for RedisConnectionManager {
+    type Connection = Connection;
+    type Error = redis::RedisError;
+
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+        self.connector.connect().await
+    }
+
+    async fn check(&self, mut conn: Self::Connection) -> Result<Self::Connection, Self::Error> {
+        redis::cmd("PING").query_async::<_, ()>(&mut conn).await?;
+        Ok(conn)
+    }
+}
+// end: mobc manager wrapper
+
+// a missing key or one without a version counts as version 0
+const SET_IF_VERSION_SCRIPT: &str = r#"
+local current = redis.call('GET', KEYS[1])
+if current then
+    local ok, decoded = pcall(cjson.decode, current)
+    if ok and type(decoded) == 'table' and tonumber(decoded['version'] or 0) > tonumber(ARGV[2]) then
+        return 0
+    end
+end
+if tonumber(ARGV[3]) > 0 then
+    redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[3])
+else
+    redis.call('SET', KEYS[1], ARGV[1])
+end
+return 1
+"#;
+
+// ARGV: expiry in ms, then field and value pairs
+const REPLACE_HASH_SCRIPT: &str = r#"
+redis.call('DEL', KEYS[1])
+for i = 2, #ARGV, 2 do
+    redis.call('HSET', KEYS[1], ARGV[i], ARGV[i + 1])
+end
+if #ARGV > 1 and tonumber(ARGV[1]) > 0 then
+    redis.call('PEXPIRE', KEYS[1], ARGV[1])
+end
+return 1
+"#;
+
+// ARGV: field, value, order field, 1 if higher is better
+// values that aren't objects with a number under the order field can always be replaced
+const HSET_IF_BETTER_SCRIPT: &str = r#"
+local current = redis.call('HGET', KEYS[1], ARGV[1])
+if current then
+    local current_ok, current_decoded = pcall(cjson.decode, current)
+    local candidate_ok, candidate_decoded = pcall(cjson.decode, ARGV[2])
+    if current_ok and candidate_ok and type(current_decoded) == 'table' and type(candidate_decoded) == 'table' then
+        local current_order = current_decoded[ARGV[3]]
+        local candidate_order = candidate_decoded[ARGV[3]]
+        if type(current_order) == 'number' and type(candidate_order) == 'number' then
+            if ARGV[4] == '1' and candidate_order <= current_order then
+                return 0
+            elseif ARGV[4] ~= '1' and candidate_order >= current_order then
+                return 0
+            end
+        end
+    end
+end
+redis.call('HSET', KEYS[1], ARGV[1], ARGV[2])
+return 1
+"#;
+
+// ARGV: value, expected version or '', expiry in ms or 0, index prefix, indexed field, target, target prefix
+// the index entries and the evicted holder are named by values the script reads, so they can't be declared in KEYS
+// that's fine on a single redis or behind sentinels, redis cluster isn't supported
+const SET_INDEXED_SCRIPT: &str = r#"
+local current = redis.call('GET', KEYS[1])
+local previous = nil
+if current then
+    local ok, decoded = pcall(cjson.decode, current)
+    if ok and type(decoded) == 'table' then
+        if ARGV[2] ~= '' and tonumber(decoded['version'] or 0) > tonumber(ARGV[2]) then
+            return 0
+        end
+        previous = decoded[ARGV[5]]
+    end
+end
+local function set(key, value)
+    if tonumber(ARGV[3]) > 0 then
+        redis.call('SET', key, value, 'PX', ARGV[3])
+    else
+        redis.call('SET', key, value)
+    end
+end
+set(KEYS[1], ARGV[1])
+local ok, decoded = pcall(cjson.decode, ARGV[1])
+local next = nil
+if ok and type(decoded) == 'table' then
+    next = decoded[ARGV[5]]
+end
+if type(next) == 'string' then
+    -- whoever held the field value before is evicted, their cached copy still claims it
+    local holder = redis.call('GET', ARGV[4] .. next)
+    if holder and holder ~= ARGV[6] then
+        redis.call('DEL', ARGV[7] .. holder)
+    end
+    set(ARGV[4] .. next, ARGV[6])
+end
+if type(previous) == 'string' and previous ~= next and redis.call('GET', ARGV[4] .. previous) == ARGV[6] then
+    redis.call('DEL', ARGV[4] .. previous)
+end
+return 1
+"#;
+
+const CACHE_POOL_MAX_OPEN: u64 = 16; // max connections
+const CACHE_POOL_MAX_IDLE: u64 = 8; // max unused connections
+const CACHE_POOL_TIMEOUT_SECONDS: u64 = 1; // await a connection from pool @ 1 second max
+const CACHE_POOL_EXPIRE_SECONDS: u64 = 60; // inactive connections die after a minute
+const DEFAULT_PORT: u16 = 6379;
+const DEFAULT_SENTINEL_PORT: u16 = 26379;
+
+#[derive(Clone)]
+pub struct RedisOptions {
+    // host:port, or a redis:// or rediss:// URL
+    pub host: Option<String>,
+    // these override whatever the URL says
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub database: Option<i64>,
+    pub tls: bool,
+    // skips certificate hostname verification
+    pub tls_insecure: bool,
+    // host:port or URLs of sentinels, when set the master is looked up by name instead of using host
+    pub sentinels: Vec<String>,
+    pub sentinel_master: String,
+    pub pool_max_open: u64,
+    pub pool_max_idle: u64,
+    pub pool_timeout_seconds: u64,
+    pub pool_max_lifetime_seconds: u64
+}
+
+impl Default for RedisOptions {
+    fn default() -> Self {
+        RedisOptions {
+            host: None,
+            username: None,
+            password: None,
+            database: None,
+            tls: false,
+            tls_insecure: false,
+            sentinels: Vec::new(),
+            sentinel_master: String::from("mymaster"),
+            pool_max_open: CACHE_POOL_MAX_OPEN,
+            pool_max_idle: CACHE_POOL_MAX_IDLE,
+            pool_timeout_seconds: CACHE_POOL_TIMEOUT_SECONDS,
+            pool_max_lifetime_seconds: CACHE_POOL_EXPIRE_SECONDS
+        }
+    }
+}
+
+impl RedisOptions {
+    pub fn to_connection_info(&self, address: &str, default_port: u16) -> redis::RedisResult<ConnectionInfo> {
+        let mut info = if address.contains("://") {
+            let mut info = address.into_connection_info()?;
+            // redis-tls applies to URLs too, a redis:// URL is upgraded rather than silently connecting in plaintext
+            if self.tls {
+                info.addr = match info.addr {
+                    ConnectionAddr::Tcp(host, port) => ConnectionAddr::TcpTls { host, port, insecure: self.tls_insecure },
+                    ConnectionAddr::TcpTls { host, port, insecure } => ConnectionAddr::TcpTls { host, port, insecure: insecure || self.tls_insecure },
+                    ConnectionAddr::Unix(_) => return Err(RedisError::from((ErrorKind::InvalidClientConfig, "redis-tls can't be used with a unix socket", address.to_owned())))
+                };
+            };
+            info
+        } else {
+            let (host, port) = split_host_port(address, default_port)?;
+            let addr = if self.tls {
+                ConnectionAddr::TcpTls { host, port, insecure: self.tls_insecure }
+            } else {
+                ConnectionAddr::Tcp(host, port)
+            };
+            ConnectionInfo { addr, redis: RedisConnectionInfo::default() }
+        };
+        if self.username.is_some() {
+            info.redis.username = self.username.clone();
+        };
+        if self.password.is_some() {
+            info.redis.password = self.password.clone();
+        };
+        if let Some(database) = self.database {
+            info.redis.db = database;
+        };
+        Ok(info)
+    }
+}
+
+// host, host:port, [ipv6] or [ipv6]:port, a bare IPv6 literal is taken as a host without a port
+fn split_host_port(address: &str, default_port: u16) -> redis::RedisResult<(String, u16)> {
+    let invalid = |reason: &'static str| RedisError::from((ErrorKind::InvalidClientConfig, reason, address.to_owned()));
+    let parse_port = |port: &str| port.parse::<u16>().map_err(|_| invalid("Invalid port"));
+    if let Some(bracketed) = address.strip_prefix('[') {
+        let (host, rest) = bracketed.split_once(']').ok_or_else(|| invalid("Unclosed '[' in IPv6 address"))?;
+        return match rest {
+            "" => Ok((host.to_owned(), default_port)),
+            _ => Ok((host.to_owned(), parse_port(rest.strip_prefix(':').ok_or_else(|| invalid("Expected ':' after ']'"))?)?))
+        };
+    };
+    match address.split_once(':') {
+        Some((host, port)) if !port.contains(':') => Ok((host.to_owned(), parse_port(port)?)),
+        _ => Ok((address.to_owned(), default_port))
+    }
+}
+
+fn format_host_port(host: &str, port: u16) -> String {
+    if host.contains(':') { format!("[{}]:{}", host, port) } else { format!("{}:{}", host, port) }
+}
+
+// opens connections to the configured redis, or to whichever node the sentinels currently name as master
+// the master is looked up for every new connection, so the pool follows a failover as old connections expire
+pub struct RedisConnector {
+    options: RedisOptions,
+    direct: Option<Client>
+}
+
+impl RedisConnector {
+    pub fn new(options: RedisOptions) -> anyhow::Result<Self> {
+        let direct = if options.sentinels.is_empty() {
+            let host = match &options.host {
+                Some(host) => host,
+                None => return Err(ConfigMissingFieldError { field_name: String::from("redis-host") }.into())
+            };
+            Some(Client::open(options.to_connection_info(host, DEFAULT_PORT)?)?)
+        } else {
+            None
+        };
+        Ok(RedisConnector { options, direct })
+    }
+
+    pub async fn connect(&self) -> redis::RedisResult<Connection> {
+        match &self.direct {
+            Some(client) => client.get_async_connection().await,
+            None => Client::open(self.find_master().await?)?.get_async_connection().await
+        }
+    }
+
+    async fn find_master(&self) -> redis::RedisResult<ConnectionInfo> {
+        let mut last_error = RedisError::from((ErrorKind::InvalidClientConfig, "No sentinels configured"));
+        for sentinel in self.options.sentinels.iter() {
+            let info = match self.options.to_connection_info(sentinel, DEFAULT_SENTINEL_PORT) {
+                // sentinels have their own credentials and no databases, so only URLs carry them
+                Ok(info) if sentinel.contains("://") => info,
+                Ok(info) => ConnectionInfo { addr: info.addr, redis: RedisConnectionInfo::default() },
+                Err(e) => {
+                    last_error = e;
+                    continue;
+                }
+            };
+            let master : redis::RedisResult<Option<(String, u16)>> = async {
+                let mut connection = Client::open(info)?.get_async_connection().await?;
+                redis::cmd("SENTINEL").arg("get-master-addr-by-name").arg(&self.options.sentinel_master).query_async(&mut connection).await
+            }.await;
+            match master {
+                Ok(Some((host, port))) => return self.options.to_connection_info(&format_host_port(&host, port), DEFAULT_PORT),
+                Ok(None) => last_error = RedisError::from((ErrorKind::InvalidClientConfig, "Unknown sentinel master", self.options.sentinel_master.clone())),
+                Err(e) => {
+                    warn!("Could not ask sentinel '{}' for the master: {}", sentinel, e);
+                    last_error = e;
+                }
+            };
+        }
+        Err(last_error)
+    }
+
+    fn describe(&self) -> String {
+        match &self.options.host {
+            _ if !self.options.sentinels.is_empty() => format!("master '{}' via sentinels {}", self.options.sentinel_master, self.options.sentinels.join(", ")),
+            // credentials stay out of the log
+            Some(host) => host.rsplit('@').next().unwrap_or_default().to_owned(),
+            None => String::new()
+        }
+    }
+}
+
+pub struct RedisCacheStore {
+    pub pool: Pool<RedisConnectionManager>,
+    // pub/sub needs a dedicated connection outside the pool
+    pub connector: Arc<RedisConnector>
+}
+
+impl RedisCacheStore {
+    pub async fn connect(options: &RedisOptions) -> anyhow::Result<Self> {
+        let connector = Arc::new(RedisConnector::new(options.clone())?);
+        info!("Connecting to redis at {}", connector.describe());
+        let manager = RedisConnectionManager::new(Arc::clone(&connector));
+        let pool = Pool::builder()
+            .get_timeout(Some(Duration::from_secs(options.pool_timeout_seconds)))
+            .max_open(options.pool_max_open)
+            .max_idle(options.pool_max_idle)
+            .max_lifetime(Some(Duration::from_secs(options.pool_max_lifetime_seconds)))
+            .build(manager);
+        let store = RedisCacheStore { pool, connector };
+        if !store.ping().await {
+            return Err(anyhow!("Could not connect to Redis. Is it running?"));
+        };
+        Ok(store)
+    }
+
+    async fn query<T: redis::FromRedisValue>(&self, cmd: &redis::Cmd) -> anyhow::Result<T> {
+        let mut conn = self.pool.get().await?;
+        Ok(cmd.query_async::<Connection, T>(&mut conn).await?)
+    }
+}
+
+#[async_trait]
+impl CacheStore for RedisCacheStore {
+    async fn ping(&self) -> bool {
+        self.query::<String>(redis::cmd("PING").arg("we love warzone")).await.is_ok()
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        self.query(redis::cmd("GET").arg(key)).await
+    }
+
+    async fn set(&self, key: &str, value: &str, expiry_ms: Option<usize>) -> anyhow::Result<()> {
+        match expiry_ms {
+            Some(expiry_ms) => self.query(redis::cmd("PSETEX").arg(key).arg(expiry_ms).arg(value)).await,
+            None => self.query(redis::cmd("SET").arg(key).arg(value)).await
+        }
+    }
+
+    async fn set_if_absent(&self, key: &str, value: &str, expiry_ms: usize) -> anyhow::Result<bool> {
+        let written : Option<String> = self.query(redis::cmd("SET").arg(key).arg(value).arg("NX").arg("PX").arg(expiry_ms)).await?;
+        Ok(written.is_some())
+    }
+
+    async fn del(&self, key: &str) -> anyhow::Result<()> {
+        self.query(redis::cmd("DEL").arg(key)).await
+    }
+
+    async fn del_if_equals(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        let mut conn = self.pool.get().await?;
+        redis::Script::new("if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('DEL', KEYS[1]) else return 0 end")
+            .key(key).arg(value).invoke_async::<Connection, i64>(&mut conn).await?;
+        Ok(())
+    }
+
+    async fn pexpire(&self, key: &str, expiry_ms: usize) -> anyhow::Result<bool> {
+        self.query(redis::cmd("PEXPIRE").arg(key).arg(expiry_ms)).await
+    }
+
+    async fn rename(&self, key: &str, new_key: &str) -> anyhow::Result<()> {
+        self.query(redis::cmd("RENAME").arg(key).arg(new_key)).await
+    }
+
+    async fn set_if_version(&self, key: &str, value: &str, expected_version: u64, expiry_ms: Option<usize>) -> anyhow::Result<bool> {
+        let mut conn = self.pool.get().await?;
+        let written = redis::Script::new(SET_IF_VERSION_SCRIPT)
+            .key(key).arg(value).arg(expected_version).arg(expiry_ms.unwrap_or(0))
+            .invoke_async::<Connection, i64>(&mut conn).await?;
+        Ok(written == 1)
+    }
+
+    async fn set_indexed(&self, key: &str, value: &str, expected_version: Option<u64>, expiry_ms: Option<usize>, index: &CacheIndex<'_>) -> anyhow::Result<bool> {
+        let mut conn = self.pool.get().await?;
+        let written = redis::Script::new(SET_INDEXED_SCRIPT)
+            .key(key).arg(value).arg(expected_version.map(|version| version.to_string()).unwrap_or_default()).arg(expiry_ms.unwrap_or(0))
+            .arg(index.prefix).arg(index.field).arg(index.target).arg(index.target_prefix)
+            .invoke_async::<Connection, i64>(&mut conn).await?;
+        Ok(written == 1)
+    }
+
+    async fn hset(&self, key: &str, field: &str, value: &str) -> anyhow::Result<()> {
+        self.query(redis::cmd("HSET").arg(key).arg(field).arg(value)).await
+    }
+
+    async fn hget(&self, key: &str, field: &str) -> anyhow::Result<Option<String>> {
+        self.query(redis::cmd("HGET").arg(key).arg(field)).await
+    }
+
+    async fn hdel(&self, key: &str, field: &str) -> anyhow::Result<()> {
+        self.query(redis::cmd("HDEL").arg(key).arg(field)).await
+    }
+
+    async fn hvals(&self, key: &str) -> anyhow::Result<Vec<String>> {
+        self.query(redis::cmd("HVALS").arg(key)).await
+    }
+
+    async fn hgetall(&self, key: &str) -> anyhow::Result<HashMap<String, String>> {
+        self.query(redis::cmd("HGETALL").arg(key)).await
+    }
+
+    async fn hset_all(&self, key: &str, fields: &[(String, String)]) -> anyhow::Result<()> {
+        if fields.is_empty() {
+            return Ok(());
+        };
+        self.query(redis::cmd("HSET").arg(key).arg(fields)).await
+    }
+
+    async fn replace_hash(&self, key: &str, fields: &[(String, String)], expiry_ms: usize) -> anyhow::Result<()> {
+        let mut conn = self.pool.get().await?;
+        redis::Script::new(REPLACE_HASH_SCRIPT)
+            .key(key).arg(expiry_ms).arg(fields)
+            .invoke_async::<Connection, i64>(&mut conn).await?;
+        Ok(())
+    }
+
+    async fn hset_if_better(&self, key: &str, field: &str, value: &str, order_field: &str, higher_is_better: bool) -> anyhow::Result<bool> {
+        let mut conn = self.pool.get().await?;
+        let written = redis::Script::new(HSET_IF_BETTER_SCRIPT)
+            .key(key).arg(field).arg(value).arg(order_field).arg(if higher_is_better { 1 } else { 0 })
+            .invoke_async::<Connection, i64>(&mut conn).await?;
+        Ok(written == 1)
+    }
+
+    async fn hincrbyfloat(&self, key: &str, field: &str, increment: f64) -> anyhow::Result<f64> {
+        self.query(redis::cmd("HINCRBYFLOAT").arg(key).arg(field).arg(increment)).await
+    }
+
+    async fn sadd(&self, key: &str, member: &str) -> anyhow::Result<()> {
+        self.query(redis::cmd("SADD").arg(key).arg(member)).await
+    }
+
+    async fn srem(&self, key: &str, member: &str) -> anyhow::Result<()> {
+        self.query(redis::cmd("SREM").arg(key).arg(member)).await
+    }
+
+    async fn smembers(&self, key: &str) -> anyhow::Result<Vec<String>> {
+        self.query(redis::cmd("SMEMBERS").arg(key)).await
+    }
+
+    async fn push_recent(&self, key: &str, value: &str, max_len: usize, expiry_ms: usize) -> anyhow::Result<Vec<String>> {
+        let mut conn = self.pool.get().await?;
+        let (recent,) : (Vec<String>,) = redis::pipe()
+            .cmd("LPUSH").arg(key).arg(value).ignore()
+            .cmd("LTRIM").arg(key).arg(0).arg(max_len.saturating_sub(1)).ignore()
+            .cmd("PEXPIRE").arg(key).arg(expiry_ms).ignore()
+            .cmd("LRANGE").arg(key).arg(0).arg(-1)
+            .query_async::<Connection, (Vec<String>,)>(&mut conn).await?;
+        Ok(recent)
+    }
+
+    async fn zadd(&self, key: &str, members: &[(String, f64)]) -> anyhow::Result<()> {
+        if members.is_empty() {
+            return Ok(());
+        };
+        let mut cmd = redis::cmd("ZADD");
+        cmd.arg(key);
+        for (member, score) in members.iter() {
+            cmd.arg(*score).arg(member);
+        };
+        self.query(&cmd).await
+    }
+
+    async fn zadd_if_greater(&self, key: &str, member: &str, score: f64) -> anyhow::Result<()> {
+        // GT keeps this atomic when several instances write the same member
+        self.query(redis::cmd("ZADD").arg(key).arg("GT").arg(score).arg(member)).await
+    }
+
+    async fn zincrby(&self, key: &str, member: &str, increment: f64) -> anyhow::Result<()> {
+        self.query(redis::cmd("ZINCRBY").arg(key).arg(increment).arg(member)).await
+    }
+
+    async fn zrevrange_with_scores(&self, key: &str, start: usize, stop: usize) -> anyhow::Result<Vec<(String, f64)>> {
+        self.query(redis::cmd("ZRANGE").arg(key).arg(start).arg(stop).arg("REV").arg("WITHSCORES")).await
+    }
+
+    async fn zrevrank(&self, key: &str, member: &str) -> anyhow::Result<Option<u64>> {
+        self.query(redis::cmd("ZREVRANK").arg(key).arg(member)).await
+    }
+
+    async fn keys_with_prefix(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        // SCAN instead of KEYS, so a large keyspace doesn't block redis
+        let pattern = format!("{}*", prefix.replace('\\', "\\\\").replace('*', "\\*").replace('?', "\\?").replace('[', "\\["));
+        let mut keys = Vec::new();
+        let mut cursor : u64 = 0;
+        loop {
+            let (next, batch) : (u64, Vec<String>) = self.query(redis::cmd("SCAN").arg(cursor).arg("MATCH").arg(&pattern).arg("COUNT").arg(1000)).await?;
+            keys.extend(batch);
+            if next == 0 {
+                break;
+            };
+            cursor = next;
+        }
+        keys.sort();
+        keys.dedup();
+        Ok(keys)
+    }
+
+    async fn publish(&self, channel: &str, message: &str) -> anyhow::Result<usize> {
+        self.query(redis::cmd("PUBLISH").arg(channel).arg(message)).await
+    }
+
+    async fn subscribe(&self, channels: &[String]) -> anyhow::Result<BoxStream<'static, (String, String)>> {
+        let mut pubsub = self.connector.connect().await?.into_pubsub();
+        for channel in channels.iter() {
+            pubsub.subscribe(channel).await?;
+        };
+        Ok(pubsub.into_on_message().filter_map(|message| async move {
+            let payload : String = message.get_payload().ok()?;
+            Some((message.get_channel_name().to_owned(), payload))
+        }).boxed())
+    }
+}
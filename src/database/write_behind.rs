@@ -0,0 +1,347 @@
+use std::{collections::{BTreeMap, HashMap}, sync::{Arc, Mutex}, time::Duration};
+
+use mars_api_rs_macro::IdentifiableDocument;
+use mongodb::bson::{self, doc, Document};
+use serde::Serialize;
+
+use super::{CollectionOwner, Versioned, models::player::Player, stat_delta::StatDelta, store::{BatchUpdate, DocumentStore}};
+
+const FLUSH_INTERVAL_MS: u64 = 250;
+const MAX_BATCH_SIZE: usize = 500;
+// well under the 16MB command limit, leaving room for the command around the statements
+const MAX_BATCH_BYTES: usize = 8 << 20;
+// a write the database rejected on its own is retried this many times before it's given up on
+const MAX_WRITE_ATTEMPTS: u32 = 5;
+// past this many waiting documents, writers flush the queue themselves instead of adding to it
+const MAX_PENDING: usize = 10_000;
+
+#[derive(Hash, PartialEq, Eq, Clone)]
+struct WriteKey {
+    collection: String,
+    id: String,
+    is_stats: bool
+}
+
+enum PendingWrite {
+    // the latest copy wins
+    Save(Document),
+    // the highest version wins, and is skipped if the stored copy is already newer
+    SaveVersioned { document: Document, insert_only: Document, version: i64 },
+    Stats(StatDelta)
+}
+
+impl PendingWrite {
+    // combines an older pending write of a document with a newer one
+    fn merge(self, newer: PendingWrite) -> PendingWrite {
+        match (self, newer) {
+            (PendingWrite::Stats(mut older), PendingWrite::Stats(newer)) => {
+                older.merge(newer);
+                PendingWrite::Stats(older)
+            },
+            (older @ PendingWrite::SaveVersioned { .. }, newer @ PendingWrite::SaveVersioned { .. }) => {
+                if older.get_version() > newer.get_version() { older } else { newer }
+            },
+            (_, newer) => newer
+        }
+    }
+
+    fn get_version(&self) -> i64 {
+        match self {
+            PendingWrite::SaveVersioned { version, .. } => *version,
+            _ => 0
+        }
+    }
+
+    fn is_stats(&self) -> bool {
+        matches!(self, PendingWrite::Stats(_))
+    }
+
+    fn to_updates(&self, id: &str) -> Vec<BatchUpdate> {
+        match self {
+            PendingWrite::Save(document) => vec![
+                BatchUpdate { filter: doc! { "_id": id }, update: doc! { "$set": document.clone() }, upsert: true }
+            ],
+            PendingWrite::SaveVersioned { document, insert_only, version } => {
+                let mut update = doc! { "$set": document.clone() };
+                if !insert_only.is_empty() {
+                    update.insert("$setOnInsert", insert_only.clone());
+                };
+                vec![BatchUpdate {
+                    filter: doc! { "_id": id, "$or": [{ "version": { "$lt": version } }, { "version": null }] },
+                    update,
+                    upsert: true
+                }]
+            },
+            PendingWrite::Stats(delta) => {
+                let mut updates : Vec<BatchUpdate> = delta.get_update().into_iter()
+                    .map(|update| BatchUpdate { filter: doc! { "_id": id }, update, upsert: false })
+                    .collect();
+                updates.extend(delta.get_record_updates(id).into_iter().map(|(filter, update)| BatchUpdate { filter, update, upsert: false }));
+                updates
+            }
+        }
+    }
+}
+
+// only the rejected update is retried, since the others of the same write, like a stat increment, already landed
+struct FailedUpdate {
+    key: WriteKey,
+    update: BatchUpdate,
+    attempts: u32
+}
+
+// database writes that can land a moment late, coalesced per document and flushed as batches
+pub struct WriteBehindQueue {
+    store: Arc<dyn DocumentStore>,
+    pending: Mutex<HashMap<WriteKey, PendingWrite>>,
+    // one flush at a time, so an older batch can't land after a newer one
+    flush_lock: tokio::sync::Mutex<()>,
+    // single updates the database rejected, retried with the next flush
+    retries: Mutex<Vec<FailedUpdate>>,
+    max_batch_size: usize,
+    max_batch_bytes: usize,
+    max_pending: usize
+}
+
+impl WriteBehindQueue {
+    pub fn new(store: Arc<dyn DocumentStore>) -> Self {
+        Self::with_limits(store, MAX_BATCH_SIZE, MAX_BATCH_BYTES, MAX_PENDING)
+    }
+
+    pub fn with_limits(store: Arc<dyn DocumentStore>, max_batch_size: usize, max_batch_bytes: usize, max_pending: usize) -> Self {
+        WriteBehindQueue {
+            store,
+            pending: Mutex::new(HashMap::new()),
+            flush_lock: tokio::sync::Mutex::new(()),
+            retries: Mutex::new(Vec::new()),
+            max_batch_size,
+            max_batch_bytes,
+            max_pending
+        }
+    }
+
+    pub fn spawn_flusher(queue: Arc<WriteBehindQueue>) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(FLUSH_INTERVAL_MS));
+            loop {
+                interval.tick().await;
+                queue.flush().await;
+            }
+        });
+    }
+
+    pub async fn save<R>(&self, record: &R) where R: CollectionOwner<R> + Serialize + IdentifiableDocument {
+        let document = match bson::to_document(record) {
+            Ok(document) => document,
+            Err(e) => return warn!("Could not serialize {} '{}': {}", R::get_collection_name(), record.get_id_value(), e)
+        };
+        self.enqueue(Self::get_key::<R>(&record.get_id_value(), false), PendingWrite::Save(document)).await;
+    }
+
+    // like Database::save_versioned, except a stale write is only logged once it's flushed
+    pub async fn save_versioned<R>(&self, record: &R) where R: CollectionOwner<R> + Serialize + IdentifiableDocument + Versioned {
+        let mut document = match bson::to_document(record) {
+            Ok(document) => document,
+            Err(e) => return warn!("Could not serialize {} '{}': {}", R::get_collection_name(), record.get_id_value(), e)
+        };
+        let mut insert_only = Document::new();
+        for field in R::get_insert_only_fields() {
+            if let Some(value) = document.remove(*field) {
+                insert_only.insert(*field, value);
+            };
+        }
+        let write = PendingWrite::SaveVersioned { document, insert_only, version: record.get_version() as i64 };
+        self.enqueue(Self::get_key::<R>(&record.get_id_value(), false), write).await;
+    }
+
+    pub async fn apply_stat_delta(&self, player_id: &str, delta: StatDelta) {
+        if delta.is_empty() {
+            return;
+        };
+        self.enqueue(Self::get_key::<Player>(player_id, true), PendingWrite::Stats(delta)).await;
+    }
+
+    pub fn get_pending_count(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    pub async fn flush(&self) {
+        let _flushing = self.flush_lock.lock().await;
+        let retries = std::mem::take(&mut *self.retries.lock().unwrap());
+        self.write_retries(retries).await;
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        if pending.is_empty() {
+            return;
+        };
+        // whole documents are flushed before stat updates, which don't create a missing player
+        let mut by_collection : BTreeMap<(String, bool), Vec<(WriteKey, PendingWrite)>> = BTreeMap::new();
+        for (key, write) in pending.into_iter() {
+            by_collection.entry((key.collection.clone(), key.is_stats)).or_default().push((key, write));
+        }
+        for ((collection, _), writes) in by_collection.into_iter() {
+            let mut batch : Vec<(WriteKey, PendingWrite)> = Vec::new();
+            let (mut batch_size, mut batch_bytes) = (0, 0);
+            for (key, write) in writes.into_iter() {
+                let updates = write.to_updates(&key.id);
+                let bytes = Self::get_byte_size(&updates);
+                if (batch_size + updates.len() > self.max_batch_size || batch_bytes + bytes > self.max_batch_bytes) && !batch.is_empty() {
+                    self.write_batch(&collection, std::mem::take(&mut batch)).await;
+                    (batch_size, batch_bytes) = (0, 0);
+                };
+                batch_size += updates.len();
+                batch_bytes += bytes;
+                batch.push((key, write));
+            }
+            self.write_batch(&collection, batch).await;
+        }
+    }
+
+    // writes whatever is queued for one document, without waiting on the rest of the queue
+    // returns whether anything was queued
+    pub async fn flush_document<R: CollectionOwner<R>>(&self, id: &str) -> bool {
+        let _flushing = self.flush_lock.lock().await;
+        let retries : Vec<FailedUpdate> = {
+            let mut retries = self.retries.lock().unwrap();
+            let (own, others) = std::mem::take(&mut *retries).into_iter()
+                .partition(|retry| retry.key.id == id && retry.key.collection == R::get_collection_name());
+            *retries = others;
+            own
+        };
+        let mut writes : Vec<(WriteKey, PendingWrite)> = {
+            let mut pending = self.pending.lock().unwrap();
+            [false, true].into_iter()
+                .filter_map(|is_stats| {
+                    let key = Self::get_key::<R>(id, is_stats);
+                    pending.remove(&key).map(|write| (key, write))
+                })
+                .collect()
+        };
+        if writes.is_empty() && retries.is_empty() {
+            return false;
+        };
+        self.write_retries(retries).await;
+        // the whole document before its stat updates, like a full flush
+        writes.sort_by_key(|(_, write)| write.is_stats());
+        for write in writes.into_iter() {
+            self.write_batch(R::get_collection_name(), vec![write]).await;
+        }
+        true
+    }
+
+    fn get_byte_size(updates: &[BatchUpdate]) -> usize {
+        updates.iter()
+            .map(|update| bson::to_vec(&update.filter).map_or(0, |bytes| bytes.len()) + bson::to_vec(&update.update).map_or(0, |bytes| bytes.len()))
+            .sum()
+    }
+
+    async fn write_batch(&self, collection: &str, batch: Vec<(WriteKey, PendingWrite)>) {
+        let mut updates = Vec::new();
+        let mut owners = Vec::new();
+        for (index, (key, write)) in batch.iter().enumerate() {
+            for update in write.to_updates(&key.id) {
+                updates.push(update);
+                owners.push(index);
+            }
+        }
+        if updates.is_empty() {
+            return;
+        };
+        let bytes = Self::get_byte_size(&updates);
+        match self.store.update_batch(collection, updates.clone()).await {
+            Ok(failures) => {
+                let mut failed = Vec::new();
+                for (index, error) in failures.into_iter() {
+                    let (key, write) = match owners.get(index).and_then(|owner| batch.get(*owner)) {
+                        Some(entry) => entry,
+                        None => continue
+                    };
+                    match write {
+                        // expected whenever a newer copy got there first, nothing to retry
+                        PendingWrite::SaveVersioned { version, .. } => warn!("Skipped stale version {} of {} '{}': {}", version, collection, key.id, error),
+                        _ => {
+                            warn!("Could not write {} '{}', retrying: {}", collection, key.id, error);
+                            failed.push(FailedUpdate { key: key.clone(), update: updates[index].clone(), attempts: 1 });
+                        }
+                    };
+                }
+                self.retries.lock().unwrap().extend(failed);
+            },
+            Err(e) if batch.len() == 1 && bytes > self.max_batch_bytes => {
+                // too big to ever fit in a command, retrying it would only hold up the queue
+                error!("Dropped a {} byte write to {} '{}' that can't be sent: {}", bytes, collection, batch[0].0.id, e);
+            },
+            Err(e) => {
+                // nothing in the batch is known to have landed, so it's retried with the next flush
+                warn!("Could not flush {} write(s) to '{}', retrying: {}", batch.len(), collection, e);
+                let mut pending = self.pending.lock().unwrap();
+                for (key, write) in batch.into_iter() {
+                    let write = match pending.remove(&key) {
+                        Some(newer) => write.merge(newer),
+                        None => write
+                    };
+                    pending.insert(key, write);
+                }
+            }
+        };
+    }
+
+    // a rejected update may only have lost a race, so it gets a few more tries before it's given up on
+    async fn write_retries(&self, retries: Vec<FailedUpdate>) {
+        let mut by_collection : BTreeMap<String, Vec<FailedUpdate>> = BTreeMap::new();
+        {
+            let pending = self.pending.lock().unwrap();
+            for retry in retries.into_iter() {
+                // a newer copy of a whole document is already on its way
+                if !retry.key.is_stats && pending.contains_key(&retry.key) {
+                    continue;
+                };
+                by_collection.entry(retry.key.collection.clone()).or_default().push(retry);
+            }
+        }
+        for (collection, retries) in by_collection.into_iter() {
+            let updates : Vec<BatchUpdate> = retries.iter().map(|retry| retry.update.clone()).collect();
+            let failures = match self.store.update_batch(&collection, updates).await {
+                Ok(failures) => failures,
+                Err(e) => {
+                    warn!("Could not retry {} write(s) to '{}': {}", retries.len(), collection, e);
+                    self.retries.lock().unwrap().extend(retries);
+                    continue;
+                }
+            };
+            let mut retries : Vec<Option<FailedUpdate>> = retries.into_iter().map(Some).collect();
+            let mut failed = Vec::new();
+            for (index, error) in failures.into_iter() {
+                let mut retry = match retries.get_mut(index).and_then(Option::take) {
+                    Some(retry) => retry,
+                    None => continue
+                };
+                retry.attempts += 1;
+                if retry.attempts < MAX_WRITE_ATTEMPTS {
+                    failed.push(retry);
+                } else {
+                    error!("Gave up on writing {} '{}' after {} attempts, {} was lost: {}", collection, retry.key.id, retry.attempts, retry.update.update, error);
+                };
+            }
+            self.retries.lock().unwrap().extend(failed);
+        }
+    }
+
+    async fn enqueue(&self, key: WriteKey, write: PendingWrite) {
+        let pending_count = {
+            let mut pending = self.pending.lock().unwrap();
+            let write = match pending.remove(&key) {
+                Some(older) => older.merge(write),
+                None => write
+            };
+            pending.insert(key, write);
+            pending.len()
+        };
+        if pending_count >= self.max_pending {
+            self.flush().await;
+        };
+    }
+
+    fn get_key<R: CollectionOwner<R>>(id: &str, is_stats: bool) -> WriteKey {
+        WriteKey { collection: R::get_collection_name().to_owned(), id: id.to_owned(), is_stats }
+    }
+}
@@ -1,8 +1,9 @@
 use futures::future::join_all;
 use mongodb::bson::doc;
-use mongodb::results::DeleteResult;
+use crate::http::idempotency::HashedJson;
+use crate::database::store::DeleteResult;
 use rocket::http::Status;
-use rocket::{State, Rocket, Build, serde::json::Json};
+use rocket::{Rocket, Build, serde::json::Json};
 use uuid::Uuid;
 
 use crate::database::Database;
@@ -12,15 +13,15 @@ use crate::util::responder::JsonResponder;
 use crate::database::models::achievement::achievement_metadata_preset_to_metadata;
 use crate::database::models::achievement::Achievement;
 use crate::http::achievements::payload::AchievementCreateRequest;
-use crate::MarsAPIState;
+use crate::network::NetworkState;
 use crate::{util::auth::AuthorizationToken};
 
 mod payload;
 
 #[post("/", format = "json", data = "<achievement_create_req>")]
 async fn add_achievement(
-    state: &State<MarsAPIState>,
-    achievement_create_req: Json<AchievementCreateRequest>,
+    state: NetworkState<'_>,
+    achievement_create_req: HashedJson<AchievementCreateRequest>,
     _auth_guard: AuthorizationToken
 ) -> Result<JsonResponder<Achievement>, ApiErrorResponder> {
     match state.database.find_by_id_or_name::<Achievement>(&achievement_create_req.name).await {
@@ -48,7 +49,7 @@ async fn add_achievement(
 
 #[delete("/<achievement_id>")]
 async fn delete_achievement(
-    state: &State<MarsAPIState>,
+    state: NetworkState<'_>,
     achievement_id: &str,
     _auth_guard: AuthorizationToken
 ) -> Result<(), ApiErrorResponder> {
@@ -71,7 +72,8 @@ async fn delete_achievement(
         let mut remove_from_cache_futures : Vec<_> = Vec::new();
         for player in players_with_achievement.iter_mut() {
             player.stats.achievements.remove(&achievement_id.to_owned());
-            remove_from_cache_futures.push(state.player_cache.set(&state.database, &player.name, player, false));
+            let name = player.name.clone();
+            remove_from_cache_futures.push(async move { state.player_cache.overwrite(&state.database, &name, player, false).await });
         };
         join_all(remove_from_cache_futures).await;
     }
@@ -80,7 +82,7 @@ async fn delete_achievement(
 
 #[get("/<achievement_id>")]
 async fn get_achievement_by_id(
-    state: &State<MarsAPIState>,
+    state: NetworkState<'_>,
     achievement_id: &str
 ) -> Result<JsonResponder<Achievement>, ApiErrorResponder> {
     Ok(JsonResponder::ok(
@@ -93,7 +95,7 @@ async fn get_achievement_by_id(
 
 #[get("/")]
 async fn get_achievements(
-    state: &State<MarsAPIState>,
+    state: NetworkState<'_>,
 ) -> Json<Vec<Achievement>> {
     Json(state.database.get_all_documents::<Achievement>().await)
 }
@@ -1,9 +1,9 @@
-use rocket::{serde::json::Json, State, Build, Rocket};
-use crate::{database::models::broadcast::Broadcast, MarsAPIState};
+use rocket::{serde::json::Json, Build, Rocket};
+use crate::{database::models::broadcast::Broadcast, network::NetworkState};
 
 #[get("/")]
-pub fn broadcasts(state: &State<MarsAPIState>) -> Json<&Vec<Broadcast>> {
-    Json(&state.config.data.broadcasts) 
+pub fn broadcasts(state: NetworkState<'_>) -> Json<&Vec<Broadcast>> {
+    Json(&state.inner().config.data.broadcasts) 
 }
 
 pub fn mount(rocket_build: Rocket<Build>) -> Rocket<Build> {
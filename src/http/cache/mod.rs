@@ -0,0 +1,103 @@
+use rocket::{Rocket, Build, http::Status, serde::json::{serde_json, Value}};
+
+use crate::{database::SaveError, network::NetworkState, util::{auth::AuthorizationToken, error::ApiErrorResponder, r#macro::unwrap_helper, responder::JsonResponder}};
+
+use self::payload::CacheFlushResponse;
+
+pub mod payload;
+
+enum CacheResource {
+    Players,
+    Matches
+}
+
+impl CacheResource {
+    fn parse(resource: &str) -> Result<Self, ApiErrorResponder> {
+        match resource {
+            "players" => Ok(CacheResource::Players),
+            "matches" => Ok(CacheResource::Matches),
+            _ => Err(ApiErrorResponder::validation_error_with_message("Unknown cache resource"))
+        }
+    }
+}
+
+// the value as the rest of the api would read it, players include their cached stats
+#[get("/<resource>/<key>")]
+async fn get_cached_value(
+    state: NetworkState<'_>,
+    resource: &str,
+    key: &str,
+    _auth_guard: AuthorizationToken
+) -> Result<JsonResponder<Value>, ApiErrorResponder> {
+    let value = match CacheResource::parse(resource)? {
+        CacheResource::Players => state.player_cache.query(key).await.and_then(|player| serde_json::to_value(player).ok()),
+        CacheResource::Matches => state.match_cache.query(key).await.and_then(|r#match| serde_json::to_value(r#match).ok())
+    };
+    let value = unwrap_helper::return_default!(value, Err(ApiErrorResponder::cache_entry_missing()));
+    Ok(JsonResponder::ok(value))
+}
+
+#[post("/<resource>/<key>/persist")]
+async fn persist_cached_value(
+    state: NetworkState<'_>,
+    resource: &str,
+    key: &str,
+    auth_guard: AuthorizationToken
+) -> Result<(), ApiErrorResponder> {
+    let persisted = match CacheResource::parse(resource)? {
+        CacheResource::Players => state.player_cache.persist_cached_value(&state.database, key).await,
+        CacheResource::Matches => state.match_cache.persist_cached_value(&state.database, key).await
+    };
+    if !persisted.map_err(from_save_error)? {
+        return Err(ApiErrorResponder::cache_entry_missing());
+    };
+    info!("Persisted cached {} '{}' (requested by '{}')", resource, key, auth_guard.server_id);
+    Ok(())
+}
+
+// matches in progress are only cached, so a match is persisted before it's evicted
+#[delete("/<resource>/<key>")]
+async fn evict_cached_value(
+    state: NetworkState<'_>,
+    resource: &str,
+    key: &str,
+    auth_guard: AuthorizationToken
+) -> Result<(), ApiErrorResponder> {
+    let evicted = match CacheResource::parse(resource)? {
+        CacheResource::Players => state.player_cache.evict(key).await,
+        CacheResource::Matches => state.match_cache.persist_and_evict(&state.database, key).await.map_err(from_save_error)?.is_some()
+    };
+    if !evicted {
+        return Err(ApiErrorResponder::cache_entry_missing());
+    };
+    info!("Evicted cached {} '{}' (requested by '{}')", resource, key, auth_guard.server_id);
+    Ok(())
+}
+
+#[delete("/<resource>")]
+async fn flush_cache(
+    state: NetworkState<'_>,
+    resource: &str,
+    auth_guard: AuthorizationToken
+) -> Result<JsonResponder<CacheFlushResponse>, ApiErrorResponder> {
+    let (evicted, kept) = match CacheResource::parse(resource)? {
+        CacheResource::Players => (state.player_cache.flush().await, 0),
+        CacheResource::Matches => state.match_cache.persist_and_flush(&state.database).await
+    };
+    info!("Flushed {} cached {}, kept {} that couldn't be persisted (requested by '{}')", evicted, resource, kept, auth_guard.server_id);
+    Ok(JsonResponder::ok(CacheFlushResponse { evicted, kept }))
+}
+
+fn from_save_error(error: SaveError) -> ApiErrorResponder {
+    match error {
+        SaveError::Conflict(_) => ApiErrorResponder::version_conflict(),
+        SaveError::Unwritten(reason) => {
+            warn!("{}", reason);
+            ApiErrorResponder::create_anonymous_error(Status::InternalServerError, "The cached value could not be persisted")
+        }
+    }
+}
+
+pub fn mount(rocket_build: Rocket<Build>) -> Rocket<Build> {
+    rocket_build.mount("/mc/cache", routes![get_cached_value, persist_cached_value, evict_cached_value, flush_cache])
+}
@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheFlushResponse {
+    pub evicted: usize,
+    // values that couldn't be persisted first, only matches are persisted
+    pub kept: usize
+}
@@ -0,0 +1,58 @@
+use mongodb::{bson::{doc, Document}, options::FindOptions};
+use rocket::{Rocket, Build};
+
+use crate::{database::{Database, models::chat_message::ChatMessage}, network::NetworkState, util::{auth::AuthorizationToken, error::ApiErrorResponder, r#macro::unwrap_helper, responder::JsonResponder, string::escape_regex}};
+
+#[get("/?<player>&<server>&<match_id>&<after>&<before>&<text>&<flagged>&<limit>")]
+#[allow(clippy::too_many_arguments)]
+async fn search_chat(
+    state: NetworkState<'_>,
+    player: Option<&str>,
+    server: Option<&str>,
+    match_id: Option<&str>,
+    after: Option<u64>,
+    before: Option<u64>,
+    text: Option<&str>,
+    flagged: Option<bool>,
+    limit: Option<i64>,
+    _auth_guard: AuthorizationToken
+) -> Result<JsonResponder<Vec<ChatMessage>>, ApiErrorResponder> {
+    let mut filter = Document::new();
+    if let Some(player) = player {
+        // accept names as well as IDs
+        let player = unwrap_helper::return_default!(state.player_cache.get(&state.database, player).await, Err(ApiErrorResponder::missing_player()));
+        filter.insert("player.id", player.id);
+    };
+    if let Some(server) = server {
+        filter.insert("serverId", server.to_lowercase());
+    };
+    if let Some(match_id) = match_id {
+        filter.insert("matchId", match_id);
+    };
+    let mut created_at = Document::new();
+    if let Some(after) = after {
+        created_at.insert("$gte", after as i64);
+    };
+    if let Some(before) = before {
+        created_at.insert("$lt", before as i64);
+    };
+    if !created_at.is_empty() {
+        filter.insert("createdAt", created_at);
+    };
+    if let Some(text) = text {
+        filter.insert("message", doc! { "$regex": escape_regex(text), "$options": "i" });
+    };
+
+    if let Some(flagged) = flagged {
+        filter.insert("flags.0", doc! { "$exists": flagged });
+    };
+
+    let limit = limit.unwrap_or(100).clamp(1, 500);
+    let opts = FindOptions::builder().sort(doc! { "createdAt": -1 }).limit(limit).build();
+    let messages = Database::consume_cursor_into_owning_vec_option(state.database.chat_messages.find(filter, Some(opts)).await.ok()).await;
+    Ok(JsonResponder::ok(messages))
+}
+
+pub fn mount(rocket_build: Rocket<Build>) -> Rocket<Build> {
+    rocket_build.mount("/mc/chat", routes![search_chat])
+}
@@ -0,0 +1,115 @@
+use std::{str::FromStr, sync::Arc};
+
+use mongodb::{bson::{doc, Document}, options::FindOptions};
+use rocket::{Rocket, Build};
+
+use crate::{database::{Database, models::dead_letter::{DeadLetter, DeadLetterReason}}, network::NetworkState, socket::dead_letter, util::{auth::AuthorizationToken, error::ApiErrorResponder, r#macro::unwrap_helper, responder::JsonResponder, string::enumify}};
+
+use self::payload::DeadLetterRedriveResponse;
+
+pub mod payload;
+
+fn build_filter(server: Option<&str>, reason: Option<&str>, resolved: Option<bool>) -> Result<Document, ApiErrorResponder> {
+    let mut filter = Document::new();
+    if let Some(server) = server {
+        filter.insert("serverId", server);
+    };
+    if let Some(reason) = reason {
+        let reason = unwrap_helper::return_default!(DeadLetterReason::from_str(&enumify(reason)).ok(), Err(ApiErrorResponder::validation_error_with_message("Unknown dead letter reason")));
+        filter.insert("reason", reason.to_string());
+    };
+    if let Some(resolved) = resolved {
+        filter.insert("resolvedAt", if resolved { doc! { "$ne": null } } else { doc! { "$eq": null } });
+    };
+    Ok(filter)
+}
+
+#[get("/?<server>&<reason>&<resolved>&<limit>")]
+async fn list_dead_letters(
+    state: NetworkState<'_>,
+    server: Option<&str>,
+    reason: Option<&str>,
+    resolved: Option<bool>,
+    limit: Option<i64>,
+    _auth_guard: AuthorizationToken
+) -> Result<JsonResponder<Vec<DeadLetter>>, ApiErrorResponder> {
+    let filter = build_filter(server, reason, resolved)?;
+    let limit = limit.unwrap_or(100).clamp(1, 500);
+    let opts = FindOptions::builder().sort(doc! { "createdAt": -1 }).limit(limit).build();
+    let dead_letters = Database::consume_cursor_into_owning_vec_option(state.database.dead_letters.find(filter, Some(opts)).await.ok()).await;
+    Ok(JsonResponder::ok(dead_letters))
+}
+
+#[get("/<dead_letter_id>")]
+async fn get_dead_letter(
+    state: NetworkState<'_>,
+    dead_letter_id: &str,
+    _auth_guard: AuthorizationToken
+) -> Result<JsonResponder<DeadLetter>, ApiErrorResponder> {
+    let dead_letter = unwrap_helper::return_default!(Database::find_by_id(&state.database.dead_letters, dead_letter_id).await, Err(ApiErrorResponder::dead_letter_missing()));
+    Ok(JsonResponder::ok(dead_letter))
+}
+
+// routes the event again, a failure is recorded on the letter rather than returned as an error
+#[post("/<dead_letter_id>/redrive")]
+async fn redrive_dead_letter(
+    state: NetworkState<'_>,
+    dead_letter_id: &str,
+    _auth_guard: AuthorizationToken
+) -> Result<JsonResponder<DeadLetter>, ApiErrorResponder> {
+    let mut dead_letter = unwrap_helper::return_default!(Database::find_by_id(&state.database.dead_letters, dead_letter_id).await, Err(ApiErrorResponder::dead_letter_missing()));
+    if dead_letter.resolved_at.is_some() {
+        return Err(ApiErrorResponder::dead_letter_resolved());
+    };
+    if dead_letter::redrive(&Arc::new(state.inner().clone()), &mut dead_letter).await.is_none() {
+        return Err(ApiErrorResponder::dead_letter_redriving());
+    };
+    Ok(JsonResponder::ok(dead_letter))
+}
+
+// redrives unresolved letters oldest first, so events from one server keep their order
+#[post("/redrive?<server>&<reason>&<limit>")]
+async fn redrive_dead_letters(
+    state: NetworkState<'_>,
+    server: Option<&str>,
+    reason: Option<&str>,
+    limit: Option<i64>,
+    _auth_guard: AuthorizationToken
+) -> Result<JsonResponder<DeadLetterRedriveResponse>, ApiErrorResponder> {
+    let filter = build_filter(server, reason, Some(false))?;
+    let limit = limit.unwrap_or(100).clamp(1, 1000);
+    let opts = FindOptions::builder().sort(doc! { "createdAt": 1 }).limit(limit).build();
+    let dead_letters = Database::consume_cursor_into_owning_vec_option(state.database.dead_letters.find(filter, Some(opts)).await.ok()).await;
+
+    let api_state = Arc::new(state.inner().clone());
+    let mut response = DeadLetterRedriveResponse { attempted: 0, resolved: 0, skipped: 0, failed_ids: Vec::new() };
+    for mut dead_letter in dead_letters {
+        match dead_letter::redrive(&api_state, &mut dead_letter).await {
+            Some(Ok(_)) => response.resolved += 1,
+            Some(Err(_)) => response.failed_ids.push(dead_letter.id),
+            // resolved or being redriven by another request since the list was read
+            None => {
+                response.skipped += 1;
+                continue;
+            }
+        };
+        response.attempted += 1;
+    }
+    Ok(JsonResponder::ok(response))
+}
+
+#[delete("/<dead_letter_id>")]
+async fn delete_dead_letter(
+    state: NetworkState<'_>,
+    dead_letter_id: &str,
+    _auth_guard: AuthorizationToken
+) -> Result<(), ApiErrorResponder> {
+    match state.database.delete_by_id::<DeadLetter>(dead_letter_id).await {
+        Some(result) if result.deleted_count > 0 => Ok(()),
+        _ => Err(ApiErrorResponder::dead_letter_missing())
+    }
+}
+
+pub fn mount(rocket_build: Rocket<Build>) -> Rocket<Build> {
+    rocket_build.mount("/mc/dead_letters", routes![list_dead_letters, get_dead_letter, redrive_dead_letter, redrive_dead_letters, delete_dead_letter])
+}
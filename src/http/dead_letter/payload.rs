@@ -0,0 +1,10 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetterRedriveResponse {
+    pub attempted: u32,
+    pub resolved: u32,
+    pub skipped: u32,
+    pub failed_ids: Vec<String>
+}
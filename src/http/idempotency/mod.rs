@@ -0,0 +1,255 @@
+use std::{io::{self, Cursor}, ops::Deref, sync::Arc};
+
+use rocket::{Build, Data, Request, Response, Rocket, data::{self, FromData, Limits}, fairing::{Fairing, Info, Kind}, http::{ContentType, Header, Method, Status, uri::Origin}, request::{self, FromRequest, Outcome}, response::{self, Responder}, serde::json::serde_json};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+
+use crate::{database::store::CacheStore, network::Networks, util::{auth::AuthorizationToken, error::ApiErrorResponder}};
+
+pub const HEADER: &str = "Idempotency-Key";
+pub const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+// how long a response is replayed for
+const WINDOW_MS: usize = 86_400_000;
+// how long a key stays claimed by a request that never finishes
+const CLAIM_MS: usize = 60_000;
+const MAX_KEY_LENGTH: usize = 255;
+const REPLAY_PATH: &str = "/_idempotency/replay";
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredResponse {
+    status: u16,
+    content_type: Option<String>,
+    body: String
+}
+
+// the response is None while the first request is still being handled
+// the body hash is None when the handler never read a body, so a retry's body isn't compared
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IdempotencyRecord {
+    request: String,
+    #[serde(default)]
+    body_hash: Option<String>,
+    response: Option<StoredResponse>
+}
+
+#[derive(Clone, Copy)]
+enum Rejection {
+    InvalidKey,
+    InUse,
+    Reused
+}
+
+enum IdempotencyOutcome {
+    None,
+    Claimed { store: Arc<dyn CacheStore>, key: String, request: String },
+    Replay { response: StoredResponse, body_hash: Option<String> },
+    Rejected(Rejection)
+}
+
+// set by HashedJson once the whole body has been read
+struct BodyHash(Option<String>);
+
+fn hash_body(body: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(body))
+}
+
+// a fairing only sees the first 512 bytes of a body, so the body of a keyed request is hashed as the route reads it
+// routes taking JSON use this in place of Json, its hash is stored with the response and checked against retries
+pub struct HashedJson<T>(pub T);
+
+impl<T> Deref for HashedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T: DeserializeOwned> FromData<'r> for HashedJson<T> {
+    type Error = io::Error;
+
+    async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let limit = req.limits().get("json").unwrap_or(Limits::JSON);
+        let body = match data.open(limit).into_bytes().await {
+            Ok(body) if body.is_complete() => body.into_inner(),
+            Ok(_) => return data::Outcome::Error((Status::PayloadTooLarge, io::Error::new(io::ErrorKind::UnexpectedEof, "data limit exceeded"))),
+            Err(e) => return data::Outcome::Error((Status::BadRequest, e))
+        };
+        req.local_cache(|| BodyHash(Some(hash_body(&body))));
+        match serde_json::from_slice(&body) {
+            Ok(value) => data::Outcome::Success(HashedJson(value)),
+            Err(e) if e.classify() == serde_json::error::Category::Data => data::Outcome::Error((Status::UnprocessableEntity, e.into())),
+            Err(e) => data::Outcome::Error((Status::BadRequest, e.into()))
+        }
+    }
+}
+
+// POST and PUT requests carrying an Idempotency-Key are handled once per server and key,
+// a retry gets the first response back instead of running the handler again
+pub struct Idempotency;
+
+impl Idempotency {
+    // the body is checked by the replay route, it can't be read here
+    async fn claim(req: &Request<'_>, key: &str) -> IdempotencyOutcome {
+        // only authenticated servers get stored responses, the handler answers everyone else itself
+        let server_id = match req.guard::<AuthorizationToken>().await {
+            Outcome::Success(token) => token.server_id,
+            _ => return IdempotencyOutcome::None
+        };
+        if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+            return IdempotencyOutcome::Rejected(Rejection::InvalidKey);
+        };
+        let state = match req.rocket().state::<Networks>().and_then(|networks| networks.resolve(req)) {
+            Some(state) => state,
+            None => return IdempotencyOutcome::None
+        };
+        let store = Arc::clone(&state.redis.store);
+        let record_key = format!("idempotency:{}:{}", server_id, key);
+        let request = format!("{} {}", req.method(), req.uri());
+
+        let pending = IdempotencyRecord { request: request.clone(), body_hash: None, response: None };
+        let pending = serde_json::to_string(&pending).unwrap_or_default();
+        match store.set_if_absent(&record_key, &pending, CLAIM_MS).await {
+            Ok(true) => return IdempotencyOutcome::Claimed { store, key: record_key, request },
+            Ok(false) => {},
+            Err(e) => {
+                // without redis the request goes through as if it had no key
+                warn!("Could not claim idempotency key '{}': {}", record_key, e);
+                return IdempotencyOutcome::None;
+            }
+        };
+        let record = store.get(&record_key).await.ok().flatten().and_then(|raw| serde_json::from_str::<IdempotencyRecord>(&raw).ok());
+        match record {
+            Some(IdempotencyRecord { response: Some(_), request: stored, .. }) if stored != request => IdempotencyOutcome::Rejected(Rejection::Reused),
+            Some(IdempotencyRecord { response: Some(response), body_hash, .. }) => IdempotencyOutcome::Replay { response, body_hash },
+            // still pending, or expired between the two calls
+            _ => IdempotencyOutcome::Rejected(Rejection::InUse)
+        }
+    }
+
+    async fn store(res: &mut Response<'_>, store: &Arc<dyn CacheStore>, key: &str, request: &str, body_hash: Option<String>) {
+        // nothing was applied, or it was only turned away for now, so a retry should run again
+        if res.status().class().is_server_error() || [Status::Unauthorized, Status::Conflict, Status::TooManyRequests].contains(&res.status()) {
+            let _ = store.del(key).await;
+            return;
+        };
+        let body = match res.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Could not read the response for idempotency key '{}': {}", key, e);
+                let _ = store.del(key).await;
+                return;
+            }
+        };
+        res.set_sized_body(body.len(), Cursor::new(body.clone()));
+        let body = match String::from_utf8(body) {
+            Ok(body) => body,
+            Err(_) => {
+                let _ = store.del(key).await;
+                return;
+            }
+        };
+        let response = StoredResponse { status: res.status().code, content_type: res.content_type().map(|content_type| content_type.to_string()), body };
+        let record = IdempotencyRecord { request: request.to_owned(), body_hash, response: Some(response) };
+        if let Ok(stringified) = serde_json::to_string(&record) {
+            if let Err(e) = store.set(key, &stringified, Some(WINDOW_MS)).await {
+                warn!("Could not store the response for idempotency key '{}': {}", key, e);
+            };
+        };
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Idempotency {
+    fn info(&self) -> Info {
+        Info { name: "Idempotency keys", kind: Kind::Request | Kind::Response }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _data: &mut Data<'_>) {
+        if req.method() != Method::Post && req.method() != Method::Put {
+            return;
+        };
+        let key = match req.headers().get_one(HEADER) {
+            Some(key) => key.trim().to_owned(),
+            None => return
+        };
+        let outcome = Self::claim(req, &key).await;
+        if matches!(outcome, IdempotencyOutcome::Replay { .. } | IdempotencyOutcome::Rejected(_)) {
+            // answered by the replay route instead of the handler, the method is kept so it can read the body
+            req.set_uri(Origin::parse(REPLAY_PATH).unwrap());
+        };
+        req.local_cache(|| outcome);
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        if let IdempotencyOutcome::Claimed { store, key, request } = req.local_cache(|| IdempotencyOutcome::None) {
+            let body_hash = req.local_cache(|| BodyHash(None)).0.clone();
+            Self::store(res, store, key, request, body_hash).await;
+        };
+    }
+}
+
+pub struct ReplayedResponse(StoredResponse);
+
+impl<'r> Responder<'r, 'static> for ReplayedResponse {
+    fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response.status(Status::from_code(self.0.status).unwrap_or(Status::Ok))
+            .header(Header::new(REPLAYED_HEADER, "true"))
+            .sized_body(self.0.body.len(), Cursor::new(self.0.body));
+        if let Some(content_type) = self.0.content_type.as_deref().and_then(ContentType::parse_flexible) {
+            response.header(content_type);
+        };
+        response.ok()
+    }
+}
+
+pub struct IdempotencyReplay(Result<(StoredResponse, Option<String>), Rejection>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IdempotencyReplay {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        match req.local_cache(|| IdempotencyOutcome::None) {
+            IdempotencyOutcome::Replay { response, body_hash } => request::Outcome::Success(IdempotencyReplay(Ok((response.clone(), body_hash.clone())))),
+            IdempotencyOutcome::Rejected(rejection) => request::Outcome::Success(IdempotencyReplay(Err(*rejection))),
+            _ => request::Outcome::Forward(Status::NotFound)
+        }
+    }
+}
+
+async fn replay(limits: &Limits, replay: IdempotencyReplay, data: Data<'_>) -> Result<ReplayedResponse, ApiErrorResponder> {
+    let (response, body_hash) = match replay.0 {
+        Ok(replay) => replay,
+        Err(Rejection::InvalidKey) => return Err(ApiErrorResponder::validation_error_with_message("The idempotency key must be between 1 and 255 characters")),
+        Err(Rejection::InUse) => return Err(ApiErrorResponder::idempotency_key_in_use()),
+        Err(Rejection::Reused) => return Err(ApiErrorResponder::idempotency_key_reused())
+    };
+    if let Some(body_hash) = body_hash {
+        // read within the same limit as the first request, a body over it can't be the one that was handled
+        let limit = limits.get("json").unwrap_or(Limits::JSON);
+        let body = data.open(limit).into_bytes().await;
+        if !matches!(body, Ok(body) if body.is_complete() && hash_body(&body) == body_hash) {
+            return Err(ApiErrorResponder::idempotency_key_reused());
+        };
+    };
+    Ok(ReplayedResponse(response))
+}
+
+#[post("/replay", data = "<data>")]
+async fn replay_post(limits: &Limits, replay_guard: IdempotencyReplay, _auth_guard: AuthorizationToken, data: Data<'_>) -> Result<ReplayedResponse, ApiErrorResponder> {
+    replay(limits, replay_guard, data).await
+}
+
+#[put("/replay", data = "<data>")]
+async fn replay_put(limits: &Limits, replay_guard: IdempotencyReplay, _auth_guard: AuthorizationToken, data: Data<'_>) -> Result<ReplayedResponse, ApiErrorResponder> {
+    replay(limits, replay_guard, data).await
+}
+
+pub fn mount(rocket_build: Rocket<Build>) -> Rocket<Build> {
+    rocket_build.mount("/_idempotency", routes![replay_post, replay_put]).attach(Idempotency)
+}
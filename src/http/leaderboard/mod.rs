@@ -1,8 +1,8 @@
 use std::str::FromStr;
 
-use rocket::{Rocket, Build, State, serde::json::Json};
+use rocket::{Rocket, Build, serde::json::Json};
 
-use crate::{MarsAPIState, socket::leaderboard::{ScoreType, LeaderboardEntry, LeaderboardPeriod}, util::{r#macro::unwrap_helper, error::ApiErrorResponder}};
+use crate::{network::NetworkState, socket::leaderboard::{ScoreType, LeaderboardEntry, LeaderboardPeriod}, util::{r#macro::unwrap_helper, error::ApiErrorResponder}};
 use crate::util::string::enumify;
 
 const PUBLIC_SCORE_TYPES : &[ScoreType] = &[
@@ -32,7 +32,7 @@ const PUBLIC_SCORE_TYPES : &[ScoreType] = &[
 
 #[get("/<score_type>/<period>?<limit>")]
 async fn get_leaderboard_entries(
-    state: &State<MarsAPIState>, 
+    state: NetworkState<'_>, 
     score_type: &str, 
     period: &str, 
     limit: Option<u32>
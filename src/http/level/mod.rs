@@ -1,9 +1,9 @@
-use rocket::{Rocket, Build, State, serde::json::Json};
-use crate::{MarsAPIState, database::models::level_color::LevelColor};
+use rocket::{Rocket, Build, serde::json::Json};
+use crate::{network::NetworkState, database::models::level_color::LevelColor};
 
 #[get("/colors")]
-fn get_level_colors(state: &State<MarsAPIState>) -> Json<&Vec<LevelColor>> {
-    Json(&state.config.data.level_colors)
+fn get_level_colors(state: NetworkState<'_>) -> Json<&Vec<LevelColor>> {
+    Json(&state.inner().config.data.level_colors)
 }
 
 pub fn mount(rocket_build: Rocket<Build>) -> Rocket<Build> {
@@ -1,15 +1,16 @@
 use futures::future::join_all;
 use mongodb::bson::doc;
-use rocket::{Rocket, Build, State, serde::json::Json};
+use rocket::{Rocket, Build, serde::json::Json};
 
-use crate::{MarsAPIState, http::map::payload::MapLoadOneRequest, util::{auth::AuthorizationToken, time::get_u64_time_millis, r#macro::unwrap_helper, error::ApiErrorResponder}, database::{models::level::{Level, LevelRecords}, Database}};
+use crate::http::idempotency::HashedJson;
+use crate::{network::NetworkState, http::map::payload::MapLoadOneRequest, util::{auth::AuthorizationToken, time::get_u64_time_millis, r#macro::unwrap_helper, error::ApiErrorResponder}, database::{models::level::{Level, LevelRecords}, Database}};
 
 mod payload;
 
 #[post("/", format = "json", data = "<maps>")]
 async fn add_maps(
-    state: &State<MarsAPIState>,
-    maps: Json<Vec<MapLoadOneRequest>>,
+    state: NetworkState<'_>,
+    maps: HashedJson<Vec<MapLoadOneRequest>>,
     _auth_guard: AuthorizationToken
 ) -> Json<Vec<Level>> {
     let map_list = maps.0;
@@ -59,12 +60,12 @@ async fn add_maps(
 }
 
 #[get("/")]
-async fn get_all_maps(state: &State<MarsAPIState>) -> Json<Vec<Level>> {
+async fn get_all_maps(state: NetworkState<'_>) -> Json<Vec<Level>> {
     Json(state.database.get_all_documents::<Level>().await)
 }
 
 #[get("/<map_id>")]
-async fn get_map_by_id(state: &State<MarsAPIState>, map_id: &str) -> Result<Json<Level>, ApiErrorResponder> {
+async fn get_map_by_id(state: NetworkState<'_>, map_id: &str) -> Result<Json<Level>, ApiErrorResponder> {
     let map = unwrap_helper::return_default!(Database::find_by_id(&state.database.levels, map_id).await, Err(ApiErrorResponder::missing_map()));
     Ok(Json(map))
 }
@@ -1,9 +1,9 @@
-use rocket::{State, Build, Rocket};
-use crate::{database::models::r#match::Match, MarsAPIState, util::{responder::JsonResponder, error::ApiErrorResponder, r#macro::unwrap_helper}};
+use rocket::{Build, Rocket};
+use crate::{database::models::r#match::Match, network::NetworkState, util::{responder::JsonResponder, error::ApiErrorResponder, r#macro::unwrap_helper}};
 
 #[get("/<match_id>")]
 pub async fn matches(
-    state: &State<MarsAPIState>,
+    state: NetworkState<'_>,
     match_id: &str
 ) -> Result<JsonResponder<Match>, ApiErrorResponder> {
     let match_id = match_id.to_lowercase();
@@ -17,7 +17,7 @@ pub async fn matches(
 
 #[get("/?<limit>")]
 pub async fn recent_matches(
-    state: &State<MarsAPIState>,
+    state: NetworkState<'_>,
     limit: Option<i64>,
 ) -> Result<JsonResponder<Vec<Match>>, ApiErrorResponder> {
     let limit = limit.unwrap_or(5).min(10);
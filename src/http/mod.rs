@@ -12,3 +12,7 @@ pub mod tag;
 pub mod perks;
 pub mod r#match;
 pub mod achievements;
+pub mod chat;
+pub mod dead_letter;
+pub mod cache;
+pub mod idempotency;
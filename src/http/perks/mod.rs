@@ -1,6 +1,7 @@
-use rocket::{Rocket, State, Build, serde::json::Json};
+use rocket::{Rocket, Build, serde::json::Json};
 
-use crate::{MarsAPIState, database::models::{join_sound::JoinSound, player::Player}, util::{auth::AuthorizationToken, responder::JsonResponder, error::ApiErrorResponder}};
+use crate::http::idempotency::HashedJson;
+use crate::{network::NetworkState, database::models::{join_sound::JoinSound, player::Player}, util::{auth::AuthorizationToken, responder::JsonResponder, error::ApiErrorResponder}};
 
 use self::payload::JoinSoundSetRequest;
 
@@ -8,26 +9,28 @@ mod payload;
 
 #[get("/join_sounds")]
 fn get_join_sounds(
-    state: &State<MarsAPIState>
+    state: NetworkState<'_>
 ) -> Json<&Vec<JoinSound>> {
-    Json(&state.config.data.join_sounds)
+    Json(&state.inner().config.data.join_sounds)
 }
 
 #[post("/join_sounds/<player_id>/sound", format = "json", data = "<set_join_req>")]
 async fn update_join_sound(
-    state: &State<MarsAPIState>,
+    state: NetworkState<'_>,
     player_id: &str,
-    set_join_req: Json<JoinSoundSetRequest>,
+    set_join_req: HashedJson<JoinSoundSetRequest>,
     _auth_guard: AuthorizationToken
 ) -> Result<JsonResponder<Player>, ApiErrorResponder> {
     match state.player_cache.get(&state.database, player_id).await {
-        Some(mut p) => {
+        Some(p) => {
             let current_sound = set_join_req.0.active_join_sound_id;
             if p.active_join_sound_id == current_sound {
                 return Ok(JsonResponder::ok(p));
             };
-            p.active_join_sound_id = current_sound;
-            state.player_cache.set(&state.database, &p.name, &p, true).await;
+            let p = state.player_cache.update(&state.database, &p.name, |p| {
+                p.active_join_sound_id = current_sound.clone();
+                Ok(())
+            }).await.map_err(ApiErrorResponder::from_player_update)?;
             Ok(JsonResponder::ok(p))
         },
         None => {
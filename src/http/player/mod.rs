@@ -3,9 +3,10 @@ mod payloads;
 use futures::future::join_all;
 use mongodb::bson::doc;
 use payloads::PlayerPreLoginRequest;
-use rocket::{serde::json::Json, Build, Rocket, State, http::Status};
+use rocket::{serde::json::Json, Build, Rocket, http::Status};
 use uuid::Uuid;
-use crate::{util::{auth::AuthorizationToken, error::{ApiError, ApiErrorResponder}, string::to_utf8_byte_array, responder::{JsonResponder, EmptyResponse}, time::get_u64_time_millis, r#macro::unwrap_helper}, MarsAPIState, database::{Database, models::{punishment::{Punishment, PunishmentKind, StaffNote}, player::{Player, PlayerStats, SessionRecord}, session::Session, rank::Rank, tag::Tag}}, http::player::payloads::{PlayerLoginRequest, PlayerLookupResponse, PlayerAddNoteRequest, PlayerSetActiveTagRequest}, socket::leaderboard::{Leaderboard, ScoreType, LeaderboardPeriod}};
+use crate::http::idempotency::HashedJson;
+use crate::{util::{auth::AuthorizationToken, error::{ApiError, ApiErrorResponder}, string::to_utf8_byte_array, responder::{JsonResponder, EmptyResponse}, time::get_u64_time_millis, r#macro::unwrap_helper}, MarsAPIState, network::NetworkState, database::{Database, presence::PlayerPresence, models::{punishment::{Punishment, PunishmentKind, StaffNote}, player::{Player, PlayerStats, SessionRecord}, session::Session, rank::Rank, tag::Tag}}, http::player::payloads::{PlayerLoginRequest, PlayerLookupResponse, PlayerAddNoteRequest, PlayerMessageRequest, PlayerSetActiveTagRequest}, socket::{event_type::EventType, leaderboard::{Leaderboard, ScoreType, LeaderboardPeriod}, player::player_events::MessageData}};
 use sha2::{Sha256, Digest};
 
 use self::payloads::{PlayerPreLoginResponse, PlayerPreLoginResponder, PlayerLoginResponse, PlayerLogoutRequest, PlayerProfileResponder, PlayerProfileResponse, PlayerAltResponse};
@@ -14,10 +15,25 @@ use crate::database::models::ip_identity::IpIdentity;
 
 use super::punishment::payloads::PunishmentIssueRequest;
 
+// the previous holder's cached copy still claims the name, so it's evicted before it can be written back
+async fn free_player_name(state: &MarsAPIState, name: &String, player_id: &String) -> Result<(), ApiErrorResponder> {
+    let holder_ids = match state.database.ensure_player_name_uniqueness(name, player_id).await {
+        Ok(holder_ids) => holder_ids,
+        Err(e) => {
+            warn!("Could not free the name {} for {}: {}", name, player_id, e);
+            return Err(ApiErrorResponder::create_anonymous_error(Status::InternalServerError, "Could not free the player's name"));
+        }
+    };
+    for holder_id in holder_ids {
+        state.player_cache.evict(&holder_id).await;
+    }
+    Ok(())
+}
+
 #[post("/<player_id>/prelogin", format = "json", data = "<prelogin_req>")]
 pub async fn prelogin(
-    state: &State<MarsAPIState>, 
-    prelogin_req: Json<PlayerPreLoginRequest>, 
+    state: NetworkState<'_>, 
+    prelogin_req: HashedJson<PlayerPreLoginRequest>, 
     player_id: &str, 
     _auth_guard: AuthorizationToken
 ) -> Result<PlayerPreLoginResponder, ApiErrorResponder> {
@@ -60,8 +76,9 @@ pub async fn prelogin(
                 is_ip_banned
             }
         };
-        state.player_cache.set(&state.database, &returning_player.name, &returning_player, true).await;
-        state.database.ensure_player_name_uniqueness(&data.player.name, &data.player.id).await;
+        // frees the name first, players.nameLower is a unique index
+        free_player_name(&state, &data.player.name, &data.player.id).await?;
+        state.player_cache.overwrite(&state.database, &returning_player.name.clone(), &mut returning_player, true).await;
         // denormalize ip player relationship
         if new_ip {
             IpIdentity::add_player_ip(&state.database, &ip,  &returning_player.id).await;
@@ -78,7 +95,7 @@ pub async fn prelogin(
     } else {
         debug!("Could not find player {} in database!", player_id);
         let time_millis : f64 = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as f64;
-        let player = Player {
+        let mut player = Player {
             id: data.player.id.clone(),
             name: data.player.name.clone(),
             name_lower: data.player.name.to_lowercase(),
@@ -92,11 +109,14 @@ pub async fn prelogin(
             gamemode_stats: HashMap::new(),
             notes: Vec::new(),
             last_session_id: None,
-            active_join_sound_id: None
+            active_join_sound_id: None,
+            version: 0,
+            loaded_stats: None
         };
 
-        state.player_cache.set(&state.database, &player.name, &player, true).await;
-        state.database.ensure_player_name_uniqueness(&data.player.name, &data.player.id).await;
+        // frees the name first, players.nameLower is a unique index
+        free_player_name(&state, &data.player.name, &data.player.id).await?;
+        state.player_cache.overwrite(&state.database, &player.name.clone(), &mut player, true).await;
         IpIdentity::add_player_ip(&state.database, &ip, &data.player.id).await;
 
         Ok(PlayerPreLoginResponder {
@@ -127,13 +147,13 @@ macro_rules! async_extract_player_from_url_v2 {
 
 #[post("/<player_id>/login", format = "json", data = "<login_req>")]
 pub async fn login(
-    state: &State<MarsAPIState>, 
-    login_req: Json<PlayerLoginRequest>, 
+    state: NetworkState<'_>, 
+    login_req: HashedJson<PlayerLoginRequest>, 
     player_id: &str, 
     auth_guard: AuthorizationToken
 ) -> Result<JsonResponder<PlayerLoginResponse>, ApiErrorResponder> {
     let data = login_req.0;
-    let mut player : Player = async_extract_player_from_url_v2!(&data.player.name, state);
+    let player : Player = async_extract_player_from_url_v2!(&data.player.name, state);
 
     if player_id != player.id || player.id != data.player.id { 
         return Err(ApiErrorResponder::validation_error());
@@ -149,7 +169,7 @@ pub async fn login(
         id: Uuid::new_v4().to_string(),
         player: player.to_simple(),
         ip: ip.clone(),
-        server_id: auth_guard.server_id,
+        server_id: auth_guard.server_id.clone(),
         created_at: time_millis,
         ended_at: None
     };
@@ -163,10 +183,12 @@ pub async fn login(
     player_ranks.append(&mut default_ranks);
     player_ranks.dedup();
 
-    player.last_joined_at = time_millis as f64;
-    player.last_session_id = Some(active_session.id.clone());
-
-    state.player_cache.set(&state.database, &player.name, &player, true).await;
+    let player = state.player_cache.update(&state.database, &player.name, |player| {
+        player.last_joined_at = time_millis as f64;
+        player.last_session_id = Some(active_session.id.clone());
+        Ok(())
+    }).await.map_err(ApiErrorResponder::from_player_update)?;
+    state.presence.set_online(&player.to_simple(), &auth_guard.server_id, &active_session.id).await;
 
     Ok(JsonResponder::from(PlayerLoginResponse { active_session }, Status::Created))
 }
@@ -174,12 +196,12 @@ pub async fn login(
 
 #[post("/logout", format = "json", data = "<logout_req>")]
 pub async fn logout(
-    state: &State<MarsAPIState>, 
-    logout_req: Json<PlayerLogoutRequest>, 
+    state: NetworkState<'_>, 
+    logout_req: HashedJson<PlayerLogoutRequest>, 
     _auth_guard: AuthorizationToken
 ) -> Result<JsonResponder<EmptyResponse>, ApiErrorResponder> {
     let data = logout_req.0;
-    let mut player : Player = async_extract_player_from_url_v2!(&data.player.name, state);
+    let player : Player = async_extract_player_from_url_v2!(&data.player.name, state);
     let mut session = if let Some(session) = state.database.find_session_for_player(&player, data.session_id).await {
         session
     } else {
@@ -191,21 +213,24 @@ pub async fn logout(
 
     let time_millis : u64 = u64::try_from(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis()).unwrap_or(u64::MAX);
     session.ended_at = Some(time_millis);
-    player.stats.server_playtime += (data.playtime as i64);
+    let player = state.player_cache.update(&state.database, &player.name, |player| {
+        player.stats.server_playtime += (data.playtime as i64);
 
-    state.leaderboards.server_playtime.increment(&player.id_name(), Some(u32::try_from(data.playtime).unwrap_or(u32::MAX))).await; // Will break in 2106
+        let record_session = if let Some(session_record) = &player.stats.records.longest_session {
+            Some(session_record.length.clone())
+        } else {
+            None
+        };
+        if record_session.is_none() || data.playtime > record_session.unwrap() {
+            player.stats.records.longest_session = Some(SessionRecord { session_id: session.id.clone(), length: data.playtime.clone() });
+        };
+        Ok(())
+    }).await.map_err(ApiErrorResponder::from_player_update)?;
 
-    let record_session = if let Some(session_record) = &player.stats.records.longest_session {
-        Some(session_record.length.clone())
-    } else {
-        None
-    };
-    if record_session.is_none() || data.playtime > record_session.unwrap() {
-        player.stats.records.longest_session = Some(SessionRecord { session_id: session.id.clone(), length: data.playtime.clone() });
-    };
+    state.leaderboards.server_playtime.increment(&player.id_name(), Some(u32::try_from(data.playtime).unwrap_or(u32::MAX))).await; // Will break in 2106
 
     state.database.save(&session).await;
-    state.player_cache.set(&state.database, &player.name, &player, true).await;
+    state.presence.set_offline(&player.id, &session.id).await;
 
     Ok(JsonResponder::ok(EmptyResponse {}))
 }
@@ -213,7 +238,7 @@ pub async fn logout(
 
 #[get("/<player_id>?<include_leaderboard_positions>")]
 pub async fn profile(
-    state: &State<MarsAPIState>, 
+    state: NetworkState<'_>, 
     player_id: &str,
     include_leaderboard_positions: bool
 ) -> Result<PlayerProfileResponder, ApiErrorResponder> {
@@ -271,11 +296,47 @@ pub async fn profile(
 }
 
 
+#[get("/online")]
+pub async fn online_players(state: NetworkState<'_>) -> JsonResponder<Vec<PlayerPresence>> {
+    JsonResponder::ok(state.presence.get_all().await)
+}
+
+#[get("/<player_id>/presence")]
+pub async fn player_presence(
+    state: NetworkState<'_>, 
+    player_id: &str
+) -> Result<JsonResponder<PlayerPresence>, ApiErrorResponder> {
+    if let Some(presence) = state.presence.get(player_id).await {
+        return Ok(JsonResponder::ok(presence));
+    };
+    // also accept names
+    let player : Player = async_extract_player_from_url_v2!(player_id, state);
+    let presence = unwrap_helper::return_default!(state.presence.get(&player.id).await, Err(ApiErrorResponder::player_offline()));
+    Ok(JsonResponder::ok(presence))
+}
+
+#[post("/<player_id>/message", format = "json", data = "<message_req>")]
+pub async fn send_player_message(
+    state: NetworkState<'_>, 
+    player_id: &str,
+    message_req: HashedJson<PlayerMessageRequest>,
+    _auth_guard: AuthorizationToken
+) -> Result<JsonResponder<PlayerPresence>, ApiErrorResponder> {
+    let data = message_req.0;
+    let player : Player = async_extract_player_from_url_v2!(player_id, state);
+    let presence = unwrap_helper::return_default!(state.presence.get(&player.id).await, Err(ApiErrorResponder::player_offline()));
+    let message_data = MessageData { message: data.message, sound: data.sound, player_ids: vec![player.id.clone()] };
+    if !state.servers.call(&presence.server_id, &EventType::Message, message_data).await {
+        return Err(ApiErrorResponder::server_not_connected());
+    };
+    Ok(JsonResponder::ok(presence))
+}
+
 // why isn't the url parameter used?
 #[post("/<_player_id>/punishments", format = "json", data = "<pun_issue_req>")]
 pub async fn issue_punishment(
-    state: &State<MarsAPIState>, 
-    pun_issue_req: Json<PunishmentIssueRequest>,
+    state: NetworkState<'_>, 
+    pun_issue_req: HashedJson<PunishmentIssueRequest>,
     _player_id: &str,
     auth_guard: AuthorizationToken
 ) -> Result<JsonResponder<Punishment>, ApiErrorResponder> {
@@ -312,7 +373,7 @@ pub async fn issue_punishment(
 
 #[get("/<player_id>/punishments")]
 pub async fn get_punishments(
-    state: &State<MarsAPIState>, 
+    state: NetworkState<'_>, 
     player_id: &str,
     _auth_guard: AuthorizationToken
 ) -> Result<JsonResponder<Vec<Punishment>>, ApiErrorResponder> {
@@ -337,7 +398,7 @@ pub fn sha256_hash_formatted(digest: &String) -> String {
 
 #[get("/<player_id>/lookup?<alts>")]
 pub async fn lookup_player(
-    state: &State<MarsAPIState>, 
+    state: NetworkState<'_>, 
     player_id: &str,
     alts: bool,
     _auth_guard: AuthorizationToken
@@ -365,19 +426,21 @@ pub async fn lookup_player(
 
 #[post("/<player_id>/notes", format = "json", data = "<add_note_req>")]
 pub async fn add_player_note(
-    state: &State<MarsAPIState>, 
+    state: NetworkState<'_>, 
     player_id: &str,
-    add_note_req: Json<PlayerAddNoteRequest>,
+    add_note_req: HashedJson<PlayerAddNoteRequest>,
     _auth_guard: AuthorizationToken
 ) -> Result<JsonResponder<Player>, ApiErrorResponder> {
     let data = add_note_req.0;
-    let mut player : Player = async_extract_player_from_url_v2!(&player_id, state);
-    let id = player.notes.iter().max_by_key(|note| note.id).map(|note| note.id).unwrap_or(0) + 1;
-    let note = StaffNote { id, author: data.author, content: data.content, created_at: get_u64_time_millis() };
-    let note_clone = note.clone();
-    player.notes.push(note);
-    state.player_cache.set(&state.database, player_id, &player, true).await;
-    {
+    let mut added_note = None;
+    let player = state.player_cache.update(&state.database, player_id, |player| {
+        let id = player.notes.iter().max_by_key(|note| note.id).map(|note| note.id).unwrap_or(0) + 1;
+        let note = StaffNote { id, author: data.author.clone(), content: data.content.clone(), created_at: get_u64_time_millis() };
+        added_note = Some(note.clone());
+        player.notes.push(note);
+        Ok(())
+    }).await.map_err(ApiErrorResponder::from_player_update)?;
+    if let Some(note_clone) = added_note {
         // take ownership for the spawned task
         let state_clone = state.config.clone();
         let player_simple = player.to_simple();
@@ -390,17 +453,18 @@ pub async fn add_player_note(
 
 #[delete("/<player_id>/notes/<note_id>")]
 pub async fn delete_player_note(
-    state: &State<MarsAPIState>, 
+    state: NetworkState<'_>, 
     player_id: &str,
     note_id: u32,
     _auth_guard: AuthorizationToken
 ) -> Result<JsonResponder<Player>, ApiErrorResponder> {
-    let mut player : Player = async_extract_player_from_url_v2!(&player_id, state);
-    let note_index = unwrap_helper::return_default!(player.notes.iter().position(|note| { note.id == note_id }), Err(ApiErrorResponder::note_missing()));
-    let note_clone = player.notes[note_index].clone();
-    player.notes.remove(note_index);
-    state.player_cache.set(&state.database, player_id, &player, true).await;
-    {
+    let mut deleted_note = None;
+    let player = state.player_cache.update(&state.database, player_id, |player| {
+        let note_index = unwrap_helper::return_default!(player.notes.iter().position(|note| { note.id == note_id }), Err(ApiErrorResponder::note_missing()));
+        deleted_note = Some(player.notes.remove(note_index));
+        Ok(())
+    }).await.map_err(ApiErrorResponder::from_player_update)?;
+    if let Some(note_clone) = deleted_note {
         // take ownership for the spawned task
         let state_clone = state.config.clone();
         let player_simple = player.to_simple();
@@ -413,110 +477,116 @@ pub async fn delete_player_note(
 
 #[put("/<player_id>/active_tag", format = "json", data = "<tag_set_req>")]
 async fn set_active_tag(
-    state: &State<MarsAPIState>, 
+    state: NetworkState<'_>, 
     player_id: &str, 
-    tag_set_req: Json<PlayerSetActiveTagRequest>,
+    tag_set_req: HashedJson<PlayerSetActiveTagRequest>,
     _auth_guard: AuthorizationToken
 ) -> Result<JsonResponder<Player>, ApiErrorResponder> {
     let tag_id = tag_set_req.active_tag_id.clone();
-    let mut player = async_extract_player_from_url_v2!(player_id, state);
+    let player = async_extract_player_from_url_v2!(player_id, state);
 
     if tag_id == player.active_tag_id {
         return Ok(JsonResponder::from(player, Status::Ok));
     }
 
-    if tag_id.is_none() {
-        player.active_tag_id = Option::None;
-    } else {
-        if !player.tag_ids.contains(tag_id.as_ref().unwrap()) {
-            return Err(ApiErrorResponder::tag_missing_from_player());
+    let player = state.player_cache.update(&state.database, &player.name, |player| {
+        if tag_id.is_none() {
+            player.active_tag_id = Option::None;
+        } else {
+            if !player.tag_ids.contains(tag_id.as_ref().unwrap()) {
+                return Err(ApiErrorResponder::tag_missing_from_player());
+            }
+            player.active_tag_id = tag_id.clone();
         }
-        player.active_tag_id = tag_id;
-    }
-
-    state.player_cache.set(&state.database, &player.name, &player, true).await;
+        Ok(())
+    }).await.map_err(ApiErrorResponder::from_player_update)?;
     return Ok(JsonResponder::from(player, Status::Ok));
 }
 
 #[put("/<player_id>/tags/<tag_id>")]
 async fn add_tag_to_player(
-    state: &State<MarsAPIState>, 
+    state: NetworkState<'_>, 
     player_id: &str, 
     tag_id: &str, 
     _auth_guard: AuthorizationToken
 ) -> Result<JsonResponder<Player>, ApiErrorResponder> {
-    let mut player = async_extract_player_from_url_v2!(player_id, state);
+    let player = async_extract_player_from_url_v2!(player_id, state);
 
     let tag = match state.database.find_by_id_or_name::<Tag>(tag_id).await {
         Some(tag) => tag,
         None => return Err(ApiErrorResponder::tag_missing())
     };
 
-    if player.tag_ids.contains(&tag.id) {
-        return Err(ApiErrorResponder::tag_already_present());
-    }
-
-    player.tag_ids.push(tag.id.clone());
-    state.player_cache.set(&state.database, &player.name, &player, true).await;
+    let player = state.player_cache.update(&state.database, &player.name, |player| {
+        if player.tag_ids.contains(&tag.id) {
+            return Err(ApiErrorResponder::tag_already_present());
+        }
+        player.tag_ids.push(tag.id.clone());
+        Ok(())
+    }).await.map_err(ApiErrorResponder::from_player_update)?;
     return Ok(JsonResponder::from(player, Status::Ok));
 }
 
 
 #[delete("/<player_id>/tags/<tag_id>")]
 async fn delete_player_tag(
-    state: &State<MarsAPIState>,
+    state: NetworkState<'_>,
     player_id: &str,
     tag_id: &str,
     _auth_guard: AuthorizationToken
 ) -> Result<JsonResponder<Player>, ApiErrorResponder> {
-    let mut player = async_extract_player_from_url_v2!(player_id, state);
+    let player = async_extract_player_from_url_v2!(player_id, state);
     let tag = match state.database.find_by_id_or_name::<Tag>(tag_id).await {
         Some(tag) => tag,
         None => return Err(ApiErrorResponder::tag_missing())
     };
-    match player.tag_ids.iter().position(|itag| { itag == &tag.id }) {
-        Some(tag_index) => player.tag_ids.swap_remove(tag_index),
-        None => return Err(ApiErrorResponder::tag_missing_from_player())
-    };
-    if player.active_tag_id.is_some() && player.active_tag_id.as_ref().unwrap() == &tag.id {
-        player.active_tag_id = Option::None;
-    }
-    state.player_cache.set(&state.database, &player.name, &player, true).await;
+    let player = state.player_cache.update(&state.database, &player.name, |player| {
+        match player.tag_ids.iter().position(|itag| { itag == &tag.id }) {
+            Some(tag_index) => player.tag_ids.swap_remove(tag_index),
+            None => return Err(ApiErrorResponder::tag_missing_from_player())
+        };
+        if player.active_tag_id.is_some() && player.active_tag_id.as_ref().unwrap() == &tag.id {
+            player.active_tag_id = Option::None;
+        }
+        Ok(())
+    }).await.map_err(ApiErrorResponder::from_player_update)?;
     return Ok(JsonResponder::from(player, Status::Ok));
 
 }
 
 #[put("/<player_id>/ranks/<rank_id>")]
 async fn add_player_rank(
-    state: &State<MarsAPIState>, 
+    state: NetworkState<'_>, 
     player_id: &str, 
     rank_id: &str, 
     _auth_guard: AuthorizationToken
 ) -> Result<Json<Player>, ApiErrorResponder> {
-    let mut player = unwrap_helper::return_default!(state.player_cache.get(&state.database, player_id).await, Err(ApiErrorResponder::missing_player()));
+    let player = unwrap_helper::return_default!(state.player_cache.get(&state.database, player_id).await, Err(ApiErrorResponder::missing_player()));
     let rank = unwrap_helper::return_default!(state.database.find_by_id_or_name::<Rank>(rank_id).await, Err(ApiErrorResponder::missing_rank()));
 
-    if player.rank_ids.contains(&rank.id) { return Err(ApiErrorResponder::rank_already_present()); };
-    player.rank_ids.push(rank.id);
-
-    state.player_cache.set(&state.database, &player.name, &player, true).await;
+    let player = state.player_cache.update(&state.database, &player.name, |player| {
+        if player.rank_ids.contains(&rank.id) { return Err(ApiErrorResponder::rank_already_present()); };
+        player.rank_ids.push(rank.id.clone());
+        Ok(())
+    }).await.map_err(ApiErrorResponder::from_player_update)?;
     Ok(Json(player))
 }
 
 #[delete("/<player_id>/ranks/<rank_id>")]
 async fn delete_player_rank(
-    state: &State<MarsAPIState>, 
+    state: NetworkState<'_>, 
     player_id: &str, 
     rank_id: &str, 
     _auth_guard: AuthorizationToken
 ) -> Result<Json<Player>, ApiErrorResponder> {
-    let mut player = unwrap_helper::return_default!(state.player_cache.get(&state.database, player_id).await, Err(ApiErrorResponder::missing_player()));
+    let player = unwrap_helper::return_default!(state.player_cache.get(&state.database, player_id).await, Err(ApiErrorResponder::missing_player()));
     let rank = unwrap_helper::return_default!(state.database.find_by_id_or_name::<Rank>(rank_id).await, Err(ApiErrorResponder::missing_rank()));
 
-    if !player.rank_ids.contains(&rank.id) { return Err(ApiErrorResponder::rank_not_present()); };
-    player.rank_ids.retain(|rank_id| { rank_id != rank.id.as_str() });
-
-    state.player_cache.set(&state.database, &player.name, &player, true).await;
+    let player = state.player_cache.update(&state.database, &player.name, |player| {
+        if !player.rank_ids.contains(&rank.id) { return Err(ApiErrorResponder::rank_not_present()); };
+        player.rank_ids.retain(|rank_id| { rank_id != rank.id.as_str() });
+        Ok(())
+    }).await.map_err(ApiErrorResponder::from_player_update)?;
     Ok(Json(player))
 }
 
@@ -525,6 +595,9 @@ pub fn mount(rocket_build: Rocket<Build>) -> Rocket<Build> {
         prelogin, 
         login, 
         logout, 
+        online_players,
+        player_presence,
+        send_player_message,
         profile, 
         issue_punishment, 
         get_punishments,
@@ -119,6 +119,14 @@ pub struct PlayerAddNoteRequest {
     pub content: String
 }
 
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerMessageRequest {
+    pub message: String,
+    #[serde(default)]
+    pub sound: Option<String>
+}
+
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PlayerSetActiveTagRequest {
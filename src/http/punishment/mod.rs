@@ -1,19 +1,20 @@
-use rocket::{Rocket, Build, serde::json::Json, State};
+use rocket::{Rocket, Build, serde::json::Json};
 
-use crate::{database::{models::punishment::{PunishmentType, Punishment, PunishmentReversion}, Database}, MarsAPIState, util::{error::ApiErrorResponder, auth::AuthorizationToken, r#macro::unwrap_helper, time::get_u64_time_millis}};
+use crate::http::idempotency::HashedJson;
+use crate::{database::{models::punishment::{PunishmentType, Punishment, PunishmentReversion}, Database}, network::NetworkState, util::{error::ApiErrorResponder, auth::AuthorizationToken, r#macro::unwrap_helper, time::get_u64_time_millis}};
 
 use self::payloads::PunishmentRevertRequest;
 
 pub mod payloads;
 
 #[get("/types")]
-fn get_pun_types(state: &State<MarsAPIState>, _auth_guard: AuthorizationToken) -> Json<&Vec<PunishmentType>> {
-    Json(&state.config.data.punishment_types)
+fn get_pun_types(state: NetworkState<'_>, _auth_guard: AuthorizationToken) -> Json<&Vec<PunishmentType>> {
+    Json(&state.inner().config.data.punishment_types)
 }
 
 #[get("/<punishment_id>")]
 async fn get_pun(
-    state: &State<MarsAPIState>, 
+    state: NetworkState<'_>, 
     punishment_id: &str, 
     _auth_guard: AuthorizationToken
 ) -> Result<Json<Punishment>, ApiErrorResponder> {
@@ -22,9 +23,9 @@ async fn get_pun(
 
 #[post("/<punishment_id>/revert", format = "json", data = "<revert_req>")]
 async fn revert_pun(
-    state: &State<MarsAPIState>, 
+    state: NetworkState<'_>, 
     punishment_id: &str, 
-    revert_req: Json<PunishmentRevertRequest>, 
+    revert_req: HashedJson<PunishmentRevertRequest>, 
     _auth_guard: AuthorizationToken
 ) -> Result<Json<Punishment>, ApiErrorResponder> {
     let data = revert_req.0;
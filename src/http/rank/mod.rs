@@ -2,10 +2,11 @@ use std::vec;
 
 use futures::future::join_all;
 use mongodb::bson::doc;
-use rocket::{Build, Rocket, serde::json::Json, State};
+use rocket::{Build, Rocket, serde::json::Json};
 use uuid::Uuid;
 
-use crate::{database::{Database, models::{player::Player, rank::Rank}}, http::rank::payload::RankCreateRequest, MarsAPIState, util::{auth::AuthorizationToken, error::ApiErrorResponder, r#macro::unwrap_helper, time::get_u64_time_millis}};
+use crate::http::idempotency::HashedJson;
+use crate::{database::{Database, cache::UpdateError, models::{player::Player, rank::Rank}}, http::rank::payload::RankCreateRequest, network::NetworkState, util::{auth::AuthorizationToken, error::ApiErrorResponder, r#macro::unwrap_helper, time::get_u64_time_millis}};
 use crate::database::models::player::SimplePlayer;
 
 use self::payload::RankUpdateRequest;
@@ -14,8 +15,8 @@ mod payload;
 
 #[post("/", format = "json", data = "<create_req>")]
 async fn create_rank(
-    state: &State<MarsAPIState>, 
-    create_req: Json<RankCreateRequest>,
+    state: NetworkState<'_>, 
+    create_req: HashedJson<RankCreateRequest>,
     _auth_guard: AuthorizationToken
 ) -> Result<Json<Rank>, ApiErrorResponder> {
     let data = create_req.0;
@@ -46,18 +47,18 @@ async fn create_rank(
 }
 
 #[get("/")]
-async fn get_ranks(state: &State<MarsAPIState>) -> Json<Vec<Rank>> {
+async fn get_ranks(state: NetworkState<'_>) -> Json<Vec<Rank>> {
     Json(state.database.get_all_documents::<Rank>().await)
 }
 
 #[get("/<rank_id>")]
-async fn get_rank_by_id(state: &State<MarsAPIState>, rank_id: &str) -> Result<Json<Rank>, ApiErrorResponder> {
+async fn get_rank_by_id(state: NetworkState<'_>, rank_id: &str) -> Result<Json<Rank>, ApiErrorResponder> {
     let rank = unwrap_helper::return_default!(Database::find_by_id(&state.database.ranks, rank_id).await, Err(ApiErrorResponder::missing_rank()));
     Ok(Json(rank))
 }
 
 #[get("/<rank_id>/players")]
-async fn get_players_by_rank_by_id(state: &State<MarsAPIState>, rank_id: &str) -> Result<Json<Vec<SimplePlayer>>, ApiErrorResponder> {
+async fn get_players_by_rank_by_id(state: NetworkState<'_>, rank_id: &str) -> Result<Json<Vec<SimplePlayer>>, ApiErrorResponder> {
     let rank = unwrap_helper::return_default!(Database::find_by_id(&state.database.ranks, rank_id).await, Err(ApiErrorResponder::missing_rank()));
     if rank.apply_on_join || !rank.staff {
         return Ok(Json(vec![]));
@@ -68,7 +69,7 @@ async fn get_players_by_rank_by_id(state: &State<MarsAPIState>, rank_id: &str) -
 
 
 #[delete("/<rank_id>")]
-async fn delete_rank(state: &State<MarsAPIState>, rank_id: &str, _auth_guard: AuthorizationToken) -> Result<(), ApiErrorResponder> {
+async fn delete_rank(state: NetworkState<'_>, rank_id: &str, _auth_guard: AuthorizationToken) -> Result<(), ApiErrorResponder> {
     let delete_count = match state.database.delete_by_id::<Rank>(rank_id).await {
         Some(delete_result) => delete_result.deleted_count,
         None => 0
@@ -84,11 +85,16 @@ async fn delete_rank(state: &State<MarsAPIState>, rank_id: &str, _auth_guard: Au
 
     let mut cache_updates : Vec<_> = Vec::new();
     for i in 0..players_with_rank.len() {
-        let mut player = players_with_rank.swap_remove(i); // move out of vector
-        player.rank_ids.retain(|existing_rank_id| existing_rank_id != rank_id);
+        let player = players_with_rank.swap_remove(i); // move out of vector
         // move player into closure, then move closure into vector
         let wrapper = |player: Player| async move {
-            state.player_cache.set(&state.database, &player.name, &player, true).await;
+            let result = state.player_cache.update(&state.database, &player.name, |player| {
+                player.rank_ids.retain(|existing_rank_id| existing_rank_id != rank_id);
+                Ok::<(), ()>(())
+            }).await;
+            if let Err(UpdateError::Conflict(conflict)) = result {
+                warn!("Could not remove rank '{}': {}", rank_id, conflict);
+            };
         };
         cache_updates.push(wrapper(player));
     }
@@ -100,8 +106,8 @@ async fn delete_rank(state: &State<MarsAPIState>, rank_id: &str, _auth_guard: Au
 
 #[put("/<rank_id>", format = "json", data = "<rank_update_req>")]
 async fn update_rank(
-    state: &State<MarsAPIState>, 
-    rank_update_req: Json<RankUpdateRequest>, 
+    state: NetworkState<'_>, 
+    rank_update_req: HashedJson<RankUpdateRequest>, 
     rank_id: &str, 
     _auth_guard: AuthorizationToken
 ) -> Result<Json<Rank>, ApiErrorResponder> {
@@ -1,16 +1,17 @@
 mod payload;
 
-use rocket::{serde::json::Json, State, Build, Rocket};
+use rocket::{Build, Rocket};
 
-use crate::{util::{auth::AuthorizationToken, error::ApiErrorResponder}, MarsAPIState};
+use crate::http::idempotency::HashedJson;
+use crate::{util::{auth::AuthorizationToken, error::ApiErrorResponder}, network::NetworkState};
 
 use self::payload::ReportCreateRequest;
 
 
 #[post("/", format = "json", data = "<report>")]
 pub async fn new_report(
-    state: &State<MarsAPIState>,
-    report: Json<ReportCreateRequest>,
+    state: NetworkState<'_>,
+    report: HashedJson<ReportCreateRequest>,
     auth_guard: AuthorizationToken,
 ) -> Result<(), ApiErrorResponder> {
     let data = report.0;
@@ -1,14 +1,15 @@
 use futures::future::join_all;
 use mongodb::bson::doc;
-use rocket::{Rocket, Build, State, http::Status, serde::json::Json};
+use rocket::{Rocket, Build, http::Status};
 
-use crate::{MarsAPIState, util::{auth::AuthorizationToken, error::ApiErrorResponder, time::get_u64_time_millis, r#macro::unwrap_helper, responder::JsonResponder}, database::{models::{r#match::Match, session::Session, player::Player, server::ServerEvents}, Database}, http::server::payloads::{ServerStatusResponse, XPMultiplierRequest}};
+use crate::http::idempotency::HashedJson;
+use crate::{network::NetworkState, util::{auth::AuthorizationToken, error::ApiErrorResponder, time::get_u64_time_millis, r#macro::unwrap_helper, responder::JsonResponder}, database::{models::{r#match::Match, session::Session, player::Player, server::ServerEvents}, presence::PlayerPresence, Database}, http::server::payloads::{ServerStatusResponse, XPMultiplierRequest, ServerMessageRequest, ServerDisconnectPlayerRequest, ServerCycleMapRequest}, socket::{event_type::EventType, player::player_events::{MessageData, DisconnectPlayerData}, server::server_events::CycleMapData}};
 
 pub mod payloads;
 
 #[post("/<server_id>/startup")]
 async fn server_startup(
-    state: &State<MarsAPIState>, 
+    state: NetworkState<'_>, 
     server_id: &str, 
     auth_guard: AuthorizationToken
 ) -> Result<(), ApiErrorResponder> {
@@ -16,6 +17,8 @@ async fn server_startup(
         return Err(ApiErrorResponder::unauthorized());
     };
 
+    state.presence.clear_server(server_id).await;
+
     let last_alive_key = format!("server:{}:last_alive_time", server_id);
     let last_alive_time = state.redis.get_unchecked::<u64>(&last_alive_key).await;
     let time_millis : u64 = get_u64_time_millis();
@@ -32,7 +35,10 @@ async fn server_startup(
     if current_match.is_some() {
         let mut current_match = current_match.unwrap();
         current_match.ended_at = Some(last_alive_time.unwrap());
-        state.match_cache.set_with_expiry(&state.database, &current_match.id, &current_match, true, Some(3600000)).await;
+        let match_id = current_match.id.clone();
+        if let Err(conflict) = state.match_cache.compare_and_set(&state.database, &match_id, &mut current_match, true, Some(3600000)).await {
+            warn!("Could not end hanging match: {}", conflict);
+        };
     };
 
     let mut hanging_sessions = Database::consume_cursor_into_owning_vec_option(state.database.sessions.find(doc! {
@@ -40,31 +46,27 @@ async fn server_startup(
         "endedAt": null
     }, None).await.ok()).await;
     let mut sessions_to_write : Vec<Session> = Vec::new();
-    let mut players_to_write : Vec<Player> = Vec::new();
+    let mut players_to_write : Vec<(Player, i64)> = Vec::new();
 
     for hanging_session in hanging_sessions.iter_mut() {
         hanging_session.ended_at = Some(last_alive_time.unwrap());
         sessions_to_write.push(hanging_session.to_owned());
 
-        let mut cached_player = unwrap_helper::continue_default!(state.player_cache.get(&state.database, &hanging_session.player.name).await);
-        cached_player.stats.server_playtime += (hanging_session.length().unwrap_or(0) as i64);
-        players_to_write.push(cached_player);
+        let cached_player = unwrap_helper::continue_default!(state.player_cache.get(&state.database, &hanging_session.player.name).await);
+        players_to_write.push((cached_player, hanging_session.length().unwrap_or(0) as i64));
     }
 
-    // unfortunately rust's mongo driver doesn't support bulk writes yet so that's sad
     { 
-        let player_tasks : Vec<_> = players_to_write.iter().map(|player| {
-            state.database.players.replace_one(doc! {
-                "_id": &player.id
-            }, player, None)
+        let player_tasks : Vec<_> = players_to_write.iter().map(|(player, playtime)| {
+            state.player_cache.update(&state.database, &player.name, move |player| {
+                player.stats.server_playtime += playtime;
+                Ok::<(), ()>(())
+            })
         }).collect();
         join_all(player_tasks).await;
-        let session_tasks : Vec<_> = sessions_to_write.iter().map(|session| {
-            state.database.sessions.replace_one(doc! {
-                "_id": &session.id
-            }, session, None)
-        }).collect();
-        join_all(session_tasks).await;
+        for session in sessions_to_write.iter() {
+            state.database.write_behind.save(session).await;
+        }
     }
 
     state.redis.set(&format!("server:{}:last_alive_time", server_id), &get_u64_time_millis()).await;
@@ -76,7 +78,7 @@ async fn server_startup(
 
 #[get("/<server_id>/status")]
 async fn server_status(
-    state: &State<MarsAPIState>, 
+    state: NetworkState<'_>, 
     server_id: &str
 ) -> Result<JsonResponder<ServerStatusResponse>, ApiErrorResponder> {
     let server_id = server_id.to_lowercase();
@@ -98,7 +100,7 @@ async fn server_status(
 
 #[get("/<server_id>/events")]
 async fn server_events(
-    state: &State<MarsAPIState>, 
+    state: NetworkState<'_>, 
     server_id: &str
 ) -> Result<JsonResponder<ServerEvents>, ApiErrorResponder> {
     let server_id = server_id.to_lowercase();
@@ -110,9 +112,9 @@ async fn server_events(
 
 #[put("/<server_id>/events/xp_multiplier", format = "json", data = "<xp_multiplier_request>")]
 async fn xp_multiplier_event(
-    state: &State<MarsAPIState>, 
+    state: NetworkState<'_>, 
     server_id: &str,
-    xp_multiplier_request: Json<XPMultiplierRequest>,
+    xp_multiplier_request: HashedJson<XPMultiplierRequest>,
     auth_guard: AuthorizationToken
 ) -> Result<JsonResponder<ServerEvents>, ApiErrorResponder> {
     if server_id != auth_guard.server_id {
@@ -126,6 +128,84 @@ async fn xp_multiplier_event(
     Ok(JsonResponder::ok(events))
 }
 
+#[get("/<server_id>/players")]
+async fn server_players(
+    state: NetworkState<'_>, 
+    server_id: &str
+) -> JsonResponder<Vec<PlayerPresence>> {
+    JsonResponder::ok(state.presence.get_server_players(server_id).await)
+}
+
+#[post("/<server_id>/match/end")]
+async fn force_match_end(
+    state: NetworkState<'_>, 
+    server_id: &str,
+    auth_guard: AuthorizationToken
+) -> Result<(), ApiErrorResponder> {
+    if !state.servers.call(server_id, &EventType::ForceMatchEnd, ()).await {
+        return Err(ApiErrorResponder::server_not_connected());
+    };
+    info!("Forced match end on '{}' (requested by '{}')", server_id, auth_guard.server_id);
+    Ok(())
+}
+
+#[post("/<server_id>/message", format = "json", data = "<message_req>")]
+async fn send_server_message(
+    state: NetworkState<'_>, 
+    server_id: &str,
+    message_req: HashedJson<ServerMessageRequest>,
+    auth_guard: AuthorizationToken
+) -> Result<(), ApiErrorResponder> {
+    let data = message_req.0;
+    let message_data = MessageData { message: data.message, sound: data.sound, player_ids: data.player_ids };
+    if !state.servers.call(server_id, &EventType::Message, message_data).await {
+        return Err(ApiErrorResponder::server_not_connected());
+    };
+    info!("Sent message to '{}' (requested by '{}')", server_id, auth_guard.server_id);
+    Ok(())
+}
+
+#[post("/<server_id>/players/<player_id>/disconnect", format = "json", data = "<disconnect_req>")]
+async fn disconnect_player(
+    state: NetworkState<'_>, 
+    server_id: &str,
+    player_id: &str,
+    disconnect_req: HashedJson<ServerDisconnectPlayerRequest>,
+    auth_guard: AuthorizationToken
+) -> Result<(), ApiErrorResponder> {
+    let disconnect_data = DisconnectPlayerData { player_id: player_id.to_owned(), reason: disconnect_req.0.reason };
+    if !state.servers.call(server_id, &EventType::DisconnectPlayer, disconnect_data).await {
+        return Err(ApiErrorResponder::server_not_connected());
+    };
+    info!("Disconnected player '{}' from '{}' (requested by '{}')", player_id, server_id, auth_guard.server_id);
+    Ok(())
+}
+
+#[post("/<server_id>/cycle", format = "json", data = "<cycle_req>")]
+async fn cycle_map(
+    state: NetworkState<'_>, 
+    server_id: &str,
+    cycle_req: HashedJson<ServerCycleMapRequest>,
+    auth_guard: AuthorizationToken
+) -> Result<(), ApiErrorResponder> {
+    let cycle_data = CycleMapData { map_id: cycle_req.0.map_id };
+    if !state.servers.call(server_id, &EventType::CycleMap, cycle_data).await {
+        return Err(ApiErrorResponder::server_not_connected());
+    };
+    info!("Triggered map cycle on '{}' (requested by '{}')", server_id, auth_guard.server_id);
+    Ok(())
+}
+
 pub fn mount(rocket_build: Rocket<Build>) -> Rocket<Build> {
-    rocket_build.mount("/mc/servers", routes![server_startup, server_status, server_events, xp_multiplier_event])
+    rocket_build.mount("/mc/servers", routes![
+        server_startup, 
+        server_status, 
+        server_events, 
+        xp_multiplier_event,
+        server_players,
+        force_match_end,
+        send_server_message,
+        disconnect_player,
+        cycle_map
+    ])
 }
@@ -26,3 +26,26 @@ impl XPMultiplierRequest {
         }
     }
 }
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerMessageRequest {
+    pub message: String,
+    #[serde(default)]
+    pub sound: Option<String>,
+    #[serde(default)]
+    pub player_ids: Vec<String>
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerDisconnectPlayerRequest {
+    pub reason: String
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerCycleMapRequest {
+    #[serde(default)]
+    pub map_id: Option<String>
+}
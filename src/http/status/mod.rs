@@ -1,18 +1,92 @@
+use std::sync::atomic::Ordering;
+
 use rocket::{Rocket, Build};
 use rocket::serde::{Serialize, json::Json};
 use rocket::http::Status;
 
+use crate::{network::NetworkState, database::cache::{CasMetrics, HitMetrics}};
+
 #[derive(Serialize)]
 #[serde(crate = "rocket::serde")]
 pub struct StatusResponse {
     status: &'static str
 }
 
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct CasMetricsResponse {
+    writes: u64,
+    conflicts: u64,
+    retries_exhausted: u64,
+    conflict_rate: f64
+}
+
+impl From<&CasMetrics> for CasMetricsResponse {
+    fn from(metrics: &CasMetrics) -> Self {
+        CasMetricsResponse {
+            writes: metrics.writes.load(Ordering::Relaxed),
+            conflicts: metrics.conflicts.load(Ordering::Relaxed),
+            retries_exhausted: metrics.exhausted.load(Ordering::Relaxed),
+            conflict_rate: metrics.get_conflict_rate()
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ConcurrencyResponse {
+    players: CasMetricsResponse,
+    matches: CasMetricsResponse
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct HitMetricsResponse {
+    hits: u64,
+    misses: u64,
+    hit_rate: f64
+}
+
+impl From<&HitMetrics> for HitMetricsResponse {
+    fn from(metrics: &HitMetrics) -> Self {
+        HitMetricsResponse {
+            hits: metrics.hits.load(Ordering::Relaxed),
+            misses: metrics.misses.load(Ordering::Relaxed),
+            hit_rate: metrics.get_hit_rate()
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CacheResponse {
+    players: HitMetricsResponse,
+    matches: HitMetricsResponse
+}
+
 #[get("/")]
 pub fn status() -> Json<StatusResponse> {
    Json(StatusResponse { status: Status::Ok.reason().unwrap_or("OK") }) 
 }
 
+// versioned writes since startup, and how many of them lost a race
+#[get("/concurrency")]
+pub fn concurrency(state: NetworkState<'_>) -> Json<ConcurrencyResponse> {
+    Json(ConcurrencyResponse {
+        players: CasMetricsResponse::from(&state.player_cache.cache.cas_metrics),
+        matches: CasMetricsResponse::from(&state.match_cache.cas_metrics)
+    })
+}
+
+// cache lookups since startup, and how many of them found the value cached
+#[get("/cache")]
+pub fn cache(state: NetworkState<'_>) -> Json<CacheResponse> {
+    Json(CacheResponse {
+        players: HitMetricsResponse::from(&state.player_cache.cache.hit_metrics),
+        matches: HitMetricsResponse::from(&state.match_cache.hit_metrics)
+    })
+}
+
 pub fn mount(rocket_build: Rocket<Build>) -> Rocket<Build> {
-    rocket_build.mount("/status", routes![status])
+    rocket_build.mount("/status", routes![status, concurrency, cache])
 }
@@ -1,9 +1,10 @@
 use log::info;
-use mongodb::{results::DeleteResult, bson::doc};
-use rocket::{State, Rocket, Build, http::Status, serde::json::Json};
+use mongodb::bson::doc;
+use rocket::{Rocket, Build, http::Status, serde::json::Json};
 use uuid::Uuid;
 
-use crate::{util::{auth::AuthorizationToken, responder::JsonResponder, error::{ApiErrorResponder}, time::get_u64_time_millis, r#macro::unwrap_helper}, MarsAPIState, database::{models::tag::Tag, Database}};
+use crate::http::idempotency::HashedJson;
+use crate::{util::{auth::AuthorizationToken, responder::JsonResponder, error::{ApiErrorResponder}, time::get_u64_time_millis, r#macro::unwrap_helper}, network::NetworkState, database::{cache::UpdateError, models::tag::Tag, store::DeleteResult, Database}};
 
 use self::payload::TagCreateRequest;
 
@@ -11,8 +12,8 @@ mod payload;
 
 #[post("/", format = "json", data = "<tag_create_req>")]
 async fn create_tag(
-    state: &State<MarsAPIState>,
-    tag_create_req: Json<TagCreateRequest>,
+    state: NetworkState<'_>,
+    tag_create_req: HashedJson<TagCreateRequest>,
     _auth_guard: AuthorizationToken
 ) -> Result<JsonResponder<Tag>, ApiErrorResponder> {
     match state.database.find_by_id_or_name::<Tag>(&tag_create_req.name).await {
@@ -35,13 +36,13 @@ async fn create_tag(
 }
 
 #[get("/")]
-async fn get_tags(state: &State<MarsAPIState>) -> Json<Vec<Tag>> {
+async fn get_tags(state: NetworkState<'_>) -> Json<Vec<Tag>> {
     Json(state.database.get_all_documents::<Tag>().await)
 }
 
 #[get("/<tag_id>")]
 async fn get_tag_by_id(
-    state: &State<MarsAPIState>,
+    state: NetworkState<'_>,
     tag_id: &str,
 ) -> Result<JsonResponder<Tag>, ApiErrorResponder> {
     Ok(JsonResponder::ok(
@@ -75,7 +76,7 @@ async fn get_tag_by_id(
 
 #[delete("/<tag_id>")]
 async fn delete_tag(
-    state: &State<MarsAPIState>,
+    state: NetworkState<'_>,
     tag_id: &str,
     _auth_guard: AuthorizationToken
 ) -> Result<(), ApiErrorResponder> {
@@ -85,18 +86,23 @@ async fn delete_tag(
         },
         _ => {}
     };
-    let mut players_with_tag = Database::consume_cursor_into_owning_vec_option(
+    let players_with_tag = Database::consume_cursor_into_owning_vec_option(
         state.database.players.find(doc! {"tagIds": tag_id}, None).await.ok()
     ).await;
-    for player in players_with_tag.iter_mut() {
-        match player.tag_ids.iter().position(|e| { e == &tag_id.to_string() }) {
-            Some(tag_idx) => { player.tag_ids.swap_remove(tag_idx); },
-            None => {},
+    for player in players_with_tag.iter() {
+        let result = state.player_cache.update(&state.database, &player.name, |player| {
+            match player.tag_ids.iter().position(|e| { e == &tag_id.to_string() }) {
+                Some(tag_idx) => { player.tag_ids.swap_remove(tag_idx); },
+                None => {},
+            };
+            if player.active_tag_id.is_some() && player.active_tag_id.as_ref().unwrap() == &tag_id.to_string() {
+                player.active_tag_id = Option::None;
+            }
+            Ok::<(), ()>(())
+        }).await;
+        if let Err(UpdateError::Conflict(conflict)) = result {
+            warn!("Could not remove tag '{}': {}", tag_id, conflict);
         };
-        if player.active_tag_id.is_some() && player.active_tag_id.as_ref().unwrap() == &tag_id.to_string() {
-            player.active_tag_id = Option::None;
-        }
-        state.player_cache.set(&state.database, &player.name, &player, true).await;
     };
     info!(
         "Tag {} was deleted. Affected players: {}", 
@@ -111,8 +117,8 @@ async fn delete_tag(
 
 #[put("/<tag_id>", format = "json", data = "<tag_update_req>")]
 async fn update_tag(
-    state: &State<MarsAPIState>,
-    tag_update_req: Json<TagCreateRequest>,
+    state: NetworkState<'_>,
+    tag_update_req: HashedJson<TagCreateRequest>,
     tag_id: &str,
     _auth_guard: AuthorizationToken
 ) -> Result<JsonResponder<Tag>, ApiErrorResponder> {
@@ -1,22 +1,29 @@
 #[macro_use] extern crate rocket;
 
-use std::{marker::PhantomData, sync::Arc, env, net::{Ipv4Addr, IpAddr}};
+use std::{str::FromStr, marker::PhantomData, sync::Arc, env, net::{Ipv4Addr, IpAddr}, path::{Path, PathBuf}, time::Duration};
 
 use anyhow::anyhow;
-use config::{deserialize_mars_config, MarsConfig};
-use database::{Database, cache::{Cache, get_redis_pool, RedisAdapter}, models::{player::Player, r#match::Match}};
-use rocket::{figment::Figment, http::Method, Build, Config, Rocket, Shutdown};
+use config::{deserialize_mars_config, MarsConfig, NetworkConfig, DEFAULT_NETWORK_ID};
+use database::{Database, analytics_export::{self, ExportFormat}, backup, importer, indexes, retention::{self, RetentionPolicy}, cache::{Cache, CasMetrics, HitMetrics, RedisAdapter}, player_cache::PlayerCache, write_behind::WriteBehindQueue, store::{CacheStore, DocumentStore, StorageBackend, memory::{MemoryCacheStore, MemoryDocumentStore}, mongo::MongoDocumentStore, prefixed::PrefixedCacheStore, redis::RedisCacheStore}, models::r#match::Match, presence::PresenceTracker};
+use rocket::{fairing::AdHoc, figment::Figment, http::Method, Build, Config, Rocket, Shutdown};
 use rocket_cors::{AllowedOrigins, CorsOptions};
-use socket::leaderboard::MarsLeaderboards;
+use uuid::Uuid;
+use socket::{leaderboard::MarsLeaderboards, recording::{recorder::SocketRecorder, replay::replay_recording}, server::server_registry::ServerRegistry};
 use crate::database::migrations::MigrationExecutor;
+use crate::util::time::get_u64_time_millis;
 
 use crate::socket::socket_handler::{SocketState, setup_socket};
+use crate::network::Networks;
 
 mod util;
 mod config;
 mod database;
 mod http;
 mod socket;
+mod network;
+
+#[cfg(test)]
+mod tests;
 
 fn setup_logger() -> Result<(), fern::InitError> {
     fern::Dispatch::new()
@@ -43,12 +50,65 @@ pub struct MarsAPIState {
     pub config: Arc<MarsConfig>,
     pub database: Arc<Database>,
     pub redis: Arc<RedisAdapter>,
-    pub player_cache: Arc<Cache<Player>>,
+    pub player_cache: Arc<PlayerCache>,
     pub match_cache: Arc<Cache<Match>>,
     pub leaderboards: Arc<MarsLeaderboards>,
+    pub servers: Arc<ServerRegistry>,
+    pub presence: Arc<PresenceTracker>,
 }
 
-fn rocket(state: MarsAPIState) -> Rocket<Build> {
+impl MarsAPIState {
+    fn new(config: Arc<MarsConfig>, database: Database, redis_adapter: RedisAdapter) -> Self {
+        let database = Arc::new(database);
+        WriteBehindQueue::spawn_flusher(Arc::clone(&database.write_behind));
+        let redis_adapter = Arc::new(redis_adapter);
+
+        // redis player cache
+        let player_cache = Arc::new(PlayerCache::new(Arc::clone(&redis_adapter)));
+
+        // redis match cache
+        let match_cache = Arc::new(Cache {
+            redis: Arc::clone(&redis_adapter),
+            resource_name: String::from("match"),
+            lifetime_ms: 86_400_000,
+            resource_type: PhantomData,
+            name_indexed: false,
+            cas_metrics: CasMetrics::default(),
+            hit_metrics: HitMetrics::default()
+        });
+
+        // leaderboards
+        let leaderboards = Arc::new(MarsLeaderboards::new(Arc::clone(&redis_adapter), Arc::clone(&database)));
+
+        // identifies this process when several instances share one redis
+        let instance_id = env::var("MARS_INSTANCE_ID").unwrap_or_else(|_| Uuid::new_v4().to_string());
+        let servers = Arc::new(ServerRegistry::new(instance_id, Arc::clone(&redis_adapter)));
+
+        MarsAPIState {
+            config,
+            database,
+            redis: Arc::clone(&redis_adapter),
+            player_cache,
+            match_cache,
+            leaderboards,
+            servers,
+            presence: Arc::new(PresenceTracker { redis: redis_adapter })
+        }
+    }
+
+    // matches in progress and profile changes saved without persisting only live in the cache,
+    // so they're written to the database before the process exits
+    pub async fn persist_cached_state(&self) {
+        // queued stat changes land first, cached profiles don't carry stats
+        self.database.write_behind.flush().await;
+        let matches = self.match_cache.persist_all(&self.database).await;
+        let players = self.player_cache.cache.persist_all(&self.database).await;
+        self.database.write_behind.flush().await;
+        info!("Persisted {} cached match(es) and {} cached player(s)", matches, players);
+    }
+}
+
+fn rocket(networks: Networks) -> Rocket<Build> {
     let mounts : Vec<&dyn Fn(Rocket<Build>) -> Rocket<Build>> = vec![
         &http::broadcast::mount,
         &http::tag::mount,
@@ -63,7 +123,11 @@ fn rocket(state: MarsAPIState) -> Rocket<Build> {
         &http::leaderboard::mount,
         &http::report::mount,
         &http::r#match::mount,
-        &http::achievements::mount
+        &http::achievements::mount,
+        &http::chat::mount,
+        &http::dead_letter::mount,
+        &http::cache::mount,
+        &http::idempotency::mount
     ];
     let is_debug = env::var("MARS_DEBUG").unwrap_or("false".to_owned()).parse::<bool>().unwrap_or(false);
     let http_port = env::var("MARS_HTTP_PORT").unwrap_or("8000".to_owned()).parse::<u32>().unwrap_or(8000);
@@ -73,7 +137,13 @@ fn rocket(state: MarsAPIState) -> Rocket<Build> {
         .merge::<(&str, IpAddr)>(("address", Ipv4Addr::new(0, 0, 0, 0).into()))
         .merge(("port", http_port))
         .extract().unwrap();
-    let mut rocket_build = rocket::custom(config).manage(state);
+    let write_behinds : Vec<Arc<WriteBehindQueue>> = networks.iter().map(|(_, state)| Arc::clone(&state.database.write_behind)).collect();
+    let mut rocket_build = rocket::custom(config).manage(networks)
+        .attach(AdHoc::on_shutdown("Flush queued writes", |_| Box::pin(async move {
+            for write_behind in write_behinds {
+                write_behind.flush().await;
+            }
+        })));
 
     rocket_build = mounts.iter().fold(rocket_build, |mut build, mount_fn| {
         build = (mount_fn)(build);
@@ -95,9 +165,9 @@ fn get_cors_configuration() -> CorsOptions {
         .allow_credentials(true)
 }
 
-async fn spawn_rocket(state: MarsAPIState) -> Result<Shutdown, String> {
+async fn spawn_rocket(networks: Networks) -> Result<Shutdown, String> {
     let cors = get_cors_configuration().to_cors().unwrap();
-    let rocket = match rocket(state).attach(cors).ignite().await {
+    let rocket = match rocket(networks).attach(cors).ignite().await {
         Ok(rocket) => rocket,
         Err(rocket_err) => return Err(format!("{}", rocket_err))
     };
@@ -112,9 +182,9 @@ async fn spawn_rocket(state: MarsAPIState) -> Result<Shutdown, String> {
     Ok(shutdown_handle)
 }
 
-async fn setup_rocket(state: MarsAPIState) -> anyhow::Result<()> {
+async fn setup_rocket(networks: Networks) -> anyhow::Result<()> {
     // spawn rocket
-    let shutdown_rocket = match spawn_rocket(state).await {
+    let shutdown_rocket = match spawn_rocket(networks).await {
         Ok(shutdown_handle) => Some(shutdown_handle),
         Err(e) => return Err(anyhow!(e))
     };
@@ -126,6 +196,88 @@ async fn setup_rocket(state: MarsAPIState) -> anyhow::Result<()> {
     Ok(())
 }
 
+fn spawn_retention_task(state: MarsAPIState) {
+    let policies = RetentionPolicy::from_options(&state.config.options);
+    if policies.is_empty() {
+        return;
+    };
+    tokio::spawn(async move {
+        let directory = PathBuf::from(&state.config.options.archive_directory);
+        let mut interval = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            retention::apply_retention(&state.database, &policies, &directory, get_u64_time_millis()).await;
+        }
+    });
+}
+
+// every network gets its own database on the shared mongo client, or its own in-memory store,
+// and its own namespace in the shared cache store
+async fn build_network_state(network: &NetworkConfig, mongo: Option<&MongoDocumentStore>, cache_store: &Arc<dyn CacheStore>) -> MarsAPIState {
+    let document_store : Arc<dyn DocumentStore> = match mongo {
+        Some(mongo) => Arc::new(mongo.with_database(&network.mongo_database)),
+        None => Arc::new(MemoryDocumentStore::new())
+    };
+    let cache_store : Arc<dyn CacheStore> = if network.redis_prefix.is_empty() {
+        Arc::clone(cache_store)
+    } else {
+        Arc::new(PrefixedCacheStore::new(&network.redis_prefix, Arc::clone(cache_store)))
+    };
+    let state = MarsAPIState::new(Arc::clone(&network.config), Database::new(document_store), RedisAdapter::new(cache_store));
+    indexes::ensure_indexes(&state.database).await.log();
+    state
+}
+
+// replays into a separate database with a throwaway cache, so live data and caches are never touched
+async fn replay_socket_recording(network: &NetworkConfig, recording_path: &str) -> Result<(), String> {
+    let mars_config = Arc::clone(&network.config);
+    let database = match mars_config.options.storage_backend {
+        StorageBackend::External => {
+            let database_name = env::var("MARS_SOCKET_REPLAY_DATABASE").unwrap_or_else(|_| network.replay_database.clone());
+            info!("Replaying into database '{}'", database_name);
+            match database::connect_named(&mars_config.options.mongo_url, &database_name, mars_config.options.mongo_min_pool_size, mars_config.options.mongo_max_pool_size).await {
+                Ok(db) => db,
+                Err(db_err) => return Err(format!("Mongo Error: {}", db_err))
+            }
+        },
+        StorageBackend::Memory => Database::new(Arc::new(MemoryDocumentStore::new()))
+    };
+    let state = MarsAPIState::new(mars_config, database, RedisAdapter::new(Arc::new(MemoryCacheStore::new())));
+    match replay_recording(&state, Path::new(recording_path)).await {
+        Ok(summary) => {
+            info!(
+                "Replayed {} events from {} server(s), {} failed, skipped {} unreadable line(s), seeded {} player(s) and {} map(s)",
+                summary.events, summary.servers, summary.failed, summary.skipped, summary.seeded_players, summary.seeded_levels
+            );
+            Ok(())
+        },
+        Err(e) => Err(format!("Replay Error: {}", e))
+    }
+}
+
+async fn export_analytics(mars_config: Arc<MarsConfig>, directory: &str) -> Result<(), String> {
+    let format = match env::var("MARS_ANALYTICS_EXPORT_FORMAT") {
+        Ok(format) => ExportFormat::from_str(&format)?,
+        Err(_) => ExportFormat::Csv
+    };
+    let database = match mars_config.options.storage_backend {
+        StorageBackend::External => match database::connect(&mars_config.options).await {
+            Ok(db) => db,
+            Err(db_err) => return Err(format!("Mongo Error: {}", db_err))
+        },
+        StorageBackend::Memory => Database::new(Arc::new(MemoryDocumentStore::new()))
+    };
+    match analytics_export::export_analytics(&database, Path::new(directory), format, get_u64_time_millis()).await {
+        Ok(summary) => {
+            info!(
+                "Exported {} match(es), {} participant(s), {} death(s) and {} session(s) to {} file(s)",
+                summary.matches, summary.participants, summary.deaths, summary.sessions, summary.files.len()
+            );
+            Ok(())
+        },
+        Err(e) => Err(format!("Export Error: {}", e))
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<(), String> {
@@ -141,47 +293,95 @@ async fn main() -> Result<(), String> {
         Err(e) => return Err(format!("Logger Setup Error: {}", e)),
     }
 
-    // setup db pool
-    let database = Arc::new(match database::connect(&mars_config.options.mongo_url, Some(2), Some(8)).await {
-        Ok(db) => db,
-        Err(db_err) => return Err(format!("Mongo Error: {}", db_err))
-    });
+    let network_configs = match config::deserialize_network_configs(Arc::clone(&mars_config)).await {
+        Ok(network_configs) => network_configs,
+        Err(e) => return Err(format!("Network Config Error: {}", e))
+    };
+    // commands below act on a single network
+    let network_id = env::var("MARS_NETWORK").unwrap_or_else(|_| DEFAULT_NETWORK_ID.to_owned());
+    let network_config = match network_configs.iter().find(|network| network.id == network_id) {
+        Some(network) => network,
+        None => return Err(format!("Unknown network '{}'", network_id))
+    };
 
-    // setup redis pool
-    let redis_adapter = Arc::new(match get_redis_pool(&mars_config.options.redis_host).await {
-        Ok(adapter) => adapter,
-        Err(redis_error) => return Err(format!("Redis Error: {}", redis_error))
-    });
+    if let Ok(recording_path) = env::var("MARS_SOCKET_REPLAY") {
+        info!("API will not run, replaying socket recording '{}'", recording_path);
+        return replay_socket_recording(network_config, &recording_path).await;
+    };
 
-    // redis player cache
-    let player_cache = Arc::new(Cache {
-        redis: Arc::clone(&redis_adapter),
-        resource_name: String::from("player"),
-        lifetime_ms: 10_800_000,
-        resource_type: PhantomData
-    });
+    if let Ok(directory) = env::var("MARS_ANALYTICS_EXPORT") {
+        info!("API will not run, exporting analytics of network '{}' to '{}'", network_id, directory);
+        return export_analytics(Arc::clone(&network_config.config), &directory).await;
+    };
 
-    // redis match cache
-    let match_cache = Arc::new(Cache {
-        redis: Arc::clone(&redis_adapter),
-        resource_name: String::from("match"),
-        lifetime_ms: 86_400_000,
-        resource_type: PhantomData
-    });
+    let (mongo, cache_store) : (Option<MongoDocumentStore>, Arc<dyn CacheStore>) = match mars_config.options.storage_backend {
+        StorageBackend::External => {
+            // setup db pool, shared by the databases of every network
+            let options = &mars_config.options;
+            let mongo = match MongoDocumentStore::connect(&options.mongo_url, &options.mongo_database, options.mongo_min_pool_size, options.mongo_max_pool_size).await {
+                Ok(mongo) => mongo,
+                Err(db_err) => return Err(format!("Mongo Error: {}", db_err))
+            };
+            info!("Connected to database successfully.");
 
-    // leaderboards
-    let leaderboards = Arc::new(MarsLeaderboards::new(Arc::clone(&redis_adapter), Arc::clone(&database)));
+            // setup redis pool
+            let redis_store = match RedisCacheStore::connect(&options.redis).await {
+                Ok(store) => store,
+                Err(redis_error) => return Err(format!("Redis Error: {}", redis_error))
+            };
+            info!("Connected to redis successfully.");
+            (Some(mongo), Arc::new(redis_store))
+        },
+        StorageBackend::Memory => {
+            warn!("Using in-memory storage, nothing will be kept after the API stops");
+            (None, Arc::new(MemoryCacheStore::new()))
+        }
+    };
+    let mut networks = Networks::new(build_network_state(&network_configs[0], mongo.as_ref(), &cache_store).await);
+    for network in network_configs.iter().skip(1) {
+        info!("Serving network '{}' from database '{}' with redis prefix '{}'", network.id, network.mongo_database, network.redis_prefix);
+        networks.add(&network.id, build_network_state(network, mongo.as_ref(), &cache_store).await);
+    }
+    let state = networks.get(&network_id).unwrap().clone();
 
-    // immutable state for rocket to manage
-    let state = MarsAPIState { 
-        config: Arc::clone(&mars_config), 
-        database: Arc::clone(&database), 
-        redis: Arc::clone(&redis_adapter), 
-        player_cache, 
-        match_cache,
-        leaderboards
+    if let Ok(backup_path) = env::var("MARS_BACKUP") {
+        info!("API will not run, backing up to '{}'", backup_path);
+        return match backup::create_backup(&state.database, &state.redis, Path::new(&backup_path)).await {
+            Ok(summary) => {
+                info!("Backed up {} document(s) and {} leaderboard(s)", summary.documents.values().sum::<u64>(), summary.leaderboards);
+                Ok(())
+            },
+            Err(e) => Err(format!("Backup Error: {}", e))
+        };
+    };
+    if let Ok(backup_path) = env::var("MARS_RESTORE") {
+        info!("API will not run, restoring '{}'", backup_path);
+        return match backup::restore_backup(&state, Path::new(&backup_path)).await {
+            Ok(summary) => {
+                info!("Restored {} document(s) and {} leaderboard(s)", summary.documents.values().sum::<u64>(), summary.leaderboards);
+                Ok(())
+            },
+            Err(e) => Err(format!("Restore Error: {}", e))
+        };
     };
 
+    if let Ok(import_path) = env::var("MARS_IMPORT") {
+        let dry_run = env::var("MARS_IMPORT_DRY_RUN").ok().and_then(|dry_run| dry_run.parse::<bool>().ok()).unwrap_or(false);
+        info!("API will not run, importing '{}'{}", import_path, if dry_run { " as a dry run" } else { "" });
+        let report = match importer::import(&state.database, &state.leaderboards, Path::new(&import_path), dry_run).await {
+            Ok(report) => report,
+            Err(e) => return Err(format!("Import Error: {}", e))
+        };
+        let report_path = format!("{}.import-report.json", import_path.trim_end_matches('/'));
+        if let Err(e) = std::fs::write(&report_path, rocket::serde::json::serde_json::to_string_pretty(&report).unwrap_or_default()) {
+            warn!("Could not write the import report: {}", e);
+        };
+        info!(
+            "Imported {} document(s), {} conflict(s) and {} invalid document(s) left out, see {}",
+            report.imported.values().sum::<u64>(), report.conflicts.len(), report.invalid.len(), report_path
+        );
+        return Ok(());
+    };
 
     if env::var("MARS_DATABASE_MIGRATION").is_ok() {
         let migration = env::var("MARS_DATABASE_MIGRATION").unwrap_or("NONE".to_owned());
@@ -198,12 +398,31 @@ async fn main() -> Result<(), String> {
         return Ok(());
     };
 
+    for (_, network_state) in networks.iter() {
+        spawn_retention_task(network_state.clone());
+        ServerRegistry::spawn_relay_listener(Arc::clone(&network_state.servers));
+        ServerRegistry::spawn_owner_heartbeat(Arc::clone(&network_state.servers));
+        PresenceTracker::spawn_heartbeat(Arc::clone(&network_state.presence), Arc::clone(&network_state.servers));
+    }
+
+    let recorder = match env::var("MARS_SOCKET_RECORD_DIR") {
+        Ok(directory) => match SocketRecorder::start(Path::new(&directory)) {
+            Ok(recorder) => {
+                info!("Recording socket events to {}", recorder.get_path().display());
+                Some(Arc::new(recorder))
+            },
+            Err(e) => return Err(format!("Socket Recorder Error: {}", e))
+        },
+        Err(_) => None
+    };
+
     let ws_port = env::var("MARS_WS_PORT").unwrap_or("7000".to_owned()).parse::<u32>().unwrap_or(7000);
     let res = tokio::try_join!(
-        setup_rocket(state.clone()),
+        setup_rocket(networks.clone()),
         setup_socket(
             SocketState {
-                api_state: Arc::new(state.clone())
+                networks: Arc::new(networks.clone()),
+                recorder: recorder.clone()
             }, ws_port
         )
     );
@@ -211,6 +430,14 @@ async fn main() -> Result<(), String> {
     if let Err(e) = res {
         warn!("{}", e);
     };
+    // the sockets are drained, so the recording can be closed off
+    if let Some(recorder) = recorder {
+        recorder.finish();
+    };
+    // both listeners have stopped by now, so nothing writes to the caches anymore
+    for (_, network_state) in networks.iter() {
+        network_state.persist_cached_state().await;
+    }
 
     Ok(())
 }
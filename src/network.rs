@@ -0,0 +1,87 @@
+use std::ops::Deref;
+
+use rocket::{http::Status, request::{self, FromRequest}, Request};
+
+use crate::{MarsAPIState, config::DEFAULT_NETWORK_ID};
+
+// every network served by this deployment, each with its own databases, redis namespace, config data and token
+#[derive(Clone)]
+pub struct Networks {
+    // the default network comes first
+    states: Vec<(String, MarsAPIState)>
+}
+
+impl Networks {
+    pub const HEADER: &'static str = "Mars-Network";
+
+    pub fn new(default_state: MarsAPIState) -> Self {
+        Networks { states: vec![(DEFAULT_NETWORK_ID.to_owned(), default_state)] }
+    }
+
+    pub fn add(&mut self, id: &str, state: MarsAPIState) {
+        self.states.push((id.to_owned(), state));
+    }
+
+    pub fn get_default(&self) -> &MarsAPIState {
+        &self.states[0].1
+    }
+
+    pub fn get(&self, id: &str) -> Option<&MarsAPIState> {
+        self.states.iter().find(|(network_id, _)| network_id == id).map(|(_, state)| state)
+    }
+
+    pub fn find_by_token(&self, token: &str) -> Option<&MarsAPIState> {
+        self.states.iter().find(|(_, state)| state.config.token == token).map(|(_, state)| state)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(String, MarsAPIState)> {
+        self.states.iter()
+    }
+
+    // a server's token picks its network, otherwise the Mars-Network header does, otherwise it's the default network
+    // None for a network that doesn't exist
+    pub fn resolve(&self, req: &Request<'_>) -> Option<&MarsAPIState> {
+        let token = req.headers().get_one("Authorization").and_then(|value| value.strip_prefix("API-Token ")).map(str::trim);
+        if let Some(state) = token.and_then(|token| self.find_by_token(token)) {
+            return Some(state);
+        };
+        match req.headers().get_one(Self::HEADER) {
+            Some(id) => self.get(id),
+            None => Some(self.get_default())
+        }
+    }
+}
+
+// the state of the network a request is for
+#[derive(Clone, Copy)]
+pub struct NetworkState<'r>(&'r MarsAPIState);
+
+impl<'r> NetworkState<'r> {
+    pub fn inner(&self) -> &'r MarsAPIState {
+        self.0
+    }
+}
+
+impl Deref for NetworkState<'_> {
+    type Target = MarsAPIState;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for NetworkState<'r> {
+    type Error = String;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, String> {
+        let networks = match req.rocket().state::<Networks>() {
+            Some(networks) => networks,
+            None => return request::Outcome::Error((Status::InternalServerError, String::from("Internal error")))
+        };
+        match networks.resolve(req) {
+            Some(state) => request::Outcome::Success(NetworkState(state)),
+            None => request::Outcome::Error((Status::NotFound, String::from("Unknown network")))
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{database::{cache::RedisAdapter, models::{player::SimplePlayer, punishment::{Punishment, PunishmentAction, PunishmentKind, PunishmentReason}}}, socket::{player::player_context::send_message_to_player, server::server_context::ServerContext}, util::time::get_u64_time_millis};
+
+// loaded from chat_filter.yml, a missing check is disabled
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatFilterConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub blocked_words: Option<BlockedWordsCheck>,
+    #[serde(default)]
+    pub spam: Option<SpamCheck>,
+    #[serde(default)]
+    pub caps: Option<CapsCheck>,
+    #[serde(default)]
+    pub links: Option<LinkCheck>,
+    #[serde(default)]
+    pub auto_mute: Option<AutoMute>
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockedWordsCheck {
+    pub words: Vec<String>,
+    pub score: u32
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SpamCheck {
+    pub window_ms: usize,
+    pub max_repeats: usize,
+    pub score: u32
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CapsCheck {
+    pub min_length: usize,
+    pub max_ratio: f32,
+    pub score: u32
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkCheck {
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    pub score: u32
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AutoMute {
+    pub threshold: u32,
+    pub reason: PunishmentReason,
+    pub action: PunishmentAction,
+    #[serde(default)]
+    pub silent: bool
+}
+
+#[derive(Default)]
+pub struct ChatFilterResult {
+    pub flags: Vec<String>,
+    pub score: u32
+}
+
+impl ChatFilterResult {
+    fn flag(&mut self, check: &str, score: u32) {
+        self.flags.push(check.to_owned());
+        self.score += score;
+    }
+}
+
+impl ChatFilterConfig {
+    pub async fn evaluate(&self, redis: &RedisAdapter, player_id: &str, message: &str) -> ChatFilterResult {
+        let mut result = ChatFilterResult::default();
+        if !self.enabled {
+            return result;
+        };
+        if let Some(check) = &self.blocked_words {
+            if contains_blocked_word(message, &check.words) {
+                result.flag("BLOCKED_WORD", check.score);
+            };
+        };
+        if let Some(check) = &self.spam {
+            // the list expires once the player has been quiet for the whole window
+            let normalized = tokenize(message).join(" ");
+            let recent = redis.push_recent(&format!("chat_filter:recent:{}", player_id), &normalized, check.max_repeats + 1, check.window_ms).await;
+            if recent.iter().filter(|previous| **previous == normalized).count() > check.max_repeats {
+                result.flag("SPAM", check.score);
+            };
+        };
+        if let Some(check) = &self.caps {
+            if exceeds_caps_ratio(message, check.min_length, check.max_ratio) {
+                result.flag("CAPS", check.score);
+            };
+        };
+        if let Some(check) = &self.links {
+            if contains_link(message, &check.allowed_domains) {
+                result.flag("LINK", check.score);
+            };
+        };
+        result
+    }
+
+    pub fn should_mute(&self, result: &ChatFilterResult) -> bool {
+        match &self.auto_mute {
+            Some(auto_mute) => !result.flags.is_empty() && result.score >= auto_mute.threshold,
+            None => false
+        }
+    }
+}
+
+// mutes through the regular punishment model so it shows up in history, webhooks and the plugin
+// the plugin has no event to apply a punishment it didn't issue, so the player is only told, the mute is enforced from their next login
+pub async fn issue_auto_mute(server: &mut ServerContext, target: &SimplePlayer, message: &str, result: &ChatFilterResult) {
+    let api_state = server.api_state.clone();
+    let auto_mute = match &api_state.config.data.chat_filter.auto_mute {
+        Some(auto_mute) => auto_mute,
+        None => return
+    };
+    let player = match api_state.player_cache.get(&api_state.database, &target.name).await {
+        Some(player) => player,
+        None => return
+    };
+    let punishments = api_state.database.get_player_punishments(&player).await;
+    if punishments.iter().any(|punishment| punishment.is_active() && punishment.action.kind == PunishmentKind::Mute) {
+        return;
+    };
+    let offence = punishments.iter().filter(|punishment| punishment.reason.name == auto_mute.reason.name).count() as u32 + 1;
+    let punishment = Punishment {
+        id: Uuid::new_v4().to_string(),
+        reason: auto_mute.reason.clone(),
+        issued_at: get_u64_time_millis() as f64,
+        silent: auto_mute.silent,
+        offence,
+        action: auto_mute.action.clone(),
+        note: Some(format!("Automatic ({}): {}", result.flags.join(", "), message)),
+        punisher: None,
+        target: player.to_simple(),
+        target_ips: player.ips.clone(),
+        reversion: None,
+        server_id: Some(server.id.clone())
+    };
+    api_state.database.insert_one(&punishment).await;
+    send_message_to_player(server, &player, &format!("You have been muted for {}", punishment.reason.name), None).await;
+    info!("Automatically muted '{}' on '{}' (score {})", target.name, server.id, result.score);
+    tokio::spawn(async move {
+        api_state.config.webhooks.send_punishment_webhook(&punishment).await;
+    });
+}
+
+// undoes digit and symbol substitutions, only inside words that have letters so numbers are left alone
+fn deobfuscate(token: &str) -> String {
+    if !token.chars().any(|c| c.is_alphabetic()) {
+        return token.to_owned();
+    };
+    token.chars().map(|c| match c {
+        '0' => 'o',
+        '1' => 'i',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '7' => 't',
+        '8' => 'b',
+        '9' => 'g',
+        _ => c
+    }).collect()
+}
+
+// lowercased words with substitutions undone, letters spelled out one by one ("b.a.d", "b a d") are joined back up
+fn tokenize(text: &str) -> Vec<String> {
+    let text = text.to_lowercase();
+    let words = text.split(|c: char| !c.is_alphanumeric() && c != '@' && c != '$')
+        .filter(|word| !word.is_empty())
+        .map(deobfuscate);
+    let mut tokens : Vec<String> = Vec::new();
+    let mut spelled = String::new();
+    for word in words {
+        if word.chars().count() == 1 {
+            spelled.push_str(&word);
+            continue;
+        };
+        if !spelled.is_empty() {
+            tokens.push(deobfuscate(&std::mem::take(&mut spelled)));
+        };
+        tokens.push(word);
+    }
+    if !spelled.is_empty() {
+        tokens.push(deobfuscate(&spelled));
+    };
+    tokens
+}
+
+fn get_runs(word: &str) -> Vec<(char, usize)> {
+    let mut runs : Vec<(char, usize)> = Vec::new();
+    for c in word.chars() {
+        match runs.last_mut() {
+            Some((last, count)) if *last == c => *count += 1,
+            _ => runs.push((c, 1))
+        };
+    }
+    runs
+}
+
+// the token is the word, possibly with some of its letters drawn out ("baaad" for "bad")
+fn is_stretched(token: &str, word: &str) -> bool {
+    let (token_runs, word_runs) = (get_runs(token), get_runs(word));
+    token_runs.len() == word_runs.len() && token_runs.iter().zip(word_runs.iter())
+        .all(|((token_char, token_count), (word_char, word_count))| token_char == word_char && token_count >= word_count)
+}
+
+// blocked entries only match whole words, a phrase has to match consecutive words
+fn contains_blocked_word(message: &str, words: &[String]) -> bool {
+    let tokens = tokenize(message);
+    words.iter().map(|word| tokenize(word)).filter(|word| !word.is_empty()).any(|word| {
+        tokens.windows(word.len()).any(|window| window.iter().zip(word.iter()).all(|(token, word)| is_stretched(token, word)))
+    })
+}
+
+fn exceeds_caps_ratio(message: &str, min_length: usize, max_ratio: f32) -> bool {
+    let letters : Vec<char> = message.chars().filter(|c| c.is_alphabetic()).collect();
+    if letters.is_empty() || letters.len() < min_length {
+        return false;
+    };
+    let uppercase = letters.iter().filter(|c| c.is_uppercase()).count();
+    (uppercase as f32 / letters.len() as f32) > max_ratio
+}
+
+fn contains_link(message: &str, allowed_domains: &[String]) -> bool {
+    message.split_whitespace().filter_map(get_link_host).any(|host| {
+        !allowed_domains.iter().any(|domain| {
+            let domain = domain.to_lowercase();
+            host == domain || host.ends_with(&format!(".{}", domain))
+        })
+    })
+}
+
+fn get_link_host(token: &str) -> Option<String> {
+    let token = token.to_lowercase();
+    let token = token.trim_matches(|c: char| !c.is_alphanumeric());
+    let has_scheme = token.contains("://");
+    let host = token.split("://").last()?.split(['/', '?', '#', ':']).next()?;
+    let labels : Vec<&str> = host.split('.').collect();
+    if labels.len() < 2 || labels.iter().any(|label| label.is_empty()) {
+        return None;
+    };
+    let tld = labels.last()?;
+    let looks_like_domain = (2..=6).contains(&tld.len()) && tld.chars().all(|c| c.is_ascii_alphabetic());
+    if has_scheme || looks_like_domain {
+        Some(host.trim_start_matches("www.").to_owned())
+    } else {
+        None
+    }
+}
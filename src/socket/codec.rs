@@ -0,0 +1,101 @@
+use std::{borrow::Cow, collections::HashMap, io::Read, str::FromStr};
+
+use flate2::read::ZlibDecoder;
+use rocket::serde::json::{serde_json, Value};
+use serde::Serialize;
+use strum_macros::{Display, EnumString};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::util::string::deflate_string;
+
+use super::server::server_context::Packet;
+
+#[derive(Clone, Copy, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum PayloadEncoding {
+    Json,
+    #[strum(serialize = "msgpack")]
+    MessagePack,
+    Cbor
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum PayloadCompression {
+    Zlib,
+    None
+}
+
+// how a connection's packets are serialized, picked by the plugin during the handshake
+// JSON with per-message zlib is what plugins predating negotiation speak
+#[derive(Clone, Copy)]
+pub struct PacketCodec {
+    pub encoding: PayloadEncoding,
+    pub compression: PayloadCompression
+}
+
+impl Default for PacketCodec {
+    fn default() -> Self {
+        PacketCodec { encoding: PayloadEncoding::Json, compression: PayloadCompression::Zlib }
+    }
+}
+
+impl PacketCodec {
+    pub const ENCODING_HEADER: &'static str = "Mars-Encoding";
+    pub const COMPRESSION_HEADER: &'static str = "Mars-Compression";
+
+    // reads the optional `encoding` and `compression` handshake query parameters
+    pub fn from_query(query: &HashMap<String, String>) -> Result<Self, String> {
+        let mut codec = PacketCodec::default();
+        if let Some(encoding) = query.get("encoding") {
+            codec.encoding = PayloadEncoding::from_str(&encoding.to_lowercase()).map_err(|_| format!("Unsupported encoding '{}'", encoding))?;
+        };
+        if let Some(compression) = query.get("compression") {
+            codec.compression = PayloadCompression::from_str(&compression.to_lowercase()).map_err(|_| format!("Unsupported compression '{}'", compression))?;
+        };
+        Ok(codec)
+    }
+
+    pub fn encode<T: Serialize>(&self, packet: &Packet<T>) -> anyhow::Result<Message> {
+        let body = match self.encoding {
+            PayloadEncoding::Json => serde_json::to_vec(packet)?,
+            PayloadEncoding::MessagePack => rmp_serde::to_vec_named(packet)?,
+            PayloadEncoding::Cbor => {
+                let mut body = Vec::new();
+                ciborium::ser::into_writer(packet, &mut body)?;
+                body
+            }
+        };
+        Ok(Message::Binary(match self.compression {
+            PayloadCompression::Zlib => deflate_string(&body)?,
+            PayloadCompression::None => body
+        }))
+    }
+
+    // fails on unknown event types as well as malformed payloads, the API itself splits packets via dead_letter::split_packet
+    #[cfg(test)]
+    pub fn decode(&self, data: &[u8]) -> anyhow::Result<Packet<Value>> {
+        Ok(serde_json::from_value(self.decode_value(data)?)?)
+    }
+
+    // the packet body without checking it's a valid {e, d} packet
+    pub fn decode_value(&self, data: &[u8]) -> anyhow::Result<Value> {
+        let body = self.decompress(data)?;
+        Ok(match self.encoding {
+            PayloadEncoding::Json => serde_json::from_slice(&body)?,
+            PayloadEncoding::MessagePack => rmp_serde::from_slice(&body)?,
+            PayloadEncoding::Cbor => ciborium::de::from_reader(body.as_ref())?
+        })
+    }
+
+    pub fn decompress<'a>(&self, data: &'a [u8]) -> anyhow::Result<Cow<'a, [u8]>> {
+        Ok(match self.compression {
+            PayloadCompression::Zlib => {
+                let mut decompressed = Vec::new();
+                ZlibDecoder::new(data).read_to_end(&mut decompressed)?;
+                Cow::Owned(decompressed)
+            },
+            PayloadCompression::None => Cow::Borrowed(data)
+        })
+    }
+}
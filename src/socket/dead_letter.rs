@@ -0,0 +1,140 @@
+use std::{panic::AssertUnwindSafe, str::FromStr, sync::Arc};
+
+use futures::FutureExt;
+use mongodb::bson::doc;
+use rocket::serde::json::{serde_json, Value};
+use tokio::sync::mpsc;
+
+use crate::{MarsAPIState, database::{Database, models::dead_letter::{DeadLetter, DeadLetterReason}}, util::time::get_u64_time_millis};
+
+use super::{codec::PacketCodec, event_type::EventType, server::server_context::{Packet, ServerContext}, socket_router::{SocketError, SocketRouter}};
+
+// how long a redrive holds a letter before another one may take it over
+const REDRIVE_CLAIM_MS: u64 = 300_000;
+
+pub async fn store(api_state: &MarsAPIState, dead_letter: &DeadLetter) {
+    warn!("[{}] Dead-lettered {} event ({}): {}", dead_letter.server_id, dead_letter.event.as_deref().unwrap_or("unknown"), dead_letter.reason, dead_letter.error);
+    api_state.database.insert_one(dead_letter).await;
+}
+
+// for packets that never decoded, keeps whatever can be recovered of the original
+pub fn undecodable(server_id: &str, codec: &PacketCodec, data: &[u8], error: String) -> DeadLetter {
+    let mut dead_letter = DeadLetter::new(server_id, DeadLetterReason::Undecodable, error);
+    let body = codec.decompress(data).map(|body| body.into_owned()).unwrap_or_else(|_| data.to_vec());
+    dead_letter.raw = Some(match String::from_utf8(body) {
+        Ok(text) => text,
+        Err(e) => e.into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+    });
+    dead_letter
+}
+
+// splits a decoded body into its event and data
+pub fn split_packet(server_id: &str, body: Value) -> Result<(EventType, Value), Box<DeadLetter>> {
+    let (event, data) = match body {
+        Value::Object(mut packet) => (packet.remove("e"), packet.remove("d")),
+        other => {
+            let mut dead_letter = DeadLetter::new(server_id, DeadLetterReason::Undecodable, String::from("Packet is not an object"));
+            dead_letter.raw = Some(other.to_string());
+            return Err(Box::new(dead_letter));
+        }
+    };
+    let (event, data) = match (event, data) {
+        (Some(event), Some(data)) => (event, data),
+        (event, data) => {
+            let mut dead_letter = DeadLetter::new(server_id, DeadLetterReason::Undecodable, String::from("Packet is missing 'e' or 'd'"));
+            dead_letter.event = event.map(|event| event.as_str().map(str::to_owned).unwrap_or_else(|| event.to_string()));
+            dead_letter.data = data;
+            return Err(Box::new(dead_letter));
+        }
+    };
+    match serde_json::from_value::<EventType>(event.clone()) {
+        Ok(event_type) => Ok((event_type, data)),
+        Err(e) => {
+            let mut dead_letter = DeadLetter::new(server_id, DeadLetterReason::UnknownEvent, e.to_string());
+            dead_letter.event = Some(event.as_str().map(str::to_owned).unwrap_or_else(|| event.to_string()));
+            dead_letter.data = Some(data);
+            Err(Box::new(dead_letter))
+        }
+    }
+}
+
+// routes an event, turning handler errors and panics into a dead letter instead of losing the event or the connection
+pub async fn route(router: &mut SocketRouter, event_type: &EventType, data: Value) -> Result<(), Box<DeadLetter>> {
+    let kept_data = data.clone();
+    let (reason, error) = match AssertUnwindSafe(router.route(event_type, data)).catch_unwind().await {
+        Ok(Ok(_)) => return Ok(()),
+        Ok(Err(socket_error)) => (match socket_error {
+            SocketError::MalformedData(_) | SocketError::UnknownParticipant(_) => DeadLetterReason::MalformedData,
+            SocketError::Unroutable => DeadLetterReason::Unroutable,
+            SocketError::VersionConflict(_) => DeadLetterReason::VersionConflict,
+            _ => DeadLetterReason::HandlerError
+        }, socket_error.message()),
+        Err(panic) => (DeadLetterReason::HandlerPanic, get_panic_message(panic))
+    };
+    let mut dead_letter = DeadLetter::new(&router.server.id, reason, error);
+    dead_letter.event = Some(event_type.to_string());
+    dead_letter.data = Some(kept_data);
+    Err(Box::new(dead_letter))
+}
+
+fn get_panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => panic.downcast_ref::<&str>().map(|message| message.to_string()).unwrap_or_else(|| String::from("Handler panicked"))
+    }
+}
+
+// routes a dead letter again as if its server had just sent it, plugin-bound packets still reach the server if it's connected
+// the letter is claimed first and skipped, returning None, if it's resolved or another redrive holds it
+// otherwise it's updated and saved either way
+pub async fn redrive(api_state: &Arc<MarsAPIState>, dead_letter: &mut DeadLetter) -> Option<Result<(), String>> {
+    let now = get_u64_time_millis();
+    let claim = doc! {
+        "_id": &dead_letter.id,
+        "resolvedAt": null,
+        // a claim left by a redrive that never finished is taken over
+        "$or": [{ "redrivingAt": null }, { "redrivingAt": { "$lt": now.saturating_sub(REDRIVE_CLAIM_MS) as i64 } }]
+    };
+    match api_state.database.dead_letters.update_one(claim, doc! { "$set": { "redrivingAt": now as i64 } }, None).await {
+        Ok(claimed) if claimed.matched_count > 0 => {},
+        Ok(_) => return None,
+        Err(e) => return Some(Err(format!("Could not claim the dead letter: {}", e)))
+    };
+    // another redrive may have finished since the letter was read
+    *dead_letter = Database::find_by_id(&api_state.database.dead_letters, &dead_letter.id).await?;
+
+    let result = match get_redrivable_event(dead_letter) {
+        Ok((event_type, data)) => {
+            let (sender, mut receiver) = mpsc::unbounded_channel::<Packet<Value>>();
+            let forwarding_state = Arc::clone(api_state);
+            let server_id = dead_letter.server_id.clone();
+            tokio::spawn(async move {
+                while let Some(packet) = receiver.recv().await {
+                    forwarding_state.servers.call(&server_id, &packet.event, packet.data).await;
+                }
+            });
+            let mut router = SocketRouter::new(ServerContext { id: dead_letter.server_id.clone(), api_state: Arc::clone(api_state), sender });
+            route(&mut router, &event_type, data).await.map_err(|failed| failed.error)
+        },
+        Err(error) => Err(error)
+    };
+    dead_letter.redrive_attempts += 1;
+    dead_letter.redriving_at = None;
+    match &result {
+        Ok(_) => dead_letter.resolved_at = Some(get_u64_time_millis()),
+        Err(error) => dead_letter.last_redrive_error = Some(error.clone())
+    };
+    api_state.database.save(dead_letter).await;
+    Some(result)
+}
+
+fn get_redrivable_event(dead_letter: &DeadLetter) -> Result<(EventType, Value), String> {
+    if let (Some(event), Some(data)) = (&dead_letter.event, &dead_letter.data) {
+        let event_type = EventType::from_str(event).map_err(|_| format!("Unknown event type '{}'", event))?;
+        return Ok((event_type, data.clone()));
+    };
+    // packets rejected for their shape, which may route once the API accepts it
+    let raw = dead_letter.raw.as_ref().ok_or_else(|| String::from("Nothing to redrive"))?;
+    let body = serde_json::from_str::<Value>(raw).map_err(|e| format!("Raw packet is not JSON: {}", e))?;
+    split_packet(&dead_letter.server_id, body).map_err(|failed| failed.error)
+}
@@ -36,5 +36,7 @@ pub enum EventType {
     ForceMatchEnd,
     Message,
     DisconnectPlayer,
-    PlayerUpdate
+    PlayerUpdate,
+    // not handled by the Mars plugin yet, see the README for the data it carries and what the plugin is expected to do
+    CycleMap
 }
@@ -1,14 +1,13 @@
 use std::sync::Arc;
-use mongodb::{bson::doc, Cursor};
+use mongodb::bson::doc;
 use num_traits::cast::FromPrimitive;
-use redis::{aio::Connection, ToRedisArgs};
 use serde::{Serialize, Deserialize};
 use strum_macros::{Display, EnumIter, EnumString};
 use strum::IntoEnumIterator;
 
 use chrono::{Month, DateTime, Utc, TimeZone, FixedOffset, Datelike};
 
-use crate::{database::{cache::RedisAdapter, Database, models::player::Player}, util::r#macro::unwrap_helper};
+use crate::{database::{cache::RedisAdapter, Database, models::player::Player, store::Cursor}, util::r#macro::unwrap_helper};
 
 pub mod leaderboard_listener;
 
@@ -45,6 +44,9 @@ impl Season {
     }
 }
 
+// every leaderboard sorted set, of any score type and period, is stored under this prefix
+pub const LEADERBOARD_KEY_PREFIX: &str = "lb:";
+
 #[derive(EnumIter, EnumString)]
 #[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
 pub enum LeaderboardPeriod {
@@ -91,7 +93,7 @@ impl LeaderboardPeriod {
     }
 }
 
-#[derive(Display, EnumString, Serialize, Deserialize, Clone, Eq, Hash, PartialEq)]
+#[derive(Display, EnumIter, EnumString, Serialize, Deserialize, Clone, Eq, Hash, PartialEq)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 #[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
 pub enum ScoreType {
@@ -164,14 +166,6 @@ pub struct Leaderboard {
 
 
 impl Leaderboard {
-    async fn zadd_entries<T: ToRedisArgs, K: ToRedisArgs, V: ToRedisArgs>(&self, key: &T, items: &Vec<(K, V)>) {
-        let _ = self.cache.submit(|mut conn| async move {
-            let _ = redis::cmd("ZADD")
-                .arg(key)
-                .arg(items)
-                .query_async::<Connection, ()>(&mut conn).await;
-        }).await;
-    }
 
     pub async fn populate_all_time(&self) {
         let cursor : Cursor<Player> = match self.database.players.find(doc! {}, None).await {
@@ -186,41 +180,32 @@ impl Leaderboard {
             players
         };
         let members = {
-            let mut members : Vec<(String, u64)> = Vec::new();
+            let mut members : Vec<(String, f64)> = Vec::new();
             for player in players.iter() {
-                members.push((player.id_name(), player.stats.get_score(&self.score_type) as u64));
+                members.push((player.id_name(), player.stats.get_score(&self.score_type) as f64));
             };
             members
         };
-        self.zadd_entries(&self.get_id(&LeaderboardPeriod::AllTime), &members).await;
+        self.cache.zadd(&self.get_id(&LeaderboardPeriod::AllTime), &members).await;
     }
 
-    pub async fn set(&self, id: &String, score: u32) {
-        let u64_score = score as u64;
-        let _ = self.cache.submit(|mut conn| async move {
-            for period in LeaderboardPeriod::iter() {
-                let _ = redis::cmd("ZADD").arg(&self.get_id(&period)).arg(u64_score).arg(id).query_async::<Connection, ()>(&mut conn).await;
-            };
-        }).await;
+    pub async fn set(&self, id: &str, score: u32) {
+        for period in LeaderboardPeriod::iter() {
+            self.cache.zadd(&self.get_id(&period), &[(id.to_owned(), score as f64)]).await;
+        };
     }
 
-    pub async fn increment(&self, id: &String, incr: Option<u32>) {
-        let u64_incr = incr.unwrap_or(1) as u64;
-        let _ = self.cache.submit(|mut conn| async move {
-            for period in LeaderboardPeriod::iter() {
-                let _ = redis::cmd("ZINCRBY").arg(&self.get_id(&period)).arg(u64_incr).arg(id).query_async::<Connection, ()>(&mut conn).await;
-            };
-        }).await;
+    pub async fn increment(&self, id: &str, incr: Option<u32>) {
+        let incr = incr.unwrap_or(1) as f64;
+        for period in LeaderboardPeriod::iter() {
+            self.cache.zincrby(&self.get_id(&period), id, incr).await;
+        };
     }
 
-    fn strings_as_leaderboard_entries(raw: Vec<String>) -> Vec<LeaderboardEntry> {
+    fn scores_as_leaderboard_entries(raw: Vec<(String, f64)>) -> Vec<LeaderboardEntry> {
         let mut entries : Vec<LeaderboardEntry> = Vec::new();
-        if raw.len() <= 1 || raw.len() % 2 == 1 {
-            return entries;
-        };
-        for i in (0..=(raw.len() - 2)).step_by(2) {
-            let id_name = raw[i].clone();
-            let score = raw[i + 1].parse::<u32>().unwrap_or(0);
+        for (id_name, score) in raw.into_iter() {
+            let score = score as u32;
             let (id, name) = {
                 let mut parts = id_name.split("/");
                 let id = unwrap_helper::continue_default!(parts.next());
@@ -233,42 +218,25 @@ impl Leaderboard {
     }
 
     pub async fn fetch_top(&self, period: &LeaderboardPeriod, limit: u32) -> Vec<LeaderboardEntry> {
-        let lb_top = self.cache.submit(|mut conn| async move {
-            let top : Option<Vec<String>> = match redis::cmd("ZRANGE").arg(&self.get_id(period)).arg(0u32).arg(limit - 1).arg("REV").arg("WITHSCORES").query_async::<Connection, Vec<String>>(&mut conn).await {
-                Ok(res) => Some(res),
-                Err(_) => None
-            };
-            top.unwrap_or(Vec::new())
-        }).await.unwrap_or(Vec::new());
-        Self::strings_as_leaderboard_entries(lb_top)
+        if limit == 0 {
+            return Vec::new();
+        };
+        let lb_top = self.cache.zrevrange_with_scores(&self.get_id(period), 0, (limit - 1) as usize).await;
+        Self::scores_as_leaderboard_entries(lb_top)
     }
 
-    pub async fn set_if_higher(&self, id: &String, new: u32) {
-        let _ = self.cache.submit(|mut conn| async move {
-            for period in LeaderboardPeriod::iter() {
-                let current = match redis::cmd("ZSCORE").arg(&self.get_id(&period)).arg(id).query_async::<Connection, String>(&mut conn).await {
-                    Ok(res) => { res.parse::<u32>().unwrap() },
-                    Err(_) => { 0u32 }
-                };
-                if new > current {
-                    redis::cmd("ZADD").arg(&self.get_id(&period)).arg(new as f64).arg(id).query_async::<Connection, ()>(&mut conn).await;
-                };
-            };
-        }).await;
+    pub async fn set_if_higher(&self, id: &str, new: u32) {
+        for period in LeaderboardPeriod::iter() {
+            self.cache.zadd_if_greater(&self.get_id(&period), id, new as f64).await;
+        };
     }
 
-    pub async fn get_position(&self, id: &String, period: &LeaderboardPeriod) -> Option<u64> {
-        self.cache.submit(|mut conn| async move {
-            let rank : Option<u64> = match redis::cmd("ZREVRANK").arg(&self.get_id(period)).arg(id).query_async::<Connection, u64>(&mut conn).await {
-                Ok(res) => Some(res),
-                Err(_) => None // this error occurs when redis encounters an issue executing the query
-            };
-            rank
-        }).await.unwrap_or(None) // this unwrap occurs if a connection can't be obtained
+    pub async fn get_position(&self, id: &str, period: &LeaderboardPeriod) -> Option<u64> {
+        self.cache.zrevrank(&self.get_id(period), id).await
     }
 
     fn get_id(&self, period: &LeaderboardPeriod) -> String {
-        format!("lb:{}:{}", self.score_type, period.get_today_id())
+        format!("{}{}:{}", LEADERBOARD_KEY_PREFIX, self.score_type, period.get_today_id())
     }
 }
 
@@ -333,6 +301,13 @@ impl MarsLeaderboards {
         }
     }
 
+    // rebuilds every all-time leaderboard from the stored player stats
+    pub async fn populate_all_time(&self) {
+        for score_type in ScoreType::iter() {
+            self.from_score_type(score_type).populate_all_time().await;
+        }
+    }
+
     pub fn from_score_type(&self, score_type: ScoreType) -> &Leaderboard {
         match score_type {
             ScoreType::Kills => &self.kills,
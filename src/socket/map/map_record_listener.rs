@@ -55,7 +55,8 @@ impl PlayerListener for MapRecordListener {
                 };
             };
 
-            server_context.api_state.match_cache.set(&server_context.api_state.database, &current_match.id, &current_match, false).await;
+            // a conflict resurfaces when the router saves the match after the listeners
+            let _ = server_context.save_match(current_match, false, None).await;
         };
     }
 
@@ -80,7 +81,7 @@ impl PlayerListener for MapRecordListener {
                 }); 
             };
 
-            server_context.api_state.match_cache.set(&server_context.api_state.database, &current_match.id, &current_match, false).await;
+            let _ = server_context.save_match(current_match, false, None).await;
         };
     }
 
@@ -100,9 +101,7 @@ impl PlayerListener for MapRecordListener {
             });
         }
 
-        server_context.api_state.match_cache.set(
-            &server_context.api_state.database, &current_match.id, &current_match, false
-        ).await;
+        let _ = server_context.save_match(current_match, false, None).await;
     }
 
     async fn on_flag_place(
@@ -121,9 +120,7 @@ impl PlayerListener for MapRecordListener {
             });
         }
 
-        server_context.api_state.match_cache.set(
-            &server_context.api_state.database, &current_match.id, &current_match, false
-        ).await;
+        let _ = server_context.save_match(current_match, false, None).await;
     }
 
     async fn on_match_end_v2(
@@ -159,7 +156,7 @@ impl PlayerListener for MapRecordListener {
                     value: deaths
                 });
             };
-            server_context.api_state.match_cache.set(&server_context.api_state.database, &current_match.id, &current_match, false).await;
+            let _ = server_context.save_match(current_match, false, None).await;
         };
     }
 
@@ -23,7 +23,7 @@ impl MatchPhaseListener<'_> {
             parties.insert(party.name.clone(), Party { name: party.name, alias: party.alias, color: party.color, min: party.min, max: party.max });
         }
 
-        let new_match = Match {
+        let mut new_match = Match {
             id: match_id,
             loaded_at: time_millis,
             started_at: None,
@@ -32,11 +32,12 @@ impl MatchPhaseListener<'_> {
             parties,
             participants: HashMap::new(),
             server_id: self.server.id.clone(),
-            first_blood: None
+            first_blood: None,
+            version: 0
         };
 
 
-        self.server.api_state.match_cache.set(&self.server.api_state.database, &new_match.id, &new_match, true).await;
+        self.server.save_match(&mut new_match, true, None).await?;
         self.server.set_current_match_id(&new_match.id).await;
         info!("({}) Match loaded: {}", self.server.id, new_match.id);
         Ok(())
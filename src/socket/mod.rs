@@ -9,3 +9,8 @@ pub mod player;
 pub mod map;
 pub mod objective;
 pub mod update;
+pub mod chat;
+pub mod recording;
+pub mod codec;
+pub mod permessage_deflate;
+pub mod dead_letter;
@@ -0,0 +1,456 @@
+use std::{io, pin::Pin, sync::{Arc, OnceLock}, task::{Context, Poll}};
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+
+// the permessage-deflate websocket extension (RFC 7692), which the websocket library doesn't implement
+// it sits between the library and the TCP stream: inbound compressed messages are inflated into plain frames
+// before the library reads them, and outbound data frames are deflated after it writes them
+
+pub const EXTENSIONS_HEADER: &str = "Sec-WebSocket-Extensions";
+const EXTENSION_NAME: &str = "permessage-deflate";
+// the deflate backend always uses the largest window
+const WINDOW_BITS: u8 = 15;
+// same as the websocket library's default message limit, so a small compressed frame can't inflate without bound
+const MAX_MESSAGE_SIZE: usize = 64 << 20;
+// the empty stored block a sync flush ends with, left off on the wire
+const DEFLATE_TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+// queued outbound bytes past which writers wait for the socket
+const WRITE_HIGH_WATER: usize = 1 << 20;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeflateParams {
+    // compress every outbound message on its own
+    pub server_no_context_takeover: bool,
+    // the client compresses every message on its own
+    pub client_no_context_takeover: bool
+}
+
+// picks the first offer in the request's extension header that can be honoured
+// returns the parameters and the value for the response header
+pub fn negotiate<'a>(offers: impl Iterator<Item = &'a str>) -> Option<(DeflateParams, String)> {
+    offers.flat_map(|header| header.split(',')).find_map(accept_offer)
+}
+
+fn accept_offer(offer: &str) -> Option<(DeflateParams, String)> {
+    let mut parts = offer.split(';').map(str::trim);
+    if !parts.next()?.eq_ignore_ascii_case(EXTENSION_NAME) {
+        return None;
+    };
+    let mut params = DeflateParams { server_no_context_takeover: false, client_no_context_takeover: false };
+    let mut response = vec![EXTENSION_NAME.to_owned()];
+    for part in parts.filter(|part| !part.is_empty()) {
+        let (name, value) = match part.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+            None => (part, None)
+        };
+        match (name.to_ascii_lowercase().as_str(), value) {
+            ("server_no_context_takeover", None) => {
+                params.server_no_context_takeover = true;
+                response.push(String::from("server_no_context_takeover"));
+            },
+            ("client_no_context_takeover", None) => {
+                params.client_no_context_takeover = true;
+                response.push(String::from("client_no_context_takeover"));
+            },
+            // a smaller window can't be produced, only the largest one is acceptable
+            ("server_max_window_bits", Some(bits)) => {
+                if bits.parse::<u8>().ok()? != WINDOW_BITS {
+                    return None;
+                };
+                response.push(format!("server_max_window_bits={}", WINDOW_BITS));
+            },
+            // inflating with the largest window reads any smaller one too
+            ("client_max_window_bits", bits) => {
+                if bits.is_some_and(|bits| !matches!(bits.parse::<u8>(), Ok(8..=15))) {
+                    return None;
+                };
+            },
+            _ => return None
+        };
+    }
+    Some((params, response.join("; ")))
+}
+
+// agreed during the handshake, before any frame is exchanged
+#[derive(Clone, Default)]
+pub struct DeflateNegotiation(Arc<OnceLock<DeflateParams>>);
+
+impl DeflateNegotiation {
+    // accepts the extension if the request offers it, adding the response header
+    pub fn accept(&self, request_headers: &tokio_tungstenite::tungstenite::http::HeaderMap, response_headers: &mut tokio_tungstenite::tungstenite::http::HeaderMap) {
+        let offers = request_headers.get_all(EXTENSIONS_HEADER).iter().filter_map(|value| value.to_str().ok());
+        if let Some((params, response)) = negotiate(offers) {
+            if let Ok(value) = HeaderValue::from_str(&response) {
+                response_headers.insert(EXTENSIONS_HEADER, value);
+                let _ = self.0.set(params);
+            };
+        };
+    }
+
+    fn get(&self) -> Option<DeflateParams> {
+        self.0.get().copied()
+    }
+}
+
+struct FrameHeader {
+    fin: bool,
+    rsv1: bool,
+    opcode: u8,
+    mask: Option<[u8; 4]>,
+    header_len: usize,
+    payload_len: usize
+}
+
+impl FrameHeader {
+    // None until the whole frame is buffered
+    fn parse(buf: &[u8]) -> Option<FrameHeader> {
+        if buf.len() < 2 {
+            return None;
+        };
+        let (first, second) = (buf[0], buf[1]);
+        let (mut header_len, payload_len) = match second & 0x7f {
+            126 => (4, u16::from_be_bytes(buf.get(2..4)?.try_into().ok()?) as usize),
+            127 => (10, u64::from_be_bytes(buf.get(2..10)?.try_into().ok()?) as usize),
+            len => (2, len as usize)
+        };
+        let mask = if second & 0x80 != 0 {
+            let mask : [u8; 4] = buf.get(header_len..header_len + 4)?.try_into().ok()?;
+            header_len += 4;
+            Some(mask)
+        } else {
+            None
+        };
+        if buf.len() < header_len.checked_add(payload_len)? {
+            return None;
+        };
+        Some(FrameHeader { fin: first & 0x80 != 0, rsv1: first & 0x40 != 0, opcode: first & 0x0f, mask, header_len, payload_len })
+    }
+
+    fn frame_len(&self) -> usize {
+        self.header_len + self.payload_len
+    }
+
+    fn is_control(&self) -> bool {
+        self.opcode & 0x08 != 0
+    }
+}
+
+fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+}
+
+fn write_frame(out: &mut Vec<u8>, rsv1: bool, opcode: u8, mask: Option<[u8; 4]>, payload: &[u8]) {
+    out.push(0x80 | if rsv1 { 0x40 } else { 0 } | opcode);
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        len if len < 126 => out.push(mask_bit | len as u8),
+        len if len <= u16::MAX as usize => {
+            out.push(mask_bit | 126);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        },
+        len => {
+            out.push(mask_bit | 127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    };
+    match mask {
+        Some(mask) => {
+            out.extend_from_slice(&mask);
+            let start = out.len();
+            out.extend_from_slice(payload);
+            apply_mask(&mut out[start..], mask);
+        },
+        None => out.extend_from_slice(payload)
+    };
+}
+
+// a message whose frames are being collected until the last one arrives
+struct PendingMessage {
+    opcode: u8,
+    mask: Option<[u8; 4]>,
+    payload: Vec<u8>
+}
+
+fn inflate(inflater: &mut Decompress, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut output = Vec::with_capacity((data.len() * 4).max(64));
+    let mut consumed = 0;
+    loop {
+        if output.len() == output.capacity() {
+            if output.len() >= MAX_MESSAGE_SIZE {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Inflated message is too large"));
+            };
+            output.reserve(output.len());
+        };
+        let (before_in, before_out) = (inflater.total_in(), inflater.total_out());
+        let status = inflater.decompress_vec(&data[consumed..], &mut output, FlushDecompress::Sync)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        consumed += (inflater.total_in() - before_in) as usize;
+        let progressed = inflater.total_in() != before_in || inflater.total_out() != before_out;
+        if (consumed == data.len() && output.len() < output.capacity()) || status == Status::StreamEnd || !progressed {
+            return Ok(output);
+        };
+    }
+}
+
+fn deflate(deflater: &mut Compress, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(data.len() / 2 + 64);
+    let mut consumed = 0;
+    loop {
+        if output.len() == output.capacity() {
+            output.reserve(output.len());
+        };
+        let before_in = deflater.total_in();
+        deflater.compress_vec(&data[consumed..], &mut output, FlushCompress::Sync)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        consumed += (deflater.total_in() - before_in) as usize;
+        // the flush is complete once there's output space left over
+        if consumed == data.len() && output.len() < output.capacity() {
+            break;
+        };
+    }
+    if output.ends_with(&DEFLATE_TAIL) {
+        output.truncate(output.len() - DEFLATE_TAIL.len());
+    };
+    Ok(output)
+}
+
+enum WriteMode {
+    // the HTTP response is still being written, the length of "\r\n\r\n" matched so far
+    Handshake(usize),
+    Frames,
+    Passthrough
+}
+
+// wraps the connection's stream, passing bytes through untouched unless the extension was negotiated
+pub struct DeflateStream<S> {
+    inner: S,
+    negotiation: DeflateNegotiation,
+    params: Option<DeflateParams>,
+    inflater: Decompress,
+    deflater: Compress,
+    // bytes read from the stream that don't make up a whole frame yet, and frames ready for the library
+    read_raw: Vec<u8>,
+    read_ready: Vec<u8>,
+    read_message: Option<PendingMessage>,
+    // bytes the library wrote that don't make up a whole frame yet, and bytes ready for the stream
+    write_mode: WriteMode,
+    write_raw: Vec<u8>,
+    write_ready: Vec<u8>,
+    write_message: Option<PendingMessage>
+}
+
+impl<S> DeflateStream<S> {
+    pub fn new(inner: S) -> Self {
+        DeflateStream {
+            inner,
+            negotiation: DeflateNegotiation::default(),
+            params: None,
+            inflater: Decompress::new(false),
+            deflater: Compress::new(Compression::default(), false),
+            read_raw: Vec::new(),
+            read_ready: Vec::new(),
+            read_message: None,
+            write_mode: WriteMode::Handshake(0),
+            write_raw: Vec::new(),
+            write_ready: Vec::new(),
+            write_message: None
+        }
+    }
+
+    pub fn get_negotiation(&self) -> DeflateNegotiation {
+        self.negotiation.clone()
+    }
+
+    fn process_inbound(&mut self) -> io::Result<()> {
+        let params = match self.params {
+            Some(params) => params,
+            None => return Ok(())
+        };
+        let mut offset = 0;
+        while let Some(header) = FrameHeader::parse(&self.read_raw[offset..]) {
+            let frame = &self.read_raw[offset..offset + header.frame_len()];
+            offset += header.frame_len();
+            let continues_compressed = header.opcode == OPCODE_CONTINUATION && self.read_message.is_some();
+            let starts_compressed = header.rsv1 && (header.opcode == OPCODE_TEXT || header.opcode == OPCODE_BINARY);
+            if header.is_control() || !(continues_compressed || starts_compressed) {
+                // the library rejects whatever isn't valid
+                self.read_ready.extend_from_slice(frame);
+                continue;
+            };
+            let mut payload = frame[header.header_len..].to_vec();
+            if let Some(mask) = header.mask {
+                apply_mask(&mut payload, mask);
+            };
+            let message = self.read_message.get_or_insert_with(|| PendingMessage { opcode: header.opcode, mask: header.mask, payload: Vec::new() });
+            if message.payload.len() + payload.len() > MAX_MESSAGE_SIZE {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Compressed message is too large"));
+            };
+            message.payload.extend_from_slice(&payload);
+            if !header.fin {
+                continue;
+            };
+            let mut message = self.read_message.take().unwrap();
+            message.payload.extend_from_slice(&DEFLATE_TAIL);
+            let inflated = inflate(&mut self.inflater, &message.payload)?;
+            if params.client_no_context_takeover {
+                self.inflater.reset(false);
+            };
+            write_frame(&mut self.read_ready, false, message.opcode, message.mask, &inflated);
+        }
+        self.read_raw.drain(..offset);
+        Ok(())
+    }
+
+    fn accept_outbound(&mut self, buf: &[u8]) -> io::Result<()> {
+        let mut buf = buf;
+        if let WriteMode::Handshake(matched) = self.write_mode {
+            let mut matched = matched;
+            let end = buf.iter().position(|byte| {
+                matched = if *byte == b"\r\n\r\n"[matched] { matched + 1 } else if *byte == b'\r' { 1 } else { 0 };
+                matched == 4
+            });
+            match end {
+                Some(end) => {
+                    self.write_ready.extend_from_slice(&buf[..=end]);
+                    buf = &buf[end + 1..];
+                    // the callback has run by the time the response is written
+                    self.params = self.negotiation.get();
+                    self.write_mode = if self.params.is_some() { WriteMode::Frames } else { WriteMode::Passthrough };
+                },
+                None => {
+                    self.write_ready.extend_from_slice(buf);
+                    self.write_mode = WriteMode::Handshake(matched);
+                    return Ok(());
+                }
+            };
+        };
+        let params = match (&self.write_mode, self.params) {
+            (WriteMode::Frames, Some(params)) => params,
+            _ => {
+                self.write_ready.extend_from_slice(buf);
+                return Ok(());
+            }
+        };
+        self.write_raw.extend_from_slice(buf);
+        let mut offset = 0;
+        while let Some(header) = FrameHeader::parse(&self.write_raw[offset..]) {
+            let frame = &self.write_raw[offset..offset + header.frame_len()];
+            offset += header.frame_len();
+            let is_data = header.opcode == OPCODE_TEXT || header.opcode == OPCODE_BINARY
+                || (header.opcode == OPCODE_CONTINUATION && self.write_message.is_some());
+            if header.is_control() || !is_data {
+                self.write_ready.extend_from_slice(frame);
+                continue;
+            };
+            let message = self.write_message.get_or_insert_with(|| PendingMessage { opcode: header.opcode, mask: None, payload: Vec::new() });
+            message.payload.extend_from_slice(&frame[header.header_len..]);
+            if !header.fin {
+                continue;
+            };
+            let message = self.write_message.take().unwrap();
+            let deflated = deflate(&mut self.deflater, &message.payload)?;
+            if params.server_no_context_takeover {
+                self.deflater.reset();
+            };
+            write_frame(&mut self.write_ready, true, message.opcode, None, &deflated);
+        }
+        self.write_raw.drain(..offset);
+        Ok(())
+    }
+}
+
+impl<S: AsyncWrite + Unpin> DeflateStream<S> {
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.write_ready.is_empty() {
+            match Pin::new(&mut self.inner).poll_write(cx, &self.write_ready) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(written)) => { self.write_ready.drain(..written); },
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending
+            };
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for DeflateStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if !this.read_ready.is_empty() {
+                let len = this.read_ready.len().min(buf.remaining());
+                buf.put_slice(&this.read_ready[..len]);
+                this.read_ready.drain(..len);
+                return Poll::Ready(Ok(()));
+            };
+            if this.params.is_none() {
+                // the request has been read in full before the callback negotiates
+                this.params = this.negotiation.get();
+                if this.params.is_none() {
+                    return Pin::new(&mut this.inner).poll_read(cx, buf);
+                };
+            };
+            let mut chunk = [0u8; 8192];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            match Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf) {
+                Poll::Ready(Ok(())) => {},
+                other => return other
+            };
+            if chunk_buf.filled().is_empty() {
+                // end of stream, a partial frame is left for the library to complain about
+                let leftover = std::mem::take(&mut this.read_raw);
+                if leftover.is_empty() {
+                    return Poll::Ready(Ok(()));
+                };
+                this.read_ready = leftover;
+                continue;
+            };
+            this.read_raw.extend_from_slice(chunk_buf.filled());
+            this.process_inbound()?;
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for DeflateStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if matches!(this.write_mode, WriteMode::Passthrough) && this.write_ready.is_empty() {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        };
+        if let Poll::Ready(Err(e)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(e));
+        };
+        if this.write_ready.len() >= WRITE_HIGH_WATER {
+            return Poll::Pending;
+        };
+        this.accept_outbound(buf)?;
+        if let Poll::Ready(Err(e)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(e));
+        };
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_drain(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_flush(cx),
+            other => other
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        match this.poll_drain(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_shutdown(cx),
+            other => other
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use rocket::serde::json::Value;
+use serde::{Deserialize, Serialize};
+
+use super::event_type::EventType;
+
+pub mod recorder;
+pub mod replay;
+
+// one line of a recording, the decoded packet as the plugin sent it
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedEvent {
+    pub server_id: String,
+    pub timestamp: u64,
+    #[serde(rename = "e")]
+    pub event: EventType,
+    #[serde(rename = "d")]
+    pub data: Value
+}
@@ -0,0 +1,85 @@
+use std::{fs::File, io::{BufWriter, Write}, path::{Path, PathBuf}, sync::{Mutex, mpsc::{self, Sender, Receiver}}, thread::{self, JoinHandle}};
+
+use flate2::{write::GzEncoder, Compression};
+use rocket::serde::json::{serde_json, Value};
+
+use crate::{socket::event_type::EventType, util::time::get_u64_time_millis};
+
+use super::RecordedEvent;
+
+// appends inbound socket events to a gzipped NDJSON file, one file per API start
+// writing happens on its own thread so connections never wait on disk
+pub struct SocketRecorder {
+    path: PathBuf,
+    // None once the recorder has finished
+    sender: Mutex<Option<Sender<RecordedEvent>>>,
+    writer: Mutex<Option<JoinHandle<()>>>
+}
+
+impl SocketRecorder {
+    pub fn start(directory: &Path) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(directory)?;
+        let path = directory.join(format!("socket-{}.ndjson.gz", get_u64_time_millis()));
+        let file = File::create(&path)?;
+        let (sender, receiver) = mpsc::channel::<RecordedEvent>();
+        let writer = thread::Builder::new()
+            .name(String::from("socket-recorder"))
+            .spawn(move || Self::write_events(receiver, GzEncoder::new(BufWriter::new(file), Compression::default())))?;
+        Ok(SocketRecorder { path, sender: Mutex::new(Some(sender)), writer: Mutex::new(Some(writer)) })
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&self, server_id: &str, event: &EventType, data: &Value) {
+        let sender = self.sender.lock().unwrap();
+        let sender = match sender.as_ref() {
+            Some(sender) => sender,
+            None => return
+        };
+        let _ = sender.send(RecordedEvent {
+            server_id: server_id.to_owned(),
+            timestamp: get_u64_time_millis(),
+            event: event.clone(),
+            data: data.clone()
+        });
+    }
+
+    // closes the channel and waits for the writer, so the file ends with its gzip trailer
+    // events recorded afterwards are dropped
+    pub fn finish(&self) {
+        self.sender.lock().unwrap().take();
+        let writer = self.writer.lock().unwrap().take();
+        if let Some(writer) = writer {
+            if writer.join().is_err() {
+                warn!("The socket recording thread panicked");
+            };
+        };
+    }
+
+    fn write_events(receiver: Receiver<RecordedEvent>, mut encoder: GzEncoder<BufWriter<File>>) {
+        while let Ok(first_event) = receiver.recv() {
+            let mut pending = Some(first_event);
+            while let Some(recorded_event) = pending {
+                if let Err(e) = Self::write_event(&mut encoder, &recorded_event) {
+                    warn!("Could not record socket event: {}", e);
+                };
+                pending = receiver.try_recv().ok();
+            }
+            // flush once caught up, so a crash loses at most the burst in flight
+            if let Err(e) = encoder.flush() {
+                warn!("Could not flush socket recording: {}", e);
+            };
+        }
+        if let Err(e) = encoder.finish().and_then(|mut writer| writer.flush()) {
+            warn!("Could not finish socket recording: {}", e);
+        };
+    }
+
+    fn write_event(encoder: &mut GzEncoder<BufWriter<File>>, recorded_event: &RecordedEvent) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut *encoder, recorded_event)?;
+        encoder.write_all(b"\n")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+use std::{collections::{HashMap, HashSet}, fs::File, io::{BufRead, BufReader}, path::Path, sync::Arc};
+
+use flate2::read::MultiGzDecoder;
+use rocket::serde::json::{serde_json, Value};
+use tokio::sync::mpsc;
+
+use crate::{MarsAPIState, database::{Database, models::{level::{Level, LevelRecords}, player::{Player, PlayerStats, SimplePlayer}}}, socket::{dead_letter, event_type::EventType, server::server_context::ServerContext, socket_router::SocketRouter}, util::time::get_u64_time_millis};
+
+use super::RecordedEvent;
+
+#[derive(Default)]
+pub struct ReplaySummary {
+    pub events: u64,
+    pub skipped: u64,
+    // routed but dead-lettered in the replay database
+    pub failed: u64,
+    pub servers: usize,
+    pub seeded_players: u64,
+    pub seeded_levels: u64
+}
+
+// feeds a recording back through the router in order, one router per recorded server
+// plugin-bound packets produced along the way are discarded
+pub async fn replay_recording(state: &MarsAPIState, path: &Path) -> anyhow::Result<ReplaySummary> {
+    let reader = BufReader::new(MultiGzDecoder::new(File::open(path)?));
+    let api_state = Arc::new(state.clone());
+    let mut routers : HashMap<String, SocketRouter> = HashMap::new();
+    let mut known_players : HashSet<String> = HashSet::new();
+    let mut summary = ReplaySummary::default();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line_number = index + 1;
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                // the recorder may have been stopped mid-write
+                warn!("Recording ends unexpectedly at line {}: {}", line_number, e);
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        };
+        let recorded_event = match serde_json::from_str::<RecordedEvent>(&line) {
+            Ok(recorded_event) => recorded_event,
+            Err(e) => {
+                warn!("Skipping line {}: {}", line_number, e);
+                summary.skipped += 1;
+                continue;
+            }
+        };
+
+        seed_missing_documents(state, &recorded_event, &mut known_players, &mut summary).await;
+        let router = routers.entry(recorded_event.server_id.clone()).or_insert_with(|| {
+            let (sender, _) = mpsc::unbounded_channel();
+            SocketRouter::new(ServerContext { id: recorded_event.server_id.clone(), api_state: Arc::clone(&api_state), sender })
+        });
+        if let Err(failed) = dead_letter::route(router, &recorded_event.event, recorded_event.data).await {
+            dead_letter::store(state, &failed).await;
+            summary.failed += 1;
+        };
+        summary.events += 1;
+        if summary.events % 1000 == 0 {
+            info!("Replayed {} events...", summary.events);
+        };
+    }
+    summary.servers = routers.len();
+    state.database.write_behind.flush().await;
+    Ok(summary)
+}
+
+// profiles and maps are created over HTTP, which isn't recorded, so the replay database may lack them
+async fn seed_missing_documents(state: &MarsAPIState, recorded_event: &RecordedEvent, known_players: &mut HashSet<String>, summary: &mut ReplaySummary) {
+    if let EventType::MatchLoad = recorded_event.event {
+        if let Some(map_id) = recorded_event.data.get("mapId").and_then(Value::as_str) {
+            if Database::find_by_id(&state.database.levels, map_id).await.is_none() {
+                seed_level(state, map_id).await;
+                summary.seeded_levels += 1;
+            };
+        };
+    };
+
+    for simple_player in get_recorded_players(recorded_event) {
+        if !known_players.insert(simple_player.id.clone()) {
+            continue;
+        };
+        match Database::find_by_id(&state.database.players, &simple_player.id).await {
+            Some(mut player) => state.player_cache.overwrite(&state.database, &player.name.clone(), &mut player, false).await,
+            None => {
+                seed_player(state, &simple_player).await;
+                summary.seeded_players += 1;
+            }
+        };
+    }
+}
+
+fn get_recorded_players(recorded_event: &RecordedEvent) -> Vec<SimplePlayer> {
+    let data = &recorded_event.data;
+    let values : Vec<&Value> = match recorded_event.event {
+        EventType::MatchStart => data.get("participants").and_then(Value::as_array).map(|participants| participants.iter().collect()).unwrap_or_default(),
+        EventType::PartyJoin | EventType::PlayerChat => data.get("player").into_iter().collect(),
+        _ => Vec::new()
+    };
+    values.into_iter().filter_map(|value| serde_json::from_value::<SimplePlayer>(value.clone()).ok()).collect()
+}
+
+async fn seed_level(state: &MarsAPIState, map_id: &str) {
+    let time_millis = get_u64_time_millis();
+    state.database.save(&Level {
+        id: map_id.to_owned(),
+        loaded_at: time_millis,
+        name: map_id.to_owned(),
+        name_lower: map_id.to_lowercase(),
+        version: String::from("unknown"),
+        gamemodes: Vec::new(),
+        updated_at: time_millis,
+        authors: Vec::new(),
+        contributors: Vec::new(),
+        goals: None,
+        last_match_id: None,
+        records: LevelRecords::default()
+    }).await;
+}
+
+async fn seed_player(state: &MarsAPIState, simple_player: &SimplePlayer) {
+    let time_millis = get_u64_time_millis() as f64;
+    let mut player = Player {
+        id: simple_player.id.clone(),
+        name: simple_player.name.clone(),
+        name_lower: simple_player.name.to_lowercase(),
+        ips: Vec::new(),
+        first_joined_at: time_millis,
+        last_joined_at: time_millis,
+        rank_ids: Vec::new(),
+        tag_ids: Vec::new(),
+        active_tag_id: None,
+        stats: PlayerStats::default(),
+        gamemode_stats: HashMap::new(),
+        notes: Vec::new(),
+        last_session_id: None,
+        active_join_sound_id: None,
+        version: 0,
+        loaded_stats: None
+    };
+    state.player_cache.overwrite(&state.database, &player.name.clone(), &mut player, true).await;
+}
@@ -1,18 +1,18 @@
 use std::sync::Arc;
 
-use futures::SinkExt;
 use serde::{Serialize, Deserialize};
-use rocket::serde::json::serde_json;
-use tokio::net::TcpStream;
-use tokio_tungstenite::{WebSocketStream, tungstenite::Message};
+use rocket::serde::json::{serde_json, Value};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_tungstenite::tungstenite::Message;
 
-use crate::{database::models::r#match::Match, socket::event_type::EventType, util::string::deflate_string, MarsAPIState};
+use crate::{database::{VersionConflict, models::r#match::Match}, socket::{codec::PacketCodec, event_type::EventType}, MarsAPIState};
 use crate::database::models::server::ServerEvents;
 
 pub struct ServerContext {
     pub id: String,
     pub api_state: Arc<MarsAPIState>,
-    pub stream: WebSocketStream<TcpStream>
+    // plugin-bound packets, drained into the socket by the connection's writer task
+    pub sender: UnboundedSender<Packet<Value>>
 }
 
 impl ServerContext {
@@ -36,11 +36,21 @@ impl ServerContext {
         self.api_state.redis.get(&format!("match:{}", self.get_current_match_id().await.unwrap_or_else(|| "null".to_owned()))).await.ok()
     }
 
+    // fails if the match was written by someone else since it was read
+    // the cached copy is the one that counts, so persisting it goes through the write-behind queue
+    pub async fn save_match(&self, current_match: &mut Match, persist: bool, expiry_ms: Option<usize>) -> Result<(), VersionConflict> {
+        let match_id = current_match.id.clone();
+        self.api_state.match_cache.compare_and_set(&self.api_state.database, &match_id, current_match, false, expiry_ms).await?;
+        if persist {
+            self.api_state.database.write_behind.save_versioned(current_match).await;
+        };
+        Ok(())
+    }
+
     pub async fn call<T: Serialize>(&mut self, event_type: &EventType, data: T) {
-        let packet = Packet { event: event_type.clone(), data };
-        let body = serde_json::to_string(&packet).unwrap();
-        let binary = Message::Binary(deflate_string(body.as_bytes()).unwrap());
-        let _ = self.stream.send(binary).await;
+        if let Some(packet) = Packet::new(event_type, data) {
+            let _ = self.sender.send(packet);
+        };
     }
 
     fn get_current_match_id_key(&self) -> String {
@@ -56,10 +66,27 @@ impl ServerContext {
     }
 }
 
-#[derive(Serialize, Deserialize)]
-struct Packet<T> {
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Packet<T> {
     #[serde(rename = "e")]
-    event: EventType,
+    pub event: EventType,
     #[serde(rename = "d")]
-    data: T
+    pub data: T
+}
+
+impl Packet<Value> {
+    pub fn new<T: Serialize>(event_type: &EventType, data: T) -> Option<Self> {
+        match serde_json::to_value(data) {
+            Ok(data) => Some(Packet { event: event_type.clone(), data }),
+            Err(e) => {
+                warn!("Could not serialize {} packet: {}", event_type, e);
+                None
+            }
+        }
+    }
+
+    // JSON + zlib, what plugins get unless they negotiate otherwise
+    pub fn to_message(&self) -> anyhow::Result<Message> {
+        PacketCodec::default().encode(self)
+    }
 }
@@ -19,3 +19,9 @@ pub struct PartyData {
     pub min: u32, 
     pub max: u32
 }
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CycleMapData {
+    pub map_id: Option<String>
+}
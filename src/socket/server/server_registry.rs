@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use futures::StreamExt;
+use rocket::serde::json::{serde_json, Value};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinHandle;
+
+use crate::database::cache::RedisAdapter;
+use crate::socket::event_type::EventType;
+
+use super::server_context::Packet;
+
+struct ServerConnection {
+    connection_id: u64,
+    sender: UnboundedSender<Packet<Value>>
+}
+
+// packets relayed between API instances over redis pub/sub
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "SCREAMING_SNAKE_CASE")]
+enum RelayMessage {
+    #[serde(rename_all = "camelCase")]
+    Call {
+        server_id: String,
+        packet: Packet<Value>
+    },
+    #[serde(rename_all = "camelCase")]
+    Broadcast {
+        origin_instance_id: String,
+        except_server_id: Option<String>,
+        packet: Packet<Value>
+    }
+}
+
+// live sockets by server ID, lets the HTTP API push plugin-bound events to a server
+// servers connected to another instance are reached through redis
+pub struct ServerRegistry {
+    instance_id: String,
+    redis: Arc<RedisAdapter>,
+    connections: Mutex<HashMap<String, ServerConnection>>,
+    next_connection_id: AtomicU64,
+    owner_ttl_ms: usize,
+    owner_heartbeat: Duration
+}
+
+impl ServerRegistry {
+    const BROADCAST_CHANNEL: &'static str = "relay:broadcast";
+    // an instance that dies without unregistering stops owning its servers once this runs out
+    const OWNER_TTL_MS: usize = 30_000;
+    const OWNER_HEARTBEAT: Duration = Duration::from_secs(10);
+
+    pub fn new(instance_id: String, redis: Arc<RedisAdapter>) -> Self {
+        Self::with_owner_ttl(instance_id, redis, Self::OWNER_TTL_MS, Self::OWNER_HEARTBEAT)
+    }
+
+    // the heartbeat has to run well within the TTL, or a live instance loses its servers between beats
+    pub fn with_owner_ttl(instance_id: String, redis: Arc<RedisAdapter>, owner_ttl_ms: usize, owner_heartbeat: Duration) -> Self {
+        Self { instance_id, redis, connections: Mutex::new(HashMap::new()), next_connection_id: AtomicU64::new(0), owner_ttl_ms, owner_heartbeat }
+    }
+
+    fn get_owner_key(server_id: &str) -> String {
+        format!("socket:server:{}", server_id.to_lowercase())
+    }
+
+    fn get_instance_channel(instance_id: &str) -> String {
+        format!("relay:instance:{}", instance_id)
+    }
+
+    // returns a connection ID so a stale connection can't unregister a server that reconnected
+    pub async fn register(&self, server_id: &str, sender: UnboundedSender<Packet<Value>>) -> u64 {
+        let connection_id = self.next_connection_id.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut connections = self.connections.lock().await;
+            connections.insert(server_id.to_lowercase(), ServerConnection { connection_id, sender });
+        }
+        self.redis.set_with_expiry(&Self::get_owner_key(server_id), &self.instance_id, Some(self.owner_ttl_ms)).await;
+        connection_id
+    }
+
+    // false if the server has since reconnected on another connection
+    pub async fn unregister(&self, server_id: &str, connection_id: u64) -> bool {
+        let mut connections = self.connections.lock().await;
+        let server_id = server_id.to_lowercase();
+        if connections.get(&server_id).is_some_and(|connection| connection.connection_id == connection_id) {
+            connections.remove(&server_id);
+            // another instance may already own the server again
+            self.redis.del_if_equals(&Self::get_owner_key(&server_id), &self.instance_id).await;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub async fn is_connected(&self, server_id: &str) -> bool {
+        self.connections.lock().await.contains_key(&server_id.to_lowercase())
+            || self.redis.get::<String>(&Self::get_owner_key(server_id)).await.is_ok()
+    }
+
+    // relays to every connected server apart from the origin, returns how many local servers were reached
+    pub async fn broadcast<T: Serialize>(&self, event_type: &EventType, data: T, except_server_id: Option<&str>) -> usize {
+        let packet = match Packet::new(event_type, data) {
+            Some(packet) => packet,
+            None => return 0
+        };
+        let except_server_id = except_server_id.map(|server_id| server_id.to_lowercase());
+        let reached = self.send_local_broadcast(&packet, except_server_id.as_deref()).await;
+        self.redis.publish(Self::BROADCAST_CHANNEL, &RelayMessage::Broadcast {
+            origin_instance_id: self.instance_id.clone(),
+            except_server_id,
+            packet
+        }).await;
+        reached
+    }
+
+    // false if no instance holds a live socket for the server
+    pub async fn call<T: Serialize>(&self, server_id: &str, event_type: &EventType, data: T) -> bool {
+        let packet = match Packet::new(event_type, data) {
+            Some(packet) => packet,
+            None => return false
+        };
+        if let Some(connection) = self.connections.lock().await.get(&server_id.to_lowercase()) {
+            return connection.sender.send(packet).is_ok();
+        };
+        let owner : String = match self.redis.get(&Self::get_owner_key(server_id)).await {
+            Ok(owner) => owner,
+            Err(_) => return false
+        };
+        if owner == self.instance_id {
+            return false;
+        };
+        // nobody listening means the owning instance is gone
+        self.redis.publish(&Self::get_instance_channel(&owner), &RelayMessage::Call {
+            server_id: server_id.to_lowercase(),
+            packet
+        }).await > 0
+    }
+
+    async fn send_local_broadcast(&self, packet: &Packet<Value>, except_server_id: Option<&str>) -> usize {
+        let connections = self.connections.lock().await;
+        connections.iter()
+            .filter(|(server_id, _)| except_server_id != Some(server_id.as_str()))
+            .filter(|(_, connection)| connection.sender.send(packet.clone()).is_ok())
+            .count()
+    }
+
+    async fn on_relay_message(&self, message: RelayMessage) {
+        match message {
+            RelayMessage::Call { server_id, packet } => {
+                if let Some(connection) = self.connections.lock().await.get(&server_id) {
+                    let _ = connection.sender.send(packet);
+                };
+            },
+            RelayMessage::Broadcast { origin_instance_id, except_server_id, packet } => {
+                if origin_instance_id != self.instance_id {
+                    self.send_local_broadcast(&packet, except_server_id.as_deref()).await;
+                };
+            }
+        };
+    }
+
+    pub async fn get_local_server_ids(&self) -> Vec<String> {
+        self.connections.lock().await.keys().cloned().collect()
+    }
+
+    // keeps the owner keys of this instance's servers from expiring
+    pub fn spawn_owner_heartbeat(registry: Arc<ServerRegistry>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(registry.owner_heartbeat).await;
+                for server_id in registry.get_local_server_ids().await {
+                    registry.redis.set_with_expiry(&Self::get_owner_key(&server_id), &registry.instance_id, Some(registry.owner_ttl_ms)).await;
+                }
+            }
+        })
+    }
+
+    // delivers packets published by other instances, resubscribing if redis drops the connection
+    pub fn spawn_relay_listener(registry: Arc<ServerRegistry>) {
+        tokio::spawn(async move {
+            let channels = vec![Self::BROADCAST_CHANNEL.to_owned(), Self::get_instance_channel(&registry.instance_id)];
+            loop {
+                match registry.redis.subscribe(&channels).await {
+                    Ok(mut messages) => {
+                        info!("Relaying socket events as instance '{}'", registry.instance_id);
+                        while let Some((_, payload)) = messages.next().await {
+                            match serde_json::from_str::<RelayMessage>(&payload) {
+                                Ok(relay_message) => registry.on_relay_message(relay_message).await,
+                                Err(e) => warn!("Could not parse relayed packet: {}", e)
+                            };
+                        };
+                        warn!("Lost relay subscription, resubscribing");
+                    },
+                    Err(e) => warn!("Could not subscribe to relay channels: {}", e)
+                };
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        });
+    }
+}
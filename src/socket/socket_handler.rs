@@ -1,134 +1,223 @@
-use std::collections::{HashMap, HashSet};
-
-use std::io::{Read};
+use std::collections::HashMap;
+use std::future::Future;
 use std::sync::Arc;
+use std::time::Duration;
 
-use futures::StreamExt;
+use futures::{SinkExt, StreamExt};
 use log::info;
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
 
 
 use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::tungstenite::handshake::server::{Request, Response, ErrorResponse};
-use tokio_tungstenite::tungstenite::http::Response as HttpResponse;
-use flate2::read::ZlibDecoder;
+use tokio_tungstenite::tungstenite::http::{HeaderValue, Response as HttpResponse};
 use tokio_tungstenite::tungstenite::protocol::CloseFrame;
 use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
 
 use crate::MarsAPIState;
-use crate::socket::event_type::EventType;
+use crate::network::Networks;
 use crate::socket::socket_router::SocketRouter;
 use crate::util::error::ApiErrorResponder;
 use crate::util::r#macro::unwrap_helper;
 use crate::util::time::get_u64_time_millis;
 
-use rocket::serde::json::{serde_json, Value};
+use rocket::serde::json::{json, Value};
+
+use super::codec::PacketCodec;
+use super::dead_letter;
+use super::permessage_deflate::DeflateStream;
+use super::recording::recorder::SocketRecorder;
+use super::server::server_context::{Packet, ServerContext};
 
-use super::server::server_context::ServerContext;
+// how long servers get to acknowledge the close frame, events they send until then are still handled
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+// how long servers are asked to wait before reconnecting after a shutdown
+const RECONNECT_AFTER_MS: u64 = 5000;
 
 pub struct SocketState {
-    pub api_state: Arc<MarsAPIState>
+    pub networks: Arc<Networks>,
+    pub recorder: Option<Arc<SocketRecorder>>
 }
 
 pub struct SocketSession {
     pub server_id: String,
     pub api_state: Arc<MarsAPIState>,
-    // concurrent access may be possible
-    pub connected_servers: Arc<Mutex<HashSet<ServerContext>>>
+    pub recorder: Option<Arc<SocketRecorder>>,
+    pub codec: PacketCodec
 }
 
 pub async fn setup_socket(
     socket_state: SocketState, 
     port: u32
 ) -> anyhow::Result<()> {
-    let connected_servers : Arc<Mutex<HashSet<ServerContext>>> = Arc::new(Mutex::new(HashSet::new()));
     let addr = format!("0.0.0.0:{}", port);
     info!("Socket listening on: {}", addr);
 
     // Create the event loop and TCP listener we'll accept connections on.
     let socket = TcpListener::bind(&addr).await?;
+    serve_socket(socket_state, socket, exit_signal()).await
+}
 
+// accepts plugin connections on an already bound listener until shutdown resolves
+// then stops accepting, asks every server to reconnect later and drains what they already sent
+pub async fn serve_socket(
+    socket_state: SocketState,
+    socket: TcpListener,
+    shutdown: impl Future<Output = ()>
+) -> anyhow::Result<()> {
+    let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+    let mut connections = JoinSet::new();
+    tokio::pin!(shutdown);
     loop {
         tokio::select! {
             socket_accept_result = socket.accept() => {
                 if let Ok((stream, _)) = socket_accept_result {
-                    let mut session_state : SocketSession = SocketSession { server_id: "".to_owned(), connected_servers: Arc::clone(&connected_servers), api_state: socket_state.api_state.clone() };
+                    let mut session_state : SocketSession = SocketSession { server_id: "".to_owned(), api_state: Arc::new(socket_state.networks.get_default().clone()), recorder: socket_state.recorder.clone(), codec: PacketCodec::default() };
+                    let stream = DeflateStream::new(stream);
+                    let negotiation = stream.get_negotiation();
                     let ws_stream = match tokio_tungstenite::accept_hdr_async(stream, |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
-                        verify_connection(&socket_state, &mut session_state, request, response)
+                        let mut response = verify_connection(&socket_state, &mut session_state, request, response)?;
+                        negotiation.accept(request.headers(), response.headers_mut());
+                        Ok(response)
                     }).await {
                         Ok(ws_stream) => ws_stream,
                         Err(e) => {warn!("{}", e); continue}
                     };
-                    tokio::spawn(accept_connection(ws_stream, session_state));
+                    connections.spawn(accept_connection(ws_stream, session_state, shutdown_receiver.clone()));
                 }
             },
-            _ = exit_signal() => {
+            // finished connections are reaped as they go
+            Some(_) = connections.join_next(), if !connections.is_empty() => {},
+            _ = &mut shutdown => {
                 info!("Gracefully dropping websocket");
                 break;
             },
         };
     }
+    drop(socket);
+
+    let _ = shutdown_sender.send(true);
+    let drained = tokio::time::timeout(SHUTDOWN_DRAIN_TIMEOUT + Duration::from_secs(1), async {
+        while connections.join_next().await.is_some() {}
+    }).await;
+    if drained.is_err() {
+        warn!("Dropping {} socket connection(s) that did not close in time", connections.len());
+        connections.shutdown().await;
+    };
     Ok(())
 }
 
 async fn accept_connection(
-    ws_stream: WebSocketStream<TcpStream>, 
-    socket_session: SocketSession
+    ws_stream: WebSocketStream<DeflateStream<TcpStream>>, 
+    socket_session: SocketSession,
+    mut shutdown: watch::Receiver<bool>
 ) -> anyhow::Result<()> {
     info!("Accepted WebSocket connection from server {}", socket_session.server_id.clone());
     let server_id = socket_session.server_id.clone();
-    let server = {
-        let server = ServerContext {
-            id: socket_session.server_id.clone(), api_state: socket_session.api_state.clone(), stream: ws_stream 
+    let api_state = socket_session.api_state.clone();
+    let (mut ws_sink, mut ws_source) = ws_stream.split();
+    let codec = socket_session.codec;
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Packet<Value>>();
+    let connection_id = api_state.servers.register(&server_id, sender.clone()).await;
+
+    // plugin-bound packets come from both the router and the HTTP API, one writer owns the sink
+    let mut writer_shutdown = shutdown.clone();
+    let writer = tokio::spawn(async move {
+        let close_frame = loop {
+            tokio::select! {
+                packet = receiver.recv() => {
+                    let packet = match packet {
+                        Some(packet) => packet,
+                        None => break CloseFrame { code: CloseCode::Normal, reason: std::borrow::Cow::Borrowed("Connection closed") }
+                    };
+                    let message = unwrap_helper::continue_default!(codec.encode(&packet).ok());
+                    if ws_sink.send(message).await.is_err() {
+                        return;
+                    };
+                },
+                // the plugin keeps sending until it reads this, and should come back once the API is up again
+                _ = writer_shutdown.changed() => break CloseFrame { code: CloseCode::Restart, reason: std::borrow::Cow::Owned(get_reconnect_hint()) }
+            };
         };
-        server
+        let _ = ws_sink.send(Message::Close(Some(close_frame))).await;
+    });
+
+    let server = ServerContext {
+        id: socket_session.server_id.clone(), api_state: socket_session.api_state.clone(), sender
     };
     
     let mut router = SocketRouter::new(server);
 
-    while let Some(msg) = router.server.stream.next().await {
+    // set once shutting down, events keep being handled until the plugin acknowledges the close or this passes
+    let mut drain_deadline : Option<Instant> = None;
+    loop {
+        let msg = tokio::select! {
+            msg = ws_source.next() => match msg {
+                Some(msg) => msg,
+                None => break
+            },
+            _ = shutdown.changed(), if drain_deadline.is_none() => {
+                drain_deadline = Some(Instant::now() + SHUTDOWN_DRAIN_TIMEOUT);
+                continue;
+            },
+            _ = tokio::time::sleep_until(drain_deadline.unwrap_or_else(Instant::now)), if drain_deadline.is_some() => {
+                warn!("Server {} did not acknowledge the close in time", server_id);
+                break;
+            }
+        };
         let msg = unwrap_helper::continue_default!(msg.ok());
         let data = match msg {
-            tokio_tungstenite::tungstenite::Message::Binary(data) => data,
+            Message::Binary(data) => data,
+            // uncompressed JSON may arrive as text frames
+            Message::Text(text) => text.into_bytes(),
             _ => continue
         };
 
-        let mut zlib_decoder = ZlibDecoder::new(data.as_slice());
-        let mut decompressed = String::new();
-        if let Err(_) = zlib_decoder.read_to_string(&mut decompressed) {
-            continue;
-        };
-        let text = decompressed;
-
-
-        let json_object : Value = unwrap_helper::continue_default!(serde_json::from_str(&text).ok());
-        let event = {
-            let e_val = json_object.get("e");
-            if e_val.is_none() {
+        let body = match codec.decode_value(&data) {
+            Ok(body) => body,
+            Err(e) => {
+                dead_letter::store(&api_state, &dead_letter::undecodable(&server_id, &codec, &data, e.to_string())).await;
                 continue;
-            };
-            unwrap_helper::continue_default!(serde_json::from_value::<EventType>(e_val.unwrap().to_owned()).ok())
+            }
         };
-        let socket_data = {
-            let d_val = json_object.get("d");
-            if d_val.is_none() {
+        let (event, socket_data) = match dead_letter::split_packet(&server_id, body) {
+            Ok(packet) => packet,
+            Err(failed) => {
+                dead_letter::store(&api_state, &failed).await;
                 continue;
-            };
-            d_val.unwrap().to_owned()
+            }
         };
         let socket_data_serialized = socket_data.to_string();
+        if let Some(recorder) = &socket_session.recorder {
+            recorder.record(&server_id, &event, &socket_data);
+        };
 
-        router.route(&event, socket_data).await;
+        if let Err(failed) = dead_letter::route(&mut router, &event, socket_data).await {
+            dead_letter::store(&api_state, &failed).await;
+        };
         router.server.set_last_time_alive(get_u64_time_millis()).await;
         info!("[{}:{}] {}", server_id, event, socket_data_serialized);
     }
     info!("WebSocket connection closed from server {}", socket_session.server_id.clone());
-    let _ = router.server.stream.close(Some(CloseFrame { code: CloseCode::Normal, reason: std::borrow::Cow::Borrowed("Connection closed")  })).await;
+    // dropping the last senders lets the writer send the close frame and finish
+    if api_state.servers.unregister(&server_id, connection_id).await {
+        api_state.presence.clear_server(&server_id).await;
+    };
+    drop(router);
+    let _ = writer.await;
 
     Ok(())
 }
 
+// sent as the close reason when the API shuts down, a close reason has to fit in 123 bytes
+fn get_reconnect_hint() -> String {
+    json!({ "reconnect": true, "retryAfterMs": RECONNECT_AFTER_MS }).to_string()
+}
+
 fn verify_connection(socket_state: &SocketState, socket_session: &mut SocketSession, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
     if request.uri().path() != "/minecraft" {
         return Err(build_response_from_error_responder(ApiErrorResponder::unauthorized()));
@@ -137,10 +226,19 @@ fn verify_connection(socket_state: &SocketState, socket_session: &mut SocketSess
         let hash_query : HashMap<String, String> = url::form_urlencoded::parse(query_string.as_bytes()).into_owned().collect();
         let server_id = unwrap_helper::return_default!(hash_query.get("id"), Err(build_response_from_error_responder(ApiErrorResponder::unauthorized()))).to_owned();
         let token = unwrap_helper::return_default!(hash_query.get("token"), Err(build_response_from_error_responder(ApiErrorResponder::unauthorized()))).to_owned();
-        if token != socket_state.api_state.config.token {
-            return Err(build_response_from_error_responder(ApiErrorResponder::unauthorized()));
+        // the token decides which network the server belongs to
+        let api_state = unwrap_helper::return_default!(socket_state.networks.find_by_token(&token), Err(build_response_from_error_responder(ApiErrorResponder::unauthorized())));
+        socket_session.api_state = Arc::new(api_state.clone());
+        socket_session.codec = match PacketCodec::from_query(&hash_query) {
+            Ok(codec) => codec,
+            Err(message) => return Err(build_response_from_error_responder(ApiErrorResponder::validation_error_with_message(&message)))
         };
         socket_session.server_id = server_id;
+        // echoed so the plugin knows the API understood what it asked for
+        let mut response = response;
+        let headers = response.headers_mut();
+        headers.insert(PacketCodec::ENCODING_HEADER, HeaderValue::from_str(&socket_session.codec.encoding.to_string()).unwrap());
+        headers.insert(PacketCodec::COMPRESSION_HEADER, HeaderValue::from_str(&socket_session.codec.compression.to_string()).unwrap());
         return Ok(response);
     } else {
         return Err(build_response_from_error_responder(ApiErrorResponder::unauthorized()));
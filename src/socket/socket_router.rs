@@ -1,38 +1,58 @@
 use std::{collections::HashMap};
 
 
-use futures::future::join_all;
 use mongodb::bson::doc;
 use mongodb::options::UpdateOptions;
 use rocket::serde::{json::{serde_json, Value}, DeserializeOwned};
 
 use uuid::Uuid;
 
-use crate::{database::models::{death::Death, achievement::Achievement, r#match::{FirstBlood, MatchState}, participant::{Participant, SimpleParticipant}, player::{AchievementData, Player}}, socket::r#match::match_phase_listener::MatchPhaseListener, util::{r#macro::unwrap_helper, time::get_u64_time_millis}};
+use crate::{database::{VersionConflict, models::{death::Death, achievement::Achievement, chat_message::ChatMessage, r#match::{FirstBlood, Match, MatchState}, participant::{Participant, SimpleParticipant}, player::{AchievementData, Player}}}, socket::r#match::match_phase_listener::MatchPhaseListener, util::{r#macro::unwrap_helper, time::get_u64_time_millis}};
 
-use super::{event_type::EventType, leaderboard::leaderboard_listener::LeaderboardListener, map::map_record_listener::MapRecordListener, r#match::match_events::{MatchEndData, MatchStartData}, objective::objective_events::{ControlPointCaptureData, CoreLeakData, DestroyableDamageData, DestroyableDestroyData, FlagDropData, FlagEventData, WoolDropData, WoolEventData}, participant::{participant_party_listener::ParticipantPartyListener, participant_stat_listener::ParticipantStatListener}, player::{player_events::{KillstreakData, PartyJoinData, PartyLeaveData, PlayerAchievementData, PlayerChatData, PlayerDeathData}, player_gamemode_stat_listener::PlayerGamemodeStatListener, player_listener::PlayerListener, player_record_listener::PlayerRecordListener, player_stat_listener::PlayerStatListener, player_xp_listener::PlayerXPListener}, server::{server_context::ServerContext, server_events::MatchLoadData}, update::player_update_listener::PlayerUpdateListener};
+use super::{chat::chat_filter::{self, ChatFilterResult}, event_type::EventType, leaderboard::leaderboard_listener::LeaderboardListener, map::map_record_listener::MapRecordListener, r#match::match_events::{MatchEndData, MatchStartData}, objective::objective_events::{ControlPointCaptureData, CoreLeakData, DestroyableDamageData, DestroyableDestroyData, FlagDropData, FlagEventData, WoolDropData, WoolEventData}, participant::{participant_party_listener::ParticipantPartyListener, participant_stat_listener::ParticipantStatListener}, player::{player_events::{ChatChannel, KillstreakData, PartyJoinData, PartyLeaveData, PlayerAchievementData, PlayerChatData, PlayerDeathData}, player_gamemode_stat_listener::PlayerGamemodeStatListener, player_listener::PlayerListener, player_record_listener::PlayerRecordListener, player_stat_listener::PlayerStatListener, player_xp_listener::PlayerXPListener}, server::{server_context::ServerContext, server_events::MatchLoadData}, update::player_update_listener::PlayerUpdateListener};
 use crate::database::Database;
 
 pub struct SocketRouter {
     pub server: ServerContext,
     pub participant_listeners: Vec<Box<dyn PlayerListener<Context = Participant> + Send + Sync>>,
-    pub player_listeners: Vec<Box<dyn PlayerListener<Context = Player> + Send + Sync>>
+    pub player_listeners: Vec<Box<dyn PlayerListener<Context = Player> + Send + Sync>>,
+    // player changes made while handling an event, written once the event is handled
+    staged_players: Vec<(String, Player)>
 }
 
 pub enum SocketError {
     InvalidMatchState,
+    MalformedData(String),
+    UnknownParticipant(String),
+    Unroutable,
+    VersionConflict(VersionConflict),
     Unknown(String)
 }
 
 impl SocketError {
-    fn message(&self) -> String {
+    pub fn message(&self) -> String {
         match self {
             Self::InvalidMatchState => String::from("Encountered invalid match state or missing match"),
+            Self::MalformedData(msg) => format!("Socket passed malformed data: {}", msg),
+            Self::UnknownParticipant(player_id) => format!("Player {} is not a participant in the current match", player_id),
+            Self::Unroutable => String::from("Event is not handled by the API"),
+            Self::VersionConflict(conflict) => conflict.to_string(),
             Self::Unknown(msg) => msg.clone()
         }
     }
 }
 
+impl From<VersionConflict> for SocketError {
+    fn from(conflict: VersionConflict) -> Self {
+        Self::VersionConflict(conflict)
+    }
+}
+
+// checked before a handler changes anything, so a rejected event leaves no partial stats behind
+fn get_participant(current_match: &Match, player_id: &str) -> Result<Participant, SocketError> {
+    current_match.participants.get(player_id).cloned().ok_or_else(|| SocketError::UnknownParticipant(player_id.to_owned()))
+}
+
 impl SocketRouter {
     pub fn new(server_context: ServerContext) -> Self {
         Self { 
@@ -49,49 +69,54 @@ impl SocketRouter {
                 Box::new(PlayerXPListener {}),
                 Box::new(PlayerRecordListener {}),
                 Box::new(PlayerUpdateListener {}),
-            ]
+            ],
+            staged_players: Vec::new()
         }
     }
 
-    pub async fn route(&mut self, event_type: &EventType, data: Value) {
+    pub async fn route(&mut self, event_type: &EventType, data: Value) -> Result<(), SocketError> {
         let response : anyhow::Result<(), SocketError> = match event_type {
-            EventType::MatchLoad =>                             self.on_match_load(Self::parse_data(data)).await,
-            EventType::MatchStart =>                            self.on_match_start(Self::parse_data(data)).await,
-            EventType::MatchEnd =>                              self.on_match_end(Self::parse_data(data)).await,
-            EventType::PlayerDeath =>                           self.on_player_death(Self::parse_data(data)).await,
-            EventType::PlayerChat =>                            self.on_player_chat(Self::parse_data(data)).await,
-            EventType::Killstreak =>                            self.on_killstreak(Self::parse_data(data)).await,
-            EventType::PartyJoin =>                             self.on_party_join(Self::parse_data(data)).await,
-            EventType::PartyLeave =>                            self.on_party_leave(Self::parse_data(data)).await,
-            EventType::DestroyableDestroy =>                    self.on_destroyable_destroy(Self::parse_data(data)).await,
-            EventType::DestroyableDamage =>                     self.on_destroyable_damage(Self::parse_data(data)).await,
-            EventType::CoreLeak =>                              self.on_core_leak(Self::parse_data(data)).await,
-            EventType::FlagCapture =>                           self.on_flag_place(Self::parse_data(data)).await,
-            EventType::FlagPickup =>                            self.on_flag_pickup(Self::parse_data(data)).await,
-            EventType::FlagDrop =>                              self.on_flag_drop(Self::parse_data(data)).await,
-            EventType::FlagDefend =>                            self.on_flag_defend(Self::parse_data(data)).await,
-            EventType::WoolCapture =>                           self.on_wool_place(Self::parse_data(data)).await,
-            EventType::WoolPickup =>                            self.on_wool_pickup(Self::parse_data(data)).await,
-            EventType::WoolDrop =>                              self.on_wool_drop(Self::parse_data(data)).await,
-            EventType::WoolDefend =>                            self.on_wool_defend(Self::parse_data(data)).await,
-            EventType::ControlPointCapture =>                   self.on_control_point_capture(Self::parse_data(data)).await,
-            EventType::AchievementEarn =>                       self.on_achievement_complete(Self::parse_data(data)).await,
-            _ => {warn!("Event (srv {}) fell through router: {} - {}", self.server.id, event_type, data.to_string()); return}
-        };
-        match response {
-            Err(socket_error) => {
-                match socket_error {
-                    SocketError::InvalidMatchState => {
-                        self.server.call(&EventType::ForceMatchEnd, ()).await;
-                        let match_id = self.get_match_id().await;
-                        warn!("Forcing match end for Match ID: {}. Caused by {}: {}", match_id, event_type.to_string(), socket_error.message());
-                    },
-                    SocketError::Unknown(_text) => {
-                    }
-                }
+            EventType::MatchLoad =>                             self.on_match_load(Self::parse_data(data)?).await,
+            EventType::MatchStart =>                            self.on_match_start(Self::parse_data(data)?).await,
+            EventType::MatchEnd =>                              self.on_match_end(Self::parse_data(data)?).await,
+            EventType::PlayerDeath =>                           self.on_player_death(Self::parse_data(data)?).await,
+            EventType::PlayerChat =>                            self.on_player_chat(Self::parse_data(data)?).await,
+            EventType::Killstreak =>                            self.on_killstreak(Self::parse_data(data)?).await,
+            EventType::PartyJoin =>                             self.on_party_join(Self::parse_data(data)?).await,
+            EventType::PartyLeave =>                            self.on_party_leave(Self::parse_data(data)?).await,
+            EventType::DestroyableDestroy =>                    self.on_destroyable_destroy(Self::parse_data(data)?).await,
+            EventType::DestroyableDamage =>                     self.on_destroyable_damage(Self::parse_data(data)?).await,
+            EventType::CoreLeak =>                              self.on_core_leak(Self::parse_data(data)?).await,
+            EventType::FlagCapture =>                           self.on_flag_place(Self::parse_data(data)?).await,
+            EventType::FlagPickup =>                            self.on_flag_pickup(Self::parse_data(data)?).await,
+            EventType::FlagDrop =>                              self.on_flag_drop(Self::parse_data(data)?).await,
+            EventType::FlagDefend =>                            self.on_flag_defend(Self::parse_data(data)?).await,
+            EventType::WoolCapture =>                           self.on_wool_place(Self::parse_data(data)?).await,
+            EventType::WoolPickup =>                            self.on_wool_pickup(Self::parse_data(data)?).await,
+            EventType::WoolDrop =>                              self.on_wool_drop(Self::parse_data(data)?).await,
+            EventType::WoolDefend =>                            self.on_wool_defend(Self::parse_data(data)?).await,
+            EventType::ControlPointCapture =>                   self.on_control_point_capture(Self::parse_data(data)?).await,
+            EventType::AchievementEarn =>                       self.on_achievement_complete(Self::parse_data(data)?).await,
+            _ => {warn!("Event (srv {}) fell through router: {} - {}", self.server.id, event_type, data.to_string()); return Err(SocketError::Unroutable)}
+        };
+        // stats only move once the match write went through, a failed event can be redriven without counting twice
+        let staged_players = std::mem::take(&mut self.staged_players);
+        if response.is_ok() {
+            let api_state = &self.server.api_state;
+            for (key, mut player) in staged_players {
+                api_state.player_cache.save_stats(&api_state.database, &key, &mut player, false).await;
             }
-            _ => {}
         };
+        match response {
+            // the plugin recovers by ending the match, nothing to keep
+            Err(SocketError::InvalidMatchState) => {
+                self.server.call(&EventType::ForceMatchEnd, ()).await;
+                let match_id = self.get_match_id().await;
+                warn!("Forcing match end for Match ID: {}. Caused by {}: {}", match_id, event_type.to_string(), SocketError::InvalidMatchState.message());
+                Ok(())
+            },
+            response => response
+        }
     }
 
     async fn on_match_load(&mut self, data: MatchLoadData) -> Result<(), SocketError> {
@@ -107,7 +132,7 @@ impl SocketRouter {
             Ok(current_match) => current_match,
             Err(socket_error) => return Err(socket_error)
         };
-        self.server.api_state.match_cache.set(&self.server.api_state.database, &current_match.id, &current_match, false).await;
+        self.server.save_match(&mut current_match, false, None).await?;
         Ok(())
     }
 
@@ -125,8 +150,6 @@ impl SocketRouter {
         let participants = current_match.participants;
         current_match.participants = HashMap::new();
 
-        let mut profiles : Vec<Player> = Vec::new();
-
         for (_participant_id, mut participant) in participants.into_iter() {
             {
                 for participant_listener in self.participant_listeners.iter() {
@@ -140,22 +163,14 @@ impl SocketRouter {
                     player_listener.on_match_end_v2(&mut self.server, &mut current_match, &mut player, &mut data).await;
                 };
 
-                participant.set_player(&*self.server.api_state, &player).await;
+                // the stats are written as increments, so there's no profile left to save
+                self.stage_player(&participant, player);
             };
-            profiles.push(player);
         }
 
-        if profiles.len() > 0 {
-            let mut tasks : Vec<_> = Vec::new();
-            for profile in profiles.iter() {
-                tasks.push(self.server.api_state.database.save(profile));
-            };
-            join_all(tasks).await;
-        };
-
         {
-            self.server.api_state.database.save(&current_match.level).await;
-            self.server.api_state.match_cache.set_with_expiry(&self.server.api_state.database, &current_match.id, &current_match, true, Some(3_600_000)).await;
+            self.server.api_state.database.write_behind.save(&current_match.level).await;
+            self.server.save_match(&mut current_match, true, Some(3_600_000)).await?;
         };
         Ok(())
     }
@@ -167,18 +182,18 @@ impl SocketRouter {
             return Err(SocketError::InvalidMatchState);
         };
 
+        let mut victim = get_participant(&current_match, &data.victim.id)?;
+        let attacker = match data.attacker.as_ref() {
+            Some(attacker) if data.is_murder() => Some(get_participant(&current_match, &attacker.id)?),
+            _ => None
+        };
+
         let is_first_blood = current_match.first_blood.is_none() && data.is_murder();
         if is_first_blood {
             current_match.first_blood = Some(FirstBlood { attacker: data.attacker.as_ref().unwrap().clone(), victim: data.victim.clone(), date: get_u64_time_millis() } );
         };
 
-        if data.is_murder() {
-            let mut attacker = {
-                match data.attacker.as_ref() {
-                    Some(attacker) => { current_match.participants.get(&attacker.id).unwrap().clone() }
-                    None => { panic!("Attacker not in participants map") }
-                }
-            };
+        if let Some(mut attacker) = attacker {
             {
                 for participant_listener in self.participant_listeners.iter() {
                      participant_listener.on_kill(&mut self.server, &mut current_match, &mut attacker, &mut data, is_first_blood).await;
@@ -194,13 +209,11 @@ impl SocketRouter {
                 for player_listener in self.player_listeners.iter() {
                     player_listener.on_kill(&mut self.server, &mut current_match, &mut player, &mut data, is_first_blood).await;
                 };
-                attacker.set_player(&*self.server.api_state, &player).await;
+                self.stage_player(&attacker, player);
 
             };
         };
 
-        let mut victim = current_match.participants.get(&data.victim.id).unwrap().to_owned();
-
         {
             for participant_listener in self.participant_listeners.iter() {
                  participant_listener.on_death(&mut self.server, &mut current_match, &mut victim, &mut data, is_first_blood).await;
@@ -212,7 +225,7 @@ impl SocketRouter {
             for player_listener in self.player_listeners.iter() {
                 player_listener.on_death(&mut self.server, &mut current_match, &mut player, &mut data, is_first_blood).await;
             };
-            victim.set_player(&*self.server.api_state, &player).await;
+            self.stage_player(&victim, player);
         };
 
         {
@@ -229,7 +242,7 @@ impl SocketRouter {
                 match_id: current_match.id.clone(),
                 created_at: get_u64_time_millis(),
             }).await;
-            self.server.api_state.match_cache.set(&self.server.api_state.database, &current_match.id, &current_match, false).await;
+            self.server.save_match(&mut current_match, false, None).await?;
         };
 
 
@@ -237,6 +250,31 @@ impl SocketRouter {
     }
 
     async fn on_player_chat(&mut self, mut data: PlayerChatData) -> Result<(), SocketError> {
+        if let ChatChannel::Staff = data.channel {
+            self.server.api_state.servers.broadcast(&EventType::PlayerChat, &data, Some(&self.server.id)).await;
+        };
+
+        let filter_result = match data.channel {
+            ChatChannel::Staff => ChatFilterResult::default(),
+            _ => self.server.api_state.config.data.chat_filter.evaluate(&self.server.api_state.redis, &data.player.id, &data.message).await
+        };
+        if self.server.api_state.config.data.chat_filter.should_mute(&filter_result) {
+            chat_filter::issue_auto_mute(&mut self.server, &data.player, &data.message, &filter_result).await;
+        };
+
+        // kept for moderation, pruned after the configured retention period
+        self.server.api_state.database.insert_one(&ChatMessage {
+            id: Uuid::new_v4().to_string(),
+            player: data.player.clone(),
+            // server ids are matched case-insensitively everywhere else
+            server_id: self.server.id.to_lowercase(),
+            match_id: self.server.get_current_match_id().await,
+            channel: data.channel.clone(),
+            message: data.message.clone(),
+            flags: filter_result.flags,
+            created_at: get_u64_time_millis()
+        }).await;
+
         let mut current_match = unwrap_helper::return_default!(self.server.get_match().await, Err(SocketError::InvalidMatchState));
         let participant = match current_match.participants.get(&data.player.id) {
             Some(participant_ref) => Some(participant_ref.to_owned()),
@@ -257,11 +295,11 @@ impl SocketRouter {
             for player_listener in self.player_listeners.iter() {
                 player_listener.on_chat(&mut self.server, &mut current_match, &mut player, &mut data).await;
             };
-            self.server.api_state.player_cache.set(&self.server.api_state.database, &player.name, &player, false).await;
+            self.staged_players.push((player.name.clone(), player));
         };
 
         {
-            self.server.api_state.match_cache.set(&self.server.api_state.database, &current_match.id, &current_match, false).await;
+            self.server.save_match(&mut current_match, false, None).await?;
         };
 
         Ok(())
@@ -272,7 +310,7 @@ impl SocketRouter {
         if current_match.get_state() != MatchState::InProgress {
             return Err(SocketError::InvalidMatchState);
         };
-        let mut participant = current_match.participants.get(&data.player.id).unwrap().to_owned();
+        let mut participant = get_participant(&current_match, &data.player.id)?;
         let mut player = participant.get_player(&*self.server.api_state).await;
         if data.ended {
             for participant_listener in self.participant_listeners.iter() {
@@ -292,8 +330,8 @@ impl SocketRouter {
 
         {
             current_match.save_participants(vec![participant.clone()]);
-            participant.set_player(&*self.server.api_state, &player).await;
-            self.server.api_state.match_cache.set(&self.server.api_state.database, &current_match.id, &current_match, false).await;
+            self.stage_player(&participant, player);
+            self.server.save_match(&mut current_match, false, None).await?;
         };
         Ok(())
     }
@@ -313,6 +351,7 @@ impl SocketRouter {
         for participant_listener in self.participant_listeners.iter() {
              participant_listener.on_party_join(&mut self.server, &mut current_match, &mut participant, data.party_name.clone()).await;
         };
+        self.server.api_state.presence.set_party(&data.player.id, Some(data.party_name.clone())).await;
         let mut player = participant.get_player(&*self.server.api_state).await;
         for player_listener in self.player_listeners.iter() {
             player_listener.on_party_join(&mut self.server, &mut current_match, &mut player, data.party_name.clone()).await;
@@ -320,8 +359,8 @@ impl SocketRouter {
 
         {
             current_match.save_participants(vec![participant.clone()]);
-            participant.set_player(&*self.server.api_state, &player).await;
-            self.server.api_state.match_cache.set(&self.server.api_state.database, &current_match.id, &current_match, false).await;
+            self.stage_player(&participant, player);
+            self.server.save_match(&mut current_match, false, None).await?;
         };
         Ok(())
     }
@@ -332,10 +371,11 @@ impl SocketRouter {
             return Err(SocketError::InvalidMatchState);
         };
 
-        let mut participant = current_match.participants.get(&data.player.id).unwrap().clone();
+        let mut participant = get_participant(&current_match, &data.player.id)?;
         for participant_listener in self.participant_listeners.iter() {
              participant_listener.on_party_leave(&mut self.server, &mut current_match, &mut participant).await;
         };
+        self.server.api_state.presence.set_party(&data.player.id, None).await;
         let mut player = participant.get_player(&*self.server.api_state).await;
         for player_listener in self.player_listeners.iter() {
             player_listener.on_party_leave(&mut self.server, &mut current_match, &mut player).await;
@@ -343,8 +383,8 @@ impl SocketRouter {
 
         {
             current_match.save_participants(vec![participant.clone()]);
-            participant.set_player(&*self.server.api_state, &player).await;
-            self.server.api_state.match_cache.set(&self.server.api_state.database, &current_match.id, &current_match, false).await;
+            self.stage_player(&participant, player);
+            self.server.save_match(&mut current_match, false, None).await?;
         };
         Ok(())
     }
@@ -355,7 +395,7 @@ impl SocketRouter {
             return Err(SocketError::InvalidMatchState);
         };
 
-        let mut participant = current_match.participants.get(&data.player_id).unwrap().clone();
+        let mut participant = get_participant(&current_match, &data.player_id)?;
         let destroyable = unwrap_helper::return_default!(
             unwrap_helper::return_default!(
                 &current_match.level.goals, Ok(())
@@ -372,8 +412,8 @@ impl SocketRouter {
 
         {
             current_match.save_participants(vec![participant.clone()]);
-            participant.set_player(&*self.server.api_state, &player).await;
-            self.server.api_state.match_cache.set(&self.server.api_state.database, &current_match.id, &current_match, false).await;
+            self.stage_player(&participant, player);
+            self.server.save_match(&mut current_match, false, None).await?;
         };
         Ok(())
     }
@@ -407,17 +447,20 @@ impl SocketRouter {
 
             {
                 current_match.save_participants(vec![participant.clone()]);
-                participant.set_player(&*self.server.api_state, &player).await;
+                self.stage_player(&participant, player);
             };
         };
-        self.server.api_state.match_cache.set(&self.server.api_state.database, &current_match.id, &current_match, false).await;
+        self.server.save_match(&mut current_match, false, None).await?;
         Ok(())
     }
 
     async fn on_core_leak(&mut self, data: CoreLeakData) -> Result<(), SocketError> {
         let mut current_match = unwrap_helper::return_default!(self.server.get_match().await, Err(SocketError::InvalidMatchState));
         for contribution in data.contributions.iter() {
-            let mut participant = current_match.participants.get(&contribution.player_id).unwrap().clone();
+            get_participant(&current_match, &contribution.player_id)?;
+        };
+        for contribution in data.contributions.iter() {
+            let mut participant = get_participant(&current_match, &contribution.player_id)?;
             for participant_listener in self.participant_listeners.iter() {
                  participant_listener.on_core_leak(
                      &mut self.server, 
@@ -440,17 +483,17 @@ impl SocketRouter {
 
             {
                 current_match.save_participants(vec![participant.clone()]);
-                participant.set_player(&*self.server.api_state, &player).await;
+                self.stage_player(&participant, player);
             };
         };
-        self.server.api_state.match_cache.set(&self.server.api_state.database, &current_match.id, &current_match, false).await;
+        self.server.save_match(&mut current_match, false, None).await?;
         Ok(())
     }
 
     async fn on_flag_place(&mut self, data: FlagDropData) -> Result<(), SocketError> {
         let mut current_match = unwrap_helper::return_default!(self.server.get_match().await, Err(SocketError::InvalidMatchState));
 
-        let mut participant = current_match.participants.get(&data.player_id).unwrap().clone();
+        let mut participant = get_participant(&current_match, &data.player_id)?;
         for participant_listener in self.participant_listeners.iter() {
              participant_listener.on_flag_place(&mut self.server, &mut current_match, &mut participant, data.held_time).await;
         };
@@ -461,8 +504,8 @@ impl SocketRouter {
 
         {
             current_match.save_participants(vec![participant.clone()]);
-            participant.set_player(&*self.server.api_state, &player).await;
-            self.server.api_state.match_cache.set(&self.server.api_state.database, &current_match.id, &current_match, false).await;
+            self.stage_player(&participant, player);
+            self.server.save_match(&mut current_match, false, None).await?;
         };
         Ok(())
     }
@@ -473,7 +516,7 @@ impl SocketRouter {
             return Err(SocketError::InvalidMatchState);
         };
 
-        let mut participant = current_match.participants.get(&data.player_id).unwrap().clone();
+        let mut participant = get_participant(&current_match, &data.player_id)?;
         for participant_listener in self.participant_listeners.iter() {
              participant_listener.on_flag_pickup(&mut self.server, &mut current_match, &mut participant).await;
         };
@@ -484,8 +527,8 @@ impl SocketRouter {
 
         {
             current_match.save_participants(vec![participant.clone()]);
-            participant.set_player(&*self.server.api_state, &player).await;
-            self.server.api_state.match_cache.set(&self.server.api_state.database, &current_match.id, &current_match, false).await;
+            self.stage_player(&participant, player);
+            self.server.save_match(&mut current_match, false, None).await?;
         };
         Ok(())
     }
@@ -507,8 +550,8 @@ impl SocketRouter {
 
         {
             current_match.save_participants(vec![participant.clone()]);
-            participant.set_player(&*self.server.api_state, &player).await;
-            self.server.api_state.match_cache.set(&self.server.api_state.database, &current_match.id, &current_match, false).await;
+            self.stage_player(&participant, player);
+            self.server.save_match(&mut current_match, false, None).await?;
         };
         Ok(())
     }
@@ -519,7 +562,7 @@ impl SocketRouter {
             return Err(SocketError::InvalidMatchState);
         };
 
-        let mut participant = current_match.participants.get(&data.player_id).unwrap().clone();
+        let mut participant = get_participant(&current_match, &data.player_id)?;
         for participant_listener in self.participant_listeners.iter() {
              participant_listener.on_flag_defend(&mut self.server, &mut current_match, &mut participant).await;
         };
@@ -530,8 +573,8 @@ impl SocketRouter {
 
         {
             current_match.save_participants(vec![participant.clone()]);
-            participant.set_player(&*self.server.api_state, &player).await;
-            self.server.api_state.match_cache.set(&self.server.api_state.database, &current_match.id, &current_match, false).await;
+            self.stage_player(&participant, player);
+            self.server.save_match(&mut current_match, false, None).await?;
         };
         Ok(())
     }
@@ -539,7 +582,7 @@ impl SocketRouter {
     async fn on_wool_place(&mut self, data: WoolDropData) -> Result<(), SocketError> {
         let mut current_match = unwrap_helper::return_default!(self.server.get_match().await, Err(SocketError::InvalidMatchState));
 
-        let mut participant = current_match.participants.get(&data.player_id).unwrap().clone();
+        let mut participant = get_participant(&current_match, &data.player_id)?;
         for participant_listener in self.participant_listeners.iter() {
              participant_listener.on_wool_place(&mut self.server, &mut current_match, &mut participant, data.held_time).await;
         };
@@ -550,8 +593,8 @@ impl SocketRouter {
 
         {
             current_match.save_participants(vec![participant.clone()]);
-            participant.set_player(&*self.server.api_state, &player).await;
-            self.server.api_state.match_cache.set(&self.server.api_state.database, &current_match.id, &current_match, false).await;
+            self.stage_player(&participant, player);
+            self.server.save_match(&mut current_match, false, None).await?;
         };
         Ok(())
     }
@@ -562,7 +605,7 @@ impl SocketRouter {
             return Err(SocketError::InvalidMatchState);
         };
 
-        let mut participant = current_match.participants.get(&data.player_id).unwrap().clone();
+        let mut participant = get_participant(&current_match, &data.player_id)?;
         for participant_listener in self.participant_listeners.iter() {
              participant_listener.on_wool_pickup(&mut self.server, &mut current_match, &mut participant).await;
         };
@@ -573,8 +616,8 @@ impl SocketRouter {
 
         {
             current_match.save_participants(vec![participant.clone()]);
-            participant.set_player(&*self.server.api_state, &player).await;
-            self.server.api_state.match_cache.set(&self.server.api_state.database, &current_match.id, &current_match, false).await;
+            self.stage_player(&participant, player);
+            self.server.save_match(&mut current_match, false, None).await?;
         };
         Ok(())
     }
@@ -585,7 +628,7 @@ impl SocketRouter {
             return Err(SocketError::InvalidMatchState);
         };
 
-        let mut participant = current_match.participants.get(&data.player_id).unwrap().clone();
+        let mut participant = get_participant(&current_match, &data.player_id)?;
         for participant_listener in self.participant_listeners.iter() {
              participant_listener.on_wool_drop(&mut self.server, &mut current_match, &mut participant, data.held_time).await;
         };
@@ -596,8 +639,8 @@ impl SocketRouter {
 
         {
             current_match.save_participants(vec![participant.clone()]);
-            participant.set_player(&*self.server.api_state, &player).await;
-            self.server.api_state.match_cache.set(&self.server.api_state.database, &current_match.id, &current_match, false).await;
+            self.stage_player(&participant, player);
+            self.server.save_match(&mut current_match, false, None).await?;
         };
         Ok(())
     }
@@ -608,7 +651,7 @@ impl SocketRouter {
             return Err(SocketError::InvalidMatchState);
         };
 
-        let mut participant = current_match.participants.get(&data.player_id).unwrap().clone();
+        let mut participant = get_participant(&current_match, &data.player_id)?;
         for participant_listener in self.participant_listeners.iter() {
              participant_listener.on_wool_defend(&mut self.server, &mut current_match, &mut participant).await;
         };
@@ -619,8 +662,8 @@ impl SocketRouter {
 
         {
             current_match.save_participants(vec![participant.clone()]);
-            participant.set_player(&*self.server.api_state, &player).await;
-            self.server.api_state.match_cache.set(&self.server.api_state.database, &current_match.id, &current_match, false).await;
+            self.stage_player(&participant, player);
+            self.server.save_match(&mut current_match, false, None).await?;
         };
         Ok(())
     }
@@ -631,7 +674,10 @@ impl SocketRouter {
             return Err(SocketError::InvalidMatchState);
         };
         for capturer in data.player_ids.iter() {
-            let mut participant = current_match.participants.get(capturer).unwrap().clone();
+            get_participant(&current_match, capturer)?;
+        };
+        for capturer in data.player_ids.iter() {
+            let mut participant = get_participant(&current_match, capturer)?;
             for participant_listener in self.participant_listeners.iter() {
                  participant_listener.on_control_point_capture(
                      &mut self.server, 
@@ -652,10 +698,10 @@ impl SocketRouter {
 
             {
                 current_match.save_participants(vec![participant.clone()]);
-                participant.set_player(&*self.server.api_state, &player).await;
+                self.stage_player(&participant, player);
             };
         }
-        self.server.api_state.match_cache.set(&self.server.api_state.database, &current_match.id, &current_match, false).await;
+        self.server.save_match(&mut current_match, false, None).await?;
         Ok(())
     }
 
@@ -682,14 +728,17 @@ impl SocketRouter {
             completion_time: data.completion_time
         });
 
-        self.server.api_state.player_cache.set(database, &player.name, &player, true).await;
+        self.server.api_state.player_cache.save_stats(database, &player.name.clone(), &mut player, true).await;
 
         Ok(())
     }
 
-    fn parse_data<T: DeserializeOwned>(data: Value) -> T {
-        let debug_res = format!("Socket passed malformed data.. {data:?}");
-        serde_json::from_value(data).expect(&debug_res)
+    fn stage_player(&mut self, participant: &Participant, player: Player) {
+        self.staged_players.push((participant.get_name_lower(), player));
+    }
+
+    fn parse_data<T: DeserializeOwned>(data: Value) -> Result<T, SocketError> {
+        serde_json::from_value(data).map_err(|e| SocketError::MalformedData(e.to_string()))
     }
 
     async fn get_match_id(&self) -> String {
@@ -0,0 +1,43 @@
+use std::fs;
+
+use uuid::Uuid;
+
+use crate::{database::{Database, analytics_export::{self, ExportFormat}, models::r#match::Match}, util::time::get_u64_time_millis};
+
+use super::{harness::TestHarness, match_lifecycle::{play_match, setup_map_and_players, SERVER_ID}};
+
+#[rocket::async_test]
+async fn exports_are_partitioned_by_day_and_incremental() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let mut plugin = harness.connect_plugin(SERVER_ID).await;
+    let match_id = play_match(&harness, &mut plugin).await;
+
+    let directory = std::env::temp_dir().join(format!("mars-export-{}", Uuid::new_v4()));
+    // past the lag kept for writes that haven't landed yet
+    let now = get_u64_time_millis() + 120_000;
+    let summary = analytics_export::export_analytics(&harness.state.database, &directory, ExportFormat::Csv, now).await.unwrap();
+    assert_eq!((summary.matches, summary.participants, summary.deaths), (1, 2, 2));
+
+    let match_file = summary.files.iter().find(|path| path.starts_with(directory.join("matches"))).unwrap();
+    assert!(match_file.parent().unwrap().file_name().unwrap().to_str().unwrap().starts_with("day="));
+    let content = fs::read_to_string(match_file).unwrap();
+    let lines : Vec<&str> = content.lines().collect();
+    assert!(lines[0].starts_with("match_id,server_id,level_id"));
+    assert!(lines[1].starts_with(&format!("{},{}", match_id, SERVER_ID)));
+
+    // nothing new since the last export
+    let summary = analytics_export::export_analytics(&harness.state.database, &directory, ExportFormat::Ndjson, now + 1000).await.unwrap();
+    assert_eq!((summary.matches, summary.participants, summary.deaths, summary.sessions), (0, 0, 0, 0));
+    assert!(summary.files.is_empty());
+
+    // a match closed at startup gets the server's last alive time, which can be before the last export
+    let mut hanging : Match = Database::find_by_id(&harness.state.database.matches, &match_id).await.unwrap();
+    hanging.id = Uuid::new_v4().to_string();
+    hanging.started_at = hanging.started_at.map(|started_at| started_at - 1000);
+    hanging.ended_at = hanging.ended_at.map(|ended_at| ended_at - 1000);
+    harness.state.database.matches.insert_one(&hanging).await.unwrap();
+    let summary = analytics_export::export_analytics(&harness.state.database, &directory, ExportFormat::Ndjson, now + 2000).await.unwrap();
+    assert_eq!((summary.matches, summary.participants), (1, 2));
+    let _ = fs::remove_dir_all(directory);
+}
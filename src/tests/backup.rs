@@ -0,0 +1,120 @@
+use std::{fs::File, io::{BufRead, BufReader, Write}};
+
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use uuid::Uuid;
+
+use mongodb::bson::doc;
+use rocket::serde::json::{serde_json, Value};
+
+use crate::{database::{Database, backup, models::{player::Player, r#match::Match}, store::IndexDefinition}, socket::leaderboard::LeaderboardPeriod};
+
+use super::{harness::TestHarness, match_lifecycle::{ALICE, play_match, setup_map_and_players, SERVER_ID}};
+
+#[rocket::async_test]
+async fn backups_restore_into_an_empty_database() {
+    let live = TestHarness::start().await;
+    setup_map_and_players(&live).await;
+    let mut plugin = live.connect_plugin(SERVER_ID).await;
+    let match_id = play_match(&live, &mut plugin).await;
+
+    let path = std::env::temp_dir().join(format!("mars-backup-{}.ndjson.gz", Uuid::new_v4()));
+    let created = backup::create_backup(&live.state.database, &live.state.redis, &path).await.unwrap();
+    assert_eq!((created.documents["player"], created.documents["match"]), (2, 1));
+    assert!(created.leaderboards > 0);
+
+    // leftovers from whatever the staging database held before are dropped
+    let staging = TestHarness::start().await;
+    let store = &staging.state.redis.store;
+    store.zadd("lb:kills:stale", &[(String::from(ALICE.0), 99.0)]).await.unwrap();
+    store.hset(&format!("player_stats:{}", ALICE.0), "stats.kills", "99").await.unwrap();
+    let restored = backup::restore_backup(&staging.state, &path).await.unwrap();
+    assert_eq!(restored.documents, created.documents);
+    assert!(!store.keys_with_prefix("lb:").await.unwrap().contains(&String::from("lb:kills:stale")));
+    assert!(store.keys_with_prefix("restore:").await.unwrap().is_empty());
+    assert!(store.hgetall(&format!("player_stats:{}", ALICE.0)).await.unwrap().is_empty());
+    let alice : Player = Database::find_by_id(&staging.state.database.players, ALICE.0).await.unwrap();
+    let original : Player = Database::find_by_id(&live.state.database.players, ALICE.0).await.unwrap();
+    assert_eq!((alice.stats.kills, alice.version), (original.stats.kills, original.version));
+    let restored_match : Match = Database::find_by_id(&staging.state.database.matches, &match_id).await.unwrap();
+    assert_eq!(restored_match.participants.len(), 2);
+    let top = staging.state.leaderboards.kills.fetch_top(&LeaderboardPeriod::AllTime, 1).await;
+    assert_eq!((top[0].id.as_str(), top[0].score), (ALICE.0, 2));
+
+    // a second restore would mix two databases
+    let error = backup::restore_backup(&staging.state, &path).await.unwrap_err();
+    assert!(error.to_string().contains("empty database"));
+    let _ = std::fs::remove_file(path);
+}
+
+#[rocket::async_test]
+async fn truncated_backups_are_rejected_before_writing() {
+    let live = TestHarness::start().await;
+    setup_map_and_players(&live).await;
+    let path = std::env::temp_dir().join(format!("mars-backup-{}.ndjson.gz", Uuid::new_v4()));
+    backup::create_backup(&live.state.database, &live.state.redis, &path).await.unwrap();
+
+    // drop the end marker
+    let lines = read_lines(&path);
+    let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+    for line in &lines[..lines.len() - 1] {
+        writeln!(encoder, "{}", line).unwrap();
+    }
+    encoder.finish().unwrap();
+
+    let staging = TestHarness::start().await;
+    let error = backup::restore_backup(&staging.state, &path).await.unwrap_err();
+    assert!(error.to_string().contains("truncated"));
+    let players : Vec<Player> = staging.state.database.get_all_documents().await;
+    assert!(players.is_empty());
+    let _ = std::fs::remove_file(path);
+}
+
+fn read_lines(path: &std::path::Path) -> Vec<String> {
+    BufReader::new(MultiGzDecoder::new(File::open(path).unwrap())).lines().map(Result::unwrap).collect()
+}
+
+#[rocket::async_test]
+async fn backups_breaking_unique_indexes_are_rejected_before_writing() {
+    let live = TestHarness::start().await;
+    setup_map_and_players(&live).await;
+    let path = std::env::temp_dir().join(format!("mars-backup-{}.ndjson.gz", Uuid::new_v4()));
+    backup::create_backup(&live.state.database, &live.state.redis, &path).await.unwrap();
+
+    // a copy of alice under another ID still holds her name
+    let mut lines : Vec<Value> = read_lines(&path).iter().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let mut impostor = lines.iter().find(|line| line["collection"] == "player" && line["document"]["_id"] == ALICE.0).unwrap().clone();
+    impostor["document"]["_id"] = Value::from(Uuid::new_v4().to_string());
+    let mut end = lines.pop().unwrap();
+    lines.push(impostor);
+    end["documents"]["player"] = Value::from(end["documents"]["player"].as_u64().unwrap() + 1);
+    lines.push(end);
+    let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+    for line in &lines {
+        writeln!(encoder, "{}", line).unwrap();
+    }
+    encoder.finish().unwrap();
+
+    let staging = TestHarness::start().await;
+    let error = backup::restore_backup(&staging.state, &path).await.unwrap_err();
+    assert!(error.to_string().contains("nameLower"), "{}", error);
+    let players : Vec<Player> = staging.state.database.get_all_documents().await;
+    assert!(players.is_empty());
+    let _ = std::fs::remove_file(path);
+}
+
+#[rocket::async_test]
+async fn failed_restores_are_undone() {
+    let live = TestHarness::start().await;
+    setup_map_and_players(&live).await;
+    let path = std::env::temp_dir().join(format!("mars-backup-{}.ndjson.gz", Uuid::new_v4()));
+    backup::create_backup(&live.state.database, &live.state.redis, &path).await.unwrap();
+
+    // an index the models don't declare, which only the second player's insert breaks
+    let staging = TestHarness::start().await;
+    let store = &staging.state.database.store;
+    store.create_index("player", &IndexDefinition::new(doc! { "stats.kills": 1 }).unique()).await.unwrap();
+    assert!(backup::restore_backup(&staging.state, &path).await.is_err());
+    assert_eq!(store.count_documents("player", doc! {}).await.unwrap(), 0);
+    assert_eq!(store.count_documents("level", doc! {}).await.unwrap(), 0);
+    let _ = std::fs::remove_file(path);
+}
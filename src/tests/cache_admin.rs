@@ -0,0 +1,103 @@
+use mongodb::bson::doc;
+use rocket::{http::Status, serde::json::Value};
+
+use crate::database::{Database, models::{player::Player, r#match::Match}};
+
+use super::{harness::TestHarness, match_lifecycle::{ALICE, BOB, SERVER_ID, current_match, setup_map_and_players, start_match}};
+
+#[rocket::async_test]
+async fn corrupted_players_are_evicted_and_reloaded() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let stats_key = format!("player_stats:{}", ALICE.0);
+    harness.state.redis.hset_all(&stats_key, &[(String::from("stats.kills"), String::from("999999"))]).await;
+
+    let cached = harness.get_json::<Value>(&format!("/mc/cache/players/{}", ALICE.1)).await;
+    assert_eq!(cached["stats"]["kills"], 999999);
+
+    let (status, _) = harness.delete_json::<Value>(&format!("/mc/cache/players/{}", ALICE.1)).await;
+    assert_eq!(status, Status::Ok);
+    let store = &harness.state.redis.store;
+    assert!(store.get(&format!("player:{}", ALICE.0)).await.unwrap().is_none());
+    assert!(store.get(&format!("player_name:{}", ALICE.1.to_lowercase())).await.unwrap().is_none());
+    assert!(harness.state.redis.hgetall(&stats_key).await.is_empty());
+
+    let alice = harness.state.player_cache.get(&harness.state.database, ALICE.1).await.unwrap();
+    assert_eq!(alice.stats.kills, 0);
+    let (status, _) = harness.delete_json::<Value>("/mc/cache/players/nobody").await;
+    assert_eq!(status, Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn cached_players_are_persisted_on_request() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let state = &harness.state;
+
+    // saved to the cache only
+    let mut alice = state.player_cache.get(&state.database, ALICE.0).await.unwrap();
+    alice.tag_ids.push(String::from("cached"));
+    state.player_cache.set(&state.database, ALICE.1, &mut alice, false).await.unwrap();
+    let stored : Player = Database::find_by_id(&state.database.players, ALICE.0).await.unwrap();
+    assert!(stored.tag_ids.is_empty());
+
+    let (status, _) = harness.post_json::<Value>(&format!("/mc/cache/players/{}/persist", ALICE.0)).await;
+    assert_eq!(status, Status::Ok);
+    let stored : Player = Database::find_by_id(&state.database.players, ALICE.0).await.unwrap();
+    assert_eq!(stored.tag_ids, vec![String::from("cached")]);
+
+    let (status, _) = harness.post_json::<Value>("/mc/cache/spells/anything/persist").await;
+    assert_eq!(status, Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn whole_namespaces_are_flushed() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+
+    let (status, response) = harness.delete_json::<Value>("/mc/cache/players").await;
+    assert_eq!((status, response.unwrap()["evicted"].as_u64()), (Status::Ok, Some(2)));
+    assert!(harness.state.redis.store.keys_with_prefix("player").await.unwrap().is_empty());
+    assert!(harness.state.player_cache.query(BOB.1).await.is_none());
+    assert!(harness.state.player_cache.get(&harness.state.database, BOB.1).await.is_some());
+}
+
+#[rocket::async_test]
+async fn stale_cached_copies_are_not_persisted() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let state = &harness.state;
+    let mut alice = state.player_cache.get(&state.database, ALICE.0).await.unwrap();
+    alice.tag_ids.push(String::from("stale"));
+    state.player_cache.set(&state.database, ALICE.1, &mut alice, false).await.unwrap();
+
+    // another instance wrote a newer version straight to the database
+    state.database.players.update_one(doc! { "_id": ALICE.0 }, doc! { "$set": { "version": 100 } }, None).await.unwrap();
+    let (status, _) = harness.post_json::<Value>(&format!("/mc/cache/players/{}/persist", ALICE.0)).await;
+    assert_eq!(status, Status::Conflict);
+    let stored : Player = Database::find_by_id(&state.database.players, ALICE.0).await.unwrap();
+    assert!(stored.tag_ids.is_empty());
+}
+
+#[rocket::async_test]
+async fn matches_in_progress_are_persisted_before_eviction() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let mut plugin = harness.connect_plugin(SERVER_ID).await;
+    start_match(&harness, &mut plugin).await;
+    let match_id = current_match(&harness).await.unwrap().id;
+    harness.state.database.matches.delete_one(doc! { "_id": &match_id }).await.unwrap();
+
+    let (status, _) = harness.delete_json::<Value>(&format!("/mc/cache/matches/{}", match_id)).await;
+    assert_eq!(status, Status::Ok);
+    let stored : Match = Database::find_by_id(&harness.state.database.matches, &match_id).await.expect("the match should be persisted");
+    assert_eq!(stored.participants.len(), 2);
+
+    // flushing does the same, the next event reloads the match from the database
+    let live = harness.state.match_cache.get(&harness.state.database, &match_id).await.unwrap();
+    harness.state.match_cache.set(&harness.state.database, &match_id, &live, false).await;
+    let (status, response) = harness.delete_json::<Value>("/mc/cache/matches").await;
+    let response = response.unwrap();
+    assert_eq!((status, response["evicted"].as_u64(), response["kept"].as_u64()), (Status::Ok, Some(1), Some(0)));
+    assert!(harness.state.match_cache.get(&harness.state.database, &match_id).await.is_some());
+}
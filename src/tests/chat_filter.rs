@@ -0,0 +1,34 @@
+use std::sync::Arc;
+
+use crate::{database::{cache::RedisAdapter, store::memory::MemoryCacheStore}, socket::chat::chat_filter::{BlockedWordsCheck, ChatFilterConfig}};
+
+fn blocking(words: &[&str]) -> ChatFilterConfig {
+    ChatFilterConfig {
+        enabled: true,
+        blocked_words: Some(BlockedWordsCheck { words: words.iter().map(|word| word.to_string()).collect(), score: 10 }),
+        ..Default::default()
+    }
+}
+
+async fn is_blocked(config: &ChatFilterConfig, message: &str) -> bool {
+    let redis = RedisAdapter::new(Arc::new(MemoryCacheStore::new()));
+    config.evaluate(&redis, "player", message).await.flags.contains(&String::from("BLOCKED_WORD"))
+}
+
+#[rocket::async_test]
+async fn blocked_words_do_not_match_inside_other_words() {
+    let config = blocking(&["ass"]);
+    for message in ["he has a point", "what a class act", "nice pass!", "assassin", "as if", "I win!!", "gg | wp"] {
+        assert!(!is_blocked(&config, message).await, "'{}' should not be blocked", message);
+    }
+}
+
+#[rocket::async_test]
+async fn obfuscated_blocked_words_still_match() {
+    let config = blocking(&["ass", "bad word"]);
+    for message in ["you ass", "ASS!", "a$$", "4ss", "a.s.s", "a s s", "4 s s", "aaasss", "such a bad  w0rd"] {
+        assert!(is_blocked(&config, message).await, "'{}' should be blocked", message);
+    }
+    // numbers are left as they are
+    assert!(!is_blocked(&blocking(&["ass"]), "4 55").await);
+}
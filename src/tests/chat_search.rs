@@ -0,0 +1,28 @@
+use rocket::serde::json::json;
+
+use crate::{database::models::chat_message::ChatMessage, socket::event_type::EventType};
+
+use super::{harness::{TestHarness, wait_until}, match_lifecycle::{ALICE, SERVER_ID}};
+
+#[rocket::async_test]
+async fn chat_is_filtered_by_server_regardless_of_case() {
+    let harness = TestHarness::start().await;
+    let mut plugin = harness.connect_plugin(&SERVER_ID.to_uppercase()).await;
+    plugin.send(EventType::PlayerChat, json!({
+        "player": { "id": ALICE.0, "name": ALICE.1 },
+        "playerPrefix": "",
+        "channel": "GLOBAL",
+        "message": "hello",
+        "serverId": SERVER_ID
+    })).await;
+    wait_until("the chat message is stored", || async {
+        !harness.state.database.get_all_documents::<ChatMessage>().await.is_empty()
+    }).await;
+
+    let messages : Vec<ChatMessage> = harness.get_json(&format!("/mc/chat?server={}", SERVER_ID.to_uppercase())).await;
+    assert_eq!(messages.len(), 1);
+    let messages : Vec<ChatMessage> = harness.get_json(&format!("/mc/chat?server={}", SERVER_ID)).await;
+    assert_eq!(messages[0].server_id, SERVER_ID);
+    let messages : Vec<ChatMessage> = harness.get_json("/mc/chat?server=elsewhere").await;
+    assert!(messages.is_empty());
+}
@@ -0,0 +1,91 @@
+use std::time::Instant;
+
+use rocket::serde::json::{json, serde_json, Value};
+
+use crate::{database::{Database, models::player::Player}, socket::{codec::{PacketCodec, PayloadCompression, PayloadEncoding}, event_type::EventType, server::server_context::Packet}, util::string::deflate_string};
+
+use super::{fake_plugin::FakePlugin, harness::TestHarness, match_lifecycle::{ALICE, play_match, setup_map_and_players, SERVER_ID}};
+
+const ENCODINGS: [PayloadEncoding; 3] = [PayloadEncoding::Json, PayloadEncoding::MessagePack, PayloadEncoding::Cbor];
+const COMPRESSIONS: [PayloadCompression; 2] = [PayloadCompression::Zlib, PayloadCompression::None];
+
+fn death_packet() -> Packet<Value> {
+    Packet::new(&EventType::PlayerDeath, json!({
+        "victim": { "id": "00000000-0000-0000-0000-00000000000b", "name": "Bob" },
+        "attacker": { "id": "00000000-0000-0000-0000-00000000000a", "name": "Alice" },
+        "weapon": "IRON_SWORD",
+        "entity": null,
+        "distance": 12,
+        "key": "generic",
+        "cause": "MELEE"
+    })).unwrap()
+}
+
+fn all_codecs() -> Vec<PacketCodec> {
+    ENCODINGS.iter().flat_map(|encoding| COMPRESSIONS.iter().map(|compression| PacketCodec { encoding: *encoding, compression: *compression })).collect()
+}
+
+#[test]
+fn every_codec_round_trips_packets() {
+    let packet = death_packet();
+    for codec in all_codecs() {
+        let decoded = codec.decode(&codec.encode(&packet).unwrap().into_data()).unwrap();
+        assert_eq!(decoded.event.to_string(), packet.event.to_string(), "{}+{}", codec.encoding, codec.compression);
+        assert_eq!(decoded.data, packet.data, "{}+{}", codec.encoding, codec.compression);
+    }
+}
+
+#[test]
+fn default_codec_matches_legacy_packets() {
+    let packet = death_packet();
+    let legacy = deflate_string(serde_json::to_string(&packet).unwrap().as_bytes()).unwrap();
+    assert_eq!(PacketCodec::default().encode(&packet).unwrap().into_data(), legacy);
+}
+
+#[rocket::async_test]
+async fn msgpack_plugin_plays_a_full_match() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let codec = PacketCodec { encoding: PayloadEncoding::MessagePack, compression: PayloadCompression::None };
+    let mut plugin = harness.connect_plugin_with_codec(SERVER_ID, codec).await;
+    assert_eq!(plugin.handshake_headers.get(PacketCodec::ENCODING_HEADER).unwrap(), "msgpack");
+    assert_eq!(plugin.handshake_headers.get(PacketCodec::COMPRESSION_HEADER).unwrap(), "none");
+
+    play_match(&harness, &mut plugin).await;
+    let alice : Player = Database::find_by_id(&harness.state.database.players, ALICE.0).await.expect("alice should be saved");
+    assert_eq!((alice.stats.kills, alice.stats.wins), (2, 1));
+}
+
+#[rocket::async_test]
+async fn handshake_rejects_unknown_encoding() {
+    let harness = TestHarness::start().await;
+    let url = format!("ws://{}/minecraft?id={}&token={}&encoding=xml", harness.socket_address(), SERVER_ID, TestHarness::TOKEN);
+    assert!(tokio_tungstenite::connect_async(url).await.is_err());
+    assert!(FakePlugin::connect(harness.socket_address(), SERVER_ID, TestHarness::TOKEN).await.is_ok());
+}
+
+// cargo test --release codec_throughput -- --ignored --nocapture
+#[test]
+#[ignore]
+fn codec_throughput() {
+    const ITERATIONS: u32 = 50_000;
+    let packet = death_packet();
+    println!("{:<16} {:>8} {:>14} {:>14}", "codec", "bytes", "encode/s", "decode/s");
+    for codec in all_codecs() {
+        let encoded = codec.encode(&packet).unwrap().into_data();
+
+        let started = Instant::now();
+        for _ in 0..ITERATIONS {
+            std::hint::black_box(codec.encode(&packet).unwrap());
+        }
+        let encode_rate = f64::from(ITERATIONS) / started.elapsed().as_secs_f64();
+
+        let started = Instant::now();
+        for _ in 0..ITERATIONS {
+            std::hint::black_box(codec.decode(&encoded).unwrap());
+        }
+        let decode_rate = f64::from(ITERATIONS) / started.elapsed().as_secs_f64();
+
+        println!("{:<16} {:>8} {:>14.0} {:>14.0}", format!("{}+{}", codec.encoding, codec.compression), encoded.len(), encode_rate, decode_rate);
+    }
+}
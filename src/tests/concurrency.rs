@@ -0,0 +1,65 @@
+use futures::executor::block_on;
+use rocket::serde::json::Value;
+
+use crate::database::{Database, models::player::Player};
+
+use super::{harness::TestHarness, match_lifecycle::{ALICE, setup_map_and_players}};
+
+async fn load_alice(harness: &TestHarness) -> Player {
+    harness.state.player_cache.get(&harness.state.database, ALICE.1).await.expect("alice should be cached")
+}
+
+#[rocket::async_test]
+async fn stale_copies_are_rejected() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let cache = &harness.state.player_cache.cache;
+    let database = &harness.state.database;
+    let mut first = load_alice(&harness).await;
+    let mut second = load_alice(&harness).await;
+
+    first.tag_ids.push(String::from("first"));
+    cache.compare_and_set(database, ALICE.1, &mut first, true, None).await.expect("the first write should win");
+    second.tag_ids.push(String::from("second"));
+    let conflict = cache.compare_and_set(database, ALICE.1, &mut second, true, None).await.expect_err("the stale write should lose");
+    assert_eq!(conflict.version, first.version);
+    assert_eq!(second.version, first.version - 1);
+
+    // with the cached copy gone, the database still refuses it
+    harness.state.redis.del(&format!("player:{}", ALICE.0.to_lowercase())).await;
+    assert!(cache.compare_and_set(database, ALICE.1, &mut second, true, None).await.is_err());
+
+    let stored : Player = Database::find_by_id(&database.players, ALICE.0).await.unwrap();
+    assert_eq!((stored.tag_ids, stored.version), (vec![String::from("first")], first.version));
+}
+
+#[rocket::async_test]
+async fn updates_retry_until_they_apply_on_the_latest_copy() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let state = &harness.state;
+    let mut rival = load_alice(&harness).await;
+
+    let mut attempts = 0;
+    let updated = state.player_cache.update(&state.database, ALICE.1, |player| {
+        attempts += 1;
+        if attempts == 1 {
+            // someone else gets their write in between this read and this write
+            rival.rank_ids.push(String::from("rival"));
+            let rival_name = rival.name.clone();
+            block_on(state.player_cache.cache.compare_and_set(&state.database, &rival_name, &mut rival, true, None)).unwrap();
+        };
+        player.rank_ids.push(String::from("mine"));
+        Ok::<(), ()>(())
+    }).await.expect("the update should apply on retry");
+
+    assert_eq!(attempts, 2);
+    assert_eq!(updated.rank_ids, vec![String::from("rival"), String::from("mine")]);
+    let stored : Player = Database::find_by_id(&state.database.players, ALICE.0).await.unwrap();
+    assert_eq!(stored.rank_ids, updated.rank_ids);
+
+    let metrics : Value = harness.get_json("/status/concurrency").await;
+    assert_eq!(metrics["players"]["writes"], 3);
+    assert_eq!(metrics["players"]["conflicts"], 1);
+    assert_eq!(metrics["players"]["retriesExhausted"], 0);
+}
@@ -0,0 +1,176 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use rocket::{http::Status, serde::json::{json, Value}};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{database::{Database, models::{chat_message::ChatMessage, dead_letter::{DeadLetter, DeadLetterReason}, r#match::Match, participant::Participant, player::Player}}, http::dead_letter::payload::DeadLetterRedriveResponse, socket::{event_type::EventType, player::{player_events::PlayerDeathData, player_listener::PlayerListener}, server::server_context::ServerContext, socket_router::{SocketError, SocketRouter}}, util::{string::deflate_string, time::get_u64_time_millis}};
+
+use super::{harness::{TestHarness, wait_until}, match_lifecycle::{ALICE, BOB, SERVER_ID, current_match, setup_map_and_players, start_match}};
+
+fn chat_data(message: Option<&str>) -> rocket::serde::json::Value {
+    let mut data = json!({
+        "player": { "id": ALICE.0, "name": ALICE.1 },
+        "playerPrefix": "",
+        "channel": "GLOBAL",
+        "serverId": SERVER_ID
+    });
+    if let Some(message) = message {
+        data["message"] = json!(message);
+    };
+    data
+}
+
+#[rocket::async_test]
+async fn failed_events_are_dead_lettered_and_redriven() {
+    let harness = TestHarness::start().await;
+    let mut plugin = harness.connect_plugin(SERVER_ID).await;
+    plugin.send(EventType::PlayerChat, chat_data(None)).await;
+    plugin.send_raw(Message::Binary(b"not a packet".to_vec())).await;
+    plugin.send_raw(Message::Binary(deflate_string(json!({ "e": "FUTURE_EVENT", "d": {} }).to_string().as_bytes()).unwrap())).await;
+
+    // a failed event doesn't cost the connection
+    plugin.send(EventType::PlayerChat, chat_data(Some("still here"))).await;
+    wait_until("the valid chat message is stored", || async {
+        !harness.state.database.get_all_documents::<ChatMessage>().await.is_empty()
+    }).await;
+    wait_until("every failed event is dead-lettered", || async {
+        harness.state.database.get_all_documents::<DeadLetter>().await.len() == 3
+    }).await;
+
+    let malformed : Vec<DeadLetter> = harness.get_json(&format!("/mc/dead_letters?server={}&reason=malformed_data", SERVER_ID)).await;
+    assert_eq!(malformed.len(), 1);
+    let mut malformed = malformed.into_iter().next().unwrap();
+    assert_eq!(malformed.event.as_deref(), Some("PLAYER_CHAT"));
+    let undecodable : Vec<DeadLetter> = harness.get_json("/mc/dead_letters?reason=UNDECODABLE").await;
+    assert_eq!(undecodable[0].raw.as_deref(), Some("not a packet"));
+    let unknown : Vec<DeadLetter> = harness.get_json("/mc/dead_letters?reason=UNKNOWN_EVENT").await;
+    assert_eq!(unknown[0].event.as_deref(), Some("FUTURE_EVENT"));
+
+    // still malformed, so the attempt is recorded on the letter
+    let uri = format!("/mc/dead_letters/{}/redrive", malformed.id);
+    let (status, redriven) = harness.post_json::<DeadLetter>(&uri).await;
+    let redriven = redriven.expect("redrive should return the letter");
+    assert_eq!(status, Status::Ok);
+    assert_eq!(redriven.redrive_attempts, 1);
+    assert!(redriven.resolved_at.is_none() && redriven.last_redrive_error.is_some());
+
+    malformed.data = Some(chat_data(Some("fixed")));
+    harness.state.database.save(&malformed).await;
+    let (_, redriven) = harness.post_json::<DeadLetter>(&uri).await;
+    assert!(redriven.unwrap().resolved_at.is_some());
+    assert_eq!(harness.state.database.get_all_documents::<ChatMessage>().await.len(), 2);
+    assert_eq!(harness.post_json::<DeadLetter>(&uri).await.0, Status::Conflict);
+
+    let (_, summary) = harness.post_json::<DeadLetterRedriveResponse>("/mc/dead_letters/redrive").await;
+    let summary = summary.unwrap();
+    assert_eq!((summary.attempted, summary.resolved, summary.failed_ids.len()), (2, 0, 2));
+    let unresolved : Vec<DeadLetter> = harness.get_json("/mc/dead_letters?resolved=false").await;
+    assert_eq!(unresolved.len(), 2);
+}
+
+#[rocket::async_test]
+async fn letters_being_redriven_are_skipped() {
+    let harness = TestHarness::start().await;
+    let mut dead_letter = DeadLetter::new(SERVER_ID, DeadLetterReason::HandlerError, String::from("failed"));
+    dead_letter.event = Some(String::from("PLAYER_CHAT"));
+    dead_letter.data = Some(chat_data(Some("once")));
+    dead_letter.redriving_at = Some(get_u64_time_millis());
+    harness.state.database.save(&dead_letter).await;
+
+    // another request holds the letter
+    let uri = format!("/mc/dead_letters/{}/redrive", dead_letter.id);
+    assert_eq!(harness.post_json::<Value>(&uri).await.0, Status::Conflict);
+    let (_, summary) = harness.post_json::<DeadLetterRedriveResponse>("/mc/dead_letters/redrive").await;
+    let summary = summary.unwrap();
+    assert_eq!((summary.attempted, summary.skipped), (0, 1));
+    assert!(harness.state.database.get_all_documents::<ChatMessage>().await.is_empty());
+
+    // a claim from a redrive that never finished is taken over
+    dead_letter.redriving_at = Some(get_u64_time_millis() - 600_000);
+    harness.state.database.save(&dead_letter).await;
+    let (status, redriven) = harness.post_json::<DeadLetter>(&uri).await;
+    let redriven = redriven.unwrap();
+    assert_eq!(status, Status::Ok);
+    assert!(redriven.resolved_at.is_some() && redriven.redriving_at.is_none());
+    assert_eq!(harness.state.database.get_all_documents::<ChatMessage>().await.len(), 1);
+}
+
+#[rocket::async_test]
+async fn deaths_with_unknown_participants_change_nothing() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let mut plugin = harness.connect_plugin(SERVER_ID).await;
+    start_match(&harness, &mut plugin).await;
+
+    plugin.send(EventType::PlayerDeath, json!({
+        "victim": { "id": BOB.0, "name": BOB.1 },
+        "attacker": { "id": "00000000-0000-0000-0000-0000000000ff", "name": "Stranger" },
+        "weapon": "IRON_SWORD",
+        "entity": null,
+        "distance": null,
+        "key": "generic",
+        "cause": "MELEE"
+    })).await;
+    wait_until("the death is dead-lettered", || async {
+        !harness.state.database.get_all_documents::<DeadLetter>().await.is_empty()
+    }).await;
+
+    // rejected as bad data rather than caught as a panic, before the victim was touched
+    let letters : Vec<DeadLetter> = harness.get_json("/mc/dead_letters?reason=MALFORMED_DATA").await;
+    assert_eq!(letters.len(), 1);
+    assert_eq!(letters[0].event.as_deref(), Some("PLAYER_DEATH"));
+    let ongoing = current_match(&harness).await.expect("match should still be current");
+    assert!(ongoing.first_blood.is_none());
+    assert_eq!(ongoing.participants[BOB.0].stats.deaths, 0);
+    harness.state.database.write_behind.flush().await;
+    let bob : Player = Database::find_by_id(&harness.state.database.players, BOB.0).await.expect("bob should be saved");
+    assert_eq!(bob.stats.deaths, 0);
+}
+
+// writes the match behind the handler's back, like another connection would
+struct RivalWriter;
+
+#[async_trait]
+impl PlayerListener for RivalWriter {
+    type Context = Participant;
+
+    async fn on_death(&self, server_context: &mut ServerContext, _current_match: &mut Match, _context: &mut Participant, _data: &mut PlayerDeathData, _first_blood: bool) {
+        let mut rival = server_context.get_match().await.unwrap();
+        server_context.save_match(&mut rival, false, None).await.unwrap();
+    }
+}
+
+fn router(harness: &TestHarness) -> SocketRouter {
+    let (sender, _) = mpsc::unbounded_channel();
+    SocketRouter::new(ServerContext { id: SERVER_ID.to_owned(), api_state: Arc::new(harness.state.clone()), sender })
+}
+
+#[rocket::async_test]
+async fn events_losing_the_match_write_leave_player_stats_alone() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let mut plugin = harness.connect_plugin(SERVER_ID).await;
+    start_match(&harness, &mut plugin).await;
+    let death : Value = json!({
+        "victim": { "id": BOB.0, "name": BOB.1 },
+        "attacker": { "id": ALICE.0, "name": ALICE.1 },
+        "weapon": "IRON_SWORD",
+        "entity": null,
+        "distance": null,
+        "key": "generic",
+        "cause": "MELEE"
+    });
+    let kills = || async { harness.state.player_cache.get(&harness.state.database, ALICE.0).await.unwrap().stats.kills };
+
+    let mut losing_router = router(&harness);
+    losing_router.participant_listeners.push(Box::new(RivalWriter));
+    let result = losing_router.route(&EventType::PlayerDeath, death.clone()).await;
+    assert!(matches!(result, Err(SocketError::VersionConflict(_))));
+    assert_eq!(kills().await, 0);
+
+    // so the redrive is the only time the kill counts
+    assert!(router(&harness).route(&EventType::PlayerDeath, death).await.is_ok());
+    assert_eq!(kills().await, 1);
+}
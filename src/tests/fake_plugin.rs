@@ -0,0 +1,78 @@
+use std::{net::SocketAddr, time::Duration};
+
+use futures::{SinkExt, StreamExt};
+use rocket::serde::json::Value;
+use serde::Serialize;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async, tungstenite::{Message, http::HeaderMap, protocol::CloseFrame}};
+
+use crate::socket::{codec::PacketCodec, event_type::EventType, server::server_context::Packet};
+
+// speaks the plugin side of the socket protocol: {e, d} packets, zlib-deflated JSON unless another codec is negotiated
+pub struct FakePlugin {
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    codec: PacketCodec,
+    pub handshake_headers: HeaderMap
+}
+
+impl FakePlugin {
+    pub async fn connect(address: SocketAddr, server_id: &str, token: &str) -> anyhow::Result<Self> {
+        let url = format!("ws://{}/minecraft?id={}&token={}", address, server_id, token);
+        Self::connect_to(url, PacketCodec::default()).await
+    }
+
+    // asks for the given encoding and compression in the handshake
+    pub async fn connect_with_codec(address: SocketAddr, server_id: &str, token: &str, codec: PacketCodec) -> anyhow::Result<Self> {
+        let url = format!("ws://{}/minecraft?id={}&token={}&encoding={}&compression={}", address, server_id, token, codec.encoding, codec.compression);
+        Self::connect_to(url, codec).await
+    }
+
+    async fn connect_to(url: String, codec: PacketCodec) -> anyhow::Result<Self> {
+        let (stream, response) = connect_async(url).await?;
+        Ok(FakePlugin { stream, codec, handshake_headers: response.headers().clone() })
+    }
+
+    pub async fn send<T: Serialize>(&mut self, event_type: EventType, data: T) {
+        let packet = Packet::new(&event_type, data).expect("packet did not serialize");
+        self.stream.send(self.codec.encode(&packet).expect("packet did not encode")).await.expect("could not send packet");
+    }
+
+    // bypasses the codec, for packets a real plugin wouldn't produce
+    pub async fn send_raw(&mut self, message: Message) {
+        self.stream.send(message).await.expect("could not send message");
+    }
+
+    // None once the API closes the socket or nothing arrives in time
+    pub async fn recv(&mut self) -> Option<Packet<Value>> {
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(5), self.stream.next()).await.ok()??.ok()?;
+            let data = match message {
+                Message::Binary(data) => data,
+                Message::Close(_) => return None,
+                _ => continue
+            };
+            return self.codec.decode(&data).ok();
+        }
+    }
+
+    // skips other plugin-bound packets until one of the given type arrives
+    pub async fn expect_event(&mut self, event_type: EventType) -> Value {
+        while let Some(packet) = self.recv().await {
+            if packet.event.to_string() == event_type.to_string() {
+                return packet.data;
+            };
+        }
+        panic!("plugin never received {}", event_type);
+    }
+
+    // skips anything else until the API closes the socket, then acknowledges the close like a plugin would
+    pub async fn expect_close(&mut self) -> Option<CloseFrame<'static>> {
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(5), self.stream.next()).await.ok()??.ok()?;
+            if let Message::Close(frame) = message {
+                let _ = self.stream.flush().await;
+                return frame;
+            };
+        }
+    }
+}
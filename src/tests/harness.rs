@@ -0,0 +1,134 @@
+use std::{future::Future, net::SocketAddr, sync::Arc, time::Duration};
+
+use rocket::{http::{ContentType, Header, Status}, local::asynchronous::Client};
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::{net::TcpListener, sync::oneshot, task::JoinHandle};
+
+use crate::{MarsAPIState, rocket, config::{MarsConfig, MarsConfigData, MarsConfigOptions}, database::{Database, indexes, cache::RedisAdapter, store::{CacheStore, StorageBackend, memory::{MemoryCacheStore, MemoryDocumentStore}, prefixed::PrefixedCacheStore}}, network::Networks, socket::{codec::PacketCodec, recording::recorder::SocketRecorder, socket_handler::{SocketState, serve_socket}}, util::webhook::WebhookUtils};
+
+use super::fake_plugin::FakePlugin;
+
+// the HTTP API and the websocket listener running against in-memory storage
+pub struct TestHarness {
+    // the default network
+    pub state: MarsAPIState,
+    pub networks: Networks,
+    pub client: Client,
+    socket_address: SocketAddr,
+    socket_shutdown: Option<oneshot::Sender<()>>,
+    socket_task: Option<JoinHandle<anyhow::Result<()>>>
+}
+
+impl TestHarness {
+    pub const TOKEN: &'static str = "test-token";
+
+    pub async fn start() -> Self {
+        Self::start_with_recorder(None).await
+    }
+
+    pub async fn start_with_recorder(recorder: Option<Arc<SocketRecorder>>) -> Self {
+        Self::start_with_networks(recorder, &[]).await
+    }
+
+    // extra networks are (id, token), each gets its own document store and a "network:<id>:" namespace in the shared cache store
+    pub async fn start_with_networks(recorder: Option<Arc<SocketRecorder>>, extra_networks: &[(&str, &str)]) -> Self {
+        let cache_store : Arc<dyn CacheStore> = Arc::new(MemoryCacheStore::new());
+        let state = Self::start_network(Self::TOKEN, Arc::clone(&cache_store)).await;
+        let mut networks = Networks::new(state.clone());
+        for (id, token) in extra_networks {
+            let prefixed = Arc::new(PrefixedCacheStore::new(&format!("network:{}:", id), Arc::clone(&cache_store)));
+            networks.add(id, Self::start_network(token, prefixed).await);
+        }
+
+        // an ephemeral port keeps parallel tests from fighting over the listener
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("could not bind socket listener");
+        let socket_address = listener.local_addr().expect("socket listener has no address");
+        let (socket_shutdown, shutdown_received) = oneshot::channel::<()>();
+        let socket_task = tokio::spawn(serve_socket(SocketState { networks: Arc::new(networks.clone()), recorder }, listener, async move {
+            let _ = shutdown_received.await;
+        }));
+
+        let client = Client::tracked(rocket(networks.clone())).await.expect("invalid rocket instance");
+        TestHarness { state, networks, client, socket_address, socket_shutdown: Some(socket_shutdown), socket_task: Some(socket_task) }
+    }
+
+    async fn start_network(token: &str, cache_store: Arc<dyn CacheStore>) -> MarsAPIState {
+        let config = Arc::new(MarsConfig {
+            token: token.to_owned(),
+            options: MarsConfigOptions { storage_backend: StorageBackend::Memory, ..Default::default() },
+            data: MarsConfigData::default(),
+            webhooks: WebhookUtils::new(&None, &None, &None)
+        });
+        let state = MarsAPIState::new(config, Database::new(Arc::new(MemoryDocumentStore::new())), RedisAdapter::new(cache_store));
+        indexes::ensure_indexes(&state.database).await;
+        state
+    }
+
+    pub async fn connect_plugin(&self, server_id: &str) -> FakePlugin {
+        FakePlugin::connect(self.socket_address, server_id, Self::TOKEN).await.expect("plugin could not connect")
+    }
+
+    pub async fn connect_plugin_with_codec(&self, server_id: &str, codec: PacketCodec) -> FakePlugin {
+        FakePlugin::connect_with_codec(self.socket_address, server_id, Self::TOKEN, codec).await.expect("plugin could not connect")
+    }
+
+    // what a SIGTERM does to the socket listener, returns once every connection is drained
+    pub async fn stop_socket(&mut self) {
+        if let Some(socket_shutdown) = self.socket_shutdown.take() {
+            let _ = socket_shutdown.send(());
+        };
+        if let Some(socket_task) = self.socket_task.take() {
+            socket_task.await.expect("socket listener panicked").expect("socket listener failed");
+        };
+    }
+
+    pub fn socket_address(&self) -> SocketAddr {
+        self.socket_address
+    }
+
+    pub async fn post<T: Serialize>(&self, uri: &str, body: &T) -> Status {
+        self.client.post(uri)
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", format!("API-Token {}", Self::TOKEN)))
+            .header(Header::new("Mars-Server-ID", "test"))
+            .body(rocket::serde::json::serde_json::to_string(body).expect("request body did not serialize"))
+            .dispatch().await
+            .status()
+    }
+
+    pub async fn post_json<T: DeserializeOwned + Send + 'static>(&self, uri: &str) -> (Status, Option<T>) {
+        let response = self.client.post(uri)
+            .header(Header::new("Authorization", format!("API-Token {}", Self::TOKEN)))
+            .header(Header::new("Mars-Server-ID", "test"))
+            .dispatch().await;
+        (response.status(), response.into_json::<T>().await)
+    }
+
+    pub async fn delete_json<T: DeserializeOwned + Send + 'static>(&self, uri: &str) -> (Status, Option<T>) {
+        let response = self.client.delete(uri)
+            .header(Header::new("Authorization", format!("API-Token {}", Self::TOKEN)))
+            .header(Header::new("Mars-Server-ID", "test"))
+            .dispatch().await;
+        (response.status(), response.into_json::<T>().await)
+    }
+
+    pub async fn get_json<T: DeserializeOwned + Send + 'static>(&self, uri: &str) -> T {
+        let response = self.client.get(uri)
+            .header(Header::new("Authorization", format!("API-Token {}", Self::TOKEN)))
+            .header(Header::new("Mars-Server-ID", "test"))
+            .dispatch().await;
+        assert_eq!(response.status(), Status::Ok, "GET {} failed", uri);
+        response.into_json::<T>().await.unwrap_or_else(|| panic!("GET {} returned an unexpected body", uri))
+    }
+}
+
+// socket events are handled off the test task, so poll until their effects land
+pub async fn wait_until<F, Fut>(description: &str, condition: F) where F: Fn() -> Fut, Fut: Future<Output = bool> {
+    for _ in 0..200 {
+        if condition().await {
+            return;
+        };
+        tokio::time::sleep(Duration::from_millis(25)).await;
+    }
+    panic!("timed out waiting until {}", description);
+}
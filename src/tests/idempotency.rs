@@ -0,0 +1,127 @@
+use rocket::{http::{ContentType, Header, Status}, serde::json::{json, Value}};
+
+use crate::database::{Database, models::player::Player};
+
+use super::{harness::TestHarness, match_lifecycle::{ALICE, BOB, setup_map_and_players}};
+
+async fn send(harness: &TestHarness, path: &str, body: Value, key: &str, token: &str) -> (Status, bool, Option<Value>) {
+    let response = harness.client.post(path.to_owned())
+        .header(ContentType::JSON)
+        .header(Header::new("Authorization", format!("API-Token {}", token)))
+        .header(Header::new("Mars-Server-ID", "test"))
+        .header(Header::new("Idempotency-Key", key.to_owned()))
+        .body(body.to_string())
+        .dispatch().await;
+    let replayed = response.headers().get_one("Idempotent-Replayed").is_some();
+    (response.status(), replayed, response.into_json::<Value>().await)
+}
+
+async fn add_note(harness: &TestHarness, player_id: &str, key: &str) -> (Status, bool, Option<Value>) {
+    let note = json!({ "author": { "id": BOB.0, "name": BOB.1 }, "content": "griefing" });
+    send(harness, &format!("/mc/players/{}/notes", player_id), note, key, TestHarness::TOKEN).await
+}
+
+async fn note_count(harness: &TestHarness) -> usize {
+    let alice : Player = Database::find_by_id(&harness.state.database.players, ALICE.0).await.unwrap();
+    alice.notes.len()
+}
+
+#[rocket::async_test]
+async fn retries_replay_the_first_response() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+
+    let (status, replayed, first) = add_note(&harness, ALICE.0, "note-1").await;
+    assert_eq!((status, replayed), (Status::Created, false));
+    let (status, replayed, retry) = add_note(&harness, ALICE.0, "note-1").await;
+    assert_eq!((status, replayed), (Status::Created, true));
+    assert_eq!(first, retry);
+    assert_eq!(note_count(&harness).await, 1);
+
+    // another key is another request
+    let (status, replayed, _) = add_note(&harness, ALICE.0, "note-2").await;
+    assert_eq!((status, replayed), (Status::Created, false));
+    assert_eq!(note_count(&harness).await, 2);
+}
+
+#[rocket::async_test]
+async fn keys_are_not_shared_between_requests() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+
+    add_note(&harness, ALICE.0, "note-1").await;
+    let (status, _, _) = add_note(&harness, BOB.0, "note-1").await;
+    assert_eq!(status, Status::UnprocessableEntity);
+
+    // a request still being handled holds its key
+    let pending = json!({ "request": format!("POST /mc/players/{}/notes", ALICE.0), "response": null }).to_string();
+    harness.state.redis.store.set("idempotency:test:note-2", &pending, None).await.unwrap();
+    let (status, _, _) = add_note(&harness, ALICE.0, "note-2").await;
+    assert_eq!(status, Status::Conflict);
+
+    let (status, _, _) = add_note(&harness, ALICE.0, &"k".repeat(256)).await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(note_count(&harness).await, 1);
+}
+
+#[rocket::async_test]
+async fn replays_need_the_token_and_the_same_body() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    add_note(&harness, ALICE.0, "note-1").await;
+    let path = format!("/mc/players/{}/notes", ALICE.0);
+
+    // knowing the server ID and key isn't enough
+    let note = json!({ "author": { "id": BOB.0, "name": BOB.1 }, "content": "griefing" });
+    let (status, replayed, _) = send(&harness, &path, note, "note-1", "not-the-token").await;
+    assert_eq!((status, replayed), (Status::Unauthorized, false));
+
+    let other_note = json!({ "author": { "id": BOB.0, "name": BOB.1 }, "content": "spamming" });
+    let (status, _, _) = send(&harness, &path, other_note, "note-1", TestHarness::TOKEN).await;
+    assert_eq!(status, Status::UnprocessableEntity);
+
+    assert_eq!(note_count(&harness).await, 1);
+}
+
+#[rocket::async_test]
+async fn long_bodies_are_compared_whole() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let path = format!("/mc/players/{}/punishments", ALICE.0);
+    let punishment = |last_ip: &str| json!({
+        "reason": { "name": "Griefing", "message": "Destroying or defacing builds that belong to other players", "short": "griefing" },
+        "offence": 2,
+        "action": { "kind": "BAN", "length": 604_800_000 },
+        "note": "Broke the spawn fountain twice after being warned in chat, then logged out and came back on an alt to finish it off. See the replay from the evening match on Mars, and the earlier warn from the same session",
+        "punisher": { "id": BOB.0, "name": BOB.1 },
+        "targetName": ALICE.1,
+        "targetIps": ["127.0.0.1", "10.0.0.12", "10.0.0.57", "192.168.1.20", "172.16.4.33", last_ip],
+        "silent": false
+    });
+    // the two bodies only differ past what a fairing can peek
+    assert!(punishment("203.0.113.78").to_string().find("203.0.113.78").unwrap() > 512);
+
+    let (status, replayed, first) = send(&harness, &path, punishment("203.0.113.78"), "ban-1", TestHarness::TOKEN).await;
+    assert_eq!((status, replayed), (Status::Created, false));
+    let (status, replayed, retry) = send(&harness, &path, punishment("203.0.113.78"), "ban-1", TestHarness::TOKEN).await;
+    assert_eq!((status, replayed), (Status::Created, true));
+    assert_eq!(first, retry);
+
+    let (status, _, _) = send(&harness, &path, punishment("203.0.113.79"), "ban-1", TestHarness::TOKEN).await;
+    assert_eq!(status, Status::UnprocessableEntity);
+    let issued = harness.state.database.punishments.count_documents(None).await.unwrap();
+    assert_eq!(issued, 1);
+}
+
+#[rocket::async_test]
+async fn conflicts_are_not_stored() {
+    let harness = TestHarness::start().await;
+    let rank = json!({ "name": "Admin", "priority": 1 });
+    let (status, _, _) = send(&harness, "/mc/ranks", rank.clone(), "rank-1", TestHarness::TOKEN).await;
+    assert_eq!(status, Status::Ok);
+    let (status, replayed, _) = send(&harness, "/mc/ranks", rank.clone(), "rank-2", TestHarness::TOKEN).await;
+    assert_eq!((status, replayed), (Status::Conflict, false));
+    // the retry runs the handler again rather than replaying the conflict
+    let (status, replayed, _) = send(&harness, "/mc/ranks", rank, "rank-2", TestHarness::TOKEN).await;
+    assert_eq!((status, replayed), (Status::Conflict, false));
+}
@@ -0,0 +1,97 @@
+use std::fs;
+
+use rocket::serde::json::json;
+use uuid::Uuid;
+
+use crate::{database::{Database, importer, models::{player::Player, punishment::Punishment, rank::Rank}}, socket::leaderboard::LeaderboardPeriod};
+
+use super::{harness::TestHarness, match_lifecycle::{ALICE, setup_map_and_players}};
+
+const CAROL: (&str, &str) = ("00000000-0000-0000-0000-00000000000c", "Carol");
+
+fn kotlin_player(id: &str, name: &str, kills: u32) -> String {
+    // as mongoexport writes it, with 64-bit numbers in extended JSON
+    json!({
+        "_id": id, "name": name, "nameLower": name.to_lowercase(), "lastSessionId": null,
+        "firstJoinedAt": { "$numberLong": "1600000000000" }, "lastJoinedAt": { "$numberLong": "1600000000000" },
+        "ips": [], "notes": [], "rankIds": ["admin"], "tagIds": [], "activeTagId": null,
+        "stats": { "kills": kills, "serverPlaytime": { "$numberLong": "3600000" } }, "gamemodeStats": {}, "activeJoinSoundId": null
+    }).to_string()
+}
+
+#[rocket::async_test]
+async fn kotlin_dumps_are_imported_without_overwriting() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let directory = std::env::temp_dir().join(format!("mars-import-{}", Uuid::new_v4()));
+    fs::create_dir_all(&directory).unwrap();
+    fs::write(directory.join("rank.json"), json!([{
+        "_id": "admin", "name": "Admin", "priority": 10, "permissions": ["*"], "staff": true, "applyOnJoin": false, "createdAt": 0.0
+    }]).to_string()).unwrap();
+    let players = [kotlin_player(CAROL.0, CAROL.1, 40), kotlin_player("impostor", ALICE.1, 99), String::from("{\"_id\": \"broken\"}")];
+    fs::write(directory.join("player.json"), players.join("\n")).unwrap();
+    fs::write(directory.join("punishment.json"), json!({
+        "_id": "pun", "reason": { "name": "Cheating", "message": "Cheating", "short": "cheat" }, "issuedAt": 0.0, "silent": false, "offence": 1,
+        "action": { "kind": "BAN", "length": -1 }, "target": { "id": CAROL.0, "name": CAROL.1 }, "targetIps": []
+    }).to_string()).unwrap();
+
+    let dry_run = importer::import(&harness.state.database, &harness.state.leaderboards, &directory, true).await.unwrap();
+    assert_eq!(dry_run.imported.values().sum::<u64>(), 3);
+    assert!(Database::find_by_id(&harness.state.database.players, CAROL.0).await.is_none());
+
+    let report = importer::import(&harness.state.database, &harness.state.leaderboards, &directory, false).await.unwrap();
+    assert_eq!((report.imported["rank"], report.imported["player"], report.imported["punishment"]), (1, 1, 1));
+    assert_eq!(report.conflicts.iter().map(|conflict| conflict.id.clone().unwrap()).collect::<Vec<_>>(), vec![String::from("impostor")]);
+    assert_eq!(report.invalid.len(), 1);
+
+    let carol : Player = Database::find_by_id(&harness.state.database.players, CAROL.0).await.unwrap();
+    assert_eq!((carol.stats.kills, carol.stats.server_playtime), (40, 3_600_000));
+    let rank : Rank = Database::find_by_id(&harness.state.database.ranks, "admin").await.unwrap();
+    assert_eq!(rank.name_lower, "admin");
+    assert!(Database::find_by_id::<Punishment>(&harness.state.database.punishments, "pun").await.is_some());
+    let top = harness.state.leaderboards.kills.fetch_top(&LeaderboardPeriod::AllTime, 1).await;
+    assert_eq!((top[0].id.as_str(), top[0].score), (CAROL.0, 40));
+
+    // everything is in already
+    let again = importer::import(&harness.state.database, &harness.state.leaderboards, &directory, false).await.unwrap();
+    assert!(again.imported.is_empty());
+    assert_eq!(again.conflicts.len(), 4);
+    let _ = fs::remove_dir_all(directory);
+}
+
+#[rocket::async_test]
+async fn stat_csvs_create_players() {
+    let harness = TestHarness::start().await;
+    let path = std::env::temp_dir().join(format!("mars-import-{}.csv", Uuid::new_v4()));
+    fs::write(&path, format!("uuid,Username,Kills,first_bloods,Deaths,elo\n{},{},12,3,4,1500\nmissing,,1,1,1,1\n", CAROL.0, CAROL.1)).unwrap();
+
+    let report = importer::import(&harness.state.database, &harness.state.leaderboards, &path, false).await.unwrap();
+    assert_eq!(report.imported["player"], 1);
+    assert_eq!(report.ignored_columns, vec![String::from("elo")]);
+    assert_eq!(report.invalid.len(), 1);
+    let carol : Player = Database::find_by_id(&harness.state.database.players, CAROL.0).await.unwrap();
+    assert_eq!((carol.name_lower.as_str(), carol.stats.kills, carol.stats.first_bloods, carol.stats.deaths), ("carol", 12, 3, 4));
+    let _ = fs::remove_file(path);
+}
+
+#[rocket::async_test]
+async fn bad_rows_and_repeats_are_left_out_of_dry_runs_too() {
+    let harness = TestHarness::start().await;
+    let path = std::env::temp_dir().join(format!("mars-import-{}.csv", Uuid::new_v4()));
+    let rows = [
+        format!("{},{},12", CAROL.0, CAROL.1),
+        format!("{},Caroline,5", CAROL.0),
+        String::from("someone-else,CAROL,7"),
+        String::from("dave,Dave,lots")
+    ];
+    fs::write(&path, format!("id,name,kills\n{}\n", rows.join("\n"))).unwrap();
+
+    for dry_run in [true, false] {
+        let report = importer::import(&harness.state.database, &harness.state.leaderboards, &path, dry_run).await.unwrap();
+        assert_eq!(report.imported["player"], 1);
+        assert_eq!(report.conflicts.iter().map(|conflict| conflict.id.clone().unwrap()).collect::<Vec<_>>(), vec![CAROL.0.to_owned(), String::from("someone-else")]);
+        assert_eq!(report.invalid.iter().map(|invalid| invalid.id.clone().unwrap()).collect::<Vec<_>>(), vec![String::from("dave")]);
+    }
+    assert!(Database::find_by_id::<Player>(&harness.state.database.players, "dave").await.is_none());
+    let _ = fs::remove_file(path);
+}
@@ -0,0 +1,49 @@
+use mongodb::bson::doc;
+use rocket::{http::Status, serde::json::json};
+
+use crate::database::{Database, indexes, models::player::Player, store::IndexDefinition};
+
+use super::{harness::TestHarness, match_lifecycle::{ALICE, BOB, setup_map_and_players}};
+
+#[rocket::async_test]
+async fn declared_indexes_are_ensured_once() {
+    let harness = TestHarness::start().await;
+    let database = &harness.state.database;
+    let names : Vec<String> = database.store.list_indexes("player").await.unwrap().into_iter().map(|(name, _)| name).collect();
+    assert!(names.contains(&String::from("nameLower_1")));
+
+    database.store.create_index("player", &IndexDefinition::new(doc! { "legacyField": 1 })).await.unwrap();
+    let report = indexes::ensure_indexes(database).await;
+    assert!(report.created.is_empty());
+    assert!(report.failed.is_empty());
+    assert_eq!(report.extra, vec![String::from("player.legacyField_1")]);
+}
+
+#[rocket::async_test]
+async fn player_names_stay_unique() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let mut impostor : Player = Database::find_by_id(&harness.state.database.players, BOB.0).await.unwrap();
+    impostor.id = String::from("impostor");
+    impostor.name_lower = ALICE.1.to_lowercase();
+    assert!(harness.state.database.players.insert_one(&impostor).await.is_err());
+
+    // the holder's own login keeps the name
+    let displaced = harness.state.database.ensure_player_name_uniqueness(&ALICE.1.to_owned(), &ALICE.0.to_owned()).await.unwrap();
+    assert!(displaced.is_empty());
+    assert!(harness.state.player_cache.query(ALICE.1).await.is_some());
+
+    // bob logging in under alice's name takes it over, alice is given a placeholder
+    let status = harness.post(&format!("/mc/players/{}/prelogin", BOB.0), &json!({ "player": { "id": BOB.0, "name": ALICE.1 }, "ip": "127.0.0.1" })).await;
+    assert_eq!(status, Status::Ok);
+    let bob : Player = Database::find_by_id(&harness.state.database.players, BOB.0).await.unwrap();
+    let alice : Player = Database::find_by_id(&harness.state.database.players, ALICE.0).await.unwrap();
+    assert_eq!(bob.name, ALICE.1);
+    assert!(alice.name_lower.starts_with('>') && alice.name_lower.len() == 33);
+
+    // alice's cached copy no longer claims the name, so persisting it can't bring the name back
+    assert!(harness.state.player_cache.query(ALICE.0).await.is_none());
+    let cached_by_name = harness.state.player_cache.query(ALICE.1).await.unwrap();
+    assert_eq!(cached_by_name.id, BOB.0);
+    assert!(!harness.state.player_cache.persist_cached_value(&harness.state.database, ALICE.0).await.unwrap());
+}
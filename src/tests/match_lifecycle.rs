@@ -0,0 +1,150 @@
+use rocket::{http::Status, serde::json::{json, Value}};
+
+use crate::{database::{Database, models::{player::Player, r#match::{Match, MatchState}}}, socket::{event_type::EventType, leaderboard::LeaderboardEntry}};
+
+use super::{fake_plugin::FakePlugin, harness::{TestHarness, wait_until}};
+
+pub const SERVER_ID: &str = "lifecycle";
+pub const MAP_ID: &str = "map-1";
+pub const ALICE: (&str, &str) = ("00000000-0000-0000-0000-00000000000a", "Alice");
+pub const BOB: (&str, &str) = ("00000000-0000-0000-0000-00000000000b", "Bob");
+
+fn simple_player(player: (&str, &str)) -> Value {
+    json!({ "id": player.0, "name": player.1 })
+}
+
+pub async fn current_match(harness: &TestHarness) -> Option<Match> {
+    let match_id : String = harness.state.redis.get(&format!("server:{}:current_match_id", SERVER_ID)).await.ok()?;
+    harness.state.match_cache.query(&match_id).await
+}
+
+pub async fn setup_map_and_players(harness: &TestHarness) {
+    let maps = json!([{
+        "_id": MAP_ID,
+        "name": "Lifecycle Valley",
+        "version": "1.0.0",
+        "gamemodes": ["CAPTURE_THE_WOOL"],
+        "authors": [],
+        "contributors": []
+    }]);
+    assert_eq!(harness.post("/mc/maps", &maps).await, Status::Ok);
+    for player in [ALICE, BOB] {
+        let status = harness.post(&format!("/mc/players/{}/prelogin", player.0), &json!({ "player": simple_player(player), "ip": "127.0.0.1" })).await;
+        assert_eq!(status, Status::Created);
+    }
+}
+
+async fn kill(plugin: &mut FakePlugin, attacker: (&str, &str), victim: (&str, &str)) {
+    plugin.send(EventType::PlayerDeath, json!({
+        "victim": simple_player(victim),
+        "attacker": simple_player(attacker),
+        "weapon": "IRON_SWORD",
+        "entity": null,
+        "distance": null,
+        "key": "generic",
+        "cause": "MELEE"
+    })).await;
+}
+
+// load, start, two kills, a wool capture and a win for Red, returns the match ID once it's persisted
+// loads and starts a match with alice on red and bob on blue
+pub async fn start_match(harness: &TestHarness, plugin: &mut FakePlugin) {
+    plugin.send(EventType::MatchLoad, json!({
+        "mapId": MAP_ID,
+        "parties": [
+            { "name": "Red", "alias": "Red", "color": "RED", "min": 1, "max": 8 },
+            { "name": "Blue", "alias": "Blue", "color": "BLUE", "min": 1, "max": 8 }
+        ],
+        "goals": {
+            "cores": [],
+            "destroyables": [],
+            "flags": [],
+            "wools": [{ "id": "wool-blue", "name": "Blue Wool", "ownerName": "Red", "color": "BLUE" }],
+            "controlPoints": []
+        }
+    })).await;
+    wait_until("the match is loaded", || async {
+        current_match(harness).await.is_some_and(|loaded| loaded.get_state() == MatchState::Pre)
+    }).await;
+
+    plugin.send(EventType::MatchStart, json!({
+        "participants": [
+            { "id": ALICE.0, "name": ALICE.1, "partyName": "Red" },
+            { "id": BOB.0, "name": BOB.1, "partyName": "Blue" }
+        ]
+    })).await;
+    wait_until("the match is in progress", || async {
+        current_match(harness).await.is_some_and(|started| started.get_state() == MatchState::InProgress)
+    }).await;
+}
+
+pub async fn play_match(harness: &TestHarness, plugin: &mut FakePlugin) -> String {
+    start_match(harness, plugin).await;
+    kill(plugin, ALICE, BOB).await;
+    let update = plugin.expect_event(EventType::PlayerUpdate).await;
+    assert_eq!(update["reason"], "KILL");
+    assert_eq!(update["updated"]["_id"], ALICE.0);
+    kill(plugin, ALICE, BOB).await;
+    wait_until("both deaths are recorded", || async {
+        current_match(harness).await.is_some_and(|ongoing| ongoing.participants.get(BOB.0).is_some_and(|bob| bob.stats.deaths == 2))
+    }).await;
+
+    plugin.send(EventType::WoolPickup, json!({ "woolId": "wool-blue", "playerId": ALICE.0 })).await;
+    plugin.send(EventType::WoolCapture, json!({ "woolId": "wool-blue", "playerId": ALICE.0, "heldTime": 4000 })).await;
+    wait_until("the wool is captured", || async {
+        current_match(harness).await.is_some_and(|ongoing| ongoing.participants.get(ALICE.0).is_some_and(|alice| alice.stats.objectives.wool_captures == 1))
+    }).await;
+
+    plugin.send(EventType::MatchEnd, json!({ "winningParties": ["Red"], "bigStats": {} })).await;
+    let match_id = current_match(harness).await.expect("match should still be current").id;
+    wait_until("the match is persisted", || async {
+        Database::find_by_id(&harness.state.database.matches, &match_id).await.is_some_and(|ended| ended.ended_at.is_some())
+    }).await;
+    harness.state.database.write_behind.flush().await;
+    match_id
+}
+
+#[rocket::async_test]
+async fn full_match_updates_players_leaderboards_and_match() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let mut plugin = harness.connect_plugin(SERVER_ID).await;
+    let match_id = play_match(&harness, &mut plugin).await;
+
+    // match document
+    let finished : Match = harness.get_json(&format!("/mc/matches/{}", match_id)).await;
+    assert!(finished.get_state() == MatchState::Post);
+    assert_eq!(finished.level.id, MAP_ID);
+    assert_eq!(finished.first_blood.as_ref().map(|first_blood| first_blood.attacker.id.as_str()), Some(ALICE.0));
+    assert_eq!(finished.participants[ALICE.0].stats.kills, 2);
+    assert_eq!(finished.participants[BOB.0].stats.deaths, 2);
+
+    // player documents
+    let alice : Player = Database::find_by_id(&harness.state.database.players, ALICE.0).await.expect("alice should be saved");
+    assert_eq!(alice.stats.kills, 2);
+    assert_eq!(alice.stats.first_bloods, 1);
+    assert_eq!(alice.stats.objectives.wool_captures, 1);
+    assert_eq!(alice.stats.wins, 1);
+    assert_eq!(alice.stats.matches, 1);
+    assert!(alice.stats.xp > 0);
+    let bob : Player = Database::find_by_id(&harness.state.database.players, BOB.0).await.expect("bob should be saved");
+    assert_eq!(bob.stats.deaths, 2);
+    assert_eq!(bob.stats.first_bloods_suffered, 1);
+    assert_eq!(bob.stats.losses, 1);
+
+    // leaderboards
+    let kills : Vec<LeaderboardEntry> = harness.get_json("/mc/leaderboards/kills/all_time").await;
+    assert_eq!(kills.len(), 1);
+    assert_eq!((kills[0].id.as_str(), kills[0].name.as_str(), kills[0].score), (ALICE.0, ALICE.1, 2));
+    let wins : Vec<LeaderboardEntry> = harness.get_json("/mc/leaderboards/wins/daily").await;
+    assert_eq!(wins.iter().map(|entry| entry.id.as_str()).collect::<Vec<_>>(), vec![ALICE.0]);
+    let wool_captures : Vec<LeaderboardEntry> = harness.get_json("/mc/leaderboards/wool_captures/all_time").await;
+    assert_eq!(wool_captures.first().map(|entry| entry.score), Some(1));
+}
+
+#[rocket::async_test]
+async fn socket_rejects_wrong_token() {
+    let harness = TestHarness::start().await;
+    assert!(FakePlugin::connect(harness.socket_address(), SERVER_ID, "not-the-token").await.is_err());
+    assert!(!harness.state.servers.is_connected(SERVER_ID).await);
+}
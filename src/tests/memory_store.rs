@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use mongodb::{bson::doc, options::FindOptions};
+
+use crate::database::store::{CacheStore, DocumentStore, IndexDefinition, memory::{MemoryCacheStore, MemoryDocumentStore}};
+
+#[rocket::async_test]
+async fn filters_mongo_rejects_fail() {
+    let store = MemoryDocumentStore::new();
+    store.insert_one("player", doc! { "_id": "a", "nameLower": "alice" }).await.unwrap();
+
+    // what freeing a player's name used to send
+    let top_level_not = doc! { "$and": [{ "nameLower": "alice" }, { "$not": { "_id": "b" } }] };
+    let error = store.update_many("player", top_level_not.clone(), doc! { "$set": { "name": ">" } }, false).await.unwrap_err();
+    assert!(error.to_string().contains("unknown top level operator: $not"));
+    assert!(store.find("player", top_level_not, FindOptions::default()).await.is_err());
+    assert!(store.count_documents("player", doc! { "_id": { "$in": "a" } }).await.is_err());
+    assert!(store.delete_many("player", doc! { "_id": { "$nope": 1 } }).await.is_err());
+
+    let result = store.update_many("player", doc! { "nameLower": "alice", "_id": { "$ne": "b" } }, doc! { "$set": { "name": ">" } }, false).await.unwrap();
+    assert_eq!(result.modified_count, 1);
+}
+
+#[rocket::async_test]
+async fn ids_and_unique_values_are_indexed() {
+    let store = MemoryDocumentStore::new();
+    store.create_index("player", &IndexDefinition::new(doc! { "nameLower": 1 }).unique()).await.unwrap();
+    store.insert_one("player", doc! { "_id": "a", "nameLower": "alice" }).await.unwrap();
+    store.insert_one("player", doc! { "_id": "b", "nameLower": "bob" }).await.unwrap();
+    assert!(store.insert_one("player", doc! { "_id": "a", "nameLower": "carol" }).await.is_err());
+    assert!(store.insert_one("player", doc! { "_id": "c", "nameLower": "bob" }).await.is_err());
+    assert!(store.update_one("player", doc! { "_id": "a" }, doc! { "$set": { "nameLower": "bob" } }, false).await.is_err());
+    assert!(store.update_one("player", doc! { "_id": "a" }, doc! { "$set": { "_id": "z" } }, false).await.is_err());
+
+    // a freed value and a deleted _id can be taken again
+    store.update_one("player", doc! { "_id": "b" }, doc! { "$set": { "nameLower": "robert" } }, false).await.unwrap();
+    store.delete_one("player", doc! { "_id": "a" }).await.unwrap();
+    store.insert_one("player", doc! { "_id": "a", "nameLower": "bob" }).await.unwrap();
+    let found = store.find_one("player", doc! { "_id": "b" }, Default::default()).await.unwrap().unwrap();
+    assert_eq!(found.get_str("nameLower").unwrap(), "robert");
+    assert_eq!(store.count_documents("player", doc! {}).await.unwrap(), 2);
+}
+
+#[rocket::async_test]
+async fn cache_keys_expire_when_touched() {
+    let store = MemoryCacheStore::new();
+    store.set("lb:short", "1", Some(10)).await.unwrap();
+    store.set("lb:long", "1", None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(store.get("lb:short").await.unwrap(), None);
+    assert_eq!(store.keys_with_prefix("lb:").await.unwrap(), vec![String::from("lb:long")]);
+    assert!(store.set_if_absent("lb:short", "2", 1000).await.unwrap());
+}
+
+#[rocket::async_test]
+async fn hashes_are_replaced_whole_and_expire() {
+    let store = MemoryCacheStore::new();
+    store.hset("stats", "kills", "5").await.unwrap();
+    store.hset("stats", "deaths", "2").await.unwrap();
+
+    store.replace_hash("stats", &[(String::from("kills"), String::from("7"))], 50).await.unwrap();
+    assert_eq!(store.hgetall("stats").await.unwrap().into_iter().collect::<Vec<_>>(), vec![(String::from("kills"), String::from("7"))]);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(store.hgetall("stats").await.unwrap().is_empty());
+}
+
+#[rocket::async_test]
+async fn only_better_records_replace_the_stored_one() {
+    let store = MemoryCacheStore::new();
+    assert!(store.hset_if_better("stats", "kills", r#"{"value":5}"#, "value", true).await.unwrap());
+    assert!(!store.hset_if_better("stats", "kills", r#"{"value":3}"#, "value", true).await.unwrap());
+    assert!(!store.hset_if_better("stats", "kills", r#"{"value":5}"#, "value", true).await.unwrap());
+    assert!(store.hset_if_better("stats", "kills", r#"{"value":8}"#, "value", true).await.unwrap());
+
+    assert!(store.hset_if_better("stats", "fastest", r#"{"value":900}"#, "value", false).await.unwrap());
+    assert!(!store.hset_if_better("stats", "fastest", r#"{"value":1200}"#, "value", false).await.unwrap());
+    assert_eq!(store.hget("stats", "kills").await.unwrap(), Some(String::from(r#"{"value":8}"#)));
+    assert_eq!(store.hget("stats", "fastest").await.unwrap(), Some(String::from(r#"{"value":900}"#)));
+}
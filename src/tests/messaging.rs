@@ -0,0 +1,49 @@
+use rocket::{http::Status, serde::json::json};
+
+use crate::{database::models::player::SimplePlayer, socket::event_type::EventType};
+
+use super::{harness::TestHarness, match_lifecycle::{ALICE, BOB, setup_map_and_players}};
+
+#[rocket::async_test]
+async fn staff_chat_reaches_the_other_servers() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let mut lobby = harness.connect_plugin("lobby").await;
+    let mut arena = harness.connect_plugin("arena").await;
+
+    lobby.send(EventType::PlayerChat, json!({
+        "player": { "id": ALICE.0, "name": ALICE.1 },
+        "playerPrefix": "[Mod]",
+        "channel": "STAFF",
+        "message": "anyone around?",
+        "serverId": "lobby"
+    })).await;
+
+    let relayed = arena.expect_event(EventType::PlayerChat).await;
+    assert_eq!((relayed["message"].as_str(), relayed["serverId"].as_str()), (Some("anyone around?"), Some("lobby")));
+    assert_eq!(relayed["player"]["id"].as_str(), Some(ALICE.0));
+}
+
+#[rocket::async_test]
+async fn private_messages_reach_the_server_the_player_is_on() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let mut arena = harness.connect_plugin("arena").await;
+    let alice = SimplePlayer { id: ALICE.0.to_owned(), name: ALICE.1.to_owned() };
+    harness.state.presence.set_online(&alice, "arena", "session-1").await;
+
+    let status = harness.post(&format!("/mc/players/{}/message", ALICE.1), &json!({ "message": "hi alice" })).await;
+    assert_eq!(status, Status::Ok);
+    let message = arena.expect_event(EventType::Message).await;
+    assert_eq!((message["message"].as_str(), message["playerIds"].clone()), (Some("hi alice"), json!([ALICE.0])));
+}
+
+#[rocket::async_test]
+async fn private_messages_to_offline_players_are_not_found() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let _arena = harness.connect_plugin("arena").await;
+
+    let status = harness.post(&format!("/mc/players/{}/message", BOB.0), &json!({ "message": "hi bob" })).await;
+    assert_eq!(status, Status::NotFound);
+}
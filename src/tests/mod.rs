@@ -0,0 +1,27 @@
+mod harness;
+mod fake_plugin;
+mod match_lifecycle;
+mod recording;
+mod codec;
+mod dead_letter;
+mod stat_delta;
+mod concurrency;
+mod write_behind;
+mod indexes;
+mod retention;
+mod analytics_export;
+mod backup;
+mod importer;
+mod redis_options;
+mod networks;
+mod shutdown;
+mod player_cache;
+mod cache_admin;
+mod idempotency;
+mod chat_search;
+mod chat_filter;
+mod permessage_deflate;
+mod memory_store;
+mod presence;
+mod messaging;
+mod server_registry;
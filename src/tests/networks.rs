@@ -0,0 +1,56 @@
+use rocket::{http::{ContentType, Header, Status}, serde::json::json};
+
+use crate::database::{Database, models::player::Player};
+
+use super::{fake_plugin::FakePlugin, harness::{TestHarness, wait_until}, match_lifecycle::ALICE};
+
+const SECOND_TOKEN: &str = "second-token";
+
+async fn prelogin(harness: &TestHarness, token: &str) -> Status {
+    harness.client.post(format!("/mc/players/{}/prelogin", ALICE.0))
+        .header(ContentType::JSON)
+        .header(Header::new("Authorization", format!("API-Token {}", token)))
+        .header(Header::new("Mars-Server-ID", "test"))
+        .body(json!({ "player": { "id": ALICE.0, "name": ALICE.1 }, "ip": "127.0.0.1" }).to_string())
+        .dispatch().await
+        .status()
+}
+
+async fn get_status(harness: &TestHarness, uri: &str, network: Option<&str>) -> Status {
+    let mut request = harness.client.get(uri.to_owned());
+    if let Some(network) = network {
+        request = request.header(Header::new("Mars-Network", network.to_owned()));
+    };
+    request.dispatch().await.status()
+}
+
+#[rocket::async_test]
+async fn servers_only_see_their_own_network() {
+    let harness = TestHarness::start_with_networks(None, &[("second", SECOND_TOKEN)]).await;
+    let second = harness.networks.get("second").expect("second network is missing");
+
+    // the token picks the network, the player only exists there
+    assert_eq!(prelogin(&harness, SECOND_TOKEN).await, Status::Created);
+    assert!(Database::find_by_id(&second.database.players, ALICE.0).await.is_some());
+    assert!(Database::find_by_id::<Player>(&harness.state.database.players, ALICE.0).await.is_none());
+
+    // public routes are told the network by header
+    let profile = format!("/mc/players/{}", ALICE.1);
+    assert_eq!(get_status(&harness, &profile, Some("second")).await, Status::Ok);
+    assert_eq!(get_status(&harness, &profile, None).await, Status::NotFound);
+    assert_eq!(get_status(&harness, "/mc/players/online", Some("third")).await, Status::NotFound);
+    assert_eq!(prelogin(&harness, "unknown-token").await, Status::Unauthorized);
+
+    // both share one cache store, the second network's keys sit behind its prefix
+    assert!(!second.redis.store.keys_with_prefix("player").await.unwrap().is_empty());
+    assert!(harness.state.redis.store.keys_with_prefix("player").await.unwrap().is_empty());
+    assert!(!harness.state.redis.store.keys_with_prefix("network:second:player").await.unwrap().is_empty());
+
+    // the same player can join the default network on its own
+    assert_eq!(prelogin(&harness, TestHarness::TOKEN).await, Status::Created);
+    assert!(Database::find_by_id::<Player>(&harness.state.database.players, ALICE.0).await.is_some());
+
+    let _plugin = FakePlugin::connect(harness.socket_address(), "lobby", SECOND_TOKEN).await.expect("plugin could not connect");
+    wait_until("the server is registered on the second network", || async { second.servers.is_connected("lobby").await }).await;
+    assert!(!harness.state.servers.is_connected("lobby").await);
+}
@@ -0,0 +1,94 @@
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use rocket::serde::json::json;
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream};
+
+use crate::{database::models::chat_message::ChatMessage, socket::{codec::{PacketCodec, PayloadCompression, PayloadEncoding}, event_type::EventType, permessage_deflate::negotiate, server::server_context::Packet}};
+
+use super::{harness::{TestHarness, wait_until}, match_lifecycle::{ALICE, SERVER_ID}};
+
+const CODEC: PacketCodec = PacketCodec { encoding: PayloadEncoding::Json, compression: PayloadCompression::None };
+const TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+async fn read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await.unwrap();
+    let len = match header[1] & 0x7f {
+        126 => stream.read_u16().await.unwrap() as usize,
+        127 => stream.read_u64().await.unwrap() as usize,
+        len => len as usize
+    };
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload).await.unwrap();
+    (header[0], payload)
+}
+
+#[test]
+fn offers_are_accepted_only_when_they_can_be_honoured() {
+    let (params, response) = negotiate(["permessage-deflate; client_max_window_bits, permessage-deflate"].into_iter()).unwrap();
+    assert!(!params.server_no_context_takeover && !params.client_no_context_takeover);
+    assert_eq!(response, "permessage-deflate");
+
+    let (params, response) = negotiate(["permessage-deflate; server_max_window_bits=10, permessage-deflate; server_no_context_takeover"].into_iter()).unwrap();
+    assert!(params.server_no_context_takeover);
+    assert_eq!(response, "permessage-deflate; server_no_context_takeover");
+
+    assert!(negotiate(["x-webkit-deflate-frame"].into_iter()).is_none());
+    assert!(negotiate(["permessage-deflate; unknown_param"].into_iter()).is_none());
+}
+
+#[rocket::async_test]
+async fn messages_are_compressed_both_ways_once_negotiated() {
+    let harness = TestHarness::start().await;
+    let mut stream = TcpStream::connect(harness.socket_address()).await.unwrap();
+    stream.write_all(format!(
+        "GET /minecraft?id={}&token={}&compression=none HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+        Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Extensions: permessage-deflate; client_max_window_bits\r\n\r\n",
+        SERVER_ID, TestHarness::TOKEN
+    ).as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        response.push(stream.read_u8().await.unwrap());
+    }
+    let response = String::from_utf8(response).unwrap().to_lowercase();
+    assert!(response.starts_with("http/1.1 101"), "{}", response);
+    assert!(response.contains("sec-websocket-extensions: permessage-deflate\r\n"), "{}", response);
+
+    // a compressed, masked chat packet with RSV1 set
+    let packet = Packet::new(&EventType::PlayerChat, json!({
+        "player": { "id": ALICE.0, "name": ALICE.1 },
+        "playerPrefix": "",
+        "channel": "GLOBAL",
+        "message": "hello over deflate",
+        "serverId": SERVER_ID
+    })).unwrap();
+    let encoded = CODEC.encode(&packet).unwrap().into_data();
+    let mut compressed = Vec::with_capacity(encoded.len() + 64);
+    Compress::new(Compression::default(), false).compress_vec(&encoded, &mut compressed, FlushCompress::Sync).unwrap();
+    compressed.truncate(compressed.len() - TAIL.len());
+    let mask = [0x12, 0x34, 0x56, 0x78];
+    let mut frame = vec![0xc2, 0x80 | 126];
+    frame.extend_from_slice(&(compressed.len() as u16).to_be_bytes());
+    frame.extend_from_slice(&mask);
+    frame.extend(compressed.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+    stream.write_all(&frame).await.unwrap();
+    wait_until("the chat message is stored", || async {
+        harness.state.database.get_all_documents::<ChatMessage>().await.iter().any(|message| message.message == "hello over deflate")
+    }).await;
+
+    // and whatever the API sends back arrives compressed
+    harness.state.servers.call(SERVER_ID, &EventType::PlayerChat, json!({ "message": "hello back" })).await;
+    // the API may have queued other packets on connect, every one of them is compressed
+    let mut inflater = Decompress::new(false);
+    let packet = loop {
+        let (first, mut payload) = read_frame(&mut stream).await;
+        assert_eq!(first, 0xc2, "expected a final binary frame with RSV1 set");
+        payload.extend_from_slice(&TAIL);
+        let mut inflated = Vec::with_capacity(4096);
+        inflater.decompress_vec(&payload, &mut inflated, FlushDecompress::Sync).unwrap();
+        let packet = CODEC.decode(&inflated).unwrap();
+        if packet.event.to_string() == EventType::PlayerChat.to_string() {
+            break packet;
+        };
+    };
+    assert_eq!(packet.data["message"], "hello back");
+}
@@ -0,0 +1,75 @@
+use rocket::serde::json::Value;
+
+use crate::database::models::player::Player;
+
+use super::{harness::TestHarness, match_lifecycle::{ALICE, BOB, setup_map_and_players}};
+
+async fn rename(harness: &TestHarness, player: &mut Player, name: &str) {
+    player.name = name.to_owned();
+    player.name_lower = name.to_lowercase();
+    harness.state.player_cache.set(&harness.state.database, &player.name.clone(), player, false).await.unwrap();
+}
+
+#[rocket::async_test]
+async fn players_are_found_by_id_and_by_name() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let cache = &harness.state.player_cache;
+
+    let by_id = cache.query(ALICE.0).await.expect("alice should be cached by id");
+    let by_name = cache.query(&ALICE.1.to_uppercase()).await.expect("alice should be cached by name");
+    assert_eq!((by_id.id, by_name.id), (ALICE.0.to_owned(), ALICE.0.to_owned()));
+
+    // one copy, under the id, with the name pointing at it
+    let store = &harness.state.redis.store;
+    assert!(store.get(&format!("player:{}", ALICE.0)).await.unwrap().is_some());
+    assert!(store.get(&format!("player:{}", ALICE.1.to_lowercase())).await.unwrap().is_none());
+    assert_eq!(store.get(&format!("player_name:{}", ALICE.1.to_lowercase())).await.unwrap(), Some(ALICE.0.to_owned()));
+}
+
+#[rocket::async_test]
+async fn renames_drop_the_old_name() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let cache = &harness.state.player_cache;
+
+    let mut alice = cache.get(&harness.state.database, ALICE.0).await.unwrap();
+    rename(&harness, &mut alice, "Alicia").await;
+
+    assert!(cache.query(ALICE.1).await.is_none());
+    assert_eq!(cache.query("alicia").await.map(|player| player.id), Some(ALICE.0.to_owned()));
+    assert_eq!(cache.query(ALICE.0).await.map(|player| player.name), Some(String::from("Alicia")));
+}
+
+#[rocket::async_test]
+async fn taking_over_a_name_evicts_its_previous_holder() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let cache = &harness.state.player_cache;
+
+    // alice's cached copy still claims the name, so it can't be trusted anymore
+    let mut bob = cache.get(&harness.state.database, BOB.0).await.unwrap();
+    rename(&harness, &mut bob, ALICE.1).await;
+
+    assert!(cache.query(ALICE.0).await.is_none());
+    assert_eq!(cache.query(ALICE.1).await.map(|player| player.id), Some(BOB.0.to_owned()));
+    assert!(cache.query(BOB.1).await.is_none());
+}
+
+#[rocket::async_test]
+async fn lookups_are_counted() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let metrics = &harness.state.player_cache.cache.hit_metrics;
+    let before = harness.get_json::<Value>("/status/cache").await;
+
+    harness.state.player_cache.query(ALICE.1).await.unwrap();
+    harness.state.player_cache.query(ALICE.0).await.unwrap();
+    assert!(harness.state.player_cache.query("nobody").await.is_none());
+
+    let after = harness.get_json::<Value>("/status/cache").await;
+    let count = |response: &Value, field: &str| response["players"][field].as_u64().unwrap();
+    assert_eq!(count(&after, "hits") - count(&before, "hits"), 2);
+    assert_eq!(count(&after, "misses") - count(&before, "misses"), 1);
+    assert!(metrics.get_hit_rate() > 0.0);
+}
@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+use crate::database::{models::player::SimplePlayer, presence::PlayerPresence};
+
+use super::{harness::TestHarness, match_lifecycle::{ALICE, BOB}};
+
+fn player(player: (&str, &str)) -> SimplePlayer {
+    SimplePlayer { id: player.0.to_owned(), name: player.1.to_owned() }
+}
+
+fn player_ids(presences: Vec<PlayerPresence>) -> Vec<String> {
+    presences.into_iter().map(|presence| presence.player.id).collect()
+}
+
+#[rocket::async_test]
+async fn logins_and_logouts_are_tracked_per_server() {
+    let harness = TestHarness::start().await;
+    let presence = &harness.state.presence;
+
+    presence.set_online(&player(ALICE), "lobby", "session-1").await;
+    presence.set_online(&player(BOB), "arena", "session-2").await;
+    assert_eq!(player_ids(presence.get_server_players("LOBBY").await), vec![ALICE.0.to_owned()]);
+    assert_eq!(presence.get_all().await.len(), 2);
+
+    presence.set_offline(ALICE.0, "session-1").await;
+    assert!(presence.get(ALICE.0).await.is_none());
+    assert!(presence.get_server_players("lobby").await.is_empty());
+    assert_eq!(presence.get(BOB.0).await.map(|presence| presence.server_id), Some(String::from("arena")));
+}
+
+#[rocket::async_test]
+async fn switching_servers_leaves_only_the_new_one() {
+    let harness = TestHarness::start().await;
+    let presence = &harness.state.presence;
+
+    presence.set_online(&player(ALICE), "lobby", "session-1").await;
+    presence.set_party(ALICE.0, Some(String::from("red"))).await;
+    presence.set_online(&player(ALICE), "arena", "session-2").await;
+
+    assert!(presence.get_server_players("lobby").await.is_empty());
+    assert_eq!(player_ids(presence.get_server_players("arena").await), vec![ALICE.0.to_owned()]);
+    // the party belonged to the old session
+    assert_eq!(presence.get(ALICE.0).await.and_then(|presence| presence.party_name), None);
+
+    // the old server going away doesn't take the player with it
+    presence.clear_server("lobby").await;
+    assert_eq!(presence.get(ALICE.0).await.map(|presence| presence.session_id), Some(String::from("session-2")));
+}
+
+#[rocket::async_test]
+async fn a_late_logout_keeps_the_newer_session() {
+    let harness = TestHarness::start().await;
+    let presence = &harness.state.presence;
+
+    presence.set_online(&player(ALICE), "lobby", "session-1").await;
+    presence.set_online(&player(ALICE), "arena", "session-2").await;
+    presence.set_party(ALICE.0, Some(String::from("blue"))).await;
+    // the logout of the first session arrives after the second login
+    presence.set_offline(ALICE.0, "session-1").await;
+
+    let current = presence.get(ALICE.0).await.expect("the newer session should still be online");
+    assert_eq!((current.server_id.as_str(), current.session_id.as_str()), ("arena", "session-2"));
+    assert_eq!(current.party_name, Some(String::from("blue")));
+
+    presence.set_offline(ALICE.0, "session-2").await;
+    assert!(presence.get(ALICE.0).await.is_none());
+}
+
+#[rocket::async_test]
+async fn presences_expire_unless_their_server_is_refreshed() {
+    let harness = TestHarness::start().await;
+    let presence = &harness.state.presence;
+    let store = &harness.state.redis.store;
+
+    presence.set_online(&player(ALICE), "lobby", "session-1").await;
+    presence.set_online(&player(BOB), "arena", "session-2").await;
+    for player_id in [ALICE.0, BOB.0] {
+        assert!(store.pexpire(&format!("presence:player:{}", player_id), 50).await.unwrap());
+    }
+    presence.refresh_server("lobby").await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(player_ids(presence.get_all().await), vec![ALICE.0.to_owned()]);
+}
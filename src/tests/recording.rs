@@ -0,0 +1,56 @@
+use std::{fs::File, io::{BufRead, BufReader, Read}, path::Path, sync::Arc};
+
+use flate2::read::{GzDecoder, MultiGzDecoder};
+use uuid::Uuid;
+
+use crate::{database::{Database, models::{player::Player, r#match::Match}}, socket::{leaderboard::LeaderboardEntry, recording::{recorder::SocketRecorder, replay::replay_recording}}};
+
+use super::{harness::{TestHarness, wait_until}, match_lifecycle::{ALICE, BOB, play_match, setup_map_and_players, SERVER_ID}};
+
+// lines written so far, the gzip trailer is missing until the recorder stops
+fn count_recorded_lines(path: &Path) -> usize {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return 0
+    };
+    BufReader::new(MultiGzDecoder::new(file)).lines().map_while(Result::ok).count()
+}
+
+#[rocket::async_test]
+async fn replaying_a_recording_reproduces_stats() {
+    let directory = std::env::temp_dir().join(format!("mars-recording-{}", Uuid::new_v4()));
+    let recorder = Arc::new(SocketRecorder::start(&directory).expect("could not start recorder"));
+    let recording_path = recorder.get_path().to_owned();
+
+    let live = TestHarness::start_with_recorder(Some(Arc::clone(&recorder))).await;
+    setup_map_and_players(&live).await;
+    let mut plugin = live.connect_plugin(SERVER_ID).await;
+    let match_id = play_match(&live, &mut plugin).await;
+    wait_until("every event is recorded", || async { count_recorded_lines(&recording_path) == 7 }).await;
+
+    // the finished file is a complete gzip stream, trailer included
+    recorder.finish();
+    let mut recorded = String::new();
+    GzDecoder::new(File::open(&recording_path).unwrap()).read_to_string(&mut recorded).expect("the recording should be complete");
+    assert_eq!(recorded.lines().count(), 7);
+
+    // a fresh backend without the maps and profiles the live API had
+    let offline = TestHarness::start().await;
+    let summary = replay_recording(&offline.state, &recording_path).await.expect("could not replay recording");
+    assert_eq!((summary.events, summary.skipped, summary.servers), (7, 0, 1));
+    assert_eq!((summary.seeded_players, summary.seeded_levels), (2, 1));
+
+    let replayed_matches : Vec<Match> = offline.state.database.get_all_documents().await;
+    assert_eq!(replayed_matches.len(), 1);
+    assert_ne!(replayed_matches[0].id, match_id);
+    assert_eq!(replayed_matches[0].participants[ALICE.0].stats.kills, 2);
+
+    let alice : Player = Database::find_by_id(&offline.state.database.players, ALICE.0).await.expect("alice should be seeded");
+    assert_eq!((alice.stats.kills, alice.stats.wins, alice.stats.objectives.wool_captures), (2, 1, 1));
+    let bob : Player = Database::find_by_id(&offline.state.database.players, BOB.0).await.expect("bob should be seeded");
+    assert_eq!((bob.stats.deaths, bob.stats.losses), (2, 1));
+    let kills : Vec<LeaderboardEntry> = offline.get_json("/mc/leaderboards/kills/all_time").await;
+    assert_eq!(kills.first().map(|entry| (entry.id.as_str(), entry.score)), Some((ALICE.0, 2)));
+
+    let _ = std::fs::remove_dir_all(directory);
+}
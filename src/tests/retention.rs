@@ -0,0 +1,109 @@
+use std::{fs::File, io::{BufRead, BufReader}};
+
+use flate2::read::MultiGzDecoder;
+use mongodb::bson::doc;
+use rocket::serde::json::{serde_json, Value};
+use uuid::Uuid;
+
+use crate::database::{Database, models::{archive_aggregate::ArchiveAggregate, death::{DamageCause, Death}, player::SimplePlayer, session::Session}, retention::{self, RetentionPolicy}};
+
+use super::{harness::TestHarness, match_lifecycle::{ALICE, BOB, SERVER_ID}};
+
+const DAY: u64 = 86_400_000;
+// 2026-01-31T00:00:00Z
+const NOW: u64 = 1_769_817_600_000;
+
+fn simple_player(player: (&str, &str)) -> SimplePlayer {
+    SimplePlayer { id: player.0.to_owned(), name: player.1.to_owned() }
+}
+
+fn death(id: &str, attacker: Option<(&str, &str)>, cause: DamageCause, created_at: u64) -> Death {
+    Death {
+        id: id.to_owned(),
+        victim: simple_player(BOB),
+        attacker: attacker.map(simple_player),
+        weapon: None,
+        entity: None,
+        distance: None,
+        key: String::from("generic"),
+        cause,
+        server_id: SERVER_ID.to_owned(),
+        match_id: String::from("match"),
+        created_at
+    }
+}
+
+fn session(id: &str, created_at: u64, ended_at: Option<u64>) -> Session {
+    Session { id: id.to_owned(), ip: String::from("127.0.0.1"), player: simple_player(ALICE), server_id: SERVER_ID.to_owned(), created_at, ended_at }
+}
+
+#[rocket::async_test]
+async fn expired_documents_are_archived_and_counted() {
+    let harness = TestHarness::start().await;
+    let database = &harness.state.database;
+    for death in [
+        death("old-kill", Some(ALICE), DamageCause::Melee, NOW - 100 * DAY),
+        death("old-fall", None, DamageCause::Fall, NOW - 100 * DAY + 1000),
+        death("recent", Some(ALICE), DamageCause::Melee, NOW - DAY)
+    ] {
+        database.deaths.insert_one(&death).await.unwrap();
+    }
+    for session in [session("old", NOW - 100 * DAY, Some(NOW - 100 * DAY + 60_000)), session("active", NOW - 100 * DAY, None)] {
+        database.sessions.insert_one(&session).await.unwrap();
+    }
+    let policies = [
+        RetentionPolicy { collection: "death", timestamp_field: "createdAt", days: 90 },
+        RetentionPolicy { collection: "session", timestamp_field: "endedAt", days: 90 }
+    ];
+
+    let directory = std::env::temp_dir().join(format!("mars-archive-{}", Uuid::new_v4()));
+    let summary = retention::archive_expired(database, &policies[0], &directory, NOW).await.unwrap();
+    assert_eq!(summary.archived, 2);
+    let archived : Vec<Value> = BufReader::new(MultiGzDecoder::new(File::open(summary.path.unwrap()).unwrap())).lines()
+        .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+        .collect();
+    assert_eq!(archived.iter().map(|death| death["_id"].as_str().unwrap()).collect::<Vec<_>>(), vec!["old-kill", "old-fall"]);
+    let deaths : Vec<Death> = database.get_all_documents().await;
+    assert_eq!(deaths.iter().map(|death| death.id.as_str()).collect::<Vec<_>>(), vec!["recent"]);
+
+    retention::apply_retention(database, &policies[1..], &directory, NOW).await;
+    let sessions : Vec<Session> = database.get_all_documents().await;
+    assert_eq!(sessions.iter().map(|session| session.id.as_str()).collect::<Vec<_>>(), vec!["active"]);
+
+    let deaths : ArchiveAggregate = Database::find_by_id(&database.archive_aggregates, "death:2025-10-23").await.unwrap();
+    assert_eq!(deaths.count, 2);
+    assert_eq!((deaths.counters["kills"], deaths.counters["cause:MELEE"], deaths.counters["cause:FALL"]), (1.0, 1.0, 1.0));
+    let sessions : ArchiveAggregate = Database::find_by_id(&database.archive_aggregates, "session:2025-10-23").await.unwrap();
+    assert_eq!((sessions.count, sessions.counters["playtime"]), (1, 60_000.0));
+
+    // nothing left to archive, so no new file either
+    let summary = retention::archive_expired(database, &policies[0], &directory, NOW).await.unwrap();
+    assert!(summary.archived == 0 && summary.path.is_none());
+    let _ = std::fs::remove_dir_all(directory);
+}
+
+#[rocket::async_test]
+async fn documents_claimed_by_another_run_are_left_to_it() {
+    let harness = TestHarness::start().await;
+    let database = &harness.state.database;
+    for id in ["claimed", "abandoned", "free"] {
+        database.deaths.insert_one(&death(id, None, DamageCause::Fall, NOW - 100 * DAY)).await.unwrap();
+    }
+    // another instance is archiving one right now, and one was claimed by a run that died long ago
+    database.store.update_one("death", doc! { "_id": "claimed" }, doc! { "$set": { "archivingBy": "other", "archivingAt": (NOW - 60_000) as i64 } }, false).await.unwrap();
+    database.store.update_one("death", doc! { "_id": "abandoned" }, doc! { "$set": { "archivingBy": "dead", "archivingAt": (NOW - DAY) as i64 } }, false).await.unwrap();
+
+    let directory = std::env::temp_dir().join(format!("mars-archive-{}", Uuid::new_v4()));
+    let policy = RetentionPolicy { collection: "death", timestamp_field: "createdAt", days: 90 };
+    let summary = retention::archive_expired(database, &policy, &directory, NOW).await.unwrap();
+    assert_eq!(summary.archived, 2);
+    let archived : Vec<Value> = BufReader::new(MultiGzDecoder::new(File::open(summary.path.unwrap()).unwrap())).lines()
+        .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+        .collect();
+    assert!(archived.iter().all(|death| death["_id"] != "claimed" && death.get("archivingBy").is_none()));
+    let deaths : Vec<Death> = database.get_all_documents().await;
+    assert_eq!(deaths.iter().map(|death| death.id.as_str()).collect::<Vec<_>>(), vec!["claimed"]);
+    let aggregate : ArchiveAggregate = Database::find_by_id(&database.archive_aggregates, "death:2025-10-23").await.unwrap();
+    assert_eq!(aggregate.count, 2);
+    let _ = std::fs::remove_dir_all(directory);
+}
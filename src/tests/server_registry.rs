@@ -0,0 +1,53 @@
+use std::{sync::Arc, time::Duration};
+
+use rocket::serde::json::json;
+use tokio::sync::mpsc;
+
+use crate::{database::{cache::RedisAdapter, store::memory::MemoryCacheStore}, socket::{event_type::EventType, server::server_registry::ServerRegistry}};
+
+use super::harness::wait_until;
+
+// two instances sharing one redis, as behind a load balancer
+fn start_instances(owner_ttl_ms: usize, owner_heartbeat: Duration) -> (Arc<ServerRegistry>, Arc<ServerRegistry>) {
+    let redis = Arc::new(RedisAdapter::new(Arc::new(MemoryCacheStore::new())));
+    let first = Arc::new(ServerRegistry::with_owner_ttl(String::from("first"), Arc::clone(&redis), owner_ttl_ms, owner_heartbeat));
+    let second = Arc::new(ServerRegistry::with_owner_ttl(String::from("second"), redis, owner_ttl_ms, owner_heartbeat));
+    ServerRegistry::spawn_relay_listener(Arc::clone(&first));
+    ServerRegistry::spawn_relay_listener(Arc::clone(&second));
+    (first, second)
+}
+
+#[rocket::async_test]
+async fn calls_reach_the_instance_owning_the_server() {
+    let (first, second) = start_instances(30_000, Duration::from_secs(10));
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    first.register("Lobby", sender).await;
+
+    assert!(second.is_connected("lobby").await);
+    // the relay listener subscribes in the background
+    wait_until("the owning instance is listening", || async {
+        second.call("LOBBY", &EventType::Message, json!({ "message": "hello", "sound": null, "playerIds": [] })).await
+    }).await;
+    let packet = tokio::time::timeout(Duration::from_secs(5), receiver.recv()).await.unwrap().unwrap();
+    assert_eq!((packet.event.to_string(), packet.data["message"].as_str()), (EventType::Message.to_string(), Some("hello")));
+
+    assert!(!second.call("arena", &EventType::Message, json!({ "message": "nobody", "sound": null, "playerIds": [] })).await);
+}
+
+#[rocket::async_test]
+async fn owners_expire_once_their_heartbeat_stops() {
+    let (first, second) = start_instances(300, Duration::from_millis(100));
+    let (sender, _receiver) = mpsc::unbounded_channel();
+    first.register("lobby", sender).await;
+    let heartbeat = ServerRegistry::spawn_owner_heartbeat(Arc::clone(&first));
+
+    // outlives the TTL while the heartbeat runs
+    tokio::time::sleep(Duration::from_millis(700)).await;
+    assert!(second.is_connected("lobby").await);
+
+    // the instance dies without unregistering
+    heartbeat.abort();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(!second.is_connected("lobby").await);
+    assert!(!second.call("lobby", &EventType::Message, json!({ "message": "gone", "sound": null, "playerIds": [] })).await);
+}
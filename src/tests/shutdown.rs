@@ -0,0 +1,51 @@
+use rocket::serde::json::json;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+use crate::{database::{Database, models::r#match::{Match, MatchState}}, socket::event_type::EventType};
+
+use super::{harness::{TestHarness, wait_until}, match_lifecycle::{ALICE, BOB, MAP_ID, SERVER_ID, setup_map_and_players}};
+
+async fn cached_match(harness: &TestHarness) -> Option<Match> {
+    let match_id : String = harness.state.redis.get(&format!("server:{}:current_match_id", SERVER_ID)).await.ok()?;
+    harness.state.match_cache.query(&match_id).await
+}
+
+#[rocket::async_test]
+async fn shutdown_drains_events_persists_the_cache_and_asks_servers_to_reconnect() {
+    let mut harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let mut plugin = harness.connect_plugin(SERVER_ID).await;
+    plugin.send(EventType::MatchLoad, json!({
+        "mapId": MAP_ID,
+        "parties": [
+            { "name": "Red", "alias": "Red", "color": "RED", "min": 1, "max": 8 },
+            { "name": "Blue", "alias": "Blue", "color": "BLUE", "min": 1, "max": 8 }
+        ],
+        "goals": { "cores": [], "destroyables": [], "flags": [], "wools": [], "controlPoints": [] }
+    })).await;
+    wait_until("the match is loaded", || async { cached_match(&harness).await.is_some() }).await;
+
+    // sent before the close, so it's still handled
+    plugin.send(EventType::MatchStart, json!({
+        "participants": [
+            { "id": ALICE.0, "name": ALICE.1, "partyName": "Red" },
+            { "id": BOB.0, "name": BOB.1, "partyName": "Blue" }
+        ]
+    })).await;
+    let (_, close_frame) = tokio::join!(harness.stop_socket(), plugin.expect_close());
+    let close_frame = close_frame.expect("the API should send a close frame");
+    assert_eq!(close_frame.code, CloseCode::Restart);
+    let hint : rocket::serde::json::Value = rocket::serde::json::serde_json::from_str(&close_frame.reason).expect("the close reason should be JSON");
+    assert_eq!(hint["reconnect"], true);
+    assert!(hint["retryAfterMs"].as_u64().is_some());
+
+    let ongoing = cached_match(&harness).await.expect("the match should still be cached");
+    assert!(ongoing.get_state() == MatchState::InProgress);
+    harness.state.persist_cached_state().await;
+    let persisted : Match = Database::find_by_id(&harness.state.database.matches, &ongoing.id).await.expect("the match should be persisted");
+    assert!(persisted.get_state() == MatchState::InProgress);
+    assert_eq!(persisted.version, ongoing.version);
+
+    // nothing is accepted anymore
+    assert!(super::fake_plugin::FakePlugin::connect(harness.socket_address(), "late", TestHarness::TOKEN).await.is_err());
+}
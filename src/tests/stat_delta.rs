@@ -0,0 +1,76 @@
+use rocket::serde::json::json;
+
+use crate::database::{Database, models::player::{Player, PlayerRecord, SimplePlayer}, stat_delta::{self, StatDelta}};
+
+use super::{harness::TestHarness, match_lifecycle::{ALICE, setup_map_and_players}};
+
+async fn load_alice(harness: &TestHarness) -> Player {
+    harness.state.player_cache.get(&harness.state.database, ALICE.1).await.expect("alice should be cached")
+}
+
+fn kills_record(match_id: &str, value: u32) -> PlayerRecord<u32> {
+    PlayerRecord { match_id: match_id.to_owned(), player: SimplePlayer { id: ALICE.0.to_owned(), name: ALICE.1.to_owned() }, value }
+}
+
+#[test]
+fn delta_holds_only_what_changed() {
+    let mut player : Player = rocket::serde::json::serde_json::from_value(json!({
+        "_id": ALICE.0, "name": ALICE.1, "nameLower": "alice", "lastSessionId": null, "firstJoinedAt": 0.0, "lastJoinedAt": 0.0,
+        "ips": [], "notes": [], "rankIds": [], "tagIds": [], "activeTagId": null, "stats": {}, "gamemodeStats": {}, "activeJoinSoundId": null
+    })).unwrap();
+    let before = stat_delta::flatten_stats(&player);
+    player.stats.kills += 3;
+    player.stats.damage_given += 1.5;
+    player.stats.weapon_kills.insert(String::from("BOW"), 2);
+    player.stats.records.kills_in_match = Some(kills_record("a", 3));
+
+    let delta = StatDelta::between(&before, &stat_delta::flatten_stats(&player));
+    assert_eq!(delta.increments.len(), 3);
+    assert_eq!(delta.increments["stats.kills"], 3.0);
+    assert_eq!(delta.increments["stats.weaponKills.BOW"], 2.0);
+    assert_eq!(delta.increments["stats.damageGiven"], 1.5);
+    assert_eq!(delta.replacements.keys().collect::<Vec<_>>(), vec!["stats.records.killsInMatch"]);
+    assert!(StatDelta::between(&before, &before).is_empty());
+}
+
+// a logout and a match event holding different copies of the same player
+#[rocket::async_test]
+async fn concurrent_writers_keep_each_others_stats() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let mut from_match = load_alice(&harness).await;
+    let mut from_logout = load_alice(&harness).await;
+
+    from_match.stats.kills += 2;
+    from_match.stats.records.kills_in_match = Some(kills_record("first", 5));
+    harness.state.player_cache.save_stats(&harness.state.database, &from_match.name.clone(), &mut from_match, false).await;
+    from_logout.stats.server_playtime += 60_000;
+    from_logout.stats.records.kills_in_match = Some(kills_record("second", 3));
+    harness.state.player_cache.save_stats(&harness.state.database, &from_logout.name.clone(), &mut from_logout, true).await;
+
+    harness.state.database.write_behind.flush().await;
+    let stored : Player = Database::find_by_id(&harness.state.database.players, ALICE.0).await.unwrap();
+    let cached = load_alice(&harness).await;
+    for player in [stored, cached] {
+        assert_eq!((player.stats.kills, player.stats.server_playtime), (2, 60_000));
+        assert_eq!(player.stats.records.kills_in_match.map(|record| record.match_id), Some(String::from("first")));
+    }
+}
+
+// a stale copy that also changed the profile gets the conflict back, with none of its changes applied
+#[rocket::async_test]
+async fn stale_profile_changes_are_returned_not_dropped() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let mut first = load_alice(&harness).await;
+    let mut stale = load_alice(&harness).await;
+
+    first.tag_ids.push(String::from("first"));
+    harness.state.player_cache.set(&harness.state.database, &first.name.clone(), &mut first, false).await.unwrap();
+    stale.tag_ids.push(String::from("stale"));
+    stale.stats.kills += 2;
+    assert!(harness.state.player_cache.set(&harness.state.database, &stale.name.clone(), &mut stale, false).await.is_err());
+
+    let cached = load_alice(&harness).await;
+    assert_eq!((cached.tag_ids, cached.stats.kills), (vec![String::from("first")], 0));
+}
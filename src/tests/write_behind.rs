@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use rocket::serde::json::json;
+
+use mongodb::bson::doc;
+
+use crate::database::{Database, models::{level::{Level, LevelRecords}, player::Player}, stat_delta::StatDelta, store::{IndexDefinition, memory::MemoryDocumentStore}, write_behind::WriteBehindQueue};
+
+use super::{harness::TestHarness, match_lifecycle::{ALICE, setup_map_and_players}};
+
+fn level(id: &str, name: &str) -> Level {
+    Level {
+        id: id.to_owned(),
+        loaded_at: 0,
+        name: name.to_owned(),
+        name_lower: name.to_lowercase(),
+        version: String::from("1.0.0"),
+        gamemodes: Vec::new(),
+        updated_at: 0,
+        authors: Vec::new(),
+        contributors: Vec::new(),
+        goals: None,
+        last_match_id: None,
+        records: LevelRecords::default()
+    }
+}
+
+fn kills_delta(kills: f64, record: u32) -> StatDelta {
+    let mut delta = StatDelta::default();
+    delta.increments.insert(String::from("stats.kills"), kills);
+    delta.replacements.insert(String::from("stats.records.killsInMatch"), json!({
+        "matchId": format!("match-{}", record), "player": { "id": ALICE.0, "name": ALICE.1 }, "value": record
+    }));
+    delta
+}
+
+#[rocket::async_test]
+async fn writes_are_coalesced_until_flushed() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let database = &harness.state.database;
+    // a queue of its own, so the background flusher can't get to it first
+    let queue = WriteBehindQueue::new(Arc::clone(&database.store));
+
+    for (kills, record) in [(1.0, 5), (2.0, 3), (3.0, 4)] {
+        queue.apply_stat_delta(ALICE.0, kills_delta(kills, record)).await;
+    }
+    queue.save(&level("map-1", "First")).await;
+    queue.save(&level("map-1", "Second")).await;
+    assert_eq!(queue.get_pending_count(), 2);
+    let alice : Player = Database::find_by_id(&database.players, ALICE.0).await.unwrap();
+    assert_eq!(alice.stats.kills, 0);
+
+    queue.flush().await;
+    assert_eq!(queue.get_pending_count(), 0);
+    let alice : Player = Database::find_by_id(&database.players, ALICE.0).await.unwrap();
+    assert_eq!(alice.stats.kills, 6);
+    assert_eq!(alice.stats.records.kills_in_match.map(|record| record.value), Some(5));
+    let map : Level = Database::find_by_id(&database.levels, "map-1").await.unwrap();
+    assert_eq!(map.name, "Second");
+}
+
+#[rocket::async_test]
+async fn writers_flush_when_the_queue_is_full() {
+    let database = Database::new(Arc::new(MemoryDocumentStore::new()));
+    let queue = WriteBehindQueue::with_limits(Arc::clone(&database.store), 2, usize::MAX, 3);
+    for index in 0..10 {
+        queue.save(&level(&format!("map-{}", index), "Map")).await;
+        assert!(queue.get_pending_count() < 3);
+    }
+    queue.flush().await;
+    assert_eq!(database.levels.count_documents(None).await.unwrap(), 10);
+}
+
+#[rocket::async_test]
+async fn batches_are_capped_by_size() {
+    let database = Database::new(Arc::new(MemoryDocumentStore::new()));
+    // every write is over the cap on its own, so each goes out in a batch of its own
+    let queue = WriteBehindQueue::with_limits(Arc::clone(&database.store), 500, 1, 100);
+    for index in 0..10 {
+        queue.save(&level(&format!("map-{}", index), "Map")).await;
+    }
+    queue.flush().await;
+    assert_eq!(database.levels.count_documents(None).await.unwrap(), 10);
+}
+
+#[rocket::async_test]
+async fn rejected_writes_are_retried() {
+    let database = Database::new(Arc::new(MemoryDocumentStore::new()));
+    database.store.create_index("level", &IndexDefinition::new(doc! { "nameLower": 1 }).unique()).await.unwrap();
+    database.save(&level("map-1", "Taken")).await;
+    let queue = WriteBehindQueue::new(Arc::clone(&database.store));
+
+    queue.save(&level("map-2", "Taken")).await;
+    queue.flush().await;
+    assert!(Database::find_by_id(&database.levels, "map-2").await.is_none());
+
+    // the name frees up before the next flush
+    database.levels.delete_one(doc! { "_id": "map-1" }).await.unwrap();
+    queue.flush().await;
+    assert!(Database::find_by_id(&database.levels, "map-2").await.is_some());
+}
+
+#[rocket::async_test]
+async fn cache_misses_only_flush_the_player_they_load() {
+    let harness = TestHarness::start().await;
+    setup_map_and_players(&harness).await;
+    let database = &harness.state.database;
+    database.write_behind.flush().await;
+    database.apply_stat_delta(ALICE.0, &kills_delta(2.0, 2)).await;
+    database.write_behind.save(&level("map-2", "Elsewhere")).await;
+    harness.state.player_cache.evict(ALICE.0).await;
+
+    let alice = harness.state.player_cache.get(database, ALICE.1).await.unwrap();
+    assert_eq!(alice.stats.kills, 2);
+    assert_eq!(database.write_behind.get_pending_count(), 1);
+}
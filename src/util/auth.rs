@@ -1,6 +1,6 @@
 use rocket::{request::{FromRequest, self}, Request, http::Status};
 
-use crate::MarsAPIState;
+use crate::network::Networks;
 
 struct TokenType;
 impl TokenType {
@@ -33,11 +33,15 @@ impl<'r> FromRequest<'r> for AuthorizationToken {
     async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, AuthorizationError> {
         let header_map = req.headers();
         let server_id = if let Some(id) = header_map.get_one("Mars-Server-ID") { Some(String::from(id)) } else { None };
-        let actual_token = if let Some(state) = req.rocket().state::<MarsAPIState>() { 
-            &state.config.token 
+        let networks = if let Some(networks) = req.rocket().state::<Networks>() { 
+            networks 
         } else {
             return create_failure_outcome(Status::InternalServerError, String::from("Internal error"))
         };
+        let actual_token = match networks.resolve(req) {
+            Some(state) => &state.config.token,
+            None => return create_failure_outcome(Status::Unauthorized, String::from("Unknown network"))
+        };
         match header_map.get_one("Authorization") {
             Some(value) => {
                 let parts = value.split(" ").collect::<Vec<&str>>();
@@ -2,6 +2,8 @@ use serde::{Serialize, Deserialize};
 use rocket::{response::{self, Response, Responder}, Request, http::{Status, ContentType}, serde::json::Json};
 use strum_macros::Display;
 
+use crate::database::cache::UpdateError;
+
 pub struct ApiErrorResponder {
     pub status: Status,
     pub error: ApiErrorV2
@@ -83,6 +85,14 @@ impl ApiErrorResponder {
         )
     }
 
+    pub fn player_offline() -> Self {
+        ApiErrorResponder::create_api_error_responder(
+            Status::NotFound, 
+            &ApiExceptionType::PlayerOffline, 
+            "The player is not online"
+        )
+    }
+
     pub fn missing_punishment() -> Self {
         ApiErrorResponder::create_api_error_responder(
             Status::NotFound, 
@@ -151,6 +161,14 @@ impl ApiErrorResponder {
         )
     }
 
+    pub fn server_not_connected() -> Self {
+        ApiErrorResponder::create_api_error_responder(
+            Status::NotFound, 
+            &ApiExceptionType::ServerNotConnected, 
+            "The server is not connected"
+        )
+    }
+
     pub fn achievement_missing() -> Self {
         ApiErrorResponder::create_api_error_responder(
             Status::NotFound, 
@@ -166,6 +184,70 @@ impl ApiErrorResponder {
             "An achievement already exists with that name"
         )
     }
+
+    pub fn dead_letter_missing() -> Self {
+        ApiErrorResponder::create_api_error_responder(
+            Status::NotFound,
+            &ApiExceptionType::DeadLetterMissing,
+            "The dead letter does not exist"
+        )
+    }
+
+    pub fn dead_letter_redriving() -> Self {
+        ApiErrorResponder::create_api_error_responder(
+            Status::Conflict,
+            &ApiExceptionType::DeadLetterRedriving,
+            "The dead letter is already being redriven"
+        )
+    }
+
+    pub fn dead_letter_resolved() -> Self {
+        ApiErrorResponder::create_api_error_responder(
+            Status::Conflict,
+            &ApiExceptionType::DeadLetterResolved,
+            "The dead letter has already been redriven successfully"
+        )
+    }
+
+    pub fn cache_entry_missing() -> Self {
+        ApiErrorResponder::create_api_error_responder(
+            Status::NotFound,
+            &ApiExceptionType::CacheEntryMissing,
+            "The value is not cached"
+        )
+    }
+
+    pub fn idempotency_key_in_use() -> Self {
+        ApiErrorResponder::create_api_error_responder(
+            Status::Conflict,
+            &ApiExceptionType::IdempotencyKeyInUse,
+            "A request with this idempotency key is still being handled"
+        )
+    }
+
+    pub fn idempotency_key_reused() -> Self {
+        ApiErrorResponder::create_api_error_responder(
+            Status::UnprocessableEntity,
+            &ApiExceptionType::IdempotencyKeyReused,
+            "The idempotency key was already used for a different request"
+        )
+    }
+
+    pub fn version_conflict() -> Self {
+        ApiErrorResponder::create_api_error_responder(
+            Status::Conflict,
+            &ApiExceptionType::VersionConflict,
+            "The resource kept changing while it was being updated, try again"
+        )
+    }
+
+    pub fn from_player_update(error: UpdateError<ApiErrorResponder>) -> Self {
+        match error {
+            UpdateError::Missing => ApiErrorResponder::missing_player(),
+            UpdateError::Rejected(rejection) => rejection,
+            UpdateError::Conflict(_) => ApiErrorResponder::version_conflict()
+        }
+    }
 }
 
 impl<'r> Responder<'r, 'static> for ApiErrorResponder {
@@ -222,6 +304,7 @@ enum ApiExceptionType {
     SessionMissing,
     SessionInactive,
     PlayerMissing,
+    PlayerOffline,
     RankConflict,
     RankMissing,
     RankAlreadyPresent,
@@ -235,5 +318,13 @@ enum ApiExceptionType {
     AchievementMising,
     PunishmentMissing,
     NoteMissing,
+    ServerNotConnected,
+    DeadLetterMissing,
+    DeadLetterResolved,
+    DeadLetterRedriving,
+    VersionConflict,
+    CacheEntryMissing,
+    IdempotencyKeyInUse,
+    IdempotencyKeyReused,
     Anonymous
 }
@@ -15,4 +15,16 @@ pub fn deflate_string(data: &[u8]) -> anyhow::Result<Vec<u8>> {
 
 pub fn enumify(target: &str) -> String {
     target.trim().to_uppercase().replace(" ", "_")
-}
\ No newline at end of file
+}
+
+// escapes user input so it can be matched literally inside a mongo $regex
+pub fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\.+*?()|[]{}^$#&-~".contains(c) {
+            escaped.push('\\');
+        };
+        escaped.push(c);
+    };
+    escaped
+}